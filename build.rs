@@ -9,6 +9,7 @@ fn main() {
         .pure()
         .cargo_out_dir("protos")
         .input("src/protos/Mumble.proto")
+        .input("src/protos/MumbleUDP.proto")
         .include("src/protos")
         .run_from_script();
 
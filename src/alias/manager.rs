@@ -1,6 +1,7 @@
 use crate::database::connection::DbPool;
 use crate::database::entities::aliases as alias_entity;
 use crate::error::Error;
+use crate::users::Identity;
 use chrono::{DateTime, Utc};
 use rusqlite::{OptionalExtension, params};
 
@@ -27,11 +28,13 @@ impl AliasManager {
         &self,
         name: &str,
         author: &str,
+        author_identity: Option<&Identity>,
         commands: &str,
     ) -> Result<(), Error> {
         let pool = self.db.clone();
         let name = name.to_string();
         let author = author.to_string();
+        let author_identity = author_identity.map(Identity::to_string);
         let commands = commands.to_string();
 
         tokio::task::spawn_blocking(move || -> Result<(), Error> {
@@ -40,8 +43,9 @@ impl AliasManager {
                 .map_err(|e| Error::DatabaseError(format!("Failed to open database: {}", e)))?;
             let created_at = Utc::now().to_rfc3339();
             let result = conn.execute(
-                "INSERT INTO aliases (name, author, created_at, commands) VALUES (?1, ?2, ?3, ?4)",
-                params![name, author, created_at, commands],
+                "INSERT INTO aliases (name, author, created_at, commands, author_identity)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![name, author, created_at, commands, author_identity],
             );
 
             match result {
@@ -74,7 +78,7 @@ impl AliasManager {
 
             let row = conn
                 .query_row(
-                    "SELECT name, author, created_at, commands FROM aliases WHERE name = ?1",
+                    "SELECT name, author, created_at, commands, author_identity FROM aliases WHERE name = ?1",
                     params![name],
                     |row| {
                         let created_at: String = row.get(2)?;
@@ -83,18 +87,20 @@ impl AliasManager {
                             row.get::<_, String>(1)?,
                             created_at,
                             row.get::<_, String>(3)?,
+                            row.get::<_, Option<String>>(4)?,
                         ))
                     },
                 )
                 .optional()
                 .map_err(|e| Error::DatabaseError(format!("Failed to get alias: {}", e)))?;
 
-            if let Some((name, author, created_at_raw, commands)) = row {
+            if let Some((name, author, created_at_raw, commands, author_identity)) = row {
                 Ok(Some(alias_entity::Model {
                     name,
                     author,
                     created_at: Self::parse_created_at(&created_at_raw)?,
                     commands,
+                    author_identity,
                 }))
             } else {
                 Ok(None)
@@ -138,7 +144,7 @@ impl AliasManager {
                 .map_err(|e| Error::DatabaseError(format!("Failed to open database: {}", e)))?;
             let mut stmt = conn
                 .prepare(
-                    "SELECT name, author, created_at, commands
+                    "SELECT name, author, created_at, commands, author_identity
                      FROM aliases
                      ORDER BY name ASC
                      LIMIT ?1 OFFSET ?2",
@@ -152,20 +158,23 @@ impl AliasManager {
                         row.get::<_, String>(1)?,
                         row.get::<_, String>(2)?,
                         row.get::<_, String>(3)?,
+                        row.get::<_, Option<String>>(4)?,
                     ))
                 })
                 .map_err(|e| Error::DatabaseError(format!("Failed to list aliases: {}", e)))?;
 
             let mut aliases = Vec::new();
             for row in rows {
-                let (name, author, created_at_raw, commands) = row.map_err(|e| {
-                    Error::DatabaseError(format!("Failed to read alias row: {}", e))
-                })?;
+                let (name, author, created_at_raw, commands, author_identity) =
+                    row.map_err(|e| {
+                        Error::DatabaseError(format!("Failed to read alias row: {}", e))
+                    })?;
                 aliases.push(alias_entity::Model {
                     name,
                     author,
                     created_at: Self::parse_created_at(&created_at_raw)?,
                     commands,
+                    author_identity,
                 });
             }
             Ok(aliases)
@@ -209,7 +218,7 @@ impl AliasManager {
                 .map_err(|e| Error::DatabaseError(format!("Failed to open database: {}", e)))?;
             let mut stmt = conn
                 .prepare(
-                    "SELECT name, author, created_at, commands
+                    "SELECT name, author, created_at, commands, author_identity
                      FROM aliases
                      WHERE name LIKE ?1 OR commands LIKE ?1
                      ORDER BY name ASC
@@ -224,20 +233,23 @@ impl AliasManager {
                         row.get::<_, String>(1)?,
                         row.get::<_, String>(2)?,
                         row.get::<_, String>(3)?,
+                        row.get::<_, Option<String>>(4)?,
                     ))
                 })
                 .map_err(|e| Error::DatabaseError(format!("Failed to search aliases: {}", e)))?;
 
             let mut aliases = Vec::new();
             for row in rows {
-                let (name, author, created_at_raw, commands) = row.map_err(|e| {
-                    Error::DatabaseError(format!("Failed to read alias row: {}", e))
-                })?;
+                let (name, author, created_at_raw, commands, author_identity) =
+                    row.map_err(|e| {
+                        Error::DatabaseError(format!("Failed to read alias row: {}", e))
+                    })?;
                 aliases.push(alias_entity::Model {
                     name,
                     author,
                     created_at: Self::parse_created_at(&created_at_raw)?,
                     commands,
+                    author_identity,
                 });
             }
             Ok(aliases)
@@ -1,90 +1,379 @@
 use std::{
+    collections::BTreeMap,
     io::{self},
-    path::Path,
-    sync::Arc,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
 };
 
 use log::trace;
 
 use tokio::{
     io::AsyncReadExt,
-    sync::{Mutex, mpsc},
+    sync::{Mutex, Notify, mpsc},
     time::{self, Duration},
 };
 
 use opus::Encoder;
 
 use crate::{
-    config::{AudioEffectSettings, BehaviorSettings},
+    config::{
+        AudioEffectSettings, BehaviorSettings, InputNormalizationMode, MusicSettings, PlaybackMode,
+    },
+    protos,
     session::OutgoingMessage,
-    util,
 };
 use effects::{AudioEffect, AudioEffectsProcessor};
+use encoder::{EncoderConfig, SharedEncoderConfig};
+use music::{MusicQueue, Track};
+use packet::VoicePacket;
+use protobuf::Message;
+use queue::{PlayQueue, QueuedSound};
+use whisper::{VoiceTargets, WhisperTarget};
 
+pub mod clip;
 pub mod effects;
+pub mod encoder;
+pub mod music;
+pub mod packet;
+pub mod queue;
+pub mod receive;
+pub mod whisper;
 
 const SAMPLE_RATE: usize = 48000;
 const CHANNELS: usize = 2;
-const FRAME_SAMPLES: usize = 960 * CHANNELS;
-const FRAME_SIZE_MS: u64 = 20;
+/// Fades and voice sequence numbers advance in 10ms steps, whatever the frame size
+const STEP_MS: u64 = 10;
+/// How far ahead music is decoded before the reader waits for playback
+const MUSIC_BUFFER_SAMPLES: usize = SAMPLE_RATE * CHANNELS * 10;
+/// Per-frame change of the music ducking level, so ducking takes about 200ms
+const DUCK_STEP: f32 = 0.1;
+
+/// Identifies one play request, from the queue through to the end of playback
+pub type StreamId = u64;
+
+/// Linear fade to silence, counted in 10ms steps
+#[derive(Debug, Clone, Copy)]
+struct Fade {
+    steps_left: u32,
+    total_steps: u32,
+}
 
 struct AudioStream {
+    id: StreamId,
     buffer: Arc<Mutex<Vec<i16>>>,
     finished: Arc<Mutex<bool>>,
+    label: String,
+    /// The stream `!skip`, `!pause` and `!nowplaying` act on
+    primary: bool,
+    /// A `!music` track, which ducks under other streams and is never primary
+    music: bool,
+    /// Voice target the stream is sent to
+    target: u8,
+    paused: bool,
+    gain: f32,
+    fade: Option<Fade>,
+    /// Interleaved samples mixed so far
+    played: usize,
+}
+
+impl AudioStream {
+    /// Gain for the next frame of `steps` 10ms steps, advancing any fade in progress
+    fn next_gain(&mut self, steps: u32) -> f32 {
+        match &mut self.fade {
+            Some(fade) => {
+                let gain = self.gain * fade.steps_left as f32 / fade.total_steps as f32;
+                fade.steps_left = fade.steps_left.saturating_sub(steps);
+                gain
+            }
+            None => self.gain,
+        }
+    }
+
+    fn faded_out(&self) -> bool {
+        self.fade.is_some_and(|fade| fade.steps_left == 0)
+    }
+
+    async fn info(&self) -> StreamInfo {
+        let buffered = self.buffer.lock().await.len();
+        let duration =
+            (*self.finished.lock().await).then(|| samples_to_duration(self.played + buffered));
+
+        StreamInfo {
+            id: self.id,
+            label: self.label.clone(),
+            elapsed: samples_to_duration(self.played),
+            duration,
+            gain: self.gain,
+            primary: self.primary,
+            whisper: self.target != packet::TARGET_NORMAL,
+            paused: self.paused,
+            fading: self.fade.is_some(),
+        }
+    }
+}
+
+/// Converts a count of interleaved stereo samples to playback time
+fn samples_to_duration(samples: usize) -> Duration {
+    Duration::from_secs_f64(samples as f64 / (SAMPLE_RATE * CHANNELS) as f64)
+}
+
+/// Moves the ducking level one step towards `target`
+fn duck_towards(level: f32, target: f32) -> f32 {
+    if level > target {
+        (level - DUCK_STEP).max(target)
+    } else {
+        (level + DUCK_STEP).min(target)
+    }
+}
+
+/// Adds `samples` scaled by `gain` into `mixed`, saturating at the i16 range
+fn mix_into(mixed: &mut [i16], samples: &[i16], gain: f32) {
+    if gain == 1.0 {
+        for (out, &sample) in mixed.iter_mut().zip(samples) {
+            *out = out.saturating_add(sample);
+        }
+    } else {
+        for (out, &sample) in mixed.iter_mut().zip(samples) {
+            let scaled = (sample as f32 * gain) as i32;
+            *out = (*out as i32 + scaled).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        }
+    }
+}
+
+/// What happened to a play request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayOutcome {
+    /// The sound is playing now
+    Started(StreamId),
+    /// The sound is waiting in the queue at this 1-based position
+    Queued(StreamId, usize),
+}
+
+/// What a new stream is for, which decides how it is mixed and controlled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StreamKind {
+    /// A sound clip in the channel; the primary one is what `!skip` acts on
+    Sound { primary: bool },
+    /// A `!music` track
+    Music,
+    /// A sound whispered to the given voice target
+    Whisper(u8),
+}
+
+/// Snapshot of one playing stream
+#[derive(Debug, Clone)]
+pub struct StreamInfo {
+    pub id: StreamId,
+    pub label: String,
+    pub elapsed: Duration,
+    /// Only known once the whole sound has been decoded
+    pub duration: Option<Duration>,
+    pub gain: f32,
+    pub primary: bool,
+    /// Whispered to a user or channel instead of played in the channel
+    pub whisper: bool,
+    pub paused: bool,
+    pub fading: bool,
+}
+
+impl StreamInfo {
+    /// Time left to play, once the total duration is known
+    pub fn remaining(&self) -> Option<Duration> {
+        self.duration
+            .map(|duration| duration.saturating_sub(self.elapsed))
+    }
 }
 
+#[derive(Clone)]
 pub struct AudioMixerControl {
     streams: Arc<Mutex<Vec<AudioStream>>>,
+    next_id: Arc<AtomicU64>,
+    queue: Arc<Mutex<PlayQueue>>,
+    primary_ended: Arc<Notify>,
+    playback_mode: PlaybackMode,
     audio_effects: AudioEffectSettings,
     audio_buffer_size: usize,
+    music: Arc<Mutex<MusicQueue>>,
+    music_ended: Arc<Notify>,
+    music_settings: MusicSettings,
+    ytdlp_cookies: Option<PathBuf>,
+    voice_targets: Arc<Mutex<VoiceTargets>>,
+    /// Sends music announcements and voice target registrations
+    outgoing: mpsc::Sender<OutgoingMessage>,
 }
 
 pub struct AudioMixerTask {
     streams: Arc<Mutex<Vec<AudioStream>>>,
+    next_id: Arc<AtomicU64>,
+    queue: Arc<Mutex<PlayQueue>>,
+    primary_ended: Arc<Notify>,
+    playback_mode: PlaybackMode,
     audio_effects: AudioEffectSettings,
     audio_buffer_size: usize,
+    music: Arc<Mutex<MusicQueue>>,
+    music_ended: Arc<Notify>,
+    music_settings: MusicSettings,
+    ytdlp_cookies: Option<PathBuf>,
+    voice_targets: Arc<Mutex<VoiceTargets>>,
+    /// Sends music announcements and voice target registrations
+    outgoing: mpsc::Sender<OutgoingMessage>,
     _task_handle: tokio::task::JoinHandle<()>,
+    _queue_task_handle: tokio::task::JoinHandle<()>,
+    _music_task_handle: tokio::task::JoinHandle<()>,
 }
 
 impl AudioMixerTask {
     pub fn control(&self) -> AudioMixerControl {
         AudioMixerControl {
             streams: self.streams.clone(),
+            next_id: self.next_id.clone(),
+            queue: self.queue.clone(),
+            primary_ended: self.primary_ended.clone(),
+            playback_mode: self.playback_mode,
             audio_effects: self.audio_effects.clone(),
             audio_buffer_size: self.audio_buffer_size,
+            music: self.music.clone(),
+            music_ended: self.music_ended.clone(),
+            music_settings: self.music_settings.clone(),
+            ytdlp_cookies: self.ytdlp_cookies.clone(),
+            voice_targets: self.voice_targets.clone(),
+            outgoing: self.outgoing.clone(),
         }
     }
 }
 
 pub struct AudioMixer {
     streams: Arc<Mutex<Vec<AudioStream>>>,
+    /// Signalled whenever the primary stream ends
+    primary_ended: Arc<Notify>,
+    /// Signalled whenever a music track ends
+    music_ended: Arc<Notify>,
+    /// Music volume multiplier while other streams play
+    duck_volume: f32,
+    /// Current music ducking level, eased towards 1.0 or `duck_volume`
+    duck_level: f32,
     writer_sender: mpsc::Sender<OutgoingMessage>,
-    encoder: Encoder,
-    seq: u32,
+    /// Encoder settings requested by the session
+    encoder_config: Arc<SharedEncoderConfig>,
+    /// Encoder settings the outputs currently use
+    active_config: EncoderConfig,
+    /// One output per voice target, created when a stream first uses it
+    outputs: BTreeMap<u8, TargetOutput>,
+    /// Voice sequence number, counting 10ms frames since the mixer started
+    seq: u64,
     volume: f32,
     // Pre-allocated buffers to reduce allocations in hot path
-    mixed_buffer: Vec<i16>,
     temp_buffer: Vec<i16>,
 }
 
+/// Mix buffer and encoder for the streams sent to one voice target
+struct TargetOutput {
+    encoder: Encoder,
+    mixed: Vec<i16>,
+    /// Streams mixed into the current frame
+    active: usize,
+    /// Whether the current frame ends the transmission
+    last_frame: bool,
+    /// Whether the last frame sent left a transmission open
+    transmitting: bool,
+}
+
+impl TargetOutput {
+    fn new(config: &EncoderConfig) -> Self {
+        TargetOutput {
+            encoder: config.encoder().unwrap(),
+            mixed: vec![0; config.frame_samples()],
+            active: 0,
+            last_frame: true,
+            transmitting: false,
+        }
+    }
+}
+
 impl AudioMixer {
     pub fn spawn(
         writer_sender: mpsc::Sender<OutgoingMessage>,
         behavior_settings: &BehaviorSettings,
         audio_effects: &AudioEffectSettings,
+        music_settings: &MusicSettings,
+        ytdlp_cookies: Option<PathBuf>,
+        encoder_config: Arc<SharedEncoderConfig>,
     ) -> AudioMixerTask {
-        let mut mixer = AudioMixer::new(writer_sender, behavior_settings, audio_effects);
+        let outgoing = writer_sender.clone();
+        let mut mixer = AudioMixer::new(
+            writer_sender,
+            behavior_settings,
+            audio_effects,
+            encoder_config,
+        );
+        mixer.duck_volume = music_settings.duck_volume;
         let streams = mixer.streams.clone();
+        let primary_ended = mixer.primary_ended.clone();
+        let music_ended = mixer.music_ended.clone();
 
         let task_handle = tokio::spawn(async move {
             mixer.mix_loop().await;
         });
 
+        let next_id = Arc::new(AtomicU64::new(1));
+        let queue = Arc::new(Mutex::new(PlayQueue::new(
+            behavior_settings.max_queue_length,
+        )));
+        let music = Arc::new(Mutex::new(MusicQueue::new(music_settings.max_queue_length)));
+        let voice_targets = Arc::new(Mutex::new(VoiceTargets::default()));
+        let queue_control = AudioMixerControl {
+            streams: streams.clone(),
+            next_id: next_id.clone(),
+            queue: queue.clone(),
+            primary_ended: primary_ended.clone(),
+            playback_mode: behavior_settings.playback_mode,
+            audio_effects: audio_effects.clone(),
+            audio_buffer_size: behavior_settings.audio_buffer_size,
+            music: music.clone(),
+            music_ended: music_ended.clone(),
+            music_settings: music_settings.clone(),
+            ytdlp_cookies: ytdlp_cookies.clone(),
+            voice_targets: voice_targets.clone(),
+            outgoing: outgoing.clone(),
+        };
+        let music_control = queue_control.clone();
+
+        // Promotes the next sound whenever the primary stream ends
+        let queue_task_handle = tokio::spawn(async move {
+            loop {
+                queue_control.primary_ended.notified().await;
+                queue_control.advance().await;
+            }
+        });
+
+        // Starts the next track whenever the music stream ends
+        let music_task_handle = tokio::spawn(async move {
+            loop {
+                music_control.music_ended.notified().await;
+                music_control.next_track().await;
+            }
+        });
+
         AudioMixerTask {
             streams,
+            next_id,
+            queue,
+            primary_ended,
+            playback_mode: behavior_settings.playback_mode,
             audio_effects: audio_effects.clone(),
             audio_buffer_size: behavior_settings.audio_buffer_size,
+            music,
+            music_ended,
+            music_settings: music_settings.clone(),
+            ytdlp_cookies,
+            voice_targets,
+            outgoing,
             _task_handle: task_handle,
+            _queue_task_handle: queue_task_handle,
+            _music_task_handle: music_task_handle,
         }
     }
 
@@ -92,129 +381,212 @@ impl AudioMixer {
         writer_sender: mpsc::Sender<OutgoingMessage>,
         behavior_settings: &BehaviorSettings,
         _audio_effects: &AudioEffectSettings,
+        encoder_config: Arc<SharedEncoderConfig>,
     ) -> Self {
+        let active_config = encoder_config.get();
         let mixer = AudioMixer {
             streams: Arc::new(Mutex::new(Vec::new())),
+            primary_ended: Arc::new(Notify::new()),
+            music_ended: Arc::new(Notify::new()),
+            duck_volume: 1.0,
+            duck_level: 1.0,
             writer_sender,
-            encoder: Encoder::new(
-                SAMPLE_RATE.try_into().unwrap(),
-                opus::Channels::Stereo,
-                opus::Application::Voip,
-            )
-            .unwrap(),
+            encoder_config,
+            active_config,
+            // Normal talking is always in use, so create its encoder up front
+            outputs: BTreeMap::from([(packet::TARGET_NORMAL, TargetOutput::new(&active_config))]),
             seq: 0,
             volume: behavior_settings.volume,
             // Pre-allocate buffers for better performance
-            mixed_buffer: vec![0; FRAME_SAMPLES],
-            temp_buffer: Vec::with_capacity(FRAME_SAMPLES),
+            temp_buffer: Vec::with_capacity(active_config.frame_samples()),
         };
 
         mixer
     }
 
-    pub async fn mix_loop(&mut self) {
-        let mut interval = time::interval(Duration::from_millis(FRAME_SIZE_MS));
+    fn frame_interval(config: &EncoderConfig) -> time::Interval {
+        let mut interval = time::interval(Duration::from_millis(config.frame_ms));
         interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+        interval
+    }
+
+    /// Switches to new encoder settings. A new bitrate is applied in place;
+    /// any other change rebuilds the encoders.
+    fn reconfigure(&mut self, config: EncoderConfig) {
+        log::info!(
+            "Encoding voice at {} bit/s in {}ms frames ({:?})",
+            config.bitrate,
+            config.frame_ms,
+            config.application
+        );
+
+        let bitrate_only = EncoderConfig {
+            bitrate: config.bitrate,
+            ..self.active_config
+        } == config;
+        self.active_config = config;
+
+        if bitrate_only {
+            for output in self.outputs.values_mut() {
+                let bitrate = opus::Bitrate::Bits(config.bitrate as i32);
+                if let Err(e) = output.encoder.set_bitrate(bitrate) {
+                    log::warn!("Failed to change the encoder bitrate: {}", e);
+                }
+            }
+        } else {
+            for output in self.outputs.values_mut() {
+                *output = TargetOutput {
+                    transmitting: output.transmitting,
+                    ..TargetOutput::new(&config)
+                };
+            }
+        }
+    }
+
+    pub async fn mix_loop(&mut self) {
+        let mut interval = Self::frame_interval(&self.active_config);
 
         loop {
             interval.tick().await;
 
-            // Reuse pre-allocated buffers instead of allocating new ones
-            self.mixed_buffer.fill(0);
-            let mut active = 0;
+            let config = self.encoder_config.get();
+            if config != self.active_config {
+                let frame_changed = config.frame_ms != self.active_config.frame_ms;
+                self.reconfigure(config);
+                if frame_changed {
+                    interval = Self::frame_interval(&config);
+                }
+            }
+
+            if !self.mix_frame().await {
+                return;
+            }
+        }
+    }
+
+    /// Mixes and sends one frame for every voice target in use. Returns
+    /// `false` once the writer is gone.
+    async fn mix_frame(&mut self) -> bool {
+        let config = self.active_config;
+        let frame_samples = config.frame_samples();
+        let steps = (config.frame_ms / STEP_MS) as u32;
+
+        // The sequence tracks elapsed time, including silence, so receivers can
+        // place each packet correctly in their jitter buffer
+        self.seq = self.seq.wrapping_add(steps as u64);
+
+        // Reuse pre-allocated buffers instead of allocating new ones
+        for output in self.outputs.values_mut() {
+            output.mixed.fill(0);
+            output.active = 0;
+        }
+
+        // Pre-allocate vectors to reduce allocations in hot path
+        let mut streams_to_remove = Vec::new();
 
-            // Pre-allocate vectors to reduce allocations in hot path
-            let mut streams_to_remove = Vec::new();
+        {
+            let mut streams = self.streams.lock().await;
 
+            // Music ducks while any other stream is audible in the channel
+            let duck_target = if streams
+                .iter()
+                .any(|s| !s.music && !s.paused && s.target == packet::TARGET_NORMAL)
             {
-                let mut streams = self.streams.lock().await;
+                self.duck_volume
+            } else {
+                1.0
+            };
+            self.duck_level = duck_towards(self.duck_level, duck_target);
 
-                for (stream_index, stream) in streams.iter().enumerate() {
-                    // Try to acquire locks without blocking - use try_lock for better performance
-                    if let Ok(mut pcm) = stream.buffer.try_lock() {
-                        if let Ok(is_finished) = stream.finished.try_lock() {
-                            if pcm.len() < FRAME_SAMPLES {
-                                if *is_finished && !pcm.is_empty() {
-                                    // Pad with zeros to complete the last frame
-                                    self.temp_buffer.clear();
-                                    self.temp_buffer.extend_from_slice(&pcm);
-                                    self.temp_buffer.resize(FRAME_SAMPLES, 0);
-
-                                    for i in 0..FRAME_SAMPLES {
-                                        self.mixed_buffer[i] = self.mixed_buffer[i]
-                                            .saturating_add(self.temp_buffer[i]);
-                                    }
-                                    pcm.clear();
-                                    active += 1;
-                                    streams_to_remove.push(stream_index);
-                                } else if *is_finished {
-                                    streams_to_remove.push(stream_index);
-                                }
-                                continue;
-                            }
+            for (stream_index, stream) in streams.iter_mut().enumerate() {
+                if stream.paused {
+                    continue;
+                }
+                if stream.faded_out() {
+                    streams_to_remove.push(stream_index);
+                    continue;
+                }
+                let mut gain = stream.next_gain(steps);
+                if stream.music {
+                    gain *= self.duck_level;
+                }
+                let output = self
+                    .outputs
+                    .entry(stream.target)
+                    .or_insert_with(|| TargetOutput::new(&config));
 
-                            // Process full frame
-                            for i in 0..FRAME_SAMPLES {
-                                self.mixed_buffer[i] = self.mixed_buffer[i].saturating_add(pcm[i]);
-                            }
+                // Try to acquire locks without blocking - use try_lock for better performance
+                if let Ok(mut pcm) = stream.buffer.try_lock() {
+                    if let Ok(is_finished) = stream.finished.try_lock() {
+                        if pcm.len() < frame_samples {
+                            if *is_finished && !pcm.is_empty() {
+                                // Pad with zeros to complete the last frame
+                                self.temp_buffer.clear();
+                                self.temp_buffer.extend_from_slice(&pcm);
+                                self.temp_buffer.resize(frame_samples, 0);
 
-                            pcm.drain(0..FRAME_SAMPLES);
-                            active += 1;
+                                mix_into(&mut output.mixed, &self.temp_buffer, gain);
+                                stream.played += pcm.len();
+                                pcm.clear();
+                                output.active += 1;
+                                streams_to_remove.push(stream_index);
+                            } else if *is_finished {
+                                streams_to_remove.push(stream_index);
+                            }
+                            continue;
                         }
+
+                        // Process full frame
+                        mix_into(&mut output.mixed, &pcm[..frame_samples], gain);
+
+                        pcm.drain(0..frame_samples);
+                        stream.played += frame_samples;
+                        output.active += 1;
                     }
                 }
+            }
 
-                // Remove finished streams (iterate in reverse to maintain indices)
-                for &index in streams_to_remove.iter().rev() {
-                    streams.remove(index);
-                }
+            // Remove finished streams (iterate in reverse to maintain indices)
+            let mut primary_finished = false;
+            let mut music_finished = false;
+            for &index in streams_to_remove.iter().rev() {
+                let removed = streams.remove(index);
+                primary_finished |= removed.primary;
+                music_finished |= removed.music;
+            }
+            if primary_finished {
+                self.primary_ended.notify_one();
+            }
+            if music_finished {
+                self.music_ended.notify_one();
             }
 
-            // If no active streams, don't bother encoding
-            if active == 0 {
-                continue;
+            // Nothing left to play for a target after this frame, so it ends
+            // that target's transmission
+            for (target, output) in self.outputs.iter_mut() {
+                output.last_frame = streams
+                    .iter()
+                    .filter(|stream| stream.target == *target)
+                    .all(|stream| stream.paused);
             }
+        }
 
-            // Apply global volume multiplier to the mixed audio
-            if self.volume != 1.0 {
-                // Use integer arithmetic when possible for better performance
-                if self.volume == 0.5 {
-                    // Common case: half volume can use bit shifting
-                    for sample in self.mixed_buffer.iter_mut() {
-                        *sample = *sample >> 1;
-                    }
-                } else if self.volume == 2.0 {
-                    // Common case: double volume with saturation
-                    for sample in self.mixed_buffer.iter_mut() {
-                        let doubled = (*sample as i32) << 1;
-                        *sample = doubled.max(i16::MIN as i32).min(i16::MAX as i32) as i16;
-                    }
-                } else {
-                    // General case: use floating point
-                    for sample in self.mixed_buffer.iter_mut() {
-                        let scaled_sample = (*sample as f32 * self.volume) as i32;
-                        // Clamp to i16 range to prevent overflow
-                        *sample = scaled_sample.max(i16::MIN as i32).min(i16::MAX as i32) as i16;
-                    }
+        for (&target, output) in self.outputs.iter_mut() {
+            if output.active == 0 {
+                // Nothing to encode, unless a pause or skip cut a transmission
+                // short and its end still needs to be marked
+                if !output.transmitting {
+                    continue;
                 }
+                output.last_frame = true;
             }
+            output.transmitting = !output.last_frame;
 
-            // Opus frame structure:
-            // 1 byte header
-            // varint sequence number
-            // opus payload
-            // positional data
+            apply_volume(&mut output.mixed, self.volume);
 
-            self.seq = self.seq.wrapping_add(2); // any other values cause choppy audio. what?
+            let mut opus_buf = vec![0; config.max_frame_bytes()];
 
-            let header_byte = 0b1000_0000 as u8; // First bit indicates OPUS encoding
-            let seq = util::encode_varint_long(self.seq as u64);
-            let mut opus_buf = vec![0; 1000];
-
-            match self
-                .encoder
-                .encode(&self.mixed_buffer[..], &mut opus_buf[..])
-            {
+            match output.encoder.encode(&output.mixed[..], &mut opus_buf[..]) {
                 Ok(len) => {
                     opus_buf.truncate(len);
                 }
@@ -224,18 +596,13 @@ impl AudioMixer {
                 }
             }
 
-            let opus_header_value = opus_buf.len() as u64;
-            let mut opus_header = util::encode_varint_long(opus_header_value);
-
-            opus_header[0] |= 0x20; // Force termination bit
-
-            let final_frame = [
-                &[header_byte],
-                seq.as_slice(),
-                opus_header.as_slice(),
-                opus_buf.as_slice(),
-            ]
-            .concat();
+            let final_frame = VoicePacket {
+                target,
+                session: None,
+                sequence: self.seq,
+                opus: opus_buf,
+                terminator: output.last_frame,
+            };
 
             if let Err(e) = self
                 .writer_sender
@@ -243,16 +610,48 @@ impl AudioMixer {
                 .await
             {
                 eprintln!("Failed to send audio data: {}", e);
-                break;
+                return false;
             }
 
-            trace!("Wrote audio frame with sequence number {}", self.seq);
+            trace!(
+                "Wrote audio frame for target {} with sequence number {}",
+                target, self.seq
+            );
+        }
+        true
+    }
+}
+
+/// Applies the global volume multiplier to a mixed frame
+fn apply_volume(samples: &mut [i16], volume: f32) {
+    if volume == 1.0 {
+        return;
+    }
+
+    // Use integer arithmetic when possible for better performance
+    if volume == 0.5 {
+        // Common case: half volume can use bit shifting
+        for sample in samples.iter_mut() {
+            *sample >>= 1;
+        }
+    } else if volume == 2.0 {
+        // Common case: double volume with saturation
+        for sample in samples.iter_mut() {
+            let doubled = (*sample as i32) << 1;
+            *sample = doubled.max(i16::MIN as i32).min(i16::MAX as i32) as i16;
+        }
+    } else {
+        // General case: use floating point
+        for sample in samples.iter_mut() {
+            let scaled_sample = (*sample as f32 * volume) as i32;
+            // Clamp to i16 range to prevent overflow
+            *sample = scaled_sample.max(i16::MIN as i32).min(i16::MAX as i32) as i16;
         }
     }
 }
 
 impl AudioMixerControl {
-    pub async fn play_sound(&self, file: &str) -> io::Result<()> {
+    pub async fn play_sound(&self, file: &str) -> io::Result<PlayOutcome> {
         self.play_sound_with_effects(file, &[]).await
     }
 
@@ -260,7 +659,97 @@ impl AudioMixerControl {
         &self,
         file: &str,
         effects: &[AudioEffect],
+    ) -> io::Result<PlayOutcome> {
+        // Sound files are named after their code
+        let label = Path::new(file)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| file.to_string());
+        self.play_labeled(file, effects, &label).await
+    }
+
+    /// Plays a sound only to `target`, registering a voice target for it.
+    /// Whispers skip the queue and never become the primary stream.
+    pub async fn play_whisper(
+        &self,
+        file: &str,
+        effects: &[AudioEffect],
+        label: &str,
+        target: WhisperTarget,
+    ) -> io::Result<StreamId> {
+        // Held until the stream is added, so no other whisper takes the ID meanwhile
+        let mut voice_targets = self.voice_targets.lock().await;
+        let busy: Vec<u8> = self
+            .streams
+            .lock()
+            .await
+            .iter()
+            .map(|s| s.target)
+            .filter(|target| *target != packet::TARGET_NORMAL)
+            .collect();
+        let target_id = voice_targets.assign(target, &busy).ok_or_else(|| {
+            io::Error::other("Every voice target is busy with a whisper, try again shortly")
+        })?;
+        let payload = target
+            .voice_target(target_id)
+            .write_to_bytes()
+            .map_err(io::Error::other)?;
+        self.outgoing
+            .send(OutgoingMessage::Raw(
+                protos::types::MESSAGE_VOICE_TARGET,
+                payload,
+            ))
+            .await
+            .map_err(|e| io::Error::other(e.to_string()))?;
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        log::info!("Whispering sound {} (#{}) to {:?}", label, id, target);
+        self.start_stream(id, file, effects, label, StreamKind::Whisper(target_id))
+            .await?;
+        Ok(id)
+    }
+
+    /// Plays a sound, or queues it behind the primary stream in queue mode.
+    /// `label` names the sound in `!queue` and `!nowplaying`.
+    async fn play_labeled(
+        &self,
+        file: &str,
+        effects: &[AudioEffect],
+        label: &str,
+    ) -> io::Result<PlayOutcome> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut queue = self.queue.lock().await;
+        let has_primary = self.streams.lock().await.iter().any(|s| s.primary);
+
+        if self.playback_mode == PlaybackMode::Queue && (has_primary || !queue.is_empty()) {
+            let position = queue
+                .push(QueuedSound {
+                    id,
+                    file: file.to_string(),
+                    effects: effects.to_vec(),
+                    label: label.to_string(),
+                })
+                .ok_or_else(|| io::Error::other("the play queue is full"))?;
+            log::info!("Queued sound {} (#{}) at position {}", label, id, position);
+            return Ok(PlayOutcome::Queued(id, position));
+        }
+
+        let kind = StreamKind::Sound {
+            primary: !has_primary,
+        };
+        self.start_stream(id, file, effects, label, kind).await?;
+        Ok(PlayOutcome::Started(id))
+    }
+
+    async fn start_stream(
+        &self,
+        id: StreamId,
+        file: &str,
+        effects: &[AudioEffect],
+        label: &str,
+        kind: StreamKind,
     ) -> io::Result<()> {
+        let music = kind == StreamKind::Music;
         log::info!("Playing sound {} with {} effects", file, effects.len());
         for (i, effect) in effects.iter().enumerate() {
             log::info!("  Effect {}: {:?}", i, effect);
@@ -273,7 +762,12 @@ impl AudioMixerControl {
 
         // Always use the effects pipeline, even with no effects, so normalization behavior is consistent.
         log::info!("Using effects pipeline for {} effects", effects.len());
-        let processor = AudioEffectsProcessor::new(self.audio_effects.clone())
+        let mut effect_settings = self.audio_effects.clone();
+        if music {
+            // Boost-only normalization analyzes the whole input before playing
+            effect_settings.normalization_mode = InputNormalizationMode::Loudnorm;
+        }
+        let processor = AudioEffectsProcessor::new(effect_settings)
             .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
         let mut child = processor
             .apply_effects_streaming(Path::new(file), effects)
@@ -285,6 +779,17 @@ impl AudioMixerControl {
         tokio::spawn(async move {
             let mut buf = vec![0u8; buffer_size]; // Use configurable buffer size
             loop {
+                // The mixer holds the only other reference until the stream is removed
+                if Arc::strong_count(&buffer_clone) == 1 {
+                    let _ = child.kill().await;
+                    break;
+                }
+                // Music can run for hours, so only decode a little ahead of playback
+                if music && buffer_clone.lock().await.len() >= MUSIC_BUFFER_SAMPLES {
+                    time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+
                 match stdout.read(&mut buf).await {
                     Ok(0) => break,
                     Ok(n) => {
@@ -301,14 +806,427 @@ impl AudioMixerControl {
         });
 
         let mut streams = self.streams.lock().await;
-        streams.push(AudioStream { buffer, finished });
+        streams.push(AudioStream {
+            id,
+            buffer,
+            finished,
+            label: label.to_string(),
+            primary: kind == StreamKind::Sound { primary: true },
+            music,
+            target: match kind {
+                StreamKind::Whisper(target) => target,
+                _ => packet::TARGET_NORMAL,
+            },
+            paused: false,
+            gain: if music {
+                self.music_settings.volume
+            } else {
+                1.0
+            },
+            fade: None,
+            played: 0,
+        });
 
         Ok(())
     }
 
+    /// Makes the next queued sound, or else the oldest overlapping one, primary
+    async fn advance(&self) {
+        let mut queue = self.queue.lock().await;
+        if self.streams.lock().await.iter().any(|s| s.primary) {
+            return;
+        }
+
+        while let Some(next) = queue.pop() {
+            match self
+                .start_stream(
+                    next.id,
+                    &next.file,
+                    &next.effects,
+                    &next.label,
+                    StreamKind::Sound { primary: true },
+                )
+                .await
+            {
+                Ok(()) => return,
+                Err(e) => log::warn!("Failed to start queued sound {}: {}", next.label, e),
+            }
+        }
+
+        let mut streams = self.streams.lock().await;
+        let next = streams
+            .iter_mut()
+            .find(|s| !s.music && s.target == packet::TARGET_NORMAL);
+        if let Some(stream) = next {
+            stream.primary = true;
+        }
+    }
+
+    pub fn playback_mode(&self) -> PlaybackMode {
+        self.playback_mode
+    }
+
+    /// Removes matching streams, promoting the next sound or track if the
+    /// primary or music stream was among them. Returns the removed labels.
+    async fn remove_streams(&self, matches: impl Fn(&AudioStream) -> bool) -> Vec<String> {
+        let mut removed = Vec::new();
+        let mut primary_removed = false;
+        let mut music_removed = false;
+
+        self.streams.lock().await.retain(|stream| {
+            if matches(stream) {
+                removed.push(stream.label.clone());
+                primary_removed |= stream.primary;
+                music_removed |= stream.music;
+                false
+            } else {
+                true
+            }
+        });
+
+        if primary_removed {
+            self.primary_ended.notify_one();
+        }
+        if music_removed {
+            self.music_ended.notify_one();
+        }
+        removed
+    }
+
+    /// Stops the primary stream and moves on, returning the skipped sound's label
+    pub async fn skip(&self) -> Option<String> {
+        let skipped = self.remove_streams(|s| s.primary).await.pop()?;
+        log::info!("Skipped sound {}", skipped);
+        Some(skipped)
+    }
+
+    /// Stops one stream, or drops it from the queue if it has not started yet.
+    /// Returns the sound's label.
+    pub async fn stop_stream(&self, id: StreamId) -> Option<String> {
+        if let Some(queued) = self.queue.lock().await.remove_where(|s| s.id == id).pop() {
+            return Some(queued.label);
+        }
+        self.remove_streams(|s| s.id == id).await.pop()
+    }
+
+    /// Stops every stream and queued entry playing the sound `label`.
+    /// With a fade, playing streams fade out instead of stopping abruptly.
+    pub async fn stop_label(&self, label: &str, fade: Option<Duration>) -> usize {
+        let dequeued = self
+            .queue
+            .lock()
+            .await
+            .remove_where(|s| s.label.eq_ignore_ascii_case(label))
+            .len();
+
+        let matches = |s: &AudioStream| s.label.eq_ignore_ascii_case(label);
+        let stopped = match fade {
+            Some(fade) => {
+                let mut streams = self.streams.lock().await;
+                let mut faded = 0;
+                for stream in streams.iter_mut().filter(|s| matches(s)) {
+                    Self::start_fade(stream, fade);
+                    faded += 1;
+                }
+                faded
+            }
+            None => self.remove_streams(matches).await.len(),
+        };
+
+        dequeued + stopped
+    }
+
+    /// Fades a stream to silence over `duration`, then stops it
+    pub async fn fade_out(&self, id: StreamId, duration: Duration) -> Option<String> {
+        let mut streams = self.streams.lock().await;
+        let stream = streams.iter_mut().find(|s| s.id == id)?;
+        Self::start_fade(stream, duration);
+        Some(stream.label.clone())
+    }
+
+    fn start_fade(stream: &mut AudioStream, duration: Duration) {
+        let steps = (duration.as_millis() as u64 / STEP_MS).max(1) as u32;
+        stream.fade = Some(Fade {
+            steps_left: steps,
+            total_steps: steps,
+        });
+        // A paused stream would never finish fading
+        stream.paused = false;
+    }
+
+    /// Changes the gain of one stream (1.0 = unchanged)
+    pub async fn set_gain(&self, id: StreamId, gain: f32) -> bool {
+        let mut streams = self.streams.lock().await;
+        match streams.iter_mut().find(|s| s.id == id) {
+            Some(stream) => {
+                stream.gain = gain;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Pauses or resumes the primary stream, returning its label
+    pub async fn set_paused(&self, paused: bool) -> Option<String> {
+        let mut streams = self.streams.lock().await;
+        let stream = streams.iter_mut().find(|s| s.primary)?;
+        stream.paused = paused;
+        Some(stream.label.clone())
+    }
+
+    pub async fn now_playing(&self) -> Option<StreamInfo> {
+        let streams = self.streams.lock().await;
+        let stream = streams.iter().find(|s| s.primary)?;
+        Some(stream.info().await)
+    }
+
+    /// Progress of one playing stream, including its remaining duration
+    pub async fn stream_info(&self, id: StreamId) -> Option<StreamInfo> {
+        let streams = self.streams.lock().await;
+        let stream = streams.iter().find(|s| s.id == id)?;
+        Some(stream.info().await)
+    }
+
+    /// Every playing stream, oldest first
+    pub async fn streams(&self) -> Vec<StreamInfo> {
+        let streams = self.streams.lock().await;
+        let mut infos = Vec::with_capacity(streams.len());
+        for stream in streams.iter() {
+            infos.push(stream.info().await);
+        }
+        infos
+    }
+
+    /// IDs and labels of the sounds waiting in the queue, next up first
+    pub async fn queued(&self) -> Vec<(StreamId, String)> {
+        self.queue.lock().await.entries()
+    }
+
+    /// Adds tracks to the music queue, announcing them in `channel`.
+    /// Returns how many fit and whether they wait behind other music.
+    pub async fn queue_music(
+        &self,
+        tracks: Vec<Track>,
+        effects: &[AudioEffect],
+        channel: u32,
+    ) -> io::Result<(usize, bool)> {
+        let mut music = self.music.lock().await;
+        let waiting = music.current().is_some() || !music.is_empty();
+        let added = music.extend(tracks, effects);
+        if added == 0 {
+            return Err(io::Error::other("the music queue is full"));
+        }
+        music.set_channel(channel);
+
+        if !waiting {
+            self.music_ended.notify_one();
+        }
+        Ok((added, waiting))
+    }
+
+    /// Starts the next music track, skipping any that fail to resolve
+    async fn next_track(&self) {
+        if self.streams.lock().await.iter().any(|s| s.music) {
+            return;
+        }
+
+        loop {
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            let next = {
+                let mut music = self.music.lock().await;
+                let Some(next) = music.pop() else {
+                    music.set_current(None);
+                    return;
+                };
+                music.set_current(Some((id, next.track.clone())));
+                next
+            };
+
+            let url =
+                match music::resolve_stream_url(&next.track, self.ytdlp_cookies.as_deref()).await {
+                    Ok(url) => url,
+                    Err(e) => {
+                        log::warn!("Failed to resolve track {}: {}", next.track.url, e);
+                        continue;
+                    }
+                };
+
+            // Skipped or stopped while the stream URL was being resolved
+            let music = self.music.lock().await;
+            if music.current().map(|(current, _)| *current) != Some(id) {
+                continue;
+            }
+
+            match self
+                .start_stream(
+                    id,
+                    &url,
+                    &next.effects,
+                    &next.track.title,
+                    StreamKind::Music,
+                )
+                .await
+            {
+                Ok(()) => {
+                    let channel = music
+                        .channel()
+                        .filter(|_| self.music_settings.announce_tracks);
+                    if let Some(channel) = channel {
+                        let message =
+                            OutgoingMessage::TextMessage(next.track.announcement(), channel);
+                        let _ = self.outgoing.send(message).await;
+                    }
+                    return;
+                }
+                Err(e) => log::warn!("Failed to stream track {}: {}", next.track.title, e),
+            }
+        }
+    }
+
+    /// Stops the current music track and moves on to the next one
+    pub async fn skip_track(&self) -> Option<Track> {
+        let track = {
+            let mut music = self.music.lock().await;
+            let (_, track) = music.current()?.clone();
+            music.set_current(None);
+            track
+        };
+        // A track that is still resolving has no stream to remove yet
+        if self.remove_streams(|s| s.music).await.is_empty() {
+            self.music_ended.notify_one();
+        }
+        log::info!("Skipped track {}", track.title);
+        Some(track)
+    }
+
+    /// Stops music playback and empties the music queue, returning how many
+    /// tracks were dropped
+    pub async fn stop_music(&self) -> usize {
+        let dropped = self.music.lock().await.clear();
+        let stopped = self.remove_streams(|s| s.music).await.len();
+        dropped + stopped
+    }
+
+    /// The current track with its playback progress, and the tracks after it
+    pub async fn music_status(&self) -> (Option<(Track, Option<StreamInfo>)>, Vec<Track>) {
+        let (current, pending) = {
+            let music = self.music.lock().await;
+            (music.current().cloned(), music.pending())
+        };
+        let current = match current {
+            Some((id, track)) => Some((track, self.stream_info(id).await)),
+            None => None,
+        };
+        (current, pending)
+    }
+
     pub async fn stop_all_streams(&self) {
         log::info!("Stopping all audio streams");
+        let dropped = self.queue.lock().await.clear();
+        if dropped > 0 {
+            log::info!("Cleared {} queued sounds", dropped);
+        }
+        self.music.lock().await.clear();
         let mut streams = self.streams.lock().await;
         streams.clear();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_stream(gain: f32) -> AudioStream {
+        AudioStream {
+            id: 1,
+            buffer: Arc::new(Mutex::new(Vec::new())),
+            finished: Arc::new(Mutex::new(false)),
+            label: "test".to_string(),
+            primary: true,
+            music: false,
+            target: packet::TARGET_NORMAL,
+            paused: false,
+            gain,
+            fade: None,
+            played: 0,
+        }
+    }
+
+    #[test]
+    fn test_mix_into_applies_gain_and_saturates() {
+        let mut mixed = vec![100, 32_000, -32_000];
+        mix_into(&mut mixed, &[100, 100, -100], 0.5);
+        assert_eq!(mixed, vec![150, 32_050, -32_050]);
+
+        mix_into(&mut mixed, &[1000, 1000, -1000], 1.0);
+        assert_eq!(mixed, vec![1150, i16::MAX, i16::MIN]);
+
+        mix_into(&mut mixed, &[i16::MAX; 3], 2.0);
+        assert_eq!(mixed[0], i16::MAX);
+    }
+
+    #[test]
+    fn test_ducking_eases_between_levels() {
+        assert!((duck_towards(1.0, 0.3) - 0.9).abs() < 1e-6);
+        assert!((duck_towards(0.5, 1.0) - 0.6).abs() < 1e-6);
+
+        // Settles exactly on the target instead of overshooting it
+        assert_eq!(duck_towards(0.35, 0.3), 0.3);
+        assert_eq!(duck_towards(0.95, 1.0), 1.0);
+        assert_eq!(duck_towards(1.0, 1.0), 1.0);
+    }
+
+    #[test]
+    fn test_fade_ramps_down_then_finishes() {
+        let mut stream = test_stream(0.5);
+        AudioMixerControl::start_fade(&mut stream, Duration::from_millis(80));
+
+        // Four 20ms frames
+        let gains: Vec<f32> = (0..4).map(|_| stream.next_gain(2)).collect();
+        assert_eq!(gains, vec![0.5, 0.375, 0.25, 0.125]);
+        assert!(stream.faded_out());
+
+        // Fading resumes a paused stream, and always takes at least one frame
+        let mut paused = test_stream(1.0);
+        paused.paused = true;
+        AudioMixerControl::start_fade(&mut paused, Duration::ZERO);
+        assert!(!paused.paused);
+        assert!(!paused.faded_out());
+        assert_eq!(paused.next_gain(2), 1.0);
+        assert!(paused.faded_out());
+    }
+
+    #[tokio::test]
+    async fn test_whispers_are_sent_with_their_voice_target() {
+        let (sender, mut receiver) = mpsc::channel(10);
+        let config = crate::config::BotConfig::default();
+        let encoder_config = Arc::new(SharedEncoderConfig::new(EncoderConfig::from_settings(
+            &config.encoder,
+        )));
+        let mut mixer = AudioMixer::new(
+            sender,
+            &config.behavior,
+            &config.audio_effects,
+            encoder_config,
+        );
+
+        let frame_samples = mixer.active_config.frame_samples();
+        let mut normal = test_stream(1.0);
+        let mut whisper = test_stream(1.0);
+        whisper.id = 2;
+        whisper.primary = false;
+        whisper.target = 5;
+        for stream in [&mut normal, &mut whisper] {
+            *stream.buffer.lock().await = vec![1000; frame_samples * 2];
+        }
+        mixer.streams.lock().await.extend([normal, whisper]);
+
+        assert!(mixer.mix_frame().await);
+        let mut targets = Vec::new();
+        while let Ok(OutgoingMessage::AudioData(packet)) = receiver.try_recv() {
+            assert!(!packet.opus.is_empty());
+            targets.push(packet.target);
+        }
+        assert_eq!(targets, vec![packet::TARGET_NORMAL, 5]);
+    }
+}
@@ -0,0 +1,296 @@
+// Rolling buffer of recent channel audio, used to clip sounds from live voice
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::broadcast;
+
+use super::{
+    SAMPLE_RATE,
+    receive::{VoiceFrame, VoiceReceiver},
+};
+
+/// Frames arriving within this long after a segment's end continue it
+const SEGMENT_GAP_TOLERANCE: Duration = Duration::from_millis(500);
+
+/// A contiguous run of speech from one user
+struct Segment {
+    start: Instant,
+    /// Trimmed from the front as the window moves, so a user who never stops
+    /// transmitting doesn't grow it forever
+    samples: VecDeque<i16>,
+    closed: bool,
+}
+
+impl Segment {
+    fn end(&self) -> Instant {
+        self.start + samples_to_duration(self.samples.len())
+    }
+}
+
+fn samples_to_duration(samples: usize) -> Duration {
+    Duration::from_secs_f64(samples as f64 / SAMPLE_RATE as f64)
+}
+
+/// Per-speaker segments covering the last `capacity` of audio
+pub struct ClipBuffer {
+    capacity: Duration,
+    speakers: HashMap<u32, VecDeque<Segment>>,
+}
+
+impl ClipBuffer {
+    pub fn new(capacity: Duration) -> Self {
+        Self {
+            capacity,
+            speakers: HashMap::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> Duration {
+        self.capacity
+    }
+
+    /// Appends decoded audio from `session` that arrived at `now`
+    pub fn push(&mut self, session: u32, samples: &[i16], end_of_transmission: bool, now: Instant) {
+        let segments = self.speakers.entry(session).or_default();
+
+        let continues = segments
+            .back()
+            .is_some_and(|last| !last.closed && now <= last.end() + SEGMENT_GAP_TOLERANCE);
+
+        if continues {
+            let last = segments.back_mut().unwrap();
+            last.samples.extend(samples);
+            last.closed = end_of_transmission;
+        } else {
+            // The frame finished arriving now, so it started one frame earlier
+            segments.push_back(Segment {
+                start: now
+                    .checked_sub(samples_to_duration(samples.len()))
+                    .unwrap_or(now),
+                samples: samples.iter().copied().collect(),
+                closed: end_of_transmission,
+            });
+        }
+
+        self.prune(now);
+    }
+
+    /// Drops audio from before the buffer window
+    fn prune(&mut self, now: Instant) {
+        let Some(horizon) = now.checked_sub(self.capacity) else {
+            return;
+        };
+
+        self.speakers.retain(|_, segments| {
+            while segments.front().is_some_and(|s| s.end() < horizon) {
+                segments.pop_front();
+            }
+            // The oldest segment may still be open and reach past the window
+            if let Some(first) = segments.front_mut().filter(|first| first.start < horizon) {
+                let excess = ((horizon - first.start).as_secs_f64() * SAMPLE_RATE as f64) as usize;
+                let excess = excess.min(first.samples.len());
+                first.samples.drain(..excess);
+                first.start += samples_to_duration(excess);
+            }
+            !segments.is_empty()
+        });
+    }
+
+    /// Mixes the last `length` of audio (from one speaker, or everyone) into
+    /// 48kHz mono PCM, trimming silence at both ends.
+    pub fn snapshot(&self, session: Option<u32>, length: Duration, now: Instant) -> Vec<i16> {
+        let length = length.min(self.capacity);
+        let total = (length.as_secs_f64() * SAMPLE_RATE as f64) as usize;
+        let window_start = now.checked_sub(length).unwrap_or(now);
+        let mut mixed = vec![0i16; total];
+
+        let selected = self
+            .speakers
+            .iter()
+            .filter(|(id, _)| session.is_none_or(|s| s == **id));
+
+        for (_, segments) in selected {
+            for segment in segments {
+                // Offset of the segment start relative to the window, in samples
+                let offset = if segment.start >= window_start {
+                    ((segment.start - window_start).as_secs_f64() * SAMPLE_RATE as f64) as i64
+                } else {
+                    -(((window_start - segment.start).as_secs_f64() * SAMPLE_RATE as f64) as i64)
+                };
+
+                for (i, &sample) in segment.samples.iter().enumerate() {
+                    let index = offset + i as i64;
+                    if index >= 0 && (index as usize) < total {
+                        let slot = &mut mixed[index as usize];
+                        *slot = slot.saturating_add(sample);
+                    }
+                }
+            }
+        }
+
+        let first = mixed.iter().position(|&s| s != 0);
+        let last = mixed.iter().rposition(|&s| s != 0);
+        match (first, last) {
+            (Some(first), Some(last)) => mixed[first..=last].to_vec(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Keeps a `ClipBuffer` fed from the voice receiver
+pub struct ClipRecorder {
+    buffer: Arc<Mutex<ClipBuffer>>,
+    _task: tokio::task::JoinHandle<()>,
+}
+
+impl ClipRecorder {
+    pub fn spawn(receiver: &VoiceReceiver, capacity: Duration) -> Self {
+        let buffer = Arc::new(Mutex::new(ClipBuffer::new(capacity)));
+        let mut frames = receiver.subscribe();
+        let task_buffer = buffer.clone();
+
+        let task = tokio::spawn(async move {
+            loop {
+                let frame: VoiceFrame = match frames.recv().await {
+                    Ok(frame) => frame,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        debug!("Clip recorder lagged, skipped {} frames", skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+
+                task_buffer.lock().unwrap().push(
+                    frame.session,
+                    &frame.samples,
+                    frame.end_of_transmission,
+                    Instant::now(),
+                );
+            }
+        });
+
+        Self {
+            buffer,
+            _task: task,
+        }
+    }
+
+    /// How much audio is retained
+    pub fn capacity(&self) -> Duration {
+        self.buffer.lock().unwrap().capacity()
+    }
+
+    /// Returns the last `length` of audio as 48kHz mono PCM
+    pub fn clip(&self, session: Option<u32>, length: Duration) -> Vec<i16> {
+        self.buffer
+            .lock()
+            .unwrap()
+            .snapshot(session, length, Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: usize = 960; // 20ms
+
+    #[test]
+    fn test_snapshot_mixes_speakers() {
+        let base = Instant::now();
+        let mut buffer = ClipBuffer::new(Duration::from_secs(10));
+
+        // Two users talking over each other for 100ms
+        for i in 1..=5 {
+            let now = base + Duration::from_millis(20 * i);
+            buffer.push(1, &[100; FRAME], false, now);
+            buffer.push(2, &[50; FRAME], false, now);
+        }
+
+        let now = base + Duration::from_millis(100);
+        let all = buffer.snapshot(None, Duration::from_secs(1), now);
+        assert_eq!(all.len(), 5 * FRAME);
+        assert!(all.iter().all(|&s| s == 150));
+
+        let alone = buffer.snapshot(Some(2), Duration::from_secs(1), now);
+        assert_eq!(alone.len(), 5 * FRAME);
+        assert!(alone.iter().all(|&s| s == 50));
+
+        // Only the tail of the transmission when asked for less
+        let tail = buffer.snapshot(None, Duration::from_millis(40), now);
+        assert_eq!(tail.len(), 2 * FRAME);
+    }
+
+    #[test]
+    fn test_silence_between_transmissions_is_kept() {
+        let base = Instant::now();
+        let mut buffer = ClipBuffer::new(Duration::from_secs(10));
+
+        buffer.push(1, &[10; FRAME], true, base + Duration::from_millis(20));
+        buffer.push(1, &[10; FRAME], true, base + Duration::from_millis(1020));
+
+        let clip = buffer.snapshot(
+            None,
+            Duration::from_secs(5),
+            base + Duration::from_millis(1020),
+        );
+        // 20ms speech, ~980ms silence, 20ms speech
+        assert!(clip.len() >= SAMPLE_RATE);
+        assert_eq!(clip[0], 10);
+        assert_eq!(*clip.last().unwrap(), 10);
+        assert!(clip.contains(&0));
+    }
+
+    #[test]
+    fn test_continuous_transmission_is_trimmed_to_the_window() {
+        let base = Instant::now();
+        let mut buffer = ClipBuffer::new(Duration::from_secs(2));
+
+        // A minute of open-mic audio without a terminator or a gap
+        for i in 1..=3000 {
+            buffer.push(1, &[10; FRAME], false, base + Duration::from_millis(20 * i));
+        }
+
+        let segments = &buffer.speakers[&1];
+        assert_eq!(segments.len(), 1);
+        let retained = segments[0].samples.len();
+        assert!(
+            retained <= 2 * SAMPLE_RATE + FRAME,
+            "kept {} samples",
+            retained
+        );
+        assert!(
+            retained >= 2 * SAMPLE_RATE - FRAME,
+            "kept {} samples",
+            retained
+        );
+
+        // The trimmed segment still lines up with the clock
+        let now = base + Duration::from_millis(20 * 3000);
+        let clip = buffer.snapshot(None, Duration::from_secs(1), now);
+        assert_eq!(clip.len(), SAMPLE_RATE);
+        assert!(clip.iter().all(|&s| s == 10));
+    }
+
+    #[test]
+    fn test_old_audio_is_pruned() {
+        let base = Instant::now();
+        let mut buffer = ClipBuffer::new(Duration::from_secs(2));
+
+        buffer.push(1, &[10; FRAME], true, base + Duration::from_millis(20));
+        buffer.push(2, &[20; FRAME], true, base + Duration::from_secs(5));
+
+        assert!(!buffer.speakers.contains_key(&1));
+        let clip = buffer.snapshot(None, Duration::from_secs(30), base + Duration::from_secs(5));
+        assert!(clip.iter().all(|&s| s == 20));
+        assert!(
+            buffer
+                .snapshot(Some(1), Duration::from_secs(2), base)
+                .is_empty()
+        );
+    }
+}
@@ -311,6 +311,8 @@ impl PipelineBuilder {
                 PipelineStage::Ffmpeg { mut command } => {
                     // Log the exact command being executed
                     log::debug!("Stage {}: Executing ffmpeg command: {:?}", i, command);
+                    // Killed with the stream, or when the bot exits mid-playback
+                    command.kill_on_drop(true);
                     let mut child = command.spawn().map_err(|e| {
                         log::error!("Failed to spawn ffmpeg process for stage {}: {}", i, e);
                         Error::IOError(e)
@@ -348,6 +350,7 @@ impl PipelineBuilder {
                 }
                 PipelineStage::Sox { mut command } => {
                     log::debug!("Stage {}: Executing sox command: {:?}", i, command);
+                    command.kill_on_drop(true);
                     let mut child = command.spawn().map_err(|e| {
                         log::error!("Failed to spawn sox process for stage {}: {}", i, e);
                         Error::IOError(e)
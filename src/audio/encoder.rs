@@ -0,0 +1,189 @@
+// Opus encoder settings, fitted to the server's bandwidth limit
+
+use std::sync::Mutex;
+
+use opus::{Application, Bitrate, Channels, Encoder};
+
+use super::{CHANNELS, SAMPLE_RATE};
+use crate::config::{EncoderSettings, OpusApplication};
+
+/// Frame durations Mumble clients can play, in milliseconds
+const FRAME_SIZES_MS: [u64; 4] = [10, 20, 40, 60];
+const MIN_BITRATE: u32 = 8_000;
+const MAX_BITRATE: u32 = 510_000;
+/// Bytes of IP, UDP, encryption and voice headers per packet, counted the way
+/// Mumble clients do, including the extra TCP framing in case voice is tunneled
+const PACKET_OVERHEAD_BYTES: u32 = 20 + 8 + 4 + 1 + 2 + 12;
+/// Largest Opus frame that fits in a 1024-byte UDP voice packet next to the
+/// encryption header, the type byte, the sequence number and the length
+const MAX_FRAME_BYTES: u32 = 1024 - 4 - 1 - 9 - 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncoderConfig {
+    pub bitrate: u32,
+    pub frame_ms: u64,
+    pub application: OpusApplication,
+}
+
+impl EncoderConfig {
+    /// The configured settings, with the bitrate and frame size made valid
+    pub fn from_settings(settings: &EncoderSettings) -> Self {
+        let frame_ms = FRAME_SIZES_MS
+            .into_iter()
+            .min_by_key(|size| size.abs_diff(settings.frame_ms))
+            .unwrap_or(20);
+
+        Self {
+            bitrate: settings
+                .bitrate
+                .clamp(MIN_BITRATE, MAX_BITRATE)
+                .min(Self::max_bitrate(frame_ms)),
+            frame_ms,
+            application: settings.application,
+        }
+    }
+
+    /// Highest bitrate whose frames still fit in one voice packet
+    fn max_bitrate(frame_ms: u64) -> u32 {
+        MAX_FRAME_BYTES * 8 * 1000 / frame_ms as u32
+    }
+
+    /// Room to give the encoder for one frame. Frames may run over the
+    /// average size, but never past what fits in a voice packet.
+    pub fn max_frame_bytes(&self) -> usize {
+        let average = self.bitrate as u64 * self.frame_ms / 8000;
+        (average * 2).clamp(1, MAX_FRAME_BYTES as u64) as usize
+    }
+
+    /// Bits per second spent on packet headers at this frame size
+    fn overhead(frame_ms: u64) -> u32 {
+        // Each 10ms of audio also costs a length byte
+        let bytes = PACKET_OVERHEAD_BYTES + (frame_ms / 10) as u32;
+        bytes * 8 * 1000 / frame_ms as u32
+    }
+
+    /// Total bits per second sent, headers included
+    pub fn bandwidth(&self) -> u32 {
+        self.bitrate + Self::overhead(self.frame_ms)
+    }
+
+    /// Lowers the bitrate, then lengthens frames if even the lowest bitrate is
+    /// too much, until the stream fits within `max_bandwidth` bits per second
+    pub fn fit(self, max_bandwidth: u32) -> Self {
+        for frame_ms in FRAME_SIZES_MS.into_iter().filter(|f| *f >= self.frame_ms) {
+            let available = max_bandwidth.saturating_sub(Self::overhead(frame_ms));
+            if available >= MIN_BITRATE {
+                return Self {
+                    bitrate: self.bitrate.min(available).min(Self::max_bitrate(frame_ms)),
+                    frame_ms,
+                    ..self
+                };
+            }
+        }
+
+        // Nothing fits, so use as little as possible
+        Self {
+            bitrate: MIN_BITRATE,
+            frame_ms: FRAME_SIZES_MS[FRAME_SIZES_MS.len() - 1],
+            ..self
+        }
+    }
+
+    /// Interleaved stereo samples in one frame
+    pub fn frame_samples(&self) -> usize {
+        SAMPLE_RATE / 1000 * self.frame_ms as usize * CHANNELS
+    }
+
+    pub fn encoder(&self) -> Result<Encoder, opus::Error> {
+        let application = match self.application {
+            OpusApplication::Voip => Application::Voip,
+            OpusApplication::Audio => Application::Audio,
+            OpusApplication::LowDelay => Application::LowDelay,
+        };
+        let mut encoder = Encoder::new(SAMPLE_RATE as u32, Channels::Stereo, application)?;
+        encoder.set_bitrate(Bitrate::Bits(self.bitrate as i32))?;
+        Ok(encoder)
+    }
+}
+
+/// Encoder settings the session adjusts when it learns the server's limits,
+/// picked up by the mixer before its next frame
+pub struct SharedEncoderConfig(Mutex<EncoderConfig>);
+
+impl SharedEncoderConfig {
+    pub fn new(config: EncoderConfig) -> Self {
+        Self(Mutex::new(config))
+    }
+
+    pub fn get(&self) -> EncoderConfig {
+        *self.0.lock().unwrap()
+    }
+
+    pub fn set(&self, config: EncoderConfig) {
+        *self.0.lock().unwrap() = config;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(bitrate: u32, frame_ms: u64) -> EncoderConfig {
+        EncoderConfig::from_settings(&EncoderSettings {
+            bitrate,
+            frame_ms,
+            application: OpusApplication::Audio,
+        })
+    }
+
+    #[test]
+    fn test_settings_are_made_valid() {
+        assert_eq!(config(1_000, 25).bitrate, MIN_BITRATE);
+        assert_eq!(config(1_000, 25).frame_ms, 20);
+        assert_eq!(config(900_000, 10).bitrate, MAX_BITRATE);
+        assert_eq!(config(900_000, 1000).frame_ms, 60);
+        assert_eq!(config(64_000, 20).frame_samples(), 1920);
+    }
+
+    #[test]
+    fn test_frames_fit_in_a_voice_packet() {
+        // The largest settings allowed: the bitrate is lowered until a frame fits
+        for frame_ms in FRAME_SIZES_MS {
+            let config = config(MAX_BITRATE, frame_ms);
+            assert!(config.bitrate as u64 * frame_ms / 8000 <= MAX_FRAME_BYTES as u64);
+            assert!(config.max_frame_bytes() <= MAX_FRAME_BYTES as usize);
+
+            let mut encoder = config.encoder().unwrap();
+            let pcm: Vec<i16> = (0..config.frame_samples())
+                .map(|i| ((i as f32 * 0.37).sin() * 20_000.0) as i16)
+                .collect();
+            let mut out = vec![0; config.max_frame_bytes()];
+            assert!(encoder.encode(&pcm, &mut out).is_ok());
+        }
+        assert_eq!(config(192_000, 60).bitrate, 134_400);
+        assert_eq!(config(192_000, 60).fit(1_000_000).bitrate, 134_400);
+    }
+
+    #[test]
+    fn test_fit_lowers_bitrate_then_lengthens_frames() {
+        let wanted = config(96_000, 20);
+        assert_eq!(wanted.bandwidth(), 96_000 + 49 * 400);
+
+        // Plenty of room: unchanged
+        assert_eq!(wanted.fit(558_000), wanted);
+
+        // A 72 kbit/s server leaves 72000 - 19600 for audio
+        let fitted = wanted.fit(72_000);
+        assert_eq!(fitted.bitrate, 52_400);
+        assert_eq!(fitted.frame_ms, 20);
+        assert!(fitted.bandwidth() <= 72_000);
+
+        // Too little for even the minimum bitrate at 20ms frames
+        let fitted = wanted.fit(26_000);
+        assert_eq!(fitted.frame_ms, 40);
+        assert!(fitted.bandwidth() <= 26_000);
+
+        let fitted = wanted.fit(1_000);
+        assert_eq!((fitted.bitrate, fitted.frame_ms), (MIN_BITRATE, 60));
+    }
+}
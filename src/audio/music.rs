@@ -0,0 +1,287 @@
+// Long-form music tracks resolved with yt-dlp and streamed straight into the mixer
+
+use std::{collections::VecDeque, io, path::Path, process::Stdio};
+
+use tokio::time::Duration;
+
+use super::{StreamId, effects::AudioEffect};
+use crate::{commands::nowplaying::format_time, session::escape_html};
+
+/// One track from a `!music play` request
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track {
+    /// Page URL of the track, resolved to a media URL just before it plays
+    pub url: String,
+    pub title: String,
+    pub duration: Option<Duration>,
+}
+
+impl Track {
+    /// HTML channel message posted when the track starts
+    pub fn announcement(&self) -> String {
+        match self.duration {
+            Some(duration) => format!(
+                "Now playing: <b>{}</b> [{}]",
+                escape_html(&self.title),
+                format_time(duration)
+            ),
+            None => format!("Now playing: <b>{}</b>", escape_html(&self.title)),
+        }
+    }
+}
+
+/// A track waiting for its turn, with the effects it was requested with
+#[derive(Debug, Clone)]
+pub struct QueuedTrack {
+    pub track: Track,
+    pub effects: Vec<AudioEffect>,
+}
+
+/// Pending tracks plus the one currently streaming
+pub struct MusicQueue {
+    pending: VecDeque<QueuedTrack>,
+    current: Option<(StreamId, Track)>,
+    /// Channel that gets "now playing" announcements
+    channel: Option<u32>,
+    capacity: usize,
+}
+
+impl MusicQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            pending: VecDeque::new(),
+            current: None,
+            channel: None,
+            capacity,
+        }
+    }
+
+    /// Appends as many tracks as fit, returning how many were added
+    pub fn extend(&mut self, tracks: Vec<Track>, effects: &[AudioEffect]) -> usize {
+        let room = self.capacity.saturating_sub(self.pending.len());
+        let added = tracks.len().min(room);
+        self.pending
+            .extend(tracks.into_iter().take(added).map(|track| QueuedTrack {
+                track,
+                effects: effects.to_vec(),
+            }));
+        added
+    }
+
+    pub fn pop(&mut self) -> Option<QueuedTrack> {
+        self.pending.pop_front()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn clear(&mut self) -> usize {
+        let count = self.pending.len();
+        self.pending.clear();
+        self.current = None;
+        count
+    }
+
+    pub fn current(&self) -> Option<&(StreamId, Track)> {
+        self.current.as_ref()
+    }
+
+    pub fn set_current(&mut self, current: Option<(StreamId, Track)>) {
+        self.current = current;
+    }
+
+    pub fn channel(&self) -> Option<u32> {
+        self.channel
+    }
+
+    pub fn set_channel(&mut self, channel: u32) {
+        self.channel = Some(channel);
+    }
+
+    /// Tracks waiting to play, next up first
+    pub fn pending(&self) -> Vec<Track> {
+        self.pending.iter().map(|q| q.track.clone()).collect()
+    }
+}
+
+/// Parses `yt-dlp --flat-playlist --dump-json` output, one JSON object per
+/// line. A single video yields one entry, a playlist one per track.
+pub fn parse_track_listing(output: &str, requested_url: &str) -> Vec<Track> {
+    output
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line.trim()).ok())
+        .map(|entry| {
+            let field = |name: &str| entry.get(name).and_then(|v| v.as_str());
+            let url = field("webpage_url")
+                .or_else(|| field("url"))
+                .or_else(|| field("original_url"))
+                .unwrap_or(requested_url)
+                .to_string();
+            let title = field("title").unwrap_or(&url).to_string();
+            let duration = entry
+                .get("duration")
+                .and_then(|v| v.as_f64())
+                .filter(|secs| secs.is_finite() && *secs >= 0.0)
+                .map(Duration::from_secs_f64);
+
+            Track {
+                url,
+                title,
+                duration,
+            }
+        })
+        .collect()
+}
+
+fn ytdlp_command(cookies: Option<&Path>) -> tokio::process::Command {
+    let mut cmd = tokio::process::Command::new("yt-dlp");
+    cmd.arg("--no-warnings")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(cookies) = cookies.filter(|path| path.exists()) {
+        cmd.arg("--cookies").arg(cookies);
+    }
+    cmd
+}
+
+async fn run_ytdlp(mut cmd: tokio::process::Command) -> io::Result<String> {
+    let output = cmd.output().await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!(
+            "yt-dlp failed: {}",
+            stderr.lines().last().unwrap_or("unknown error")
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Whether `url` is a web address, the only thing users may hand to yt-dlp
+fn is_web_url(url: &str) -> bool {
+    let lower = url.to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+/// Looks up the track, or the first `limit` tracks of a playlist, behind `url`
+pub async fn resolve_tracks(
+    url: &str,
+    cookies: Option<&Path>,
+    limit: usize,
+) -> io::Result<Vec<Track>> {
+    if !is_web_url(url) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "only http:// and https:// URLs can be played",
+        ));
+    }
+
+    // `--` keeps the URL from being read as an option
+    let mut cmd = ytdlp_command(cookies);
+    cmd.arg("--flat-playlist")
+        .arg("--dump-json")
+        .arg("--playlist-end")
+        .arg(limit.to_string())
+        .arg("--")
+        .arg(url);
+
+    let tracks = parse_track_listing(&run_ytdlp(cmd).await?, url);
+    if tracks.is_empty() {
+        return Err(io::Error::other("no playable tracks found"));
+    }
+    Ok(tracks)
+}
+
+/// Resolves a track page to a direct audio URL that ffmpeg can stream from
+pub async fn resolve_stream_url(track: &Track, cookies: Option<&Path>) -> io::Result<String> {
+    let mut cmd = ytdlp_command(cookies);
+    cmd.arg("-f")
+        .arg("bestaudio/best")
+        .arg("--no-playlist")
+        .arg("-g")
+        .arg("--")
+        .arg(&track.url);
+
+    run_ytdlp(cmd)
+        .await?
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_string)
+        .ok_or_else(|| io::Error::other("yt-dlp returned no stream URL"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(title: &str) -> Track {
+        Track {
+            url: format!("https://example.com/{}", title),
+            title: title.to_string(),
+            duration: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_track_listing() {
+        let output = concat!(
+            r#"{"url": "https://www.youtube.com/watch?v=a", "title": "First", "duration": 125.0}"#,
+            "\n",
+            r#"{"webpage_url": "https://www.youtube.com/watch?v=b", "url": "b", "title": "Second", "duration": null}"#,
+            "\n",
+            "WARNING: not json\n",
+            r#"{"id": "c"}"#,
+            "\n"
+        );
+        let tracks = parse_track_listing(output, "https://example.com/list");
+        assert_eq!(tracks.len(), 3);
+        assert_eq!(tracks[0].title, "First");
+        assert_eq!(tracks[0].duration, Some(Duration::from_secs(125)));
+        assert_eq!(tracks[1].url, "https://www.youtube.com/watch?v=b");
+        assert_eq!(tracks[1].duration, None);
+        // Entries without a URL fall back to the request, and titles to the URL
+        assert_eq!(tracks[2].url, "https://example.com/list");
+        assert_eq!(tracks[2].title, "https://example.com/list");
+
+        assert_eq!(tracks[0].announcement(), "Now playing: <b>First</b> [2:05]");
+    }
+
+    #[test]
+    fn test_only_web_urls_reach_ytdlp() {
+        assert!(is_web_url("https://www.youtube.com/watch?v=a"));
+        assert!(is_web_url("HTTP://example.com/a.mp3"));
+        assert!(!is_web_url("--config-location=/etc/passwd"));
+        assert!(!is_web_url("file:///etc/passwd"));
+        assert!(!is_web_url("ytsearch:never gonna"));
+    }
+
+    #[tokio::test]
+    async fn test_options_are_refused_as_urls() {
+        let error = resolve_tracks("--batch-file=/etc/passwd", None, 10)
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_queue_respects_capacity() {
+        let mut queue = MusicQueue::new(3);
+        assert_eq!(queue.extend(vec![track("a"), track("b")], &[]), 2);
+        assert_eq!(queue.extend(vec![track("c"), track("d")], &[]), 1);
+        assert_eq!(
+            queue
+                .pending()
+                .iter()
+                .map(|t| t.title.as_str())
+                .collect::<Vec<_>>(),
+            vec!["a", "b", "c"]
+        );
+
+        assert_eq!(queue.pop().unwrap().track.title, "a");
+        queue.set_current(Some((7, track("a"))));
+        assert_eq!(queue.clear(), 2);
+        assert!(queue.current().is_none());
+    }
+}
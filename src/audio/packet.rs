@@ -0,0 +1,379 @@
+// Voice packet framing for UDP and the TCP UDPTunnel, in both the legacy
+// binary format and the protobuf format used by Mumble 1.5+
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use protobuf::Message;
+
+use crate::{
+    protos::{
+        generated::{Mumble, MumbleUDP},
+        version,
+    },
+    util,
+};
+
+/// Legacy voice packet types, stored in the upper 3 bits of the header byte
+pub const TYPE_PING: u8 = 1;
+pub const TYPE_OPUS: u8 = 4;
+
+/// Protobuf packet types, sent as a single byte ahead of the message
+const PROTO_TYPE_AUDIO: u8 = 0;
+const PROTO_TYPE_PING: u8 = 1;
+
+/// Voice target for normal talking to the current channel
+pub const TARGET_NORMAL: u8 = 0;
+
+/// Marks the last Opus frame of a transmission in the length field
+const OPUS_TERMINATOR: u64 = 0x2000;
+const OPUS_LENGTH_MASK: u64 = 0x1FFF;
+
+/// Wire format for voice and UDP pings, negotiated from the server version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PacketFormat {
+    #[default]
+    Legacy,
+    Protobuf,
+}
+
+impl PacketFormat {
+    pub fn for_server(server_version: &Mumble::Version) -> Self {
+        let version = server_version
+            .version_v2
+            .or_else(|| server_version.version_v1.map(version::v1_to_v2))
+            .unwrap_or(0);
+
+        if version >= version::PROTOBUF_VOICE {
+            PacketFormat::Protobuf
+        } else {
+            PacketFormat::Legacy
+        }
+    }
+}
+
+/// The packet format of the current connection, shared by every task that
+/// frames or parses voice packets
+#[derive(Default)]
+pub struct SharedPacketFormat(AtomicBool);
+
+impl SharedPacketFormat {
+    pub fn get(&self) -> PacketFormat {
+        if self.0.load(Ordering::Relaxed) {
+            PacketFormat::Protobuf
+        } else {
+            PacketFormat::Legacy
+        }
+    }
+
+    pub fn set(&self, format: PacketFormat) {
+        self.0
+            .store(format == PacketFormat::Protobuf, Ordering::Relaxed);
+    }
+}
+
+/// Builds a UDP ping carrying `timestamp`, which the server echoes back
+pub fn encode_ping(timestamp: u64, format: PacketFormat) -> Vec<u8> {
+    match format {
+        PacketFormat::Legacy => {
+            let mut out = vec![TYPE_PING << 5];
+            out.extend(util::encode_varint_long(timestamp));
+            out
+        }
+        PacketFormat::Protobuf => {
+            let ping = MumbleUDP::Ping {
+                timestamp,
+                ..Default::default()
+            };
+            let mut out = vec![PROTO_TYPE_PING];
+            // Writing to a Vec cannot fail
+            out.extend(ping.write_to_bytes().unwrap_or_default());
+            out
+        }
+    }
+}
+
+/// Extracts the timestamp from an echoed UDP ping, or `None` if this is not a ping
+pub fn decode_ping(data: &[u8], format: PacketFormat) -> Option<u64> {
+    let (&header, body) = data.split_first()?;
+    match format {
+        PacketFormat::Legacy if header >> 5 == TYPE_PING => {
+            util::decode_varint(body).map(|(timestamp, _)| timestamp)
+        }
+        PacketFormat::Protobuf if header == PROTO_TYPE_PING => {
+            MumbleUDP::Ping::parse_from_bytes(body)
+                .ok()
+                .map(|ping| ping.timestamp)
+        }
+        _ => None,
+    }
+}
+
+/// An Opus voice packet.
+///
+/// Sequence numbers count 10ms frames, so a 20ms packet advances it by 2.
+/// Client-to-server packets carry no session; the server inserts the
+/// sender's session when relaying them to other clients.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VoicePacket {
+    pub target: u8,
+    pub session: Option<u32>,
+    pub sequence: u64,
+    pub opus: Vec<u8>,
+    pub terminator: bool,
+}
+
+impl VoicePacket {
+    pub fn encode(&self, format: PacketFormat) -> Vec<u8> {
+        match format {
+            PacketFormat::Legacy => self.encode_legacy(),
+            PacketFormat::Protobuf => self.encode_protobuf(),
+        }
+    }
+
+    /// Parses a server-to-client packet, which includes the speaker's session
+    pub fn decode_incoming(data: &[u8], format: PacketFormat) -> Option<Self> {
+        match format {
+            PacketFormat::Legacy => Self::decode_legacy(data, true),
+            PacketFormat::Protobuf => Self::decode_protobuf(data),
+        }
+    }
+
+    /// Legacy layout: header, [session,] sequence, Opus length and payload
+    fn encode_legacy(&self) -> Vec<u8> {
+        let mut length = self.opus.len() as u64 & OPUS_LENGTH_MASK;
+        if self.terminator {
+            length |= OPUS_TERMINATOR;
+        }
+
+        let mut out = Vec::with_capacity(self.opus.len() + 16);
+        out.push((TYPE_OPUS << 5) | (self.target & 0x1F));
+        if let Some(session) = self.session {
+            out.extend(util::encode_varint_long(session as u64));
+        }
+        out.extend(util::encode_varint_long(self.sequence));
+        out.extend(util::encode_varint_long(length));
+        out.extend_from_slice(&self.opus);
+        out
+    }
+
+    /// Parses either legacy layout; client-to-server packets have no session
+    /// field. Positional data after the payload is ignored.
+    fn decode_legacy(data: &[u8], has_session: bool) -> Option<Self> {
+        let header = *data.first()?;
+        if header >> 5 != TYPE_OPUS {
+            return None;
+        }
+
+        let mut pos = 1;
+        let session = if has_session {
+            let (session, len) = util::decode_varint(&data[pos..])?;
+            pos += len;
+            Some(session as u32)
+        } else {
+            None
+        };
+        let (sequence, len) = util::decode_varint(&data[pos..])?;
+        pos += len;
+        let (length, len) = util::decode_varint(&data[pos..])?;
+        pos += len;
+
+        let opus_len = (length & OPUS_LENGTH_MASK) as usize;
+        let opus = data.get(pos..pos + opus_len)?.to_vec();
+
+        Some(Self {
+            target: header & 0x1F,
+            session,
+            sequence,
+            opus,
+            terminator: length & OPUS_TERMINATOR != 0,
+        })
+    }
+
+    /// Protobuf layout: type byte followed by a `MumbleUDP.Audio` message
+    fn encode_protobuf(&self) -> Vec<u8> {
+        let mut audio = MumbleUDP::Audio::new();
+        audio.set_target(self.target as u32);
+        audio.sender_session = self.session.unwrap_or_default();
+        audio.frame_number = self.sequence;
+        audio.opus_data = self.opus.clone();
+        audio.is_terminator = self.terminator;
+
+        let mut out = vec![PROTO_TYPE_AUDIO];
+        // Writing to a Vec cannot fail
+        out.extend(audio.write_to_bytes().unwrap_or_default());
+        out
+    }
+
+    fn decode_protobuf(data: &[u8]) -> Option<Self> {
+        let (&header, body) = data.split_first()?;
+        if header != PROTO_TYPE_AUDIO {
+            return None;
+        }
+
+        let audio = MumbleUDP::Audio::parse_from_bytes(body).ok()?;
+        // Servers fill in the context; targets only appear on packets we sent
+        let target = if audio.has_context() {
+            audio.context()
+        } else {
+            audio.target()
+        };
+
+        Some(Self {
+            target: target as u8,
+            session: Some(audio.sender_session),
+            sequence: audio.frame_number,
+            opus: audio.opus_data,
+            terminator: audio.is_terminator,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outgoing_layout() {
+        let packet = VoicePacket {
+            target: TARGET_NORMAL,
+            session: None,
+            sequence: 2,
+            opus: vec![0xAA; 3],
+            terminator: false,
+        };
+        assert_eq!(
+            packet.encode(PacketFormat::Legacy),
+            vec![0x80, 0x02, 0x03, 0xAA, 0xAA, 0xAA]
+        );
+        assert_eq!(
+            VoicePacket::decode_legacy(&packet.encode(PacketFormat::Legacy), false),
+            Some(packet)
+        );
+    }
+
+    #[test]
+    fn test_terminator_and_long_sequence() {
+        let packet = VoicePacket {
+            target: 1,
+            session: None,
+            sequence: 300,
+            opus: vec![1, 2],
+            terminator: true,
+        };
+        // 300 takes the two byte form, the terminator bit pushes the length to two bytes
+        assert_eq!(
+            packet.encode(PacketFormat::Legacy),
+            vec![0x81, 0x81, 0x2C, 0xA0, 0x02, 1, 2]
+        );
+        let decoded =
+            VoicePacket::decode_legacy(&packet.encode(PacketFormat::Legacy), false).unwrap();
+        assert!(decoded.terminator);
+        assert_eq!(decoded.sequence, 300);
+    }
+
+    #[test]
+    fn test_incoming_layout() {
+        let packet = VoicePacket {
+            target: TARGET_NORMAL,
+            session: Some(7),
+            sequence: 42,
+            opus: vec![1, 2, 3],
+            terminator: true,
+        };
+        let mut data = packet.encode(PacketFormat::Legacy);
+        assert_eq!(&data[..3], &[0x80, 0x07, 0x2A]);
+
+        // Trailing positional data is ignored
+        data.extend_from_slice(&[0u8; 12]);
+        assert_eq!(
+            VoicePacket::decode_incoming(&data, PacketFormat::Legacy),
+            Some(packet)
+        );
+    }
+
+    #[test]
+    fn test_rejects_malformed() {
+        let data = VoicePacket {
+            target: TARGET_NORMAL,
+            session: Some(1),
+            sequence: 0,
+            opus: vec![9; 10],
+            terminator: false,
+        }
+        .encode(PacketFormat::Legacy);
+        let legacy = |data: &[u8]| VoicePacket::decode_incoming(data, PacketFormat::Legacy);
+        assert!(legacy(&data[..data.len() - 1]).is_none());
+        assert!(legacy(&[TYPE_PING << 5, 0x01]).is_none());
+        assert!(legacy(&[]).is_none());
+    }
+
+    #[test]
+    fn test_protobuf_roundtrip() {
+        let packet = VoicePacket {
+            target: 2,
+            session: Some(12),
+            sequence: 1_000_000,
+            opus: vec![5; 40],
+            terminator: true,
+        };
+        let data = packet.encode(PacketFormat::Protobuf);
+        assert_eq!(data[0], PROTO_TYPE_AUDIO);
+        assert_eq!(
+            VoicePacket::decode_incoming(&data, PacketFormat::Protobuf),
+            Some(packet)
+        );
+
+        // A protobuf ping is not mistaken for audio
+        let ping = encode_ping(99, PacketFormat::Protobuf);
+        assert!(VoicePacket::decode_incoming(&ping, PacketFormat::Protobuf).is_none());
+    }
+
+    #[test]
+    fn test_ping_formats() {
+        for format in [PacketFormat::Legacy, PacketFormat::Protobuf] {
+            let ping = encode_ping(123_456_789, format);
+            assert_eq!(decode_ping(&ping, format), Some(123_456_789));
+        }
+        assert_eq!(encode_ping(5, PacketFormat::Legacy), vec![0x20, 0x05]);
+
+        let audio = VoicePacket {
+            target: TARGET_NORMAL,
+            session: None,
+            sequence: 0,
+            opus: vec![1],
+            terminator: false,
+        };
+        assert_eq!(
+            decode_ping(&audio.encode(PacketFormat::Legacy), PacketFormat::Legacy),
+            None
+        );
+    }
+
+    #[test]
+    fn test_format_negotiation() {
+        let server = |v1: Option<u32>, v2: Option<u64>| Mumble::Version {
+            version_v1: v1,
+            version_v2: v2,
+            ..Default::default()
+        };
+        assert_eq!(
+            PacketFormat::for_server(&server(Some(version::v1(1, 4, 287)), None)),
+            PacketFormat::Legacy
+        );
+        assert_eq!(
+            PacketFormat::for_server(&server(
+                Some(version::v1(1, 5, 0)),
+                Some(version::v2(1, 5, 634))
+            )),
+            PacketFormat::Protobuf
+        );
+        assert_eq!(
+            PacketFormat::for_server(&server(Some(version::v1(1, 5, 0)), None)),
+            PacketFormat::Protobuf
+        );
+        assert_eq!(
+            PacketFormat::for_server(&server(None, None)),
+            PacketFormat::Legacy
+        );
+    }
+}
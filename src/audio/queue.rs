@@ -0,0 +1,125 @@
+// Pending sounds for queue playback mode
+
+use std::collections::VecDeque;
+
+use super::{StreamId, effects::AudioEffect};
+
+/// A sound waiting for its turn in the queue
+#[derive(Debug, Clone)]
+pub struct QueuedSound {
+    pub id: StreamId,
+    pub file: String,
+    pub effects: Vec<AudioEffect>,
+    /// Name shown in `!queue` and `!nowplaying`, usually the sound code
+    pub label: String,
+}
+
+/// FIFO of sounds waiting to become the primary stream
+pub struct PlayQueue {
+    pending: VecDeque<QueuedSound>,
+    capacity: usize,
+}
+
+impl PlayQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            pending: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Appends a sound, returning its 1-based position, or `None` if the queue is full
+    pub fn push(&mut self, sound: QueuedSound) -> Option<usize> {
+        if self.pending.len() >= self.capacity {
+            return None;
+        }
+        self.pending.push_back(sound);
+        Some(self.pending.len())
+    }
+
+    pub fn pop(&mut self) -> Option<QueuedSound> {
+        self.pending.pop_front()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn clear(&mut self) -> usize {
+        let count = self.pending.len();
+        self.pending.clear();
+        count
+    }
+
+    /// Removes and returns every pending sound matching `predicate`
+    pub fn remove_where(&mut self, predicate: impl Fn(&QueuedSound) -> bool) -> Vec<QueuedSound> {
+        let mut removed = Vec::new();
+        self.pending.retain(|sound| {
+            if predicate(sound) {
+                removed.push(sound.clone());
+                false
+            } else {
+                true
+            }
+        });
+        removed
+    }
+
+    /// IDs and labels of the pending sounds, next up first
+    pub fn entries(&self) -> Vec<(StreamId, String)> {
+        self.pending
+            .iter()
+            .map(|sound| (sound.id, sound.label.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sound(id: StreamId, label: &str) -> QueuedSound {
+        QueuedSound {
+            id,
+            file: format!("/sounds/{}.mp3", label),
+            effects: Vec::new(),
+            label: label.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_fifo_order_and_capacity() {
+        let mut queue = PlayQueue::new(2);
+        assert_eq!(queue.push(sound(1, "a")), Some(1));
+        assert_eq!(queue.push(sound(2, "b")), Some(2));
+        assert_eq!(queue.push(sound(3, "c")), None);
+        assert_eq!(
+            queue.entries(),
+            vec![(1, "a".to_string()), (2, "b".to_string())]
+        );
+
+        assert_eq!(queue.pop().unwrap().label, "a");
+        assert_eq!(queue.push(sound(3, "c")), Some(2));
+        assert_eq!(queue.entries()[1], (3, "c".to_string()));
+
+        assert_eq!(queue.clear(), 2);
+        assert!(queue.is_empty());
+        assert!(queue.pop().is_none());
+    }
+
+    #[test]
+    fn test_remove_keeps_order() {
+        let mut queue = PlayQueue::new(10);
+        for (id, label) in [(1, "a"), (2, "b"), (3, "a"), (4, "c")] {
+            queue.push(sound(id, label));
+        }
+
+        let removed = queue.remove_where(|s| s.label == "a");
+        assert_eq!(removed.iter().map(|s| s.id).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(
+            queue.entries(),
+            vec![(2, "b".to_string()), (4, "c".to_string())]
+        );
+        assert!(queue.remove_where(|s| s.id == 9).is_empty());
+    }
+}
@@ -0,0 +1,277 @@
+// Incoming voice: parsing, per-speaker Opus decoding and PCM distribution
+
+use std::{collections::HashMap, sync::Arc};
+
+use opus::Decoder;
+use tokio::sync::{broadcast, mpsc};
+
+use super::{SAMPLE_RATE, packet::VoicePacket};
+
+/// Samples in one 10ms mono frame, the unit Mumble sequence numbers count in
+const SAMPLES_PER_SEQUENCE: usize = SAMPLE_RATE / 100;
+/// Largest Opus packet duration (120ms) at 48kHz mono
+const MAX_DECODED_SAMPLES: usize = SAMPLE_RATE * 120 / 1000;
+/// Gaps up to this many 10ms frames are filled with packet loss concealment
+const MAX_CONCEALED_FRAMES: u64 = 10;
+
+const INPUT_QUEUE_SIZE: usize = 256;
+const FRAME_BROADCAST_SIZE: usize = 512;
+
+/// Decoded 48kHz mono PCM from one voice packet of one user
+#[derive(Debug, Clone)]
+pub struct VoiceFrame {
+    pub session: u32,
+    pub samples: Arc<[i16]>,
+    /// Set on the last frame of a transmission
+    pub end_of_transmission: bool,
+}
+
+enum VoiceInput {
+    Packet(VoicePacket),
+    SpeakerLeft(u32),
+    Reset,
+}
+
+/// Handle to the voice decoding task.
+///
+/// Parsed packets from the TCP tunnel and the UDP channel are pushed in; decoded
+/// PCM is fanned out to any number of subscribers.
+#[derive(Clone)]
+pub struct VoiceReceiver {
+    input: mpsc::Sender<VoiceInput>,
+    frames: broadcast::Sender<VoiceFrame>,
+}
+
+impl VoiceReceiver {
+    pub fn spawn() -> Self {
+        let (input, mut input_receiver) = mpsc::channel(INPUT_QUEUE_SIZE);
+        let (frames, _) = broadcast::channel(FRAME_BROADCAST_SIZE);
+
+        let mut decoder = VoiceDecoder::new();
+        let frames_sender = frames.clone();
+
+        tokio::spawn(async move {
+            while let Some(input) = input_receiver.recv().await {
+                match input {
+                    VoiceInput::Packet(packet) => {
+                        for frame in decoder.handle_packet(packet) {
+                            // No subscribers is fine, the frame is simply dropped
+                            let _ = frames_sender.send(frame);
+                        }
+                    }
+                    VoiceInput::SpeakerLeft(session) => decoder.remove_speaker(session),
+                    VoiceInput::Reset => decoder.clear(),
+                }
+            }
+        });
+
+        Self { input, frames }
+    }
+
+    /// Queues a voice packet for decoding, dropping it if the decoder is behind
+    pub fn push_packet(&self, packet: VoicePacket) {
+        if self.input.try_send(VoiceInput::Packet(packet)).is_err() {
+            trace!("Voice decoder queue full, dropping packet");
+        }
+    }
+
+    /// Releases the decoder state for a user that left the server
+    pub fn speaker_left(&self, session: u32) {
+        let _ = self.input.try_send(VoiceInput::SpeakerLeft(session));
+    }
+
+    /// Drops all decoder state, used when session ids are reassigned after a reconnect
+    pub fn reset(&self) {
+        let _ = self.input.try_send(VoiceInput::Reset);
+    }
+
+    /// Receives decoded audio from every speaker
+    pub fn subscribe(&self) -> broadcast::Receiver<VoiceFrame> {
+        self.frames.subscribe()
+    }
+}
+
+struct Speaker {
+    decoder: Decoder,
+    next_sequence: Option<u64>,
+}
+
+/// Per-speaker decoder state, driven by the receiver task
+struct VoiceDecoder {
+    speakers: HashMap<u32, Speaker>,
+    pcm: Vec<i16>,
+}
+
+impl VoiceDecoder {
+    fn new() -> Self {
+        Self {
+            speakers: HashMap::new(),
+            pcm: vec![0; MAX_DECODED_SAMPLES],
+        }
+    }
+
+    fn remove_speaker(&mut self, session: u32) {
+        self.speakers.remove(&session);
+    }
+
+    fn clear(&mut self) {
+        self.speakers.clear();
+    }
+
+    fn handle_packet(&mut self, packet: VoicePacket) -> Vec<VoiceFrame> {
+        let session = packet.session.unwrap_or_default();
+
+        let speaker = match self.speakers.entry(session) {
+            std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                match Decoder::new(SAMPLE_RATE as u32, opus::Channels::Mono) {
+                    Ok(decoder) => {
+                        debug!("Created voice decoder for session {}", session);
+                        entry.insert(Speaker {
+                            decoder,
+                            next_sequence: None,
+                        })
+                    }
+                    Err(e) => {
+                        warn!("Failed to create Opus decoder: {}", e);
+                        return Vec::new();
+                    }
+                }
+            }
+        };
+
+        let mut frames = Vec::new();
+
+        if let Some(expected) = speaker.next_sequence {
+            if packet.sequence < expected {
+                trace!(
+                    "Dropping late voice packet {} from session {}",
+                    packet.sequence, session
+                );
+                return frames;
+            }
+
+            // Conceal short gaps so downstream consumers keep a continuous timeline
+            let missing = packet.sequence - expected;
+            if missing > 0 && missing <= MAX_CONCEALED_FRAMES {
+                let samples = missing as usize * SAMPLES_PER_SEQUENCE;
+                if let Ok(len) = speaker.decoder.decode(&[], &mut self.pcm[..samples], false) {
+                    frames.push(VoiceFrame {
+                        session,
+                        samples: self.pcm[..len].into(),
+                        end_of_transmission: false,
+                    });
+                }
+            }
+        }
+
+        let decoded = if packet.opus.is_empty() {
+            0
+        } else {
+            match speaker.decoder.decode(&packet.opus, &mut self.pcm, false) {
+                Ok(len) => len,
+                Err(e) => {
+                    debug!("Failed to decode voice from session {}: {}", session, e);
+                    return frames;
+                }
+            }
+        };
+
+        if packet.terminator {
+            // The next transmission starts a fresh sequence
+            speaker.next_sequence = None;
+            let _ = speaker.decoder.reset_state();
+        } else {
+            let consumed = (decoded / SAMPLES_PER_SEQUENCE).max(1) as u64;
+            speaker.next_sequence = Some(packet.sequence + consumed);
+        }
+
+        frames.push(VoiceFrame {
+            session,
+            samples: self.pcm[..decoded].into(),
+            end_of_transmission: packet.terminator,
+        });
+
+        frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A server-to-client packet the way Murmur relays it
+    fn packet(session: u32, sequence: u64, opus: &[u8], terminator: bool) -> VoicePacket {
+        VoicePacket {
+            target: 0,
+            session: Some(session),
+            sequence,
+            opus: opus.to_vec(),
+            terminator,
+        }
+    }
+
+    fn encoded_frame() -> Vec<u8> {
+        let mut encoder = opus::Encoder::new(
+            SAMPLE_RATE as u32,
+            opus::Channels::Mono,
+            opus::Application::Voip,
+        )
+        .unwrap();
+        let pcm: Vec<i16> = (0..960)
+            .map(|i| ((i as f32 * 0.05).sin() * 8000.0) as i16)
+            .collect();
+        let mut out = vec![0u8; 1000];
+        let len = encoder.encode(&pcm, &mut out).unwrap();
+        out.truncate(len);
+        out
+    }
+
+    #[test]
+    fn test_decode_sequence_handling() {
+        let opus = encoded_frame();
+        let mut decoder = VoiceDecoder::new();
+
+        let frames = decoder.handle_packet(packet(3, 0, &opus, false));
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].session, 3);
+        assert_eq!(frames[0].samples.len(), 960);
+
+        // A repeated sequence number is dropped
+        assert!(decoder.handle_packet(packet(3, 0, &opus, false)).is_empty());
+
+        // One 20ms frame missing: concealment is emitted before the real frame
+        let frames = decoder.handle_packet(packet(3, 4, &opus, false));
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].samples.len(), 960);
+        assert_eq!(frames[1].samples.len(), 960);
+
+        let frames = decoder.handle_packet(packet(3, 6, &opus, true));
+        assert!(frames.last().unwrap().end_of_transmission);
+
+        // After a terminator the next transmission may restart its sequence
+        assert_eq!(decoder.handle_packet(packet(3, 0, &opus, false)).len(), 1);
+
+        decoder.remove_speaker(3);
+        assert!(decoder.speakers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_receiver_broadcasts_decoded_frames() {
+        let receiver = VoiceReceiver::spawn();
+        let mut frames = receiver.subscribe();
+        let opus = encoded_frame();
+
+        receiver.push_packet(packet(1, 0, &opus, false));
+        receiver.push_packet(packet(2, 0, &opus, true));
+
+        for (session, end_of_transmission) in [(1, false), (2, true)] {
+            let frame = tokio::time::timeout(std::time::Duration::from_secs(2), frames.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(frame.session, session);
+            assert_eq!(frame.end_of_transmission, end_of_transmission);
+        }
+    }
+}
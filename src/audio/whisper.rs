@@ -0,0 +1,128 @@
+// Voice targets for whispering sounds to one user or channel
+
+use crate::protos::generated::Mumble;
+
+/// Voice target IDs 1-30 are free for clients to register; 0 is normal
+/// talking and 31 is reserved for server loopback
+const FIRST_TARGET_ID: u8 = 1;
+const LAST_TARGET_ID: u8 = 30;
+
+/// Who hears a whispered sound
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WhisperTarget {
+    /// A single user, by session
+    User(u32),
+    /// Everyone in a channel, optionally including its subchannels
+    Channel { channel_id: u32, children: bool },
+}
+
+impl WhisperTarget {
+    /// Builds the VoiceTarget message that registers this target under `id`
+    pub fn voice_target(&self, id: u8) -> Mumble::VoiceTarget {
+        let mut target = Mumble::voice_target::Target::new();
+        match *self {
+            WhisperTarget::User(session) => target.session.push(session),
+            WhisperTarget::Channel {
+                channel_id,
+                children,
+            } => {
+                target.channel_id = Some(channel_id);
+                target.children = Some(children);
+            }
+        }
+
+        Mumble::VoiceTarget {
+            id: Some(id as u32),
+            targets: vec![target],
+            ..Default::default()
+        }
+    }
+}
+
+/// Assigns voice target IDs to whisper targets, reusing the ID of a target
+/// seen before and recycling the least recently used idle ID once all are taken
+#[derive(Default)]
+pub struct VoiceTargets {
+    /// Registered targets, least recently used first
+    assigned: Vec<(WhisperTarget, u8)>,
+}
+
+impl VoiceTargets {
+    /// IDs in `busy` still have whispers playing and are not recycled, since
+    /// registering another target under them would redirect those whispers.
+    /// Returns `None` if every ID is busy.
+    pub fn assign(&mut self, target: WhisperTarget, busy: &[u8]) -> Option<u8> {
+        let index = match self.assigned.iter().position(|(t, _)| *t == target) {
+            Some(index) => index,
+            None if self.assigned.len() < usize::from(LAST_TARGET_ID - FIRST_TARGET_ID + 1) => {
+                let id = FIRST_TARGET_ID + self.assigned.len() as u8;
+                self.assigned.push((target, id));
+                return Some(id);
+            }
+            None => self
+                .assigned
+                .iter()
+                .position(|(_, id)| !busy.contains(id))?,
+        };
+        let (_, id) = self.assigned.remove(index);
+        self.assigned.push((target, id));
+        Some(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assign_reuses_and_recycles_ids() {
+        let mut targets = VoiceTargets::default();
+        assert_eq!(targets.assign(WhisperTarget::User(7), &[]), Some(1));
+        let channel = WhisperTarget::Channel {
+            channel_id: 3,
+            children: true,
+        };
+        assert_eq!(targets.assign(channel, &[]), Some(2));
+        assert_eq!(targets.assign(WhisperTarget::User(7), &[]), Some(1));
+
+        for session in 100..128 {
+            targets.assign(WhisperTarget::User(session), &[]);
+        }
+        // All 30 IDs are taken, so the least recently used one (the channel) goes
+        assert_eq!(targets.assign(WhisperTarget::User(500), &[]), Some(2));
+        assert_eq!(targets.assign(WhisperTarget::User(7), &[]), Some(1));
+    }
+
+    #[test]
+    fn test_assign_skips_ids_with_playing_whispers() {
+        let mut targets = VoiceTargets::default();
+        for session in 1..=30 {
+            targets.assign(WhisperTarget::User(session), &[]);
+        }
+
+        // User 1 (ID 1) is least recently used but still being whispered to
+        assert_eq!(targets.assign(WhisperTarget::User(99), &[1]), Some(2));
+        // A target keeps its own ID even while it is busy
+        assert_eq!(targets.assign(WhisperTarget::User(1), &[1]), Some(1));
+
+        let busy: Vec<u8> = (1..=30).collect();
+        assert_eq!(targets.assign(WhisperTarget::User(100), &busy), None);
+    }
+
+    #[test]
+    fn test_voice_target_message() {
+        let message = WhisperTarget::Channel {
+            channel_id: 4,
+            children: true,
+        }
+        .voice_target(5);
+        assert_eq!(message.id, Some(5));
+        assert_eq!(message.targets[0].channel_id, Some(4));
+        assert_eq!(message.targets[0].children, Some(true));
+        assert!(message.targets[0].session.is_empty());
+
+        let message = WhisperTarget::User(9).voice_target(1);
+        assert_eq!(message.targets[0].session, vec![9]);
+        assert_eq!(message.targets[0].channel_id, None);
+    }
+}
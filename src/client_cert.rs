@@ -0,0 +1,303 @@
+// The client certificate the bot identifies itself with
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use rcgen::{CertificateParams, DistinguishedName, DnType, KeyPair, PKCS_RSA_SHA256};
+use rustls_pki_types::{CertificateDer, pem::PemObject};
+
+use crate::error::Error;
+
+/// Creates a self-signed certificate and RSA key, the kind Mumble clients
+/// generate, valid for `days` days
+pub fn generate(
+    cert_path: &Path,
+    key_path: &Path,
+    common_name: &str,
+    days: u32,
+) -> Result<(), Error> {
+    let key_pair = KeyPair::generate_for(&PKCS_RSA_SHA256)
+        .map_err(|e| Error::InvalidCertificate(format!("Failed to generate key: {}", e)))?;
+
+    let mut params = CertificateParams::default();
+    params.distinguished_name = DistinguishedName::new();
+    params
+        .distinguished_name
+        .push(DnType::CommonName, common_name);
+    let now = time::OffsetDateTime::now_utc();
+    params.not_before = now - time::Duration::days(1);
+    params.not_after = now + time::Duration::days(days as i64);
+
+    let cert = params
+        .self_signed(&key_pair)
+        .map_err(|e| Error::InvalidCertificate(format!("Failed to sign certificate: {}", e)))?;
+
+    write_private(key_path, &key_pair.serialize_pem())?;
+    fs::write(cert_path, cert.pem())?;
+    Ok(())
+}
+
+/// Writes a private key, readable only by the owner where supported
+fn write_private(path: &Path, contents: &str) -> Result<(), Error> {
+    fs::write(path, contents)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
+
+fn to_pem(tag: &str, der: &[u8]) -> String {
+    let config = pem::EncodeConfig::new().set_line_ending(pem::LineEnding::LF);
+    pem::encode_config(&pem::Pem::new(tag, der), config)
+}
+
+fn read_certificate(cert_path: &Path) -> Result<CertificateDer<'static>, Error> {
+    CertificateDer::from_pem_file(cert_path).map_err(|e| {
+        Error::InvalidCertificate(format!(
+            "Error reading certificate: {}: {}",
+            cert_path.display(),
+            e
+        ))
+    })
+}
+
+/// When the certificate stops being valid
+pub fn expiry(cert: &[u8]) -> Result<DateTime<Utc>, Error> {
+    let (_, parsed) = x509_parser::parse_x509_certificate(cert)
+        .map_err(|e| Error::InvalidCertificate(format!("Failed to parse certificate: {}", e)))?;
+    DateTime::from_timestamp(parsed.validity().not_after.timestamp(), 0)
+        .ok_or_else(|| Error::InvalidCertificate("Certificate expiry is out of range".to_string()))
+}
+
+/// The SHA-1 hash Mumble servers identify registered users by
+pub fn mumble_hash(cert: &[u8]) -> String {
+    use sha1::{Digest, Sha1};
+    Sha1::digest(cert)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Logs a warning if the certificate has expired or expires within `warning_days`
+pub fn warn_if_expiring(cert: &[u8], warning_days: u32) {
+    if warning_days == 0 {
+        return;
+    }
+
+    let expires = match expiry(cert) {
+        Ok(expires) => expires,
+        Err(e) => {
+            warn!("Could not check the client certificate's expiry: {}", e);
+            return;
+        }
+    };
+
+    let remaining = expires - Utc::now();
+    if remaining <= chrono::Duration::zero() {
+        warn!(
+            "The client certificate expired on {}; run `threebot cert rotate` to replace it",
+            expires.format("%Y-%m-%d")
+        );
+    } else if remaining <= chrono::Duration::days(warning_days as i64) {
+        warn!(
+            "The client certificate expires on {} ({} days left); run `threebot cert rotate` to replace it",
+            expires.format("%Y-%m-%d"),
+            remaining.num_days()
+        );
+    }
+}
+
+/// `path` with `suffix` appended to its file name
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Moves the current certificate and key aside so they can be restored. An
+/// earlier backup is kept by adding a timestamp to the new one.
+fn back_up(cert_path: &Path, key_path: &Path) -> Result<(PathBuf, PathBuf), Error> {
+    let mut suffix = ".old".to_string();
+    if with_suffix(cert_path, &suffix).exists() || with_suffix(key_path, &suffix).exists() {
+        suffix = format!(".old.{}", Utc::now().format("%Y%m%d%H%M%S"));
+    }
+
+    let (cert_backup, key_backup) = (
+        with_suffix(cert_path, &suffix),
+        with_suffix(key_path, &suffix),
+    );
+    fs::rename(cert_path, &cert_backup)?;
+    fs::rename(key_path, &key_backup)?;
+    Ok((cert_backup, key_backup))
+}
+
+/// Replaces the certificate and key with newly generated ones, keeping the
+/// old pair next to them. Returns the old and new certificate hashes.
+pub fn rotate(
+    cert_path: &Path,
+    key_path: &Path,
+    common_name: &str,
+    days: u32,
+) -> Result<(Option<String>, String), Error> {
+    let old_hash = match cert_path.exists() {
+        true if !key_path.exists() => {
+            return Err(Error::InvalidCertificate(format!(
+                "{} has no key at {}; restore it or remove the certificate",
+                cert_path.display(),
+                key_path.display()
+            )));
+        }
+        true => Some(mumble_hash(&read_certificate(cert_path)?)),
+        false => None,
+    };
+
+    // Nothing is moved until the new pair exists
+    let (new_cert, new_key) = (
+        with_suffix(cert_path, ".new"),
+        with_suffix(key_path, ".new"),
+    );
+    generate(&new_cert, &new_key, common_name, days)?;
+    let new_hash = mumble_hash(&read_certificate(&new_cert)?);
+
+    if old_hash.is_some() {
+        let (cert_backup, _) = back_up(cert_path, key_path)?;
+        info!("Kept the previous certificate at {}", cert_backup.display());
+    }
+    fs::rename(&new_key, key_path)?;
+    fs::rename(&new_cert, cert_path)?;
+    Ok((old_hash, new_hash))
+}
+
+/// Takes over the certificate and key from a PKCS#12 bundle, such as one
+/// exported from the Mumble client, so the bot can use that identity.
+/// Returns the imported certificate's hash.
+pub fn import_pkcs12(
+    bundle_path: &Path,
+    password: &str,
+    cert_path: &Path,
+    key_path: &Path,
+) -> Result<String, Error> {
+    let bundle = fs::read(bundle_path)?;
+    let keystore = p12_keystore::KeyStore::from_pkcs12(&bundle, password).map_err(|e| {
+        Error::InvalidCertificate(format!("Failed to read {}: {}", bundle_path.display(), e))
+    })?;
+    let (_, chain) = keystore.private_key_chain().ok_or_else(|| {
+        Error::InvalidCertificate(format!(
+            "{} does not contain a private key",
+            bundle_path.display()
+        ))
+    })?;
+    let leaf = chain.chain().first().ok_or_else(|| {
+        Error::InvalidCertificate(format!(
+            "{} does not contain a certificate",
+            bundle_path.display()
+        ))
+    })?;
+
+    // Make sure rustls can use the key before replacing anything
+    KeyPair::try_from(chain.key())
+        .map_err(|e| Error::InvalidCertificate(format!("Unsupported private key: {}", e)))?;
+
+    let certs: String = chain
+        .chain()
+        .iter()
+        .map(|cert| to_pem("CERTIFICATE", cert.as_der()))
+        .collect();
+
+    if cert_path.exists() && key_path.exists() {
+        let (cert_backup, _) = back_up(cert_path, key_path)?;
+        info!("Kept the previous certificate at {}", cert_backup.display());
+    }
+    write_private(key_path, &to_pem("PRIVATE KEY", chain.key()))?;
+    fs::write(cert_path, certs)?;
+
+    Ok(mumble_hash(leaf.as_der()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("threebot-cert-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_generate_and_rotate() {
+        let dir = temp_dir("rotate");
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+
+        let (old, first) = rotate(&cert_path, &key_path, "Threebot", 30).unwrap();
+        assert_eq!(old, None);
+        let cert = read_certificate(&cert_path).unwrap();
+        let days_left = (expiry(&cert).unwrap() - Utc::now()).num_days();
+        assert!((29..=30).contains(&days_left));
+        rustls_pki_types::PrivateKeyDer::from_pem_file(&key_path).unwrap();
+
+        let (old, second) = rotate(&cert_path, &key_path, "Threebot", 30).unwrap();
+        assert_eq!(old, Some(first.clone()));
+        assert_ne!(first, second);
+        assert!(dir.join("cert.pem.old").exists());
+        assert!(dir.join("key.pem.old").exists());
+
+        // A second rotation keeps the first backup
+        let (old, _) = rotate(&cert_path, &key_path, "Threebot", 30).unwrap();
+        assert_eq!(old, Some(second));
+        let backup = read_certificate(&dir.join("cert.pem.old")).unwrap();
+        assert_eq!(mumble_hash(&backup), first);
+        let backups = fs::read_dir(&dir)
+            .unwrap()
+            .flatten()
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with("cert.pem.old")
+            })
+            .count();
+        assert_eq!(backups, 2);
+
+        // Without its key the certificate is left alone
+        fs::remove_file(&key_path).unwrap();
+        assert!(rotate(&cert_path, &key_path, "Threebot", 30).is_err());
+        assert!(cert_path.exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_import_pkcs12() {
+        let dir = temp_dir("import");
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        generate(&cert_path, &key_path, "Exported", 365).unwrap();
+
+        // Bundle the generated pair the way a client export would
+        let cert = read_certificate(&cert_path).unwrap();
+        let key = rustls_pki_types::PrivatePkcs8KeyDer::from_pem_file(&key_path).unwrap();
+        let mut keystore = p12_keystore::KeyStore::new();
+        keystore.add_entry(
+            "exported",
+            p12_keystore::KeyStoreEntry::PrivateKeyChain(p12_keystore::PrivateKeyChain::new(
+                key.secret_pkcs8_der(),
+                [1],
+                [p12_keystore::Certificate::from_der(&cert).unwrap()],
+            )),
+        );
+        let bundle_path = dir.join("exported.p12");
+        fs::write(&bundle_path, keystore.writer("secret").write().unwrap()).unwrap();
+
+        let (new_cert, new_key) = (dir.join("bot.pem"), dir.join("bot-key.pem"));
+        assert!(import_pkcs12(&bundle_path, "wrong", &new_cert, &new_key).is_err());
+        let hash = import_pkcs12(&bundle_path, "secret", &new_cert, &new_key).unwrap();
+        assert_eq!(hash, mumble_hash(&cert));
+        assert_eq!(read_certificate(&new_cert).unwrap(), cert);
+        assert_eq!(fs::read(&new_key).unwrap(), fs::read(&key_path).unwrap());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,5 +1,7 @@
 use super::{Command, CommandContext, SessionTools};
+use crate::config::Role;
 use crate::error::Error;
+use crate::users::{Identity, identity};
 
 #[derive(Default)]
 pub struct AliasCommand;
@@ -48,7 +50,8 @@ impl Command for AliasCommand {
         } else if args.len() == 2 && args[0] == "remove" {
             // Remove an alias: !alias remove <name>
             let alias_name = &args[1];
-            self.remove_alias(tools, alias_name).await
+            let caller = context.sender_identity(tools);
+            self.remove_alias(tools, alias_name, caller.as_ref()).await
         } else if args.len() == 2 && args[0] == "list" {
             // List with page number: !alias list <page>
             match args[1].parse::<u64>() {
@@ -71,7 +74,9 @@ impl Command for AliasCommand {
             let commands = &args[1];
 
             // Get author name from user info
-            let author = if let Some(user_id) = context.triggering_user_id {
+            let author = if let Some(sender) = &context.relayed_sender {
+                sender.name.clone()
+            } else if let Some(user_id) = context.triggering_user_id {
                 tools
                     .get_user_info(user_id)
                     .and_then(|user| user.name.as_ref())
@@ -81,8 +86,16 @@ impl Command for AliasCommand {
                 "unknown".to_string()
             };
 
-            self.create_alias(tools, alias_name, &author, commands)
-                .await
+            let author_identity = context.sender_identity(tools);
+
+            self.create_alias(
+                tools,
+                alias_name,
+                &author,
+                author_identity.as_ref(),
+                commands,
+            )
+            .await
         } else if args.len() == 3 && args[0] == "search" {
             // Search with page: !alias search <term> <page>
             match args[2].parse::<u64>() {
@@ -109,7 +122,9 @@ impl Command for AliasCommand {
             let commands = args[1..].join(" ");
 
             // Get author name from user info
-            let author = if let Some(user_id) = context.triggering_user_id {
+            let author = if let Some(sender) = &context.relayed_sender {
+                sender.name.clone()
+            } else if let Some(user_id) = context.triggering_user_id {
                 tools
                     .get_user_info(user_id)
                     .and_then(|user| user.name.as_ref())
@@ -119,8 +134,16 @@ impl Command for AliasCommand {
                 "unknown".to_string()
             };
 
-            self.create_alias(tools, alias_name, &author, &commands)
-                .await
+            let author_identity = context.sender_identity(tools);
+
+            self.create_alias(
+                tools,
+                alias_name,
+                &author,
+                author_identity.as_ref(),
+                &commands,
+            )
+            .await
         }
     }
 }
@@ -132,11 +155,15 @@ impl AliasCommand {
         tools: &dyn SessionTools,
         name: &str,
         author: &str,
+        author_identity: Option<&Identity>,
         commands: &str,
     ) -> Result<(), Error> {
         // Get the alias manager
         if let Some(alias_manager) = tools.get_alias_manager() {
-            match alias_manager.create_alias(name, author, commands).await {
+            match alias_manager
+                .create_alias(name, author, author_identity, commands)
+                .await
+            {
                 Ok(_) => {
                     tools
                         .reply(&format!(" Alias '{}' created successfully", name))
@@ -292,9 +319,31 @@ impl AliasCommand {
     }
 
     /// Removes an alias
-    async fn remove_alias(&self, tools: &dyn SessionTools, name: &str) -> Result<(), Error> {
+    async fn remove_alias(
+        &self,
+        tools: &dyn SessionTools,
+        name: &str,
+        caller: Option<&Identity>,
+    ) -> Result<(), Error> {
         // Get the alias manager
         if let Some(alias_manager) = tools.get_alias_manager() {
+            let owner = alias_manager
+                .get_alias(name)
+                .await
+                .ok()
+                .flatten()
+                .and_then(|alias| alias.author_identity);
+            let is_admin = tools.permissions().role_of(caller) >= Role::Admin;
+            if !is_admin && !identity::can_modify(owner.as_deref(), caller) {
+                tools
+                    .reply(&format!(
+                        "error: Permission denied: only the author of '{}' or an admin can remove it",
+                        name
+                    ))
+                    .await?;
+                return Ok(());
+            }
+
             match alias_manager.delete_alias(name).await {
                 Ok(true) => {
                     tools
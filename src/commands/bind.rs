@@ -12,31 +12,19 @@ impl Command for BindCommand {
         context: CommandContext,
         args: Vec<String>,
     ) -> Result<(), Error> {
-        // Get the user ID from the context and get their username
-        let user_id = match context.triggering_user_id {
-            Some(id) => id,
-            None => {
-                tools
-                    .reply("error: Unable to identify user for bind command")
-                    .await?;
-                return Ok(());
-            }
-        };
+        if context.triggering_user_id.is_none() && context.relayed_sender.is_none() {
+            tools
+                .reply("error: Unable to identify user for bind command")
+                .await?;
+            return Ok(());
+        }
 
-        // Get the username from the user ID
-        let username = match tools.get_user_info(user_id) {
-            Some(user_info) => match &user_info.name {
-                Some(name) if !name.is_empty() => name.clone(),
-                _ => {
-                    tools
-                        .reply("error: Unable to get valid username for bind command")
-                        .await?;
-                    return Ok(());
-                }
-            },
+        // Settings follow the user's registration or certificate, not their name
+        let identity = match context.sender_identity(tools) {
+            Some(identity) => identity,
             None => {
                 tools
-                    .reply("error: Unable to find user information for bind command")
+                    .reply("error: Bind commands need a registered account or a client certificate")
                     .await?;
                 return Ok(());
             }
@@ -45,7 +33,7 @@ impl Command for BindCommand {
         if args.is_empty() {
             // Execute the user's bind command
             if let Some(user_settings_manager) = tools.get_user_settings_manager() {
-                match user_settings_manager.get_bind(&username).await {
+                match user_settings_manager.get_bind(&identity).await {
                     Ok(Some(bind_command)) => {
                         // Execute the bind command by parsing and running it
                         tools
@@ -86,7 +74,7 @@ impl Command for BindCommand {
 
             if let Some(user_settings_manager) = tools.get_user_settings_manager() {
                 match user_settings_manager
-                    .set_bind(&username, &bind_command)
+                    .set_bind(&identity, &bind_command)
                     .await
                 {
                     Ok(()) => {
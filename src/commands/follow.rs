@@ -0,0 +1,66 @@
+use super::{Command, CommandContext, SessionTools};
+use crate::error::Error;
+
+#[derive(Default)]
+pub struct FollowCommand;
+
+#[async_trait::async_trait]
+impl Command for FollowCommand {
+    async fn execute(
+        &mut self,
+        tools: &dyn SessionTools,
+        context: CommandContext,
+        args: Vec<String>,
+    ) -> Result<(), Error> {
+        let target = match args.first().map(|s| s.as_str()) {
+            None => match tools.follow_target() {
+                Some(user) => {
+                    tools
+                        .reply(&format!(" Following {}. Use `!follow stop` to stop", user))
+                        .await?;
+                    return Ok(());
+                }
+                None => {
+                    tools.reply("Usage: !follow <user|me|stop>").await?;
+                    return Ok(());
+                }
+            },
+            Some("stop") | Some("off") => {
+                match tools.follow_target() {
+                    Some(user) => {
+                        tools.set_follow_target(None);
+                        tools
+                            .reply(&format!(" No longer following {}", user))
+                            .await?;
+                    }
+                    None => tools.reply(" Not following anyone").await?,
+                }
+                return Ok(());
+            }
+            Some("me") => context.triggering_user_id,
+            Some(_) => tools.find_user_by_name(&args.join(" ")),
+        };
+
+        let Some(user) = target.and_then(|session| tools.get_user_info(session)) else {
+            tools
+                .reply(&format!(" User '{}' is not online", args.join(" ")))
+                .await?;
+            return Ok(());
+        };
+        if user.session.is_some() && user.session == tools.current_user_id() {
+            tools.reply(" I can't follow myself").await?;
+            return Ok(());
+        }
+
+        let name = user.name.clone().unwrap_or_default();
+        let channel_id = user.channel_id;
+        tools.set_follow_target(Some(name.clone()));
+        if let Some(channel_id) = channel_id.filter(|id| Some(*id) != tools.current_channel_id()) {
+            tools.move_to_channel(channel_id).await?;
+        }
+        tools
+            .reply(&format!(" Following {} between channels", name))
+            .await?;
+        Ok(())
+    }
+}
@@ -12,31 +12,19 @@ impl Command for GreetingCommand {
         context: CommandContext,
         args: Vec<String>,
     ) -> Result<(), Error> {
-        // Get the user ID from the context and get their username
-        let user_id = match context.triggering_user_id {
-            Some(id) => id,
-            None => {
-                tools
-                    .reply(" Unable to identify user for greeting command")
-                    .await?;
-                return Ok(());
-            }
-        };
+        if context.triggering_user_id.is_none() && context.relayed_sender.is_none() {
+            tools
+                .reply(" Unable to identify user for greeting command")
+                .await?;
+            return Ok(());
+        }
 
-        // Get the username from the user ID
-        let username = match tools.get_user_info(user_id) {
-            Some(user_info) => match &user_info.name {
-                Some(name) if !name.is_empty() => name.clone(),
-                _ => {
-                    tools
-                        .reply(" Unable to get valid username for greeting command")
-                        .await?;
-                    return Ok(());
-                }
-            },
+        // Settings follow the user's registration or certificate, not their name
+        let identity = match context.sender_identity(tools) {
+            Some(identity) => identity,
             None => {
                 tools
-                    .reply(" Unable to find user information for greeting command")
+                    .reply(" Greeting commands need a registered account or a client certificate")
                     .await?;
                 return Ok(());
             }
@@ -45,7 +33,7 @@ impl Command for GreetingCommand {
         if args.is_empty() {
             // Clear/unset the user's greeting command
             if let Some(user_settings_manager) = tools.get_user_settings_manager() {
-                match user_settings_manager.clear_greeting(&username).await {
+                match user_settings_manager.clear_greeting(&identity).await {
                     Ok(true) => {
                         tools
                             .reply(" Your greeting command has been removed")
@@ -76,7 +64,7 @@ impl Command for GreetingCommand {
 
             if let Some(user_settings_manager) = tools.get_user_settings_manager() {
                 match user_settings_manager
-                    .set_greeting(&username, &greeting_command)
+                    .set_greeting(&identity, &greeting_command)
                     .await
                 {
                     Ok(()) => {
@@ -0,0 +1,33 @@
+use super::{Command, CommandContext, SessionTools};
+use crate::error::Error;
+
+#[derive(Default)]
+pub struct HomeCommand;
+
+#[async_trait::async_trait]
+impl Command for HomeCommand {
+    async fn execute(
+        &mut self,
+        tools: &dyn SessionTools,
+        _context: CommandContext,
+        _args: Vec<String>,
+    ) -> Result<(), Error> {
+        let Some(home) = tools.home_channel().map(str::to_string) else {
+            tools
+                .reply(" No home channel is configured (`server.home_channel`)")
+                .await?;
+            return Ok(());
+        };
+        let Some(channel_id) = tools.find_channel(&home) else {
+            tools
+                .reply(&format!(" Home channel '{}' not found", home))
+                .await?;
+            return Ok(());
+        };
+
+        tools.set_follow_target(None);
+        tools.move_to_channel(channel_id).await?;
+        tools.reply(&format!(" Going home to '{}'", home)).await?;
+        Ok(())
+    }
+}
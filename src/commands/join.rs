@@ -0,0 +1,51 @@
+use super::{Command, CommandContext, SessionTools};
+use crate::error::Error;
+
+#[derive(Default)]
+pub struct JoinCommand;
+
+#[async_trait::async_trait]
+impl Command for JoinCommand {
+    async fn execute(
+        &mut self,
+        tools: &dyn SessionTools,
+        _context: CommandContext,
+        args: Vec<String>,
+    ) -> Result<(), Error> {
+        if args.is_empty() {
+            tools.reply("Usage: !join <channel name or path>").await?;
+            return Ok(());
+        }
+
+        // Channel names may contain spaces
+        let query = args.join(" ");
+        let Some(channel_id) = tools.find_channel(&query) else {
+            tools
+                .reply(&format!(" Channel '{}' not found", query))
+                .await?;
+            return Ok(());
+        };
+
+        // Following someone would pull the bot straight back out
+        let stopped_following = tools.follow_target();
+        tools.set_follow_target(None);
+        tools.move_to_channel(channel_id).await?;
+
+        let name = tools
+            .get_channel_info(channel_id)
+            .and_then(|channel| channel.name.clone())
+            .unwrap_or(query);
+        match stopped_following {
+            Some(user) => {
+                tools
+                    .reply(&format!(
+                        " Joining '{}' and no longer following {}",
+                        name, user
+                    ))
+                    .await?
+            }
+            None => tools.reply(&format!(" Joining '{}'", name)).await?,
+        }
+        Ok(())
+    }
+}
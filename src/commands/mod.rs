@@ -2,7 +2,7 @@ use std::{collections::HashMap, sync::Arc};
 
 use tokio::sync::Mutex;
 
-use crate::error::Error;
+use crate::{config::Role, error::Error};
 
 fn detect_error_reply(input: &str) -> bool {
     input.trim_start().to_lowercase().starts_with("error:")
@@ -38,7 +38,7 @@ impl<'a> ContextAwareSessionTools<'a> {
 
 #[async_trait::async_trait]
 impl<'a> SessionTools for ContextAwareSessionTools<'a> {
-    async fn play_sound(&self, file_path: &str) -> Result<(), Error> {
+    async fn play_sound(&self, file_path: &str) -> Result<crate::audio::PlayOutcome, Error> {
         self.tools.play_sound(file_path).await
     }
 
@@ -46,11 +46,15 @@ impl<'a> SessionTools for ContextAwareSessionTools<'a> {
         &self,
         file_path: &str,
         effects: &[crate::audio::effects::AudioEffect],
-    ) -> Result<(), Error> {
+    ) -> Result<crate::audio::PlayOutcome, Error> {
         self.tools.play_sound_with_effects(file_path, effects).await
     }
 
-    async fn play_sound_with_code(&self, file_path: &str, sound_code: &str) -> Result<(), Error> {
+    async fn play_sound_with_code(
+        &self,
+        file_path: &str,
+        sound_code: &str,
+    ) -> Result<crate::audio::PlayOutcome, Error> {
         self.tools.play_sound_with_code(file_path, sound_code).await
     }
 
@@ -59,7 +63,7 @@ impl<'a> SessionTools for ContextAwareSessionTools<'a> {
         file_path: &str,
         effects: &[crate::audio::effects::AudioEffect],
         sound_code: &str,
-    ) -> Result<(), Error> {
+    ) -> Result<crate::audio::PlayOutcome, Error> {
         self.tools
             .play_sound_with_effects_and_code(file_path, effects, sound_code)
             .await
@@ -69,6 +73,10 @@ impl<'a> SessionTools for ContextAwareSessionTools<'a> {
         self.tools.stop_all_streams().await
     }
 
+    fn audio_control(&self) -> crate::audio::AudioMixerControl {
+        self.tools.audio_control()
+    }
+
     async fn send_channel_message(&self, channel_id: u32, message: &str) -> Result<(), Error> {
         self.tools.send_channel_message(channel_id, message).await
     }
@@ -125,6 +133,14 @@ impl<'a> SessionTools for ContextAwareSessionTools<'a> {
         self.tools.get_user_info(user_id)
     }
 
+    fn get_user_identity(&self, user_id: u32) -> Option<crate::users::Identity> {
+        self.tools.get_user_identity(user_id)
+    }
+
+    fn find_user_by_name(&self, name: &str) -> Option<u32> {
+        self.tools.find_user_by_name(name)
+    }
+
     fn get_channel_info(
         &self,
         channel_id: u32,
@@ -132,6 +148,26 @@ impl<'a> SessionTools for ContextAwareSessionTools<'a> {
         self.tools.get_channel_info(channel_id)
     }
 
+    fn find_channel(&self, query: &str) -> Option<u32> {
+        self.tools.find_channel(query)
+    }
+
+    async fn move_to_channel(&self, channel_id: u32) -> Result<(), Error> {
+        self.tools.move_to_channel(channel_id).await
+    }
+
+    fn home_channel(&self) -> Option<&str> {
+        self.tools.home_channel()
+    }
+
+    fn follow_target(&self) -> Option<String> {
+        self.tools.follow_target()
+    }
+
+    fn set_follow_target(&self, user_name: Option<String>) {
+        self.tools.set_follow_target(user_name)
+    }
+
     fn get_sounds_manager(&self) -> Option<Arc<crate::sounds::SoundsManager>> {
         self.tools.get_sounds_manager()
     }
@@ -144,6 +180,10 @@ impl<'a> SessionTools for ContextAwareSessionTools<'a> {
         self.tools.get_user_settings_manager()
     }
 
+    fn get_clip_recorder(&self) -> Option<Arc<crate::audio::clip::ClipRecorder>> {
+        self.tools.get_clip_recorder()
+    }
+
     async fn execute_command(&self, command: &str, context: &CommandContext) -> Result<(), Error> {
         self.tools.execute_command(command, context).await
     }
@@ -152,6 +192,46 @@ impl<'a> SessionTools for ContextAwareSessionTools<'a> {
         self.tools.behavior_settings()
     }
 
+    fn permissions(&self) -> &crate::permissions::Permissions {
+        self.tools.permissions()
+    }
+
+    fn connection_stats(&self) -> crate::ping::ConnectionStats {
+        self.tools.connection_stats()
+    }
+
+    fn music_settings(&self) -> &crate::config::MusicSettings {
+        self.tools.music_settings()
+    }
+
+    fn profile_settings(&self) -> &crate::config::ProfileSettings {
+        self.tools.profile_settings()
+    }
+
+    async fn register_self(&self) -> Result<(), Error> {
+        self.tools.register_self().await
+    }
+
+    fn set_comment_intro(&self, intro: Option<String>) {
+        self.tools.set_comment_intro(intro)
+    }
+
+    async fn refresh_comment(&self) -> Result<bool, Error> {
+        self.tools.refresh_comment().await
+    }
+
+    async fn set_avatar(&self, path: Option<&std::path::Path>) -> Result<(), Error> {
+        self.tools.set_avatar(path).await
+    }
+
+    fn server_name(&self) -> &str {
+        self.tools.server_name()
+    }
+
+    fn relays(&self) -> &crate::relay::Relays {
+        self.tools.relays()
+    }
+
     fn audio_effect_settings(&self) -> &crate::config::AudioEffectSettings {
         self.tools.audio_effect_settings()
     }
@@ -177,17 +257,21 @@ impl<'a> SessionTools for ContextAwareSessionTools<'a> {
 #[async_trait::async_trait]
 pub trait SessionTools: Send + Sync {
     /// Play an audio file through the audio mixer
-    async fn play_sound(&self, file_path: &str) -> Result<(), Error>;
+    async fn play_sound(&self, file_path: &str) -> Result<crate::audio::PlayOutcome, Error>;
 
     /// Play an audio file with effects through the audio mixer
     async fn play_sound_with_effects(
         &self,
         file_path: &str,
         effects: &[crate::audio::effects::AudioEffect],
-    ) -> Result<(), Error>;
+    ) -> Result<crate::audio::PlayOutcome, Error>;
 
     /// Play an audio file and record it in history
-    async fn play_sound_with_code(&self, file_path: &str, sound_code: &str) -> Result<(), Error>;
+    async fn play_sound_with_code(
+        &self,
+        file_path: &str,
+        sound_code: &str,
+    ) -> Result<crate::audio::PlayOutcome, Error>;
 
     /// Play an audio file with effects and record it in history
     async fn play_sound_with_effects_and_code(
@@ -195,11 +279,14 @@ pub trait SessionTools: Send + Sync {
         file_path: &str,
         effects: &[crate::audio::effects::AudioEffect],
         sound_code: &str,
-    ) -> Result<(), Error>;
+    ) -> Result<crate::audio::PlayOutcome, Error>;
 
     /// Stop all currently playing audio streams
     async fn stop_all_streams(&self) -> Result<(), Error>;
 
+    /// Handle to the mixer for queue and playback control
+    fn audio_control(&self) -> crate::audio::AudioMixerControl;
+
     /// Send a text message to a specific channel
     async fn send_channel_message(&self, channel_id: u32, message: &str) -> Result<(), Error>;
 
@@ -224,12 +311,33 @@ pub trait SessionTools: Send + Sync {
     /// Get information about a user by ID
     fn get_user_info(&self, user_id: u32) -> Option<&crate::protos::generated::Mumble::UserState>;
 
+    /// Get the stable identity of a connected user, if they have a registration or certificate
+    fn get_user_identity(&self, user_id: u32) -> Option<crate::users::Identity>;
+
+    /// Find a connected user's session ID by name (case-insensitive)
+    fn find_user_by_name(&self, name: &str) -> Option<u32>;
+
     /// Get information about a channel by ID
     fn get_channel_info(
         &self,
         channel_id: u32,
     ) -> Option<&crate::protos::generated::Mumble::ChannelState>;
 
+    /// Find a channel by name or `/`-separated path (case-insensitive)
+    fn find_channel(&self, query: &str) -> Option<u32>;
+
+    /// Move the bot into a channel
+    async fn move_to_channel(&self, channel_id: u32) -> Result<(), Error>;
+
+    /// Get the configured home channel name or path
+    fn home_channel(&self) -> Option<&str>;
+
+    /// Get the name of the user the bot follows between channels
+    fn follow_target(&self) -> Option<String>;
+
+    /// Follow a user between channels, or stop following with `None`
+    fn set_follow_target(&self, user_name: Option<String>);
+
     /// Get access to the sounds manager for sound-related operations
     fn get_sounds_manager(&self) -> Option<Arc<crate::sounds::SoundsManager>>;
 
@@ -239,12 +347,46 @@ pub trait SessionTools: Send + Sync {
     /// Get access to the user settings manager for user-specific settings
     fn get_user_settings_manager(&self) -> Option<Arc<crate::user_settings::UserSettingsManager>>;
 
+    /// Get access to the recorder holding recent channel audio, if enabled
+    fn get_clip_recorder(&self) -> Option<Arc<crate::audio::clip::ClipRecorder>>;
+
     /// Execute a command string
     async fn execute_command(&self, command: &str, context: &CommandContext) -> Result<(), Error>;
 
     /// Get the current behavior settings
     fn behavior_settings(&self) -> &crate::config::BehaviorSettings;
 
+    /// Get the roles of users and the roles commands require
+    fn permissions(&self) -> &crate::permissions::Permissions;
+
+    /// Get ping round trip times and packet loss for the current connection
+    fn connection_stats(&self) -> crate::ping::ConnectionStats;
+
+    /// Get the current music streaming settings
+    fn music_settings(&self) -> &crate::config::MusicSettings;
+
+    /// Get the configured registration, comment and avatar of the bot
+    fn profile_settings(&self) -> &crate::config::ProfileSettings;
+
+    /// Ask the server to register the bot's certificate
+    async fn register_self(&self) -> Result<(), Error>;
+
+    /// Replace the HTML shown above the summary in the bot's comment, or
+    /// go back to the configured one with `None`
+    fn set_comment_intro(&self, intro: Option<String>);
+
+    /// Update the bot's comment if its summary changed, returning whether it was sent
+    async fn refresh_comment(&self) -> Result<bool, Error>;
+
+    /// Set the bot's avatar from an image file, or remove it with `None`
+    async fn set_avatar(&self, path: Option<&std::path::Path>) -> Result<(), Error>;
+
+    /// Get the name of the server this session is connected to
+    fn server_name(&self) -> &str;
+
+    /// Get the inboxes of the other servers' sessions, for relaying commands
+    fn relays(&self) -> &crate::relay::Relays;
+
     /// Get the current audio effect settings
     fn audio_effect_settings(&self) -> &crate::config::AudioEffectSettings;
 
@@ -381,6 +523,23 @@ pub struct CommandContext {
     pub source_channel_id: Option<u32>,
     /// Whether this was a private message
     pub is_private_message: bool,
+    /// Sender of a command relayed from another server
+    pub relayed_sender: Option<crate::relay::RelayedSender>,
+    /// Line of the command being run, with HTML removed; set by the executor
+    pub command_line: String,
+}
+
+impl CommandContext {
+    /// Identity of whoever sent the command, on this server or, for relayed
+    /// commands, on the one it came from
+    pub fn sender_identity(&self, tools: &dyn SessionTools) -> Option<crate::users::Identity> {
+        match &self.relayed_sender {
+            Some(sender) => sender.identity.clone(),
+            None => self
+                .triggering_user_id
+                .and_then(|user_id| tools.get_user_identity(user_id)),
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -396,9 +555,21 @@ pub trait Command: Send + Sync {
 pub mod alias;
 pub mod bind;
 pub mod farewell;
+pub mod follow;
 pub mod greeting;
+pub mod home;
+pub mod join;
+pub mod music;
+pub mod nowplaying;
+pub mod pause;
 pub mod ping;
+pub mod profile;
+pub mod queue;
+pub mod relay;
+pub mod resume;
+pub mod skip;
 pub mod sound;
+pub mod whoami;
 
 // Include the generated command mappings
 include!(concat!(env!("OUT_DIR"), "/commands_generated.rs"));
@@ -432,28 +603,107 @@ impl Executor {
                 Box::new(farewell::FarewellCommand::default()) as Box<dyn Command>
             )),
         );
+        commands.insert(
+            "follow".to_string(),
+            Arc::new(Mutex::new(
+                Box::new(follow::FollowCommand::default()) as Box<dyn Command>
+            )),
+        );
         commands.insert(
             "greeting".to_string(),
             Arc::new(Mutex::new(
                 Box::new(greeting::GreetingCommand::default()) as Box<dyn Command>
             )),
         );
+        commands.insert(
+            "home".to_string(),
+            Arc::new(Mutex::new(
+                Box::new(home::HomeCommand::default()) as Box<dyn Command>
+            )),
+        );
+        commands.insert(
+            "join".to_string(),
+            Arc::new(Mutex::new(
+                Box::new(join::JoinCommand::default()) as Box<dyn Command>
+            )),
+        );
+        commands.insert(
+            "music".to_string(),
+            Arc::new(Mutex::new(
+                Box::new(music::MusicCommand::default()) as Box<dyn Command>
+            )),
+        );
+        commands.insert(
+            "nowplaying".to_string(),
+            Arc::new(Mutex::new(
+                Box::new(nowplaying::NowplayingCommand::default()) as Box<dyn Command>,
+            )),
+        );
+        commands.insert(
+            "pause".to_string(),
+            Arc::new(Mutex::new(
+                Box::new(pause::PauseCommand::default()) as Box<dyn Command>
+            )),
+        );
         commands.insert(
             "ping".to_string(),
             Arc::new(Mutex::new(
                 Box::new(ping::PingCommand::default()) as Box<dyn Command>
             )),
         );
+        commands.insert(
+            "profile".to_string(),
+            Arc::new(Mutex::new(
+                Box::new(profile::ProfileCommand::default()) as Box<dyn Command>
+            )),
+        );
+        commands.insert(
+            "queue".to_string(),
+            Arc::new(Mutex::new(
+                Box::new(queue::QueueCommand::default()) as Box<dyn Command>
+            )),
+        );
+        commands.insert(
+            "relay".to_string(),
+            Arc::new(Mutex::new(
+                Box::new(relay::RelayCommand::default()) as Box<dyn Command>
+            )),
+        );
+        commands.insert(
+            "resume".to_string(),
+            Arc::new(Mutex::new(
+                Box::new(resume::ResumeCommand::default()) as Box<dyn Command>
+            )),
+        );
+        commands.insert(
+            "skip".to_string(),
+            Arc::new(Mutex::new(
+                Box::new(skip::SkipCommand::default()) as Box<dyn Command>
+            )),
+        );
         commands.insert(
             "sound".to_string(),
             Arc::new(Mutex::new(
                 Box::new(sound::SoundCommand::default()) as Box<dyn Command>
             )),
         );
+        commands.insert(
+            "whoami".to_string(),
+            Arc::new(Mutex::new(
+                Box::new(whoami::WhoamiCommand::default()) as Box<dyn Command>
+            )),
+        );
 
         Executor { commands }
     }
 
+    /// Names of the built-in commands, sorted
+    pub fn command_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.commands.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
     /// Sanitize command line by removing HTML link tags
     fn sanitize_command_line(cmdline: &str) -> String {
         let mut result = cmdline.to_string();
@@ -537,8 +787,24 @@ impl Executor {
 
         // First, check if this is a built-in command
         if let Some(command) = self.commands.get(command_name) {
-            let mut cmd = command.lock().await;
+            // A denial is an error so that aliases and greetings stop there
+            if let Some(denied) = Self::permission_denied(
+                tools.permissions(),
+                &context,
+                &|user_id| tools.get_user_identity(user_id),
+                command_name,
+                &args,
+            ) {
+                return Err(denied);
+            }
+
+            let context = CommandContext {
+                command_line: sanitized_cmdline.clone(),
+                ..context
+            };
             let context_aware_tools = ContextAwareSessionTools::new(tools, &context);
+
+            let mut cmd = command.lock().await;
             return cmd
                 .execute(&context_aware_tools, context.clone(), args)
                 .await;
@@ -576,6 +842,41 @@ impl Executor {
         )))
     }
 
+    /// Error explaining why the triggering user may not run a command, if they may not.
+    /// Relayed senders get the role this server's permissions give their identity.
+    fn permission_denied(
+        permissions: &crate::permissions::Permissions,
+        context: &CommandContext,
+        identity_of: &dyn Fn(u32) -> Option<crate::users::Identity>,
+        command_name: &str,
+        args: &[String],
+    ) -> Option<Error> {
+        let identity = match (&context.relayed_sender, context.triggering_user_id) {
+            (Some(sender), _) => sender.identity.clone(),
+            (None, Some(user_id)) => identity_of(user_id),
+            // Commands the bot runs on its own behalf are always allowed
+            (None, None) => return None,
+        };
+        let role = permissions.role_of(identity.as_ref());
+        let subcommand = args.first().map(String::as_str);
+        let required = permissions.required_role(command_name, subcommand);
+
+        match role {
+            _ if role >= required => None,
+            Role::Banned => Some(Error::PermissionDenied(
+                "you are banned from using commands".to_string(),
+            )),
+            _ => Some(Error::PermissionDenied(format!(
+                "`!{}` needs the {} role",
+                std::iter::once(command_name)
+                    .chain(subcommand)
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                required
+            ))),
+        }
+    }
+
     /// Executes alias commands, handling variable substitution
     async fn execute_alias_commands(
         &self,
@@ -661,7 +962,12 @@ impl Executor {
 
 #[cfg(test)]
 mod tests {
-    use super::Executor;
+    use super::{CommandContext, Executor};
+    use crate::config::{PermissionSettings, Role};
+    use crate::error::Error;
+    use crate::permissions::Permissions;
+    use crate::relay::RelayedSender;
+    use crate::users::Identity;
 
     #[test]
     fn test_sanitize_command_line() {
@@ -695,4 +1001,66 @@ mod tests {
         let expected = "!sounds pull  text inside more";
         assert_eq!(Executor::sanitize_command_line(input), expected);
     }
+
+    #[test]
+    fn test_relayed_commands_use_this_servers_permissions() {
+        let mut settings = PermissionSettings::default();
+        settings.users.insert("user:1".to_string(), Role::Admin);
+        settings.users.insert("cert:aaaa".to_string(), Role::Admin);
+        settings.users.insert("cert:ffff".to_string(), Role::Banned);
+        let permissions = Permissions::new(settings, "games");
+
+        let relayed = |identity: Option<Identity>| CommandContext {
+            triggering_user_id: None,
+            source_channel_id: None,
+            is_private_message: false,
+            relayed_sender: Some(RelayedSender {
+                server: "main".to_string(),
+                name: "alice".to_string(),
+                identity,
+            }),
+            command_line: String::new(),
+        };
+        let denied = |context: &CommandContext, command: &str, args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            let games_user = |user_id| {
+                Some(Identity::Registered {
+                    server: "games".to_string(),
+                    user_id,
+                })
+            };
+            Executor::permission_denied(&permissions, context, &games_user, command, &args)
+        };
+
+        // Certificates are the same everywhere, so this server's entry applies
+        let admin = relayed(Some(Identity::Certificate("aaaa".to_string())));
+        assert!(denied(&admin, "profile", &["avatar"]).is_none());
+        let banned = relayed(Some(Identity::Certificate("ffff".to_string())));
+        assert!(matches!(
+            denied(&banned, "sound", &["play"]),
+            Some(Error::PermissionDenied(reason)) if reason.contains("banned")
+        ));
+
+        // User 1 of the other server is not this server's user 1
+        let registered = relayed(Some(Identity::Registered {
+            server: "main".to_string(),
+            user_id: 1,
+        }));
+        assert!(denied(&registered, "profile", &["avatar"]).is_some());
+        assert!(denied(&registered, "sound", &["stopall"]).is_some());
+        assert!(denied(&registered, "sound", &["play"]).is_none());
+        assert!(denied(&relayed(None), "music", &["stop"]).is_some());
+
+        let local = CommandContext {
+            triggering_user_id: Some(7),
+            relayed_sender: None,
+            ..relayed(None)
+        };
+        assert!(denied(&local, "profile", &[]).is_some());
+        let local_admin = CommandContext {
+            triggering_user_id: Some(1),
+            ..local.clone()
+        };
+        assert!(denied(&local_admin, "profile", &[]).is_none());
+    }
 }
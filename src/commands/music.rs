@@ -0,0 +1,181 @@
+use super::{Command, CommandContext, SessionTools, nowplaying::format_time};
+use crate::{
+    audio::{effects, music},
+    error::Error,
+};
+
+/// Tracks listed by `!music queue` before the rest are summarized
+const QUEUE_LISTING_LIMIT: usize = 15;
+
+#[derive(Default)]
+pub struct MusicCommand;
+
+impl MusicCommand {
+    fn usage() -> &'static str {
+        "Usage: !music <play|skip|stop|queue>\n\
+         • `!music play <url> [+effects]` - Stream a track or playlist\n\
+         • `!music skip` - Skip to the next track\n\
+         • `!music stop` - Stop the music and clear its queue\n\
+         • `!music queue` - Show the current track and what's next"
+    }
+
+    fn describe_track(track: &music::Track) -> String {
+        match track.duration {
+            Some(duration) => format!("'{}' [{}]", track.title, format_time(duration)),
+            None => format!("'{}'", track.title),
+        }
+    }
+
+    async fn play(
+        &self,
+        tools: &dyn SessionTools,
+        context: &CommandContext,
+        args: &[String],
+    ) -> Result<(), Error> {
+        let Some(url) = args.first() else {
+            tools.reply("Usage: !music play <url> [+effects]").await?;
+            return Ok(());
+        };
+
+        let effect_strings: Vec<String> = args[1..]
+            .iter()
+            .map(|s| s.strip_prefix('+').unwrap_or(s).to_string())
+            .collect();
+        let effects = match effects::parse_effects(&effect_strings) {
+            Ok(effects) => effects,
+            Err(e) => {
+                tools.reply(&format!(" {}", e)).await?;
+                return Ok(());
+            }
+        };
+
+        let Some(channel) = context
+            .source_channel_id
+            .or_else(|| tools.current_channel_id())
+        else {
+            tools.reply(" Not in a channel").await?;
+            return Ok(());
+        };
+
+        let settings = &tools.music_settings();
+        let cookies = tools.external_tools_settings().get_ytdlp_cookies_path();
+        let tracks = match music::resolve_tracks(
+            url,
+            cookies.as_deref(),
+            settings.max_playlist_tracks,
+        )
+        .await
+        {
+            Ok(tracks) => tracks,
+            Err(e) => {
+                tools
+                    .reply(&format!(" Couldn't load '{}': {}", url, e))
+                    .await?;
+                return Ok(());
+            }
+        };
+
+        let total = tracks.len();
+        let first = tracks[0].clone();
+        let (added, waiting) = match tools
+            .audio_control()
+            .queue_music(tracks, &effects, channel)
+            .await
+        {
+            Ok(result) => result,
+            Err(e) => {
+                tools.reply(&format!(" {}", e)).await?;
+                return Ok(());
+            }
+        };
+
+        let mut message = match (added, waiting) {
+            (1, false) => format!(" Starting {}", Self::describe_track(&first)),
+            (1, true) => format!(" Queued {}", Self::describe_track(&first)),
+            (count, _) => format!(
+                " Queued {} tracks, starting with {}",
+                count,
+                Self::describe_track(&first)
+            ),
+        };
+        if added < total {
+            message.push_str(&format!(
+                " ({} didn't fit in the music queue)",
+                total - added
+            ));
+        }
+        tools.reply(&message).await?;
+        Ok(())
+    }
+
+    async fn queue(&self, tools: &dyn SessionTools) -> Result<(), Error> {
+        let (current, pending) = tools.audio_control().music_status().await;
+
+        let mut lines = Vec::new();
+        match current {
+            Some((track, Some(info))) => {
+                let progress = match track.duration {
+                    Some(duration) => format!(
+                        "[{} / {}]",
+                        format_time(info.elapsed),
+                        format_time(duration)
+                    ),
+                    None => format!("[{}]", format_time(info.elapsed)),
+                };
+                lines.push(format!("**Now playing:** '{}' {}", track.title, progress));
+            }
+            Some((track, None)) => lines.push(format!(
+                "**Now playing:** {} (loading)",
+                Self::describe_track(&track)
+            )),
+            None => lines.push("**Now playing:** nothing".to_string()),
+        }
+
+        if pending.is_empty() {
+            lines.push("The music queue is empty".to_string());
+        } else {
+            lines.push(format!("**Up next ({}):**", pending.len()));
+            for (index, track) in pending.iter().take(QUEUE_LISTING_LIMIT).enumerate() {
+                lines.push(format!("{}. {}", index + 1, Self::describe_track(track)));
+            }
+            if pending.len() > QUEUE_LISTING_LIMIT {
+                lines.push(format!(
+                    "...and {} more",
+                    pending.len() - QUEUE_LISTING_LIMIT
+                ));
+            }
+        }
+
+        tools.reply(&lines.join("\n")).await?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Command for MusicCommand {
+    async fn execute(
+        &mut self,
+        tools: &dyn SessionTools,
+        context: CommandContext,
+        args: Vec<String>,
+    ) -> Result<(), Error> {
+        match args.first().map(|s| s.as_str()) {
+            Some("play") => self.play(tools, &context, &args[1..]).await?,
+            Some("skip") => match tools.audio_control().skip_track().await {
+                Some(track) => {
+                    tools
+                        .reply(&format!(" Skipped {}", Self::describe_track(&track)))
+                        .await?
+                }
+                None => tools.reply(" No music is playing").await?,
+            },
+            Some("stop") => match tools.audio_control().stop_music().await {
+                0 => tools.reply(" No music is playing").await?,
+                _ => tools.reply(" Stopped the music").await?,
+            },
+            Some("queue") => self.queue(tools).await?,
+            _ => tools.reply(Self::usage()).await?,
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use super::{Command, CommandContext, SessionTools};
+use crate::{audio::StreamInfo, error::Error};
+use std::time::Duration;
+
+#[derive(Default)]
+pub struct NowplayingCommand;
+
+/// Formats a duration as m:ss
+pub fn format_time(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// One-line description of the primary stream, e.g. `'airhorn' [0:03 / 0:10]`
+pub fn describe(now: &StreamInfo) -> String {
+    let progress = match now.duration {
+        Some(total) => format!("{} / {}", format_time(now.elapsed), format_time(total)),
+        None => format_time(now.elapsed),
+    };
+    let paused = if now.paused { " (paused)" } else { "" };
+    format!("'{}' [{}]{}", now.label, progress, paused)
+}
+
+#[async_trait::async_trait]
+impl Command for NowplayingCommand {
+    async fn execute(
+        &mut self,
+        tools: &dyn SessionTools,
+        _context: CommandContext,
+        _args: Vec<String>,
+    ) -> Result<(), Error> {
+        match tools.audio_control().now_playing().await {
+            Some(now) => {
+                tools
+                    .reply(&format!(" Now playing {}", describe(&now)))
+                    .await?
+            }
+            None => tools.reply(" Nothing is playing").await?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_progress() {
+        let mut now = StreamInfo {
+            id: 4,
+            label: "airhorn".to_string(),
+            elapsed: Duration::from_millis(3_400),
+            duration: None,
+            gain: 1.0,
+            primary: true,
+            whisper: false,
+            paused: false,
+            fading: false,
+        };
+        assert_eq!(describe(&now), "'airhorn' [0:03]");
+
+        now.duration = Some(Duration::from_secs(125));
+        now.paused = true;
+        assert_eq!(describe(&now), "'airhorn' [0:03 / 2:05] (paused)");
+    }
+}
@@ -0,0 +1,25 @@
+use super::{Command, CommandContext, SessionTools};
+use crate::error::Error;
+
+#[derive(Default)]
+pub struct PauseCommand;
+
+#[async_trait::async_trait]
+impl Command for PauseCommand {
+    async fn execute(
+        &mut self,
+        tools: &dyn SessionTools,
+        _context: CommandContext,
+        _args: Vec<String>,
+    ) -> Result<(), Error> {
+        match tools.audio_control().set_paused(true).await {
+            Some(label) => {
+                tools
+                    .reply(&format!(" Paused '{}', use `!resume` to continue", label))
+                    .await?
+            }
+            None => tools.reply(" Nothing is playing").await?,
+        }
+        Ok(())
+    }
+}
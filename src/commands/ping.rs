@@ -1,8 +1,21 @@
 use super::{Command, CommandContext, SessionTools};
+use crate::ping::{RttStats, loss_percent};
 
 #[derive(Default)]
 pub struct PingCommand;
 
+/// Last round trip plus average and jitter, e.g. "42 ms (avg 40.3 ms ± 3.1 ms over 12 pings)"
+fn describe(rtt: &RttStats) -> Option<String> {
+    let last = rtt.last()?;
+    Some(format!(
+        "{} ms (avg {:.1} ms ± {:.1} ms over {} pings)",
+        last.as_millis(),
+        rtt.average_ms(),
+        rtt.variance_ms().sqrt(),
+        rtt.packets()
+    ))
+}
+
 #[async_trait::async_trait]
 impl Command for PingCommand {
     async fn execute(
@@ -11,7 +24,44 @@ impl Command for PingCommand {
         _context: CommandContext,
         _args: Vec<String>,
     ) -> Result<(), crate::error::Error> {
-        tools.reply("Pong!").await?;
+        let stats = tools.connection_stats();
+        let mut lines = vec!["Pong!".to_string()];
+
+        let mut tcp = describe(&stats.tcp).unwrap_or_else(|| "no replies yet".to_string());
+        if stats.unanswered > 1 {
+            tcp.push_str(&format!(", {} pings unanswered", stats.unanswered));
+        }
+        lines.push(format!("**TCP:** {}", tcp));
+
+        match stats.udp {
+            Some(udp) => {
+                let mut line = format!(
+                    "**UDP:** {}, {:.1}% loss in",
+                    describe(&udp.rtt).unwrap_or_else(|| "no replies yet".to_string()),
+                    loss_percent(&udp.crypt)
+                );
+                // The server reports how our packets fared in its replies
+                if let Some(server) = stats.server.as_ref().filter(|p| p.has_good()) {
+                    let sent = crate::crypt::CryptStats {
+                        good: server.good(),
+                        late: server.late(),
+                        lost: server.lost(),
+                        resync: server.resync(),
+                    };
+                    line.push_str(&format!(", {:.1}% out", loss_percent(&sent)));
+                }
+                line.push_str(&format!(
+                    " ({} late, {} resyncs)",
+                    udp.crypt.late, udp.crypt.resync
+                ));
+                lines.push(line);
+            }
+            None => {
+                lines.push("**UDP:** not in use, voice goes through the TCP tunnel".to_string())
+            }
+        }
+
+        tools.reply(&lines.join("\n")).await?;
         Ok(())
     }
 }
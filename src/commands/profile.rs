@@ -0,0 +1,101 @@
+use super::{Command, CommandContext, SessionTools};
+use crate::error::Error;
+
+#[derive(Default)]
+pub struct ProfileCommand;
+
+const USAGE: &str = "Usage: !profile [register | comment <text|reset> | avatar <path|clear>]";
+
+impl ProfileCommand {
+    async fn show(&self, tools: &dyn SessionTools) -> Result<(), Error> {
+        let state = tools
+            .current_user_id()
+            .and_then(|session| tools.get_user_info(session));
+
+        let registered = match state.and_then(|state| state.user_id) {
+            Some(user_id) => format!("yes (user ID {})", user_id),
+            None => "no".to_string(),
+        };
+        let comment = if tools.profile_settings().comment {
+            "kept up to date"
+        } else {
+            "off (`profile.comment`)"
+        };
+        let has_avatar = state
+            .is_some_and(|state| !state.texture().is_empty() || !state.texture_hash().is_empty());
+
+        let lines = [
+            format!("**Registered:** {}", registered),
+            format!("**Comment:** {}", comment),
+            format!("**Avatar:** {}", if has_avatar { "set" } else { "none" }),
+            USAGE.to_string(),
+        ];
+        tools.reply(&lines.join("\n")).await
+    }
+}
+
+#[async_trait::async_trait]
+impl Command for ProfileCommand {
+    async fn execute(
+        &mut self,
+        tools: &dyn SessionTools,
+        _context: CommandContext,
+        args: Vec<String>,
+    ) -> Result<(), Error> {
+        let Some(subcommand) = args.first() else {
+            return self.show(tools).await;
+        };
+        let rest = args[1..].join(" ");
+
+        match subcommand.to_lowercase().as_str() {
+            "register" => {
+                let registered = tools
+                    .current_user_id()
+                    .and_then(|session| tools.get_user_info(session))
+                    .is_some_and(|state| state.user_id.is_some());
+                if registered {
+                    tools.reply(" Already registered on this server").await?;
+                    return Ok(());
+                }
+
+                tools.register_self().await?;
+                tools
+                    .reply(" Asked the server to register this bot")
+                    .await?;
+            }
+            "comment" => {
+                if !tools.profile_settings().comment {
+                    tools
+                        .reply("error: The comment is turned off (`profile.comment`)")
+                        .await?;
+                    return Ok(());
+                }
+                if rest.is_empty() {
+                    tools.reply(USAGE).await?;
+                    return Ok(());
+                }
+
+                let intro = (!rest.eq_ignore_ascii_case("reset")).then_some(rest);
+                tools.set_comment_intro(intro);
+                tools.refresh_comment().await?;
+                tools.reply(" Comment updated").await?;
+            }
+            "avatar" => match rest.as_str() {
+                "" => tools.reply(USAGE).await?,
+                "clear" => {
+                    tools.set_avatar(None).await?;
+                    tools.reply(" Avatar removed").await?;
+                }
+                path => {
+                    if let Err(e) = tools.set_avatar(Some(std::path::Path::new(path))).await {
+                        tools.reply(&format!("error: {}", e)).await?;
+                        return Ok(());
+                    }
+                    tools.reply(" Avatar updated").await?;
+                }
+            },
+            _ => tools.reply(USAGE).await?,
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,41 @@
+use super::{Command, CommandContext, SessionTools, nowplaying};
+use crate::{config::PlaybackMode, error::Error};
+
+#[derive(Default)]
+pub struct QueueCommand;
+
+#[async_trait::async_trait]
+impl Command for QueueCommand {
+    async fn execute(
+        &mut self,
+        tools: &dyn SessionTools,
+        _context: CommandContext,
+        _args: Vec<String>,
+    ) -> Result<(), Error> {
+        let control = tools.audio_control();
+        let now_playing = control.now_playing().await;
+        let queued = control.queued().await;
+
+        let mut lines = Vec::new();
+        match &now_playing {
+            Some(now) => lines.push(format!("**Now playing:** {}", nowplaying::describe(now))),
+            None => lines.push("**Now playing:** nothing".to_string()),
+        }
+
+        if control.playback_mode() == PlaybackMode::Overlap {
+            lines.push(
+                "Overlap mode is enabled, sounds play immediately instead of queueing".to_string(),
+            );
+        } else if queued.is_empty() {
+            lines.push("The queue is empty".to_string());
+        } else {
+            lines.push(format!("**Up next ({}):**", queued.len()));
+            for (index, (id, label)) in queued.iter().enumerate() {
+                lines.push(format!("{}. {} [#{}]", index + 1, label, id));
+            }
+        }
+
+        tools.reply(&lines.join("\n")).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+use super::{Command, CommandContext, SessionTools};
+use crate::error::Error;
+use crate::relay::{RelayedCommand, RelayedSender};
+
+#[derive(Default)]
+pub struct RelayCommand;
+
+/// The part of a `!relay <server> ...` line to run on the other server, with
+/// the sender's spacing and quoting intact
+fn relayed_part(command_line: &str) -> Option<&str> {
+    let (_, rest) = command_line.trim().split_once(char::is_whitespace)?;
+    let (_, rest) = rest.trim_start().split_once(char::is_whitespace)?;
+    Some(rest.trim_start()).filter(|rest| !rest.is_empty())
+}
+
+const USAGE: &str = "Usage: !relay <server> <command>";
+
+#[async_trait::async_trait]
+impl Command for RelayCommand {
+    async fn execute(
+        &mut self,
+        tools: &dyn SessionTools,
+        context: CommandContext,
+        args: Vec<String>,
+    ) -> Result<(), Error> {
+        let servers: Vec<String> = tools
+            .relays()
+            .servers()
+            .into_iter()
+            .filter(|name| name != tools.server_name())
+            .collect();
+
+        if args.len() < 2 {
+            let known = if servers.is_empty() {
+                "none".to_string()
+            } else {
+                servers.join(", ")
+            };
+            tools
+                .reply(&format!("{}\n**Other servers:** {}", USAGE, known))
+                .await?;
+            return Ok(());
+        }
+
+        if context.relayed_sender.is_some() {
+            tools
+                .reply("error: A relayed command can't be relayed again")
+                .await?;
+            return Ok(());
+        }
+
+        let command = relayed_part(&context.command_line)
+            .map(str::to_string)
+            .unwrap_or_else(|| args[1..].join(" "));
+        let command = match command.starts_with('!') {
+            true => command,
+            false => format!("!{}", command),
+        };
+
+        // The other server decides what the sender may do there
+        let name = match context.triggering_user_id {
+            Some(user_id) => tools
+                .get_user_info(user_id)
+                .map(|user| user.name().to_string())
+                .unwrap_or_else(|| format!("session {}", user_id)),
+            None => "the bot".to_string(),
+        };
+        let relayed = RelayedCommand {
+            sender: RelayedSender {
+                server: tools.server_name().to_string(),
+                name,
+                identity: context.sender_identity(tools),
+            },
+            command: command.clone(),
+        };
+        if let Err(e) = tools.relays().send(&args[0], relayed) {
+            tools.reply(&format!("error: {}", e)).await?;
+            return Ok(());
+        }
+
+        tools
+            .reply(&format!(" Sent `{}` to {}", command, args[0]))
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relayed_part_keeps_quoting() {
+        assert_eq!(
+            relayed_part("!relay games sound whisper \"Game  Room\" abc"),
+            Some("sound whisper \"Game  Room\" abc")
+        );
+        assert_eq!(
+            relayed_part("  !relay\tgames   !sound play ABCD "),
+            Some("!sound play ABCD")
+        );
+        assert_eq!(relayed_part("!relay games"), None);
+        assert_eq!(relayed_part("!relay games   "), None);
+    }
+}
@@ -0,0 +1,21 @@
+use super::{Command, CommandContext, SessionTools};
+use crate::error::Error;
+
+#[derive(Default)]
+pub struct ResumeCommand;
+
+#[async_trait::async_trait]
+impl Command for ResumeCommand {
+    async fn execute(
+        &mut self,
+        tools: &dyn SessionTools,
+        _context: CommandContext,
+        _args: Vec<String>,
+    ) -> Result<(), Error> {
+        match tools.audio_control().set_paused(false).await {
+            Some(label) => tools.reply(&format!(" Resumed '{}'", label)).await?,
+            None => tools.reply(" Nothing is playing").await?,
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,21 @@
+use super::{Command, CommandContext, SessionTools};
+use crate::error::Error;
+
+#[derive(Default)]
+pub struct SkipCommand;
+
+#[async_trait::async_trait]
+impl Command for SkipCommand {
+    async fn execute(
+        &mut self,
+        tools: &dyn SessionTools,
+        _context: CommandContext,
+        _args: Vec<String>,
+    ) -> Result<(), Error> {
+        match tools.audio_control().skip().await {
+            Some(label) => tools.reply(&format!(" Skipped '{}'", label)).await?,
+            None => tools.reply(" Nothing is playing").await?,
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,8 @@
+use super::nowplaying::format_time;
 use super::{Command, CommandContext, SessionTools};
+use crate::audio::{PlayOutcome, whisper::WhisperTarget};
+use crate::config::Role;
+use crate::users::{Identity, identity};
 use std::collections::{HashMap, HashSet};
 
 #[derive(Default)]
@@ -29,6 +33,22 @@ impl SoundCommand {
             .collect()
     }
 
+    /// Splits the destination off `!sound whisper` arguments. Names with
+    /// spaces are given in double quotes; `None` if a quote is never closed.
+    fn split_destination(args: &[String]) -> Option<(String, &[String])> {
+        let first = args.first()?;
+        if !first.starts_with('"') {
+            return Some((first.clone(), &args[1..]));
+        }
+
+        let last = args
+            .iter()
+            .enumerate()
+            .position(|(i, arg)| arg.ends_with('"') && (i > 0 || arg.len() > 1))?;
+        let name = args[..=last].join(" ");
+        Some((name[1..name.len() - 1].to_string(), &args[last + 1..]))
+    }
+
     /// Check if a string represents an audio effect (with or without + prefix)
     fn is_audio_effect(&self, arg: &str) -> bool {
         let effect_name = arg.strip_prefix('+').unwrap_or(arg);
@@ -252,23 +272,18 @@ impl SoundCommand {
             eprintln!("Warning: Failed to clean up temp directory: {}", e);
         }
 
-        // Get the author name from the triggering user
-        let author = if let Some(user_id) = context.triggering_user_id {
-            if let Some(user_info) = tools.get_user_info(user_id) {
-                user_info
-                    .name
-                    .clone()
-                    .unwrap_or_else(|| "Unknown User".to_string())
-            } else {
-                "Unknown User".to_string()
-            }
-        } else {
-            "Bot".to_string()
-        };
-
         // Add to database
+        let author = Self::author_name(tools, context);
+        let author_identity = Self::author_identity(tools, context);
         manager
-            .add_sound(&code, author, Some(url.to_string()), start, length)
+            .add_sound(
+                &code,
+                author,
+                author_identity,
+                Some(url.to_string()),
+                start,
+                length,
+            )
             .await?;
 
         // Automatically play the newly created sound
@@ -283,6 +298,117 @@ impl SoundCommand {
         Ok(code)
     }
 
+    /// Create a sound from the last `seconds` of channel audio (optionally a single user)
+    async fn clip_audio(
+        &self,
+        tools: &dyn SessionTools,
+        context: &CommandContext,
+        session: Option<u32>,
+        seconds: f64,
+    ) -> Result<(String, f64), crate::error::Error> {
+        use tokio::fs;
+        use tokio::process::Command;
+
+        let manager = tools.get_sounds_manager().ok_or_else(|| {
+            crate::error::Error::InvalidInput("Sounds manager not available".to_string())
+        })?;
+        let recorder = tools.get_clip_recorder().ok_or_else(|| {
+            crate::error::Error::InvalidInput(
+                "Clip recording is disabled (set clip_buffer_seconds in config.yml)".to_string(),
+            )
+        })?;
+
+        let samples = recorder.clip(session, std::time::Duration::from_secs_f64(seconds));
+        if samples.is_empty() {
+            return Err(crate::error::Error::InvalidInput(
+                "No audio was heard in that time".to_string(),
+            ));
+        }
+        let length = samples.len() as f64 / 48000.0;
+
+        let code = self.generate_unique_code(tools).await?;
+
+        // Write the raw PCM out and let ffmpeg encode it
+        let temp_dir = std::env::temp_dir().join(format!("mumble_sound_{}", code));
+        fs::create_dir_all(&temp_dir)
+            .await
+            .map_err(|e| crate::error::Error::IOError(e))?;
+        let raw_path = temp_dir.join("clip.raw");
+        let raw: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        fs::write(&raw_path, raw)
+            .await
+            .map_err(|e| crate::error::Error::IOError(e))?;
+
+        let final_path = manager.sounds_dir().join(format!("{}.mp3", code));
+        let ffmpeg_output = Command::new("ffmpeg")
+            .arg("-f")
+            .arg("s16le")
+            .arg("-ar")
+            .arg("48000")
+            .arg("-ac")
+            .arg("1")
+            .arg("-i")
+            .arg(&raw_path)
+            .arg("-acodec")
+            .arg("mp3")
+            .arg("-y") // Overwrite output file
+            .arg(&final_path)
+            .kill_on_drop(true)
+            .output()
+            .await
+            .map_err(|e| crate::error::Error::IOError(e))?;
+
+        if let Err(e) = fs::remove_dir_all(&temp_dir).await {
+            log::warn!("Failed to clean up temp directory: {}", e);
+        }
+
+        if !ffmpeg_output.status.success() {
+            let stderr = String::from_utf8_lossy(&ffmpeg_output.stderr);
+            return Err(crate::error::Error::InvalidInput(format!(
+                "ffmpeg failed: {}",
+                stderr
+            )));
+        }
+
+        let author = Self::author_name(tools, context);
+        let author_identity = Self::author_identity(tools, context);
+        manager
+            .add_sound(&code, author, author_identity, None, 0.0, length)
+            .await?;
+
+        // Play it back so everyone hears what was captured
+        if let Ok(Some(sound_file)) = manager.get_sound(&code).await {
+            if let Some(file_path_str) = sound_file.path_str() {
+                let _ = tools.play_sound_with_code(file_path_str, &code).await;
+            }
+        }
+
+        Ok((code, length))
+    }
+
+    /// Name recorded as the author of sounds created by the triggering user
+    fn author_name(tools: &dyn SessionTools, context: &CommandContext) -> String {
+        if let Some(sender) = &context.relayed_sender {
+            sender.name.clone()
+        } else if let Some(user_id) = context.triggering_user_id {
+            if let Some(user_info) = tools.get_user_info(user_id) {
+                user_info
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| "Unknown User".to_string())
+            } else {
+                "Unknown User".to_string()
+            }
+        } else {
+            "Bot".to_string()
+        }
+    }
+
+    /// Identity recorded as the owner of sounds created by the triggering user
+    fn author_identity(tools: &dyn SessionTools, context: &CommandContext) -> Option<Identity> {
+        context.sender_identity(tools)
+    }
+
     async fn generate_unique_code(
         &self,
         tools: &dyn SessionTools,
@@ -359,7 +485,7 @@ impl Command for SoundCommand {
     async fn execute(
         &mut self,
         tools: &dyn SessionTools,
-        _context: CommandContext,
+        context: CommandContext,
         args: Vec<String>,
     ) -> Result<(), crate::error::Error> {
         if args.is_empty() {
@@ -373,8 +499,13 @@ impl Command for SoundCommand {
                  `!sound info <code>` - Show detailed information about a sound\n\
                  `!sound remove <code>` - Remove a sound from database and delete file from disk\n\
                  `!sound pull <URL> <start> <length>` - Extract audio from a video/audio URL\n\
+                 `!sound clip [user] <seconds>` - Save the last few seconds of channel audio (or one user) as a sound\n\
                  `!sound scan` - Scan for orphaned sound files\n\
-                 `!sound stopall` - Stop all currently playing audio streams\n\n\
+                 `!sound streams` - List playing and queued sounds with their IDs\n\
+                 `!sound stop <id|code> [fade seconds]` - Stop one stream, or every stream of a sound\n\
+                 `!sound volume <id> <0-200%>` - Change the volume of one playing stream\n\
+                 `!sound whisper <user|channel> [+subchannels] <code> [effects...]` - Play a sound only to one user or channel\n\
+                 `!sound stopall` - Stop all currently playing audio streams and clear the queue\n\n\
                 **Audio Effects:**\n\
                  `loud` - Increase volume (+6dB)\n\
                  `fast` - Increase speed/tempo (1.5x)\n\
@@ -406,7 +537,11 @@ impl Command for SoundCommand {
                  `!sound history` - Show recently played sounds\n\
                  `!sound info abc123` - Show information about sound 'abc123'\n\
                  `!sound remove abc123` - Remove sound 'abc123' completely (database + file)\n\
-                 `!sound pull https://youtube.com/watch?v=... 1:30 5` - Extract 5 seconds starting at 1:30").await?;
+                 `!sound pull https://youtube.com/watch?v=... 1:30 5` - Extract 5 seconds starting at 1:30\n\
+                 `!sound clip 10` - Save the last 10 seconds of channel audio\n\
+                 `!sound clip alice 5` - Save the last 5 seconds of what alice said\n\
+                 `!sound whisper alice abc123 +echo` - Play 'abc123' with echo to alice only\n\
+                 `!sound whisper Games/Lobby +subchannels abc123` - Play 'abc123' to a channel and everything below it").await?;
             return Ok(());
         }
 
@@ -701,7 +836,15 @@ impl Command for SoundCommand {
                         };
 
                         match result {
-                            Ok(()) => {
+                            Ok(PlayOutcome::Queued(id, position)) => {
+                                tools
+                                    .reply(&format!(
+                                        " Queued sound '{}' [#{}] at position {}",
+                                        display_code, id, position
+                                    ))
+                                    .await?;
+                            }
+                            Ok(PlayOutcome::Started(id)) => {
                                 let has_random_effects =
                                     effect_strings.is_empty() && !effects.is_empty();
                                 let message = if !is_random_sound {
@@ -741,7 +884,7 @@ impl Command for SoundCommand {
                                         )
                                     }
                                 };
-                                tools.reply(&message).await?;
+                                tools.reply(&format!("{} [#{}]", message, id)).await?;
                             }
                             Err(e) => {
                                 tools
@@ -865,7 +1008,7 @@ impl Command for SoundCommand {
                     };
 
                     if let Some(_manager) = tools.get_sounds_manager() {
-                        match self.pull_audio(tools, &_context, url, start, length).await {
+                        match self.pull_audio(tools, &context, url, start, length).await {
                             Ok(code) => {
                                 tools
                                     .reply(&format!(
@@ -883,12 +1026,98 @@ impl Command for SoundCommand {
                     }
                 }
             }
+            "clip" => {
+                if args.len() < 2 {
+                    tools.reply("Usage: !sound clip [user] <seconds>").await?;
+                    return Ok(());
+                }
+
+                let seconds = match args[args.len() - 1].parse::<f64>() {
+                    Ok(s) if s > 0.0 => s,
+                    _ => {
+                        tools
+                            .reply("Error: seconds must be a positive number")
+                            .await?;
+                        return Ok(());
+                    }
+                };
+
+                let capacity = match tools.get_clip_recorder() {
+                    Some(recorder) => recorder.capacity().as_secs_f64(),
+                    None => {
+                        tools
+                            .reply("Error: clip recording is disabled (set `clip_buffer_seconds` in config.yml)")
+                            .await?;
+                        return Ok(());
+                    }
+                };
+                if seconds > capacity {
+                    tools
+                        .reply(&format!(
+                            "Error: only the last {} seconds of audio are kept",
+                            capacity
+                        ))
+                        .await?;
+                    return Ok(());
+                }
+
+                // Everything between the subcommand and the duration is the user name
+                let session = if args.len() > 2 {
+                    let name = args[1..args.len() - 1].join(" ");
+                    match tools.find_user_by_name(&name) {
+                        Some(session) => Some(session),
+                        None => {
+                            tools
+                                .reply(&format!("Error: user '{}' is not connected", name))
+                                .await?;
+                            return Ok(());
+                        }
+                    }
+                } else {
+                    None
+                };
+
+                match self.clip_audio(tools, &context, session, seconds).await {
+                    Ok((code, length)) => {
+                        tools
+                            .reply(&format!(
+                                " Clipped {:.1} seconds and saved as sound '{}' ",
+                                length, code
+                            ))
+                            .await?;
+                    }
+                    Err(e) => {
+                        tools
+                            .reply(&format!(" Error clipping audio: {}", e))
+                            .await?;
+                    }
+                }
+            }
             "remove" => {
                 if args.len() < 2 {
                     tools.reply("Usage: !sound remove <code>").await?;
                 } else {
                     let code = &args[1];
                     if let Some(manager) = tools.get_sounds_manager() {
+                        let owner = manager
+                            .get_sound(code)
+                            .await
+                            .ok()
+                            .flatten()
+                            .and_then(|sound| sound.metadata)
+                            .and_then(|metadata| metadata.author_identity);
+                        let caller = Self::author_identity(tools, &context);
+                        let is_admin = tools.permissions().role_of(caller.as_ref()) >= Role::Admin;
+                        if !is_admin && !identity::can_modify(owner.as_deref(), caller.as_ref()) {
+                            tools
+                                .reply(&format!(
+                                    "error: Permission denied: only the author of '{}' or an admin can remove it",
+                                    code
+                                ))
+                                .await?;
+                            return Ok(());
+                        }
+
                         match manager.remove_sound(code).await {
                             Ok(()) => {
                                 tools
@@ -906,6 +1135,274 @@ impl Command for SoundCommand {
                     }
                 }
             }
+            "stop" => {
+                if args.len() < 2 {
+                    tools
+                        .reply("Usage: !sound stop <id|code> [fade seconds]")
+                        .await?;
+                    return Ok(());
+                }
+
+                let fade = match args.get(2).map(|arg| arg.parse::<f64>()) {
+                    None => None,
+                    Some(Ok(seconds)) if seconds.is_finite() && seconds >= 0.0 => {
+                        Some(std::time::Duration::from_secs_f64(seconds))
+                    }
+                    Some(_) => {
+                        tools
+                            .reply(" Fade must be a non-negative number of seconds")
+                            .await?;
+                        return Ok(());
+                    }
+                };
+
+                let control = tools.audio_control();
+                let target = args[1].trim_start_matches('#');
+                match target.parse::<u64>() {
+                    Ok(id) => {
+                        // Only playing streams can fade; queued ones are simply dropped
+                        let faded = match fade {
+                            Some(fade) => control.fade_out(id, fade).await,
+                            None => None,
+                        };
+                        let reply = match faded {
+                            Some(label) => format!(" Fading out '{}' [#{}]", label, id),
+                            None => match control.stop_stream(id).await {
+                                Some(label) => format!(" Stopped '{}' [#{}]", label, id),
+                                None => format!(" No stream with ID #{}", id),
+                            },
+                        };
+                        tools.reply(&reply).await?;
+                    }
+                    Err(_) => match control.stop_label(target, fade).await {
+                        0 => {
+                            tools
+                                .reply(&format!(" Sound '{}' is not playing", target))
+                                .await?
+                        }
+                        count => {
+                            let action = if fade.is_some() {
+                                "Fading out"
+                            } else {
+                                "Stopped"
+                            };
+                            tools
+                                .reply(&format!(" {} {} stream(s) of '{}'", action, count, target))
+                                .await?
+                        }
+                    },
+                }
+            }
+            "whisper" => {
+                const USAGE: &str = "Usage: !sound whisper <user|\"channel name\"> [+subchannels] <code> [effects...]";
+                // Mumble clients send typed quotes as HTML entities
+                let whisper_args: Vec<String> = args[1..]
+                    .iter()
+                    .map(|arg| arg.replace("&quot;", "\""))
+                    .collect();
+                let Some((destination, rest)) = Self::split_destination(&whisper_args) else {
+                    tools.reply(USAGE).await?;
+                    return Ok(());
+                };
+                let destination = destination.as_str();
+
+                let rest: Vec<&String> = rest.iter().collect();
+                let children = rest
+                    .iter()
+                    .any(|arg| arg.eq_ignore_ascii_case("+subchannels"));
+                let (sound_codes, effect_args): (Vec<&String>, Vec<&String>) = rest
+                    .into_iter()
+                    .filter(|arg| !arg.eq_ignore_ascii_case("+subchannels"))
+                    .partition(|arg| !self.is_audio_effect(arg));
+                let Some(code) = sound_codes.first() else {
+                    tools.reply(USAGE).await?;
+                    return Ok(());
+                };
+
+                let effect_strings: Vec<String> = effect_args
+                    .into_iter()
+                    .map(|s| s.strip_prefix('+').unwrap_or(s).to_string())
+                    .collect();
+                let effects = match crate::audio::effects::parse_effects(&effect_strings) {
+                    Ok(effects) => effects,
+                    Err(e) => {
+                        tools.reply(&format!(" {}", e)).await?;
+                        return Ok(());
+                    }
+                };
+
+                // Users take precedence over channels with the same name
+                let (target, description) =
+                    if let Some(session) = tools.find_user_by_name(destination) {
+                        if children {
+                            tools
+                                .reply(" `+subchannels` only applies to channels")
+                                .await?;
+                            return Ok(());
+                        }
+                        (WhisperTarget::User(session), destination.to_string())
+                    } else if let Some(channel_id) = tools.find_channel(destination) {
+                        let name = tools
+                            .get_channel_info(channel_id)
+                            .and_then(|channel| channel.name.clone())
+                            .unwrap_or_else(|| destination.to_string());
+                        let description = if children {
+                            format!("channel '{}' and its subchannels", name)
+                        } else {
+                            format!("channel '{}'", name)
+                        };
+                        (
+                            WhisperTarget::Channel {
+                                channel_id,
+                                children,
+                            },
+                            description,
+                        )
+                    } else {
+                        tools
+                            .reply(&format!(" No user or channel named '{}'", destination))
+                            .await?;
+                        return Ok(());
+                    };
+
+                let Some(manager) = tools.get_sounds_manager() else {
+                    tools.reply(" Sounds manager not available").await?;
+                    return Ok(());
+                };
+                let sound_file = match manager.get_sound(code).await {
+                    Ok(Some(sound_file)) if sound_file.exists() => sound_file,
+                    Ok(_) => {
+                        tools.reply(&format!(" Sound '{}' not found", code)).await?;
+                        return Ok(());
+                    }
+                    Err(e) => {
+                        tools
+                            .reply(&format!(" Error retrieving sound '{}': {}", code, e))
+                            .await?;
+                        return Ok(());
+                    }
+                };
+                let Some(file_path) = sound_file.path_str() else {
+                    tools
+                        .reply(&format!(" Invalid file path for sound '{}'", code))
+                        .await?;
+                    return Ok(());
+                };
+
+                match tools
+                    .audio_control()
+                    .play_whisper(file_path, &effects, code, target)
+                    .await
+                {
+                    Ok(id) => {
+                        tools
+                            .reply(&format!(
+                                " Whispering '{}' to {} [#{}]",
+                                code, description, id
+                            ))
+                            .await?
+                    }
+                    Err(e) => {
+                        tools
+                            .reply(&format!(" Failed to whisper sound '{}': {}", code, e))
+                            .await?
+                    }
+                }
+            }
+            "volume" => {
+                let id = args
+                    .get(1)
+                    .and_then(|arg| arg.trim_start_matches('#').parse().ok());
+                let gain = args
+                    .get(2)
+                    .and_then(|arg| arg.trim_end_matches('%').parse::<f32>().ok())
+                    .filter(|percent| (0.0..=200.0).contains(percent))
+                    .map(|percent| percent / 100.0);
+
+                let (Some(id), Some(gain)) = (id, gain) else {
+                    tools.reply("Usage: !sound volume <id> <0-200%>").await?;
+                    return Ok(());
+                };
+
+                let control = tools.audio_control();
+                if control.set_gain(id, gain).await {
+                    let label = control
+                        .stream_info(id)
+                        .await
+                        .map(|info| info.label)
+                        .unwrap_or_default();
+                    tools
+                        .reply(&format!(
+                            " Set volume of '{}' [#{}] to {:.0}%",
+                            label,
+                            id,
+                            gain * 100.0
+                        ))
+                        .await?;
+                } else {
+                    tools
+                        .reply(&format!(" No playing stream with ID #{}", id))
+                        .await?;
+                }
+            }
+            "streams" => {
+                let control = tools.audio_control();
+                let streams = control.streams().await;
+                let queued = control.queued().await;
+
+                if streams.is_empty() && queued.is_empty() {
+                    tools.reply(" Nothing is playing").await?;
+                    return Ok(());
+                }
+
+                let headers = &["ID", "Sound", "Elapsed", "Remaining", "Volume", "State"];
+                let mut rows = Vec::new();
+                for stream in &streams {
+                    let mut state = Vec::new();
+                    if stream.primary {
+                        state.push("primary");
+                    }
+                    if stream.whisper {
+                        state.push("whisper");
+                    }
+                    if stream.paused {
+                        state.push("paused");
+                    }
+                    if stream.fading {
+                        state.push("fading");
+                    }
+
+                    rows.push(vec![
+                        format!("#{}", stream.id),
+                        stream.label.clone(),
+                        format_time(stream.elapsed),
+                        stream
+                            .remaining()
+                            .map(format_time)
+                            .unwrap_or_else(|| "?".to_string()),
+                        format!("{:.0}%", stream.gain * 100.0),
+                        if state.is_empty() {
+                            "playing".to_string()
+                        } else {
+                            state.join(", ")
+                        },
+                    ]);
+                }
+                for (position, (id, label)) in queued.iter().enumerate() {
+                    rows.push(vec![
+                        format!("#{}", id),
+                        label.clone(),
+                        "-".to_string(),
+                        "-".to_string(),
+                        "-".to_string(),
+                        format!("queued ({})", position + 1),
+                    ]);
+                }
+
+                let mut response = String::from("<b>Audio streams</b><br/>");
+                response.push_str(&tools.create_html_table(headers, &rows));
+                tools.reply_html(&response).await?;
+            }
             "stopall" => {
                 tools.stop_all_streams().await?;
                 tools.reply(" Stopped all audio streams").await?;
@@ -945,4 +1442,25 @@ mod tests {
         assert!(SoundCommand::parse_timestamp("1:2:3:4").is_err());
         assert!(SoundCommand::parse_timestamp("1:invalid:30").is_err());
     }
+
+    #[test]
+    fn test_whisper_destination_parsing() {
+        let args = |line: &str| -> Vec<String> { line.split(' ').map(String::from).collect() };
+
+        let parsed = args("bob abc loud");
+        let (name, rest) = SoundCommand::split_destination(&parsed).unwrap();
+        assert_eq!(name, "bob");
+        assert_eq!(rest, &parsed[1..]);
+
+        let parsed = args("\"Game Room\" +subchannels abc");
+        let (name, rest) = SoundCommand::split_destination(&parsed).unwrap();
+        assert_eq!(name, "Game Room");
+        assert_eq!(rest, &parsed[2..]);
+
+        let parsed = args("\"bob\" abc");
+        assert_eq!(SoundCommand::split_destination(&parsed).unwrap().0, "bob");
+
+        assert!(SoundCommand::split_destination(&args("\"Game Room abc")).is_none());
+        assert!(SoundCommand::split_destination(&args("\" abc")).is_none());
+    }
 }
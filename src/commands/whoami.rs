@@ -0,0 +1,43 @@
+use super::{Command, CommandContext, SessionTools};
+use crate::error::Error;
+
+#[derive(Default)]
+pub struct WhoamiCommand;
+
+#[async_trait::async_trait]
+impl Command for WhoamiCommand {
+    async fn execute(
+        &mut self,
+        tools: &dyn SessionTools,
+        context: CommandContext,
+        _args: Vec<String>,
+    ) -> Result<(), Error> {
+        if context.triggering_user_id.is_none() && context.relayed_sender.is_none() {
+            tools.reply(" Unable to identify user").await?;
+            return Ok(());
+        }
+
+        let identity = context.sender_identity(tools);
+        let role = tools.permissions().role_of(identity.as_ref());
+        match identity {
+            Some(identity) => {
+                tools
+                    .reply(&format!(
+                        " You are `{}` with the {} role",
+                        identity.config_key(),
+                        role
+                    ))
+                    .await?
+            }
+            None => {
+                tools
+                    .reply(&format!(
+                        " You have no registration or client certificate, so you get the {} role",
+                        role
+                    ))
+                    .await?
+            }
+        }
+        Ok(())
+    }
+}
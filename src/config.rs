@@ -1,6 +1,7 @@
 use crate::error::Error;
+use chrono::NaiveTime;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::{collections::HashMap, path::PathBuf};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BotConfig {
@@ -8,12 +9,30 @@ pub struct BotConfig {
     pub bot: BotSettings,
     /// Server connection settings
     pub server: ServerSettings,
+    /// More servers to connect to from the same process
+    #[serde(default)]
+    pub servers: Vec<ServerEntry>,
     /// Audio and behavior settings
     pub behavior: BehaviorSettings,
+    /// Long-form music streaming
+    #[serde(default)]
+    pub music: MusicSettings,
+    /// Opus encoding of the bot's voice
+    #[serde(default)]
+    pub encoder: EncoderSettings,
+    /// The bot's registration, comment and avatar
+    #[serde(default)]
+    pub profile: ProfileSettings,
+    /// Who may run which commands
+    #[serde(default)]
+    pub permissions: PermissionSettings,
     /// Audio effect parameters
     pub audio_effects: AudioEffectSettings,
     /// Paths and directories
     pub paths: PathSettings,
+    /// The client certificate the bot identifies itself with
+    #[serde(default)]
+    pub certificate: CertificateSettings,
     /// External tools configuration
     pub external_tools: ExternalToolsSettings,
 }
@@ -30,12 +49,148 @@ pub struct BotSettings {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerSettings {
+    /// Name of the server in logs and `!relay` (default: the host)
+    #[serde(default)]
+    pub name: Option<String>,
     /// Server hostname or IP address
     pub host: String,
     /// Server port
     pub port: u16,
     /// Connection timeout in seconds
     pub timeout_seconds: u64,
+    /// Automatic reconnection when the connection drops
+    #[serde(default)]
+    pub reconnect: ReconnectSettings,
+    /// Send voice over the native UDP channel, falling back to the TCP tunnel
+    #[serde(default = "default_udp_voice")]
+    pub udp_voice: bool,
+    /// Channel name or path the bot moves to after connecting
+    #[serde(default)]
+    pub home_channel: Option<String>,
+    /// Seconds between pings sent to the server
+    #[serde(default = "default_ping_interval_seconds")]
+    pub ping_interval_seconds: u64,
+    /// Reconnect once this many pings in a row went unanswered (0 = never)
+    #[serde(default = "default_max_missed_pings")]
+    pub max_missed_pings: u32,
+    /// How the server's certificate is verified
+    #[serde(default)]
+    pub trust: TrustSettings,
+}
+
+/// Another server the bot connects to. It shares the database and sounds
+/// with the others; the sections given here override the top-level ones
+/// key by key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerEntry {
+    /// Name of the server in logs and `!relay`
+    pub name: String,
+    #[serde(default)]
+    pub server: serde_yaml::Value,
+    #[serde(default)]
+    pub bot: serde_yaml::Value,
+    #[serde(default)]
+    pub behavior: serde_yaml::Value,
+    #[serde(default)]
+    pub music: serde_yaml::Value,
+    #[serde(default)]
+    pub encoder: serde_yaml::Value,
+    #[serde(default)]
+    pub profile: serde_yaml::Value,
+    #[serde(default)]
+    pub permissions: serde_yaml::Value,
+}
+
+impl ServerEntry {
+    fn overrides(&self) -> [(&'static str, &serde_yaml::Value); 7] {
+        [
+            ("server", &self.server),
+            ("bot", &self.bot),
+            ("behavior", &self.behavior),
+            ("music", &self.music),
+            ("encoder", &self.encoder),
+            ("profile", &self.profile),
+            ("permissions", &self.permissions),
+        ]
+    }
+}
+
+/// Replaces the values in `base` with those in `overlay`, descending into mappings
+fn merge_yaml(base: &mut serde_yaml::Value, overlay: &serde_yaml::Value) {
+    match (base, overlay) {
+        (_, serde_yaml::Value::Null) => {}
+        (serde_yaml::Value::Mapping(base), serde_yaml::Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(key) {
+                    Some(existing) => merge_yaml(existing, value),
+                    None => {
+                        base.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay.clone(),
+    }
+}
+
+/// How server certificates not issued by a public CA are handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrustPolicy {
+    /// Only accept pinned fingerprints and certificates saved with `threebot trust`
+    Pinned,
+    /// Accept and save the first certificate a host presents, then require it
+    TrustOnFirstUse,
+    /// Reject them: only accept certificates issued by a public CA
+    Strict,
+    /// Ask on the terminal, rejecting the certificate when there is none
+    Interactive,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrustSettings {
+    pub policy: TrustPolicy,
+    /// SHA-256 certificate fingerprints accepted for each host
+    pub fingerprints: HashMap<String, Vec<String>>,
+}
+
+impl Default for TrustSettings {
+    fn default() -> Self {
+        Self {
+            policy: TrustPolicy::TrustOnFirstUse,
+            fingerprints: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReconnectSettings {
+    /// Whether to reconnect automatically after the connection is lost
+    pub enabled: bool,
+    /// Delay before the first reconnection attempt (in milliseconds)
+    pub initial_delay_ms: u64,
+    /// Upper bound for the delay between attempts (in milliseconds)
+    pub max_delay_ms: u64,
+    /// Factor the delay grows by after each failed attempt
+    pub backoff_multiplier: f64,
+    /// Random jitter applied to each delay (0.0-1.0, fraction of the delay)
+    pub jitter: f64,
+    /// Give up after this many consecutive failed attempts (0 = retry forever)
+    pub max_attempts: u32,
+}
+
+impl Default for ReconnectSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            initial_delay_ms: 1000,
+            max_delay_ms: 60_000,
+            backoff_multiplier: 2.0,
+            jitter: 0.2,
+            max_attempts: 0,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -44,6 +199,24 @@ pub struct BehaviorSettings {
     pub auto_greetings: GreetingMode,
     /// Farewell behavior when users leave
     pub auto_farewells: FarewellMode,
+    /// When users get greeted: on connecting, on entering the bot's channel, or both
+    #[serde(default = "default_greeting_trigger")]
+    pub greeting_trigger: GreetingTrigger,
+    /// When users get a farewell: on disconnecting, on leaving the bot's channel, or both
+    #[serde(default = "default_farewell_trigger")]
+    pub farewell_trigger: FarewellTrigger,
+    /// Minimum seconds between automatic greetings for the same user
+    #[serde(default = "default_greeting_cooldown_seconds")]
+    pub greeting_cooldown_seconds: u64,
+    /// Minimum seconds between automatic farewells for the same user
+    #[serde(default = "default_farewell_cooldown_seconds")]
+    pub farewell_cooldown_seconds: u64,
+    /// Users connecting again within this many seconds of disconnecting are not greeted
+    #[serde(default = "default_reconnect_grace_seconds")]
+    pub reconnect_grace_seconds: u64,
+    /// Daily period (local time) without automatic greetings and farewells
+    #[serde(default)]
+    pub quiet_hours: Option<QuietHours>,
     /// Whether to respond to commands in private messages
     pub allow_private_commands: bool,
     /// Global volume multiplier for all outgoing audio (1.0 = normal, 0.5 = half volume, 2.0 = double volume)
@@ -56,6 +229,139 @@ pub struct BehaviorSettings {
     pub random_modifier_rounds: u32,
     /// Audio buffer size in samples (larger = more latency but smoother on slow machines)
     pub audio_buffer_size: usize,
+    /// Seconds of incoming channel audio kept for `!sound clip` (0 disables recording)
+    #[serde(default = "default_clip_buffer_seconds")]
+    pub clip_buffer_seconds: u32,
+    /// Whether new sounds mix over what is playing or wait their turn
+    #[serde(default = "default_playback_mode")]
+    pub playback_mode: PlaybackMode,
+    /// Maximum number of sounds waiting in the play queue
+    #[serde(default = "default_max_queue_length")]
+    pub max_queue_length: usize,
+    /// Register right-click menu actions (play greeting, random sound, ...) with the
+    /// server. Stock Murmur ignores entries registered by clients.
+    #[serde(default = "default_context_actions")]
+    pub context_actions: bool,
+    /// Sound code played in the bot's channel before it shuts down
+    #[serde(default)]
+    pub shutdown_sound: Option<String>,
+    /// Maximum seconds spent on a graceful shutdown, including the shutdown sound
+    #[serde(default = "default_shutdown_timeout_seconds")]
+    pub shutdown_timeout_seconds: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MusicSettings {
+    /// Volume of music streams relative to sound clips (1.0 = normal)
+    pub volume: f32,
+    /// Music volume multiplier while sound clips are playing (1.0 disables ducking)
+    pub duck_volume: f32,
+    /// Maximum number of tracks waiting in the music queue
+    pub max_queue_length: usize,
+    /// Maximum number of tracks taken from a single playlist
+    pub max_playlist_tracks: usize,
+    /// Announce each track's title and duration in the channel
+    pub announce_tracks: bool,
+}
+
+impl Default for MusicSettings {
+    fn default() -> Self {
+        Self {
+            volume: 0.6,
+            duck_volume: 0.3,
+            max_queue_length: 100,
+            max_playlist_tracks: 50,
+            announce_tracks: true,
+        }
+    }
+}
+
+/// Opus tuning, trading latency for quality
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OpusApplication {
+    /// Tuned for speech
+    Voip,
+    /// Tuned for music and general audio
+    Audio,
+    /// Lowest latency, at the cost of quality
+    LowDelay,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EncoderSettings {
+    /// Target bitrate in bits per second, lowered automatically to fit the
+    /// server's bandwidth limit
+    pub bitrate: u32,
+    /// Audio per packet in milliseconds (10, 20, 40 or 60); longer frames
+    /// save bandwidth but add latency
+    pub frame_ms: u64,
+    pub application: OpusApplication,
+}
+
+impl Default for EncoderSettings {
+    fn default() -> Self {
+        Self {
+            bitrate: 96_000,
+            frame_ms: 20,
+            application: OpusApplication::Voip,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProfileSettings {
+    /// Register the bot's certificate with the server after connecting
+    pub register: bool,
+    /// Keep the bot's comment set to a summary of its commands and sounds
+    pub comment: bool,
+    /// HTML shown above the generated summary in the comment
+    pub comment_intro: Option<String>,
+    /// PNG or JPEG image to use as the bot's avatar
+    pub avatar: Option<String>,
+}
+
+impl Default for ProfileSettings {
+    fn default() -> Self {
+        Self {
+            register: false,
+            comment: true,
+            comment_intro: None,
+            avatar: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PermissionSettings {
+    /// Role of users without an entry below, including users without an identity
+    pub default_role: Role,
+    /// Roles by identity, written as `user:<registration id>` or `cert:<certificate hash>`
+    pub users: HashMap<String, Role>,
+    /// Roles by Mumble group on the root channel (needs the bot to have Write permission there)
+    pub groups: HashMap<String, Role>,
+    /// Minimum role per command or `command subcommand`; anything unlisted needs `user`.
+    /// Configured entries are merged into the defaults rather than replacing them.
+    pub commands: HashMap<String, Role>,
+}
+
+impl Default for PermissionSettings {
+    fn default() -> Self {
+        Self {
+            default_role: Role::User,
+            users: HashMap::new(),
+            groups: HashMap::new(),
+            commands: HashMap::from([
+                ("sound stopall".to_string(), Role::Trusted),
+                ("music stop".to_string(), Role::Trusted),
+                ("profile".to_string(), Role::Admin),
+            ]),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -101,6 +407,58 @@ pub struct AudioEffectSettings {
     pub normalization_mode: InputNormalizationMode,
 }
 
+fn default_udp_voice() -> bool {
+    true
+}
+
+fn default_ping_interval_seconds() -> u64 {
+    5
+}
+
+fn default_max_missed_pings() -> u32 {
+    4
+}
+
+fn default_greeting_trigger() -> GreetingTrigger {
+    GreetingTrigger::ChannelEnter
+}
+
+fn default_farewell_trigger() -> FarewellTrigger {
+    FarewellTrigger::ChannelLeave
+}
+
+fn default_greeting_cooldown_seconds() -> u64 {
+    300
+}
+
+fn default_farewell_cooldown_seconds() -> u64 {
+    300
+}
+
+fn default_reconnect_grace_seconds() -> u64 {
+    60
+}
+
+fn default_clip_buffer_seconds() -> u32 {
+    30
+}
+
+fn default_playback_mode() -> PlaybackMode {
+    PlaybackMode::Overlap
+}
+
+fn default_max_queue_length() -> usize {
+    50
+}
+
+fn default_context_actions() -> bool {
+    false
+}
+
+fn default_shutdown_timeout_seconds() -> u64 {
+    10
+}
+
 fn default_loudnorm_target_lufs() -> f32 {
     -18.0
 }
@@ -130,6 +488,15 @@ pub enum InputNormalizationMode {
     BoostOnly,
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaybackMode {
+    /// Every sound starts immediately and mixes over anything already playing
+    Overlap,
+    /// Sounds play one after another from a FIFO queue
+    Queue,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum GreetingMode {
@@ -152,6 +519,116 @@ pub enum FarewellMode {
     None,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GreetingTrigger {
+    /// Greet users when they connect to the server, whichever channel they land in
+    ServerJoin,
+    /// Greet users when they enter the bot's channel, by connecting or moving in
+    ChannelEnter,
+    /// Greet users on either, once per arrival
+    Both,
+}
+
+impl GreetingTrigger {
+    /// Whether an arrival gets a greeting, given whether the user just connected
+    /// and whether they just entered the bot's channel
+    pub fn fires(self, joined_server: bool, entered_channel: bool) -> bool {
+        match self {
+            GreetingTrigger::ServerJoin => joined_server,
+            GreetingTrigger::ChannelEnter => entered_channel,
+            GreetingTrigger::Both => joined_server || entered_channel,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FarewellTrigger {
+    /// Say farewell when users disconnect, whichever channel they were in
+    ServerLeave,
+    /// Say farewell when users leave the bot's channel, by disconnecting or moving out
+    ChannelLeave,
+    /// Say farewell on either, once per departure
+    Both,
+}
+
+impl FarewellTrigger {
+    /// Whether a departure gets a farewell, given whether the user disconnected
+    /// and whether they were in the bot's channel before leaving
+    pub fn fires(self, left_server: bool, left_channel: bool) -> bool {
+        match self {
+            FarewellTrigger::ServerLeave => left_server,
+            FarewellTrigger::ChannelLeave => left_channel,
+            FarewellTrigger::Both => left_server || left_channel,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuietHours {
+    /// Local time quiet hours begin, as "HH:MM"
+    #[serde(with = "clock_time")]
+    pub start: NaiveTime,
+    /// Local time quiet hours end, as "HH:MM"; earlier than `start` to span midnight
+    #[serde(with = "clock_time")]
+    pub end: NaiveTime,
+}
+
+impl QuietHours {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            time >= self.start && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+/// (De)serializes times of day as "HH:MM"
+mod clock_time {
+    use chrono::NaiveTime;
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    const FORMAT: &str = "%H:%M";
+
+    pub fn serialize<S: Serializer>(time: &NaiveTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&time.format(FORMAT).to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        NaiveTime::parse_from_str(&value, FORMAT).map_err(|e| {
+            D::Error::custom(format!("invalid time '{}' (expected HH:MM): {}", value, e))
+        })
+    }
+}
+
+/// Permission levels, from least to most privileged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// May not run any commands
+    Banned,
+    /// May run everyday commands and remove what they created
+    User,
+    /// May also run disruptive commands such as `!sound stopall`
+    Trusted,
+    /// May run everything and remove anyone's sounds and aliases
+    Admin,
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Role::Banned => "banned",
+            Role::User => "user",
+            Role::Trusted => "trusted",
+            Role::Admin => "admin",
+        })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PathSettings {
     /// Directory to store bot data (sounds, database, etc.)
@@ -164,6 +641,24 @@ pub struct PathSettings {
     pub trusted_certs_dir: Option<String>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CertificateSettings {
+    /// Days a generated certificate stays valid
+    pub validity_days: u32,
+    /// Warn when the certificate expires within this many days (0 = never)
+    pub expiry_warning_days: u32,
+}
+
+impl Default for CertificateSettings {
+    fn default() -> Self {
+        Self {
+            validity_days: 3650,
+            expiry_warning_days: 30,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExternalToolsSettings {
     /// Path to cookies file for yt-dlp (for authentication and age-restricted content)
@@ -202,20 +697,44 @@ impl Default for BotConfig {
                 verbose: false,
             },
             server: ServerSettings {
+                name: None,
                 host: "localhost".to_string(),
                 port: 64738,
                 timeout_seconds: 10,
+                reconnect: ReconnectSettings::default(),
+                udp_voice: true,
+                home_channel: None,
+                ping_interval_seconds: default_ping_interval_seconds(),
+                max_missed_pings: default_max_missed_pings(),
+                trust: TrustSettings::default(),
             },
+            servers: Vec::new(),
             behavior: BehaviorSettings {
                 auto_greetings: GreetingMode::All,
                 auto_farewells: FarewellMode::Custom,
+                greeting_trigger: default_greeting_trigger(),
+                farewell_trigger: default_farewell_trigger(),
+                greeting_cooldown_seconds: default_greeting_cooldown_seconds(),
+                farewell_cooldown_seconds: default_farewell_cooldown_seconds(),
+                reconnect_grace_seconds: default_reconnect_grace_seconds(),
+                quiet_hours: None,
                 allow_private_commands: true,
                 volume: 1.0,
                 random_modifiers_enabled: true,
                 random_modifier_chance: 0.05, // 5% chance per round
                 random_modifier_rounds: 2,
                 audio_buffer_size: 8192, // Default buffer size (good balance of latency vs performance)
+                clip_buffer_seconds: 30,
+                playback_mode: PlaybackMode::Overlap,
+                max_queue_length: 50,
+                context_actions: false,
+                shutdown_sound: None,
+                shutdown_timeout_seconds: default_shutdown_timeout_seconds(),
             },
+            music: MusicSettings::default(),
+            encoder: EncoderSettings::default(),
+            profile: ProfileSettings::default(),
+            permissions: PermissionSettings::default(),
             audio_effects: AudioEffectSettings {
                 loud_boost_db: 6.0,
                 fast_speed_multiplier: 1.5,
@@ -241,6 +760,7 @@ impl Default for BotConfig {
                 key_file: None,
                 trusted_certs_dir: None,
             },
+            certificate: CertificateSettings::default(),
             external_tools: ExternalToolsSettings {
                 ytdlp_cookies_file: None,
             },
@@ -298,12 +818,61 @@ bot:
 
 # Mumble server connection settings  
 server:
+  # Name of this server in logs and `!relay` (null = the host)
+  name: null
   # Hostname or IP address of the Mumble server
   host: "localhost"
   # Port number for the Mumble server (default: 64738)
   port: 64738
   # Connection timeout in seconds
   timeout_seconds: 10
+  # Automatic reconnection with exponential backoff when the connection drops
+  reconnect:
+    enabled: true
+    # Delay before the first attempt, growing by backoff_multiplier up to max_delay_ms
+    initial_delay_ms: 1000
+    max_delay_ms: 60000
+    backoff_multiplier: 2.0
+    # Random jitter applied to each delay (0.2 = +/-20%)
+    jitter: 0.2
+    # Give up after this many consecutive failures (0 = retry forever)
+    max_attempts: 0
+  # Send voice over the native UDP channel (falls back to the TCP tunnel if unreachable)
+  udp_voice: true
+  # Channel the bot joins after connecting and after every reconnect, by name or
+  # path such as "Lobby" or "Games/Lobby" (null = stay where the server puts it)
+  home_channel: null
+  # Seconds between pings used to measure latency and detect a dead connection
+  ping_interval_seconds: 5
+  # Reconnect after this many pings in a row go unanswered (0 = never)
+  max_missed_pings: 4
+  # Server certificate verification. Certificates issued by a public CA are always
+  # checked against the CA (chain, hostname, expiry) and rejected if invalid; the
+  # policy decides about self-signed certificates and ones from an unknown issuer
+  trust:
+    # Options: "trust_on_first_use" (save the first certificate a host presents and
+    # reject any other later), "pinned" (only the fingerprints below and certificates
+    # saved with `threebot trust <host>`), "strict" (only certificates issued by a
+    # public CA), "interactive" (ask on the terminal; rejects when run without one)
+    policy: trust_on_first_use
+    # SHA-256 fingerprints accepted per host, as printed by `threebot trust <host>`
+    fingerprints: {}
+    #   "mumble.example.com":
+    #     - "AB:CD:..."
+
+# More servers for the same bot to connect to. They all share the database and the
+# sounds; each entry may override keys of the server, bot, behavior, music, encoder,
+# profile and permissions sections above. Run a command on another server with
+# `!relay <name> <command>`
+servers: []
+#  - name: "games"
+#    server:
+#      host: "games.example.com"
+#      home_channel: "Lobby"
+#    bot:
+#      username: "Threebot-Games"
+#    behavior:
+#      auto_greetings: none
 
 # Bot behavior settings
 behavior:
@@ -313,6 +882,23 @@ behavior:
   # Farewell sounds when users leave  
   # Options: "all" (custom + random fallback), "custom" (only user-set), "none" (silent)
   auto_farewells: custom
+  # When users get greeted
+  # Options: "server_join" (on connecting, in any channel), "channel_enter" (on
+  # entering the bot's channel, by connecting or moving in), "both"
+  greeting_trigger: channel_enter
+  # When users get a farewell
+  # Options: "server_leave" (on disconnecting, from any channel), "channel_leave" (on
+  # leaving the bot's channel, by disconnecting or moving out), "both"
+  farewell_trigger: channel_leave
+  # Minimum seconds between automatic greetings (and farewells) for the same user
+  greeting_cooldown_seconds: 300
+  farewell_cooldown_seconds: 300
+  # Users reconnecting within this many seconds of dropping out are not greeted again
+  reconnect_grace_seconds: 60
+  # Local time period without automatic greetings and farewells (null = never quiet)
+  quiet_hours: null
+  #   start: "23:00"
+  #   end: "07:00"
   # Allow users to send commands via private messages
   allow_private_commands: true
   # Global volume multiplier for all outgoing audio (1.0 = normal, 0.5 = half volume, 2.0 = double volume)
@@ -326,6 +912,81 @@ behavior:
   # Audio buffer size in bytes (larger = more latency but smoother on slow machines)
   # Default: 8192, Low-end machines: 16384 or 32768, High-end machines: 4096
   audio_buffer_size: 8192
+  # Seconds of channel audio kept in memory for `!sound clip` (0 disables recording)
+  clip_buffer_seconds: 30
+  # How sounds share the audio output
+  # Options: "overlap" (mix on top of whatever is playing), "queue" (play one at a time, in order)
+  playback_mode: overlap
+  # Maximum number of sounds waiting in the queue (queue mode only)
+  max_queue_length: 50
+  # Add bot actions to the right-click menu of users and channels
+  # (play greeting, play random sound, stop all sounds, show sound history).
+  # Stock Murmur ignores menu entries registered by clients, and actions are only
+  # run when the server says who picked them, so this needs a server that does
+  context_actions: false
+  # Sound code to play before shutting down on SIGINT/SIGTERM (null = none)
+  shutdown_sound: null
+  # Maximum seconds to spend shutting down gracefully, including the shutdown sound
+  shutdown_timeout_seconds: 10
+
+# Long-form music streaming (`!music play <url>`)
+music:
+  # Volume of music relative to sound clips (1.0 = same loudness)
+  volume: 0.6
+  # Music volume multiplier while sound clips play over it (1.0 = no ducking)
+  duck_volume: 0.3
+  # Maximum number of tracks waiting in the music queue
+  max_queue_length: 100
+  # Maximum number of tracks taken from a single playlist
+  max_playlist_tracks: 50
+  # Post the title and duration of each track in the channel when it starts
+  announce_tracks: true
+
+# Opus encoding of the bot's voice. The bitrate (and if needed the frame size)
+# is lowered automatically when the server's bandwidth limit is smaller.
+encoder:
+  # Target bitrate in bits per second (lowered to fit the server's bandwidth limit and
+  # so a frame fits in one voice packet, e.g. at most 134400 with 60ms frames)
+  bitrate: 96000
+  # Audio per packet in milliseconds: 10, 20, 40 or 60 (longer saves bandwidth, adds latency)
+  frame_ms: 20
+  # Options: "voip" (speech), "audio" (music and general sound), "low_delay"
+  application: voip
+
+# The bot's own profile, also managed at runtime with `!profile` (admins only)
+profile:
+  # Register the bot's certificate with the server after connecting (the server must
+  # allow self-registration); registered users keep their comment and avatar
+  register: false
+  # Keep the bot's comment set to a summary of its commands and sound count
+  comment: true
+  # HTML shown above the summary in the comment (null = none)
+  comment_intro: null
+  # PNG or JPEG image to use as the bot's avatar (null = none)
+  avatar: null
+
+# Who may run which commands. Roles from least to most privileged:
+# banned (no commands), user, trusted, admin (can also remove anyone's sounds and aliases)
+permissions:
+  # Role of everyone not listed below
+  default_role: user
+  # Roles by identity: "user:<registration id>" for registered users, or
+  # "cert:<certificate hash>" for everyone else (see `!whoami`). Registration IDs
+  # are per server: "user:" entries here only apply to this server, so list other
+  # servers' users under their entry in `servers`
+  users: {}
+  #   "user:1": admin
+  #   "cert:0123456789abcdef0123456789abcdef01234567": banned
+  # Roles for members of Mumble groups on the root channel. Reading groups
+  # needs the bot to have Write permission on the root channel.
+  groups: {}
+  #   admin: admin
+  # Minimum role for a command or "command subcommand" (everything else needs user).
+  # Entries are added to the ones below; list a command to change its role.
+  commands:
+    "sound stopall": trusted
+    "music stop": trusted
+    profile: admin
 
 # Audio effect parameters
 audio_effects:
@@ -376,6 +1037,15 @@ paths:
   # Directory for trusted server certificates (if null, uses data_dir/trusted_certificates)
   trusted_certs_dir: null
 
+# Client certificate, generated on first run. Servers recognize registered users by
+# certificate, so replacing it (`threebot cert rotate`) loses the bot's registration;
+# `threebot cert import <file.p12>` takes over an identity exported from Mumble instead
+certificate:
+  # Days a generated certificate stays valid
+  validity_days: 3650
+  # Warn when the certificate expires within this many days (0 = never)
+  expiry_warning_days: 30
+
 # External tools configuration
 external_tools:
   # Path to cookies file for yt-dlp (for authentication and age-restricted content)
@@ -433,6 +1103,77 @@ external_tools:
         }
     }
 
+    /// Get the trusted server certificates directory, using default if not specified
+    pub fn get_trusted_certs_dir(&self) -> PathBuf {
+        if let Some(trusted_certs_dir) = &self.paths.trusted_certs_dir {
+            PathBuf::from(trusted_certs_dir)
+        } else {
+            self.get_data_dir().join("trusted_certificates")
+        }
+    }
+
+    /// Name of the server in `server`
+    pub fn server_name(&self) -> String {
+        self.server
+            .name
+            .clone()
+            .unwrap_or_else(|| self.server.host.clone())
+    }
+
+    /// The configuration of every server to connect to: the top-level one
+    /// first, then each entry of `servers` applied on top of it
+    pub fn server_configs(&self) -> Result<Vec<BotConfig>, Error> {
+        let mut primary = self.clone();
+        primary.servers.clear();
+        primary.server.name = Some(self.server_name());
+
+        let base = serde_yaml::to_value(&primary)
+            .map_err(|e| Error::ConfigError(format!("Failed to read configuration: {}", e)))?;
+
+        let mut configs = vec![primary];
+        for entry in &self.servers {
+            if entry.name.is_empty() || entry.name.contains(char::is_whitespace) {
+                return Err(Error::ConfigError(format!(
+                    "Server name '{}' must be one word",
+                    entry.name
+                )));
+            }
+            if configs
+                .iter()
+                .any(|config| config.server_name().eq_ignore_ascii_case(&entry.name))
+            {
+                return Err(Error::ConfigError(format!(
+                    "More than one server is named '{}'",
+                    entry.name
+                )));
+            }
+
+            let mut value = base.clone();
+            // Registration IDs only mean something on the server they came from
+            if let Some(users) = value
+                .get_mut("permissions")
+                .and_then(|permissions| permissions.get_mut("users"))
+                .and_then(serde_yaml::Value::as_mapping_mut)
+            {
+                users.retain(|key, _| !key.as_str().is_some_and(|key| key.starts_with("user:")));
+            }
+            for (section, overrides) in entry.overrides() {
+                if let Some(section) = value.get_mut(section) {
+                    merge_yaml(section, overrides);
+                }
+            }
+            let mut config: BotConfig = serde_yaml::from_value(value).map_err(|e| {
+                Error::ConfigError(format!(
+                    "Invalid settings for server '{}': {}",
+                    entry.name, e
+                ))
+            })?;
+            config.server.name = Some(entry.name.clone());
+            configs.push(config);
+        }
+        Ok(configs)
+    }
+
     /// Get the configuration file path for the bot
     pub fn get_config_path() -> PathBuf {
         let home_dir = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
@@ -468,6 +1209,55 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_greeting_and_farewell_triggers() {
+        // Connecting straight into the bot's channel
+        assert!(GreetingTrigger::ServerJoin.fires(true, true));
+        assert!(GreetingTrigger::ChannelEnter.fires(true, true));
+        // Connecting into another channel
+        assert!(GreetingTrigger::ServerJoin.fires(true, false));
+        assert!(!GreetingTrigger::ChannelEnter.fires(true, false));
+        // Moving into the bot's channel
+        assert!(!GreetingTrigger::ServerJoin.fires(false, true));
+        assert!(GreetingTrigger::Both.fires(false, true));
+
+        assert!(!FarewellTrigger::ChannelLeave.fires(true, false));
+        assert!(FarewellTrigger::ChannelLeave.fires(false, true));
+        assert!(!FarewellTrigger::ServerLeave.fires(false, true));
+        assert!(FarewellTrigger::Both.fires(true, false));
+
+        let behavior: BehaviorSettings = serde_yaml::from_str(
+            "auto_greetings: all\nauto_farewells: custom\ngreeting_trigger: both\n\
+             allow_private_commands: true\nvolume: 1.0\nrandom_modifiers_enabled: false\n\
+             random_modifier_chance: 0.0\nrandom_modifier_rounds: 0\naudio_buffer_size: 8192\n",
+        )
+        .unwrap();
+        assert_eq!(behavior.greeting_trigger, GreetingTrigger::Both);
+        assert_eq!(behavior.farewell_trigger, FarewellTrigger::ChannelLeave);
+    }
+
+    #[test]
+    fn test_quiet_hours() {
+        let at = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+        let night: QuietHours = serde_yaml::from_str("start: \"23:00\"\nend: \"07:30\"").unwrap();
+        assert!(night.contains(at(23, 0)));
+        assert!(night.contains(at(3, 0)));
+        assert!(!night.contains(at(7, 30)));
+        assert!(!night.contains(at(12, 0)));
+
+        let lunch = QuietHours {
+            start: at(12, 0),
+            end: at(13, 0),
+        };
+        assert!(lunch.contains(at(12, 30)));
+        assert!(!lunch.contains(at(13, 0)));
+        let yaml = serde_yaml::to_string(&lunch).unwrap();
+        assert!(yaml.contains("12:00"));
+        assert_eq!(serde_yaml::from_str::<QuietHours>(&yaml).unwrap(), lunch);
+
+        assert!(serde_yaml::from_str::<QuietHours>("start: \"25:00\"\nend: \"07:00\"").is_err());
+    }
+
     #[test]
     fn test_config_serialization() {
         let config = BotConfig::default();
@@ -527,4 +1317,63 @@ mod tests {
         external_tools.ytdlp_cookies_file = None;
         assert!(external_tools.get_ytdlp_cookies_path().is_none());
     }
+
+    #[test]
+    fn test_server_entries_override_top_level_settings() {
+        let mut config = BotConfig::default();
+        config.server.home_channel = Some("Lobby".to_string());
+        config.permissions.users = HashMap::from([
+            ("user:1".to_string(), Role::Admin),
+            ("cert:abcd".to_string(), Role::Trusted),
+        ]);
+        config.servers = serde_yaml::from_str(
+            r#"
+- name: games
+  server:
+    host: "games.example.com"
+    trust:
+      policy: strict
+  bot:
+    username: "Threebot-Games"
+  behavior:
+    auto_greetings: none
+  permissions:
+    users:
+      "user:2": trusted
+"#,
+        )
+        .unwrap();
+
+        let configs = config.server_configs().unwrap();
+        assert_eq!(configs.len(), 2);
+        assert_eq!(configs[0].server_name(), "localhost");
+
+        let games = &configs[1];
+        assert_eq!(games.server_name(), "games");
+        assert_eq!(games.server.host, "games.example.com");
+        assert_eq!(games.server.port, 64738);
+        assert_eq!(games.server.home_channel, Some("Lobby".to_string()));
+        assert_eq!(games.server.trust.policy, TrustPolicy::Strict);
+        assert_eq!(games.bot.username, "Threebot-Games");
+        assert!(matches!(games.behavior.auto_greetings, GreetingMode::None));
+        assert!(matches!(
+            games.behavior.auto_farewells,
+            FarewellMode::Custom
+        ));
+
+        // Registered users from the top level belong to the first server only
+        assert_eq!(configs[0].permissions.users.len(), 2);
+        assert_eq!(
+            games.permissions.users,
+            HashMap::from([
+                ("cert:abcd".to_string(), Role::Trusted),
+                ("user:2".to_string(), Role::Trusted),
+            ])
+        );
+
+        config.servers[0].name = "LOCALHOST".to_string();
+        assert!(config.server_configs().is_err());
+        config.servers[0].name = "two words".to_string();
+        assert!(config.server_configs().is_err());
+    }
 }
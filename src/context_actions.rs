@@ -0,0 +1,90 @@
+// Right-click menu entries the bot registers with the server
+
+use crate::protos::generated::Mumble::{self, context_action_modify::Context};
+
+/// What a context action does when a user picks it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActionKind {
+    /// Runs the custom greeting of the user the action was used on
+    Greeting,
+    /// Runs a bot command
+    Command(&'static str),
+}
+
+pub struct ContextAction {
+    /// Identifier sent back by the server when the action is used
+    pub action: &'static str,
+    /// Menu text shown to users
+    pub text: &'static str,
+    /// `Context` bit flags saying where the entry is shown
+    pub context: u32,
+    pub kind: ActionKind,
+}
+
+pub const ACTIONS: &[ContextAction] = &[
+    ContextAction {
+        action: "threebot_greeting",
+        text: "Play greeting for this user",
+        context: Context::User as u32,
+        kind: ActionKind::Greeting,
+    },
+    ContextAction {
+        action: "threebot_random_sound",
+        text: "Play random sound",
+        context: Context::Server as u32 | Context::Channel as u32 | Context::User as u32,
+        kind: ActionKind::Command("sound play"),
+    },
+    ContextAction {
+        action: "threebot_stop_all",
+        text: "Stop all sounds",
+        context: Context::Server as u32 | Context::Channel as u32 | Context::User as u32,
+        kind: ActionKind::Command("sound stopall"),
+    },
+    ContextAction {
+        action: "threebot_history",
+        text: "Show sound history",
+        context: Context::Server as u32 | Context::Channel as u32,
+        kind: ActionKind::Command("sound history"),
+    },
+];
+
+/// Looks up a registered action by the identifier the server sends back
+pub fn find(action: &str) -> Option<&'static ContextAction> {
+    ACTIONS.iter().find(|entry| entry.action == action)
+}
+
+/// ContextActionModify messages adding every action
+pub fn registrations() -> Vec<Mumble::ContextActionModify> {
+    ACTIONS
+        .iter()
+        .map(|entry| {
+            let mut modify = Mumble::ContextActionModify::new();
+            modify.set_action(entry.action.to_string());
+            modify.set_text(entry.text.to_string());
+            modify.set_context(entry.context);
+            modify.set_operation(Mumble::context_action_modify::Operation::Add);
+            modify
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protobuf::Message;
+
+    #[test]
+    fn test_registrations_match_actions() {
+        let registrations = registrations();
+        assert_eq!(registrations.len(), ACTIONS.len());
+        for (modify, entry) in registrations.iter().zip(ACTIONS) {
+            assert_eq!(modify.action(), entry.action);
+            assert_eq!(modify.text(), entry.text);
+            assert!(modify.is_initialized());
+            assert_eq!(find(entry.action).map(|found| found.kind), Some(entry.kind));
+        }
+
+        assert_eq!(registrations[0].context(), 0x04);
+        assert!(find("unknown").is_none());
+    }
+}
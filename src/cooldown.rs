@@ -0,0 +1,210 @@
+// Keeps automatic greetings and farewells from flooding the channel
+
+use std::collections::HashMap;
+
+use chrono::NaiveTime;
+use tokio::time::{Duration, Instant};
+
+use crate::config::{BehaviorSettings, QuietHours};
+
+/// Why an automatic greeting or farewell was held back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Suppressed {
+    /// The user got one recently
+    Cooldown,
+    /// The user dropped out moments ago and is only reconnecting
+    Reconnect,
+    /// It is currently quiet hours
+    QuietHours,
+}
+
+/// Per-identity timestamps of recent greetings, farewells and disconnects
+pub struct GreetingCooldowns {
+    greeting_cooldown: Duration,
+    farewell_cooldown: Duration,
+    reconnect_grace: Duration,
+    quiet_hours: Option<QuietHours>,
+    last_greeting: HashMap<String, Instant>,
+    last_farewell: HashMap<String, Instant>,
+    last_disconnect: HashMap<String, Instant>,
+}
+
+impl GreetingCooldowns {
+    pub fn new(settings: &BehaviorSettings) -> Self {
+        Self {
+            greeting_cooldown: Duration::from_secs(settings.greeting_cooldown_seconds),
+            farewell_cooldown: Duration::from_secs(settings.farewell_cooldown_seconds),
+            reconnect_grace: Duration::from_secs(settings.reconnect_grace_seconds),
+            quiet_hours: settings.quiet_hours,
+            last_greeting: HashMap::new(),
+            last_farewell: HashMap::new(),
+            last_disconnect: HashMap::new(),
+        }
+    }
+
+    /// Checks whether a user may be greeted, recording the greeting if so.
+    /// `joined_server` tells a fresh connection apart from a channel move.
+    pub fn check_greeting(
+        &mut self,
+        key: &str,
+        joined_server: bool,
+        now: Instant,
+        time_of_day: NaiveTime,
+    ) -> Result<(), Suppressed> {
+        self.prune(now);
+        if joined_server && Self::within(&self.last_disconnect, key, now, self.reconnect_grace) {
+            return Err(Suppressed::Reconnect);
+        }
+        self.check(key, now, time_of_day, true)
+    }
+
+    /// Checks whether a user may get a farewell, recording the farewell if so
+    pub fn check_farewell(
+        &mut self,
+        key: &str,
+        now: Instant,
+        time_of_day: NaiveTime,
+    ) -> Result<(), Suppressed> {
+        self.prune(now);
+        self.check(key, now, time_of_day, false)
+    }
+
+    /// Remembers when a user disconnected, to recognize them reconnecting
+    pub fn user_disconnected(&mut self, key: &str, now: Instant) {
+        self.last_disconnect.insert(key.to_string(), now);
+    }
+
+    fn check(
+        &mut self,
+        key: &str,
+        now: Instant,
+        time_of_day: NaiveTime,
+        greeting: bool,
+    ) -> Result<(), Suppressed> {
+        if self
+            .quiet_hours
+            .is_some_and(|quiet| quiet.contains(time_of_day))
+        {
+            return Err(Suppressed::QuietHours);
+        }
+
+        let (history, cooldown) = if greeting {
+            (&mut self.last_greeting, self.greeting_cooldown)
+        } else {
+            (&mut self.last_farewell, self.farewell_cooldown)
+        };
+        if Self::within(history, key, now, cooldown) {
+            return Err(Suppressed::Cooldown);
+        }
+        history.insert(key.to_string(), now);
+        Ok(())
+    }
+
+    fn within(
+        history: &HashMap<String, Instant>,
+        key: &str,
+        now: Instant,
+        window: Duration,
+    ) -> bool {
+        history
+            .get(key)
+            .is_some_and(|at| now.saturating_duration_since(*at) < window)
+    }
+
+    /// Forgets entries too old to matter
+    fn prune(&mut self, now: Instant) {
+        let keep = |window: Duration| {
+            move |_: &String, at: &mut Instant| now.saturating_duration_since(*at) < window
+        };
+        self.last_greeting.retain(keep(self.greeting_cooldown));
+        self.last_farewell.retain(keep(self.farewell_cooldown));
+        self.last_disconnect.retain(keep(self.reconnect_grace));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BotConfig;
+
+    fn cooldowns(quiet_hours: Option<QuietHours>) -> GreetingCooldowns {
+        let mut settings = BotConfig::default().behavior;
+        settings.greeting_cooldown_seconds = 300;
+        settings.farewell_cooldown_seconds = 60;
+        settings.reconnect_grace_seconds = 30;
+        settings.quiet_hours = quiet_hours;
+        GreetingCooldowns::new(&settings)
+    }
+
+    fn noon() -> NaiveTime {
+        NaiveTime::from_hms_opt(12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_greeting_cooldown_per_user() {
+        let mut cooldowns = cooldowns(None);
+        let start = Instant::now();
+        assert_eq!(
+            cooldowns.check_greeting("cert:aa", true, start, noon()),
+            Ok(())
+        );
+        assert_eq!(
+            cooldowns.check_greeting("cert:aa", false, start + Duration::from_secs(100), noon()),
+            Err(Suppressed::Cooldown)
+        );
+        assert_eq!(
+            cooldowns.check_greeting("cert:bb", true, start, noon()),
+            Ok(())
+        );
+        assert_eq!(
+            cooldowns.check_greeting("cert:aa", false, start + Duration::from_secs(300), noon()),
+            Ok(())
+        );
+
+        // Farewells have their own cooldown
+        assert_eq!(cooldowns.check_farewell("cert:aa", start, noon()), Ok(()));
+        assert_eq!(
+            cooldowns.check_farewell("cert:aa", start + Duration::from_secs(59), noon()),
+            Err(Suppressed::Cooldown)
+        );
+    }
+
+    #[test]
+    fn test_reconnect_is_not_greeted() {
+        let mut cooldowns = cooldowns(None);
+        let start = Instant::now();
+        cooldowns.user_disconnected("user:4", start);
+        assert_eq!(
+            cooldowns.check_greeting("user:4", true, start + Duration::from_secs(10), noon()),
+            Err(Suppressed::Reconnect)
+        );
+        assert_eq!(
+            cooldowns.check_greeting("user:4", true, start + Duration::from_secs(31), noon()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_quiet_hours() {
+        let quiet = QuietHours {
+            start: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+        };
+        let mut cooldowns = cooldowns(Some(quiet));
+        let now = Instant::now();
+        let night = NaiveTime::from_hms_opt(23, 15, 0).unwrap();
+        assert_eq!(
+            cooldowns.check_greeting("user:1", true, now, night),
+            Err(Suppressed::QuietHours)
+        );
+        assert_eq!(
+            cooldowns.check_farewell("user:1", now, night),
+            Err(Suppressed::QuietHours)
+        );
+        // Nothing was recorded during quiet hours
+        assert_eq!(
+            cooldowns.check_greeting("user:1", true, now, noon()),
+            Ok(())
+        );
+    }
+}
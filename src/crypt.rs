@@ -0,0 +1,455 @@
+// OCB2-AES128 voice encryption, compatible with Mumble's CryptStateOCB2
+
+use aes::{
+    Aes128,
+    cipher::{BlockDecrypt, BlockEncrypt, KeyInit, generic_array::GenericArray},
+};
+use std::time::Instant;
+
+pub const KEY_SIZE: usize = 16;
+pub const BLOCK_SIZE: usize = 16;
+/// Bytes prepended to every encrypted packet (IV byte + truncated tag)
+pub const HEADER_SIZE: usize = 4;
+
+type Block = [u8; BLOCK_SIZE];
+
+/// Packet statistics kept by the decryptor, reported in pings
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CryptStats {
+    pub good: u32,
+    pub late: u32,
+    pub lost: u32,
+    pub resync: u32,
+}
+
+/// Encryption state for the UDP voice channel.
+///
+/// Each side holds its own encrypt IV (the client nonce for us) and tracks the
+/// peer's IV for decryption, tolerating reordered and lost packets.
+pub struct CryptState {
+    cipher: Aes128,
+    encrypt_iv: Block,
+    decrypt_iv: Block,
+    decrypt_history: [u8; 256],
+    pub stats: CryptStats,
+    last_good: Option<Instant>,
+}
+
+impl CryptState {
+    /// Creates a state from the values of a full `CryptSetup` message
+    pub fn new(key: &[u8], encrypt_iv: &[u8], decrypt_iv: &[u8]) -> Option<Self> {
+        let key: [u8; KEY_SIZE] = key.try_into().ok()?;
+        let encrypt_iv: Block = encrypt_iv.try_into().ok()?;
+        let decrypt_iv: Block = decrypt_iv.try_into().ok()?;
+
+        Some(Self {
+            cipher: Aes128::new(&GenericArray::from(key)),
+            encrypt_iv,
+            decrypt_iv,
+            decrypt_history: [0; 256],
+            stats: CryptStats::default(),
+            last_good: None,
+        })
+    }
+
+    pub fn encrypt_iv(&self) -> &Block {
+        &self.encrypt_iv
+    }
+
+    /// Replaces the decrypt IV after the server answered a resync request
+    pub fn set_decrypt_iv(&mut self, iv: &[u8]) -> bool {
+        match iv.try_into() {
+            Ok(iv) => {
+                self.decrypt_iv = iv;
+                self.stats.resync += 1;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Time of the last packet that decrypted successfully
+    pub fn last_good(&self) -> Option<Instant> {
+        self.last_good
+    }
+
+    /// Encrypts a plaintext voice packet, returning header + ciphertext
+    pub fn encrypt(&mut self, plain: &[u8]) -> Vec<u8> {
+        increment_iv(&mut self.encrypt_iv);
+
+        let mut out = vec![0u8; HEADER_SIZE + plain.len()];
+        let tag = self.ocb_encrypt(plain, &mut out[HEADER_SIZE..], &self.encrypt_iv.clone());
+
+        out[0] = self.encrypt_iv[0];
+        out[1..4].copy_from_slice(&tag[..3]);
+        out
+    }
+
+    /// Decrypts a packet produced by the peer's `encrypt`.
+    ///
+    /// Returns `None` for forged, replayed or hopelessly out-of-order packets.
+    pub fn decrypt(&mut self, source: &[u8]) -> Option<Vec<u8>> {
+        if source.len() < HEADER_SIZE {
+            return None;
+        }
+
+        let saved_iv = self.decrypt_iv;
+        let iv_byte = source[0];
+        let mut restore = false;
+        let mut late = 0;
+        let mut lost: i64 = 0;
+
+        if self.decrypt_iv[0].wrapping_add(1) == iv_byte {
+            // In order as expected
+            if iv_byte > self.decrypt_iv[0] {
+                self.decrypt_iv[0] = iv_byte;
+            } else if iv_byte < self.decrypt_iv[0] {
+                self.decrypt_iv[0] = iv_byte;
+                increment_iv_from(&mut self.decrypt_iv, 1);
+            } else {
+                return None;
+            }
+        } else {
+            // Either out of order or a repeat
+            let mut diff = iv_byte as i32 - self.decrypt_iv[0] as i32;
+            if diff > 128 {
+                diff -= 256;
+            } else if diff < -128 {
+                diff += 256;
+            }
+
+            if iv_byte < self.decrypt_iv[0] && diff > -30 && diff < 0 {
+                // Late packet, no wraparound
+                late = 1;
+                lost = -1;
+                self.decrypt_iv[0] = iv_byte;
+                restore = true;
+            } else if iv_byte > self.decrypt_iv[0] && diff > -30 && diff < 0 {
+                // Late packet from before the last wraparound
+                late = 1;
+                lost = -1;
+                self.decrypt_iv[0] = iv_byte;
+                decrement_iv_from(&mut self.decrypt_iv, 1);
+                restore = true;
+            } else if iv_byte > self.decrypt_iv[0] && diff > 0 {
+                // Lost a few packets
+                lost = (iv_byte - self.decrypt_iv[0] - 1) as i64;
+                self.decrypt_iv[0] = iv_byte;
+            } else if iv_byte < self.decrypt_iv[0] && diff > 0 {
+                // Lost a few packets and wrapped around
+                lost = 256 - self.decrypt_iv[0] as i64 + iv_byte as i64 - 1;
+                self.decrypt_iv[0] = iv_byte;
+                increment_iv_from(&mut self.decrypt_iv, 1);
+            } else {
+                return None;
+            }
+
+            if self.decrypt_history[self.decrypt_iv[0] as usize] == self.decrypt_iv[1] {
+                self.decrypt_iv = saved_iv;
+                return None;
+            }
+        }
+
+        let mut plain = vec![0u8; source.len() - HEADER_SIZE];
+        let iv = self.decrypt_iv;
+        let (ok, tag) = self.ocb_decrypt(&source[HEADER_SIZE..], &mut plain, &iv);
+
+        if !ok || tag[..3] != source[1..4] {
+            self.decrypt_iv = saved_iv;
+            return None;
+        }
+
+        self.decrypt_history[self.decrypt_iv[0] as usize] = self.decrypt_iv[1];
+
+        if restore {
+            self.decrypt_iv = saved_iv;
+        }
+
+        self.stats.good += 1;
+        self.stats.late += late;
+        self.stats.lost = (self.stats.lost as i64 + lost).max(0) as u32;
+        self.last_good = Some(Instant::now());
+
+        Some(plain)
+    }
+
+    fn aes_encrypt(&self, block: &Block) -> Block {
+        let mut b = GenericArray::from(*block);
+        self.cipher.encrypt_block(&mut b);
+        b.into()
+    }
+
+    fn aes_decrypt(&self, block: &Block) -> Block {
+        let mut b = GenericArray::from(*block);
+        self.cipher.decrypt_block(&mut b);
+        b.into()
+    }
+
+    fn ocb_encrypt(&self, plain: &[u8], encrypted: &mut [u8], nonce: &Block) -> Block {
+        let mut delta = self.aes_encrypt(nonce);
+        let mut checksum = [0u8; BLOCK_SIZE];
+        let mut offset = 0;
+        let mut len = plain.len();
+
+        while len > BLOCK_SIZE {
+            let block: Block = plain[offset..offset + BLOCK_SIZE].try_into().unwrap();
+
+            // Countermeasure against the XEX* attack (eprint 2019/311, section 9):
+            // a second-to-last block of all zeroes except the last byte is
+            // altered slightly instead of being encrypted as-is
+            let flip_a_bit =
+                len - BLOCK_SIZE <= BLOCK_SIZE && block[..BLOCK_SIZE - 1].iter().all(|&b| b == 0);
+
+            s2(&mut delta);
+            let mut tmp = xor(&delta, &block);
+            if flip_a_bit {
+                tmp[0] ^= 1;
+            }
+            let tmp = self.aes_encrypt(&tmp);
+            encrypted[offset..offset + BLOCK_SIZE].copy_from_slice(&xor(&delta, &tmp));
+            checksum = xor(&checksum, &block);
+            if flip_a_bit {
+                checksum[0] ^= 1;
+            }
+
+            len -= BLOCK_SIZE;
+            offset += BLOCK_SIZE;
+        }
+
+        s2(&mut delta);
+        let mut tmp = [0u8; BLOCK_SIZE];
+        tmp[8..].copy_from_slice(&((len * 8) as u64).to_be_bytes());
+        let pad = self.aes_encrypt(&xor(&tmp, &delta));
+
+        let mut tmp = pad;
+        tmp[..len].copy_from_slice(&plain[offset..]);
+        checksum = xor(&checksum, &tmp);
+        let tmp = xor(&pad, &tmp);
+        encrypted[offset..].copy_from_slice(&tmp[..len]);
+
+        s3(&mut delta);
+        self.aes_encrypt(&xor(&delta, &checksum))
+    }
+
+    fn ocb_decrypt(&self, encrypted: &[u8], plain: &mut [u8], nonce: &Block) -> (bool, Block) {
+        let mut delta = self.aes_encrypt(nonce);
+        let mut checksum = [0u8; BLOCK_SIZE];
+        let mut offset = 0;
+        let mut len = encrypted.len();
+        let mut success = true;
+
+        while len > BLOCK_SIZE {
+            let block: Block = encrypted[offset..offset + BLOCK_SIZE].try_into().unwrap();
+
+            s2(&mut delta);
+            let tmp = self.aes_decrypt(&xor(&delta, &block));
+            let decrypted = xor(&delta, &tmp);
+            plain[offset..offset + BLOCK_SIZE].copy_from_slice(&decrypted);
+            checksum = xor(&checksum, &decrypted);
+
+            len -= BLOCK_SIZE;
+            offset += BLOCK_SIZE;
+        }
+
+        s2(&mut delta);
+        let mut tmp = [0u8; BLOCK_SIZE];
+        tmp[8..].copy_from_slice(&((len * 8) as u64).to_be_bytes());
+        let pad = self.aes_encrypt(&xor(&tmp, &delta));
+
+        let mut tmp = [0u8; BLOCK_SIZE];
+        tmp[..len].copy_from_slice(&encrypted[offset..]);
+        let tmp = xor(&tmp, &pad);
+        checksum = xor(&checksum, &tmp);
+        plain[offset..].copy_from_slice(&tmp[..len]);
+
+        // Countermeasure against the XEX* attack: a forged last block would
+        // decrypt to delta ^ len(128)
+        if tmp[..BLOCK_SIZE - 1] == delta[..BLOCK_SIZE - 1] {
+            success = false;
+        }
+
+        s3(&mut delta);
+        (success, self.aes_encrypt(&xor(&delta, &checksum)))
+    }
+}
+
+fn xor(a: &Block, b: &Block) -> Block {
+    let mut out = [0u8; BLOCK_SIZE];
+    for i in 0..BLOCK_SIZE {
+        out[i] = a[i] ^ b[i];
+    }
+    out
+}
+
+/// Multiplies the block by 2 in GF(2^128)
+fn s2(block: &mut Block) {
+    let carry = block[0] >> 7;
+    for i in 0..BLOCK_SIZE - 1 {
+        block[i] = (block[i] << 1) | (block[i + 1] >> 7);
+    }
+    block[BLOCK_SIZE - 1] = (block[BLOCK_SIZE - 1] << 1) ^ (carry * 0x87);
+}
+
+/// Multiplies the block by 3 in GF(2^128)
+fn s3(block: &mut Block) {
+    let original = *block;
+    s2(block);
+    *block = xor(block, &original);
+}
+
+fn increment_iv(iv: &mut Block) {
+    increment_iv_from(iv, 0);
+}
+
+fn increment_iv_from(iv: &mut Block, start: usize) {
+    for byte in iv.iter_mut().skip(start) {
+        *byte = byte.wrapping_add(1);
+        if *byte != 0 {
+            break;
+        }
+    }
+}
+
+fn decrement_iv_from(iv: &mut Block, start: usize) {
+    for byte in iv.iter_mut().skip(start) {
+        let was_nonzero = *byte != 0;
+        *byte = byte.wrapping_sub(1);
+        if was_nonzero {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+    /// Client and server states sharing a key, with nonces mirrored
+    fn pair() -> (CryptState, CryptState) {
+        let client_nonce = [0x11; 16];
+        let server_nonce = [0x22; 16];
+        (
+            CryptState::new(&KEY, &client_nonce, &server_nonce).unwrap(),
+            CryptState::new(&KEY, &server_nonce, &client_nonce).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_ocb2_vectors() {
+        let cs = CryptState::new(&KEY, &[0; 16], &[0; 16]).unwrap();
+        let nonce = KEY;
+
+        let tag = cs.ocb_encrypt(&[], &mut [], &nonce);
+        assert_eq!(
+            tag,
+            [
+                0xbf, 0x31, 0x08, 0x13, 0x07, 0x73, 0xad, 0x5e, 0xc7, 0x0e, 0xc6, 0x9e, 0x78, 0x75,
+                0xa7, 0xb0
+            ]
+        );
+
+        let source: Vec<u8> = (0..40).collect();
+        let mut crypted = vec![0u8; 40];
+        let tag = cs.ocb_encrypt(&source, &mut crypted, &nonce);
+        assert_eq!(
+            tag,
+            [
+                0x9d, 0xb0, 0xcd, 0xf8, 0x80, 0xf7, 0x3e, 0x3e, 0x10, 0xd4, 0xeb, 0x32, 0x17, 0x76,
+                0x66, 0x88
+            ]
+        );
+        assert_eq!(
+            crypted,
+            [
+                0xf7, 0x5d, 0x6b, 0xc8, 0xb4, 0xdc, 0x8d, 0x66, 0xb8, 0x36, 0xa2, 0xb0, 0x8b, 0x32,
+                0xa6, 0x36, 0x9f, 0x1c, 0xd3, 0xc5, 0x22, 0x8d, 0x79, 0xfd, 0x6c, 0x26, 0x7f, 0x5f,
+                0x6a, 0xa7, 0xb2, 0x31, 0xc7, 0xdf, 0xb9, 0xd5, 0x99, 0x51, 0xae, 0x9c
+            ]
+        );
+
+        let mut decrypted = vec![0u8; 40];
+        let (ok, dtag) = cs.ocb_decrypt(&crypted, &mut decrypted, &nonce);
+        assert!(ok);
+        assert_eq!(dtag, tag);
+        assert_eq!(decrypted, source);
+    }
+
+    #[test]
+    fn test_roundtrip_all_lengths() {
+        let (mut client, mut server) = pair();
+        for len in 0..128 {
+            let plain: Vec<u8> = (0..len as u8).collect();
+            let packet = client.encrypt(&plain);
+            assert_eq!(packet.len(), plain.len() + HEADER_SIZE);
+            assert_eq!(server.decrypt(&packet).unwrap(), plain);
+        }
+        assert_eq!(server.stats.good, 128);
+        assert_eq!(server.stats.lost, 0);
+    }
+
+    #[test]
+    fn test_tampered_packet_is_rejected() {
+        let (mut client, mut server) = pair();
+        let mut packet = client.encrypt(b"hello voice");
+        let last = packet.len() - 1;
+        packet[last] ^= 0x01;
+        assert!(server.decrypt(&packet).is_none());
+
+        // The IV is restored, so the next genuine packet still decrypts
+        let packet = client.encrypt(b"next");
+        assert_eq!(server.decrypt(&packet).unwrap(), b"next");
+    }
+
+    #[test]
+    fn test_replay_late_and_lost() {
+        let (mut client, mut server) = pair();
+        let packets: Vec<Vec<u8>> = (0..5).map(|i| client.encrypt(&[i; 20])).collect();
+
+        assert!(server.decrypt(&packets[0]).is_some());
+        // Skip 1 and 2, accept 3
+        assert!(server.decrypt(&packets[3]).is_some());
+        assert_eq!(server.stats.lost, 2);
+        // 1 arrives late
+        assert_eq!(server.decrypt(&packets[1]).unwrap(), vec![1; 20]);
+        assert_eq!(server.stats.late, 1);
+        assert_eq!(server.stats.lost, 1);
+        // Replays are refused
+        assert!(server.decrypt(&packets[1]).is_none());
+        assert!(server.decrypt(&packets[3]).is_none());
+        assert!(server.decrypt(&packets[4]).is_some());
+    }
+
+    #[test]
+    fn test_iv_wraparound() {
+        let (mut client, mut server) = pair();
+        for i in 0..600u32 {
+            let plain = i.to_le_bytes();
+            let packet = client.encrypt(&plain);
+            assert_eq!(server.decrypt(&packet).unwrap(), plain);
+        }
+        assert_eq!(client.encrypt_iv(), &server.decrypt_iv);
+    }
+
+    #[test]
+    fn test_resync_restores_decryption() {
+        let (mut client, mut server) = pair();
+        // Desynchronize the server's view of the client IV
+        server.set_decrypt_iv(&[0x55; 16]);
+        let packet = client.encrypt(b"lost");
+        assert!(server.decrypt(&packet).is_none());
+
+        // The resync hands the server our current encrypt IV
+        let iv = *client.encrypt_iv();
+        server.set_decrypt_iv(&iv);
+        let packet = client.encrypt(b"found");
+        assert_eq!(server.decrypt(&packet).unwrap(), b"found");
+    }
+
+    #[test]
+    fn test_invalid_setup_is_rejected() {
+        assert!(CryptState::new(&KEY[..8], &[0; 16], &[0; 16]).is_none());
+        assert!(CryptState::new(&KEY, &[0; 15], &[0; 16]).is_none());
+    }
+}
@@ -12,6 +12,7 @@ pub struct DatabaseManager {
 
 impl DatabaseManager {
     /// Creates a new database manager with a connection pool to the SQLite database
+    /// and brings its schema up to date by applying any pending migrations
     pub async fn new(database_path: &Path) -> Result<Self, Error> {
         if let Some(parent) = database_path.parent() {
             std::fs::create_dir_all(parent).map_err(|e| {
@@ -26,13 +27,16 @@ impl DatabaseManager {
             .map_err(|e| Error::DatabaseError(format!("Failed to create database pool: {}", e)))?;
 
         {
-            let conn = pool
+            let mut conn = pool
                 .get()
                 .map_err(|e| Error::DatabaseError(format!("Failed to open database: {}", e)))?;
             conn.execute_batch("PRAGMA journal_mode=WAL; PRAGMA foreign_keys=ON;")
                 .map_err(|e| {
                     Error::DatabaseError(format!("Failed to configure database pragmas: {}", e))
                 })?;
+
+            let version = super::migrations::run_migrations(&mut conn)?;
+            debug!("Database schema is at version {}", version);
         }
 
         let manager = Self { pool };
@@ -43,4 +47,28 @@ impl DatabaseManager {
     pub fn pool_clone(&self) -> DbPool {
         self.pool.clone()
     }
+
+    /// Copies the write-ahead log back into the database file and truncates it,
+    /// leaving a single self-contained file behind on shutdown
+    pub async fn checkpoint(&self) -> Result<(), Error> {
+        let pool = self.pool.clone();
+        tokio::task::spawn_blocking(move || -> Result<(), Error> {
+            let conn = pool
+                .get()
+                .map_err(|e| Error::DatabaseError(format!("Failed to open database: {}", e)))?;
+            let busy: i64 = conn
+                .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| row.get(0))
+                .map_err(|e| {
+                    Error::DatabaseError(format!("Failed to checkpoint database: {}", e))
+                })?;
+            if busy != 0 {
+                return Err(Error::DatabaseError(
+                    "Database was busy, checkpoint incomplete".to_string(),
+                ));
+            }
+            Ok(())
+        })
+        .await
+        .map_err(|e| Error::DatabaseError(format!("Checkpoint task failed: {}", e)))?
+    }
 }
@@ -6,4 +6,6 @@ pub struct Model {
     pub author: String,
     pub created_at: DateTime<Utc>,
     pub commands: String,
+    /// Identity key of the author, if they had one
+    pub author_identity: Option<String>,
 }
@@ -8,4 +8,6 @@ pub struct Model {
     pub source_url: Option<String>,
     pub start_time: String,
     pub length: f64,
+    /// Identity key of the author, `None` for sounds added by the bot itself
+    pub author_identity: Option<String>,
 }
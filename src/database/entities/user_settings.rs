@@ -1,7 +1,7 @@
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Model {
     pub id: String,
-    pub username: String,
+    pub identity: String,
     pub setting_type: String,
     pub setting_value: String,
     pub created_at: chrono::DateTime<chrono::Utc>,
@@ -0,0 +1,293 @@
+use crate::error::Error;
+use chrono::Utc;
+use rusqlite::{Connection, OptionalExtension, params};
+
+/// A single schema migration, applied once and recorded in `schema_version`
+pub struct Migration {
+    /// Monotonically increasing schema version this migration upgrades to
+    pub version: u32,
+    /// Short human-readable name, stored alongside the version
+    pub name: &'static str,
+    /// SQL batch executed to perform the migration
+    pub sql: &'static str,
+}
+
+/// All known migrations, in the order they must be applied.
+///
+/// Never edit a migration that has shipped; append a new one instead so that
+/// existing databases are upgraded in place.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial_schema",
+        // IF NOT EXISTS lets databases created before versioning adopt the schema as-is
+        sql: "
+        CREATE TABLE IF NOT EXISTS sounds (
+            code TEXT PRIMARY KEY NOT NULL,
+            author TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            source_url TEXT,
+            start_time TEXT NOT NULL,
+            length REAL NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS aliases (
+            name TEXT PRIMARY KEY NOT NULL,
+            author TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            commands TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS user_settings (
+            id TEXT PRIMARY KEY NOT NULL,
+            username TEXT NOT NULL,
+            setting_type TEXT NOT NULL,
+            setting_value TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_user_settings_username ON user_settings (username);
+    ",
+    },
+    Migration {
+        version: 2,
+        name: "user_identities",
+        // Rows keyed by display name move to a "name:" legacy identity, which the
+        // first identified user connecting under that name claims
+        sql: "
+        CREATE TABLE users (
+            identity TEXT PRIMARY KEY NOT NULL,
+            name TEXT NOT NULL,
+            first_seen TEXT NOT NULL,
+            last_seen TEXT NOT NULL
+        );
+
+        CREATE INDEX idx_users_name ON users (name);
+
+        ALTER TABLE user_settings RENAME COLUMN username TO identity;
+        UPDATE user_settings SET identity = 'name:' || identity, id = 'name:' || id;
+        DROP INDEX IF EXISTS idx_user_settings_username;
+        CREATE INDEX idx_user_settings_identity ON user_settings (identity);
+
+        ALTER TABLE sounds ADD COLUMN author_identity TEXT;
+        UPDATE sounds SET author_identity = 'name:' || author;
+
+        ALTER TABLE aliases ADD COLUMN author_identity TEXT;
+        UPDATE aliases SET author_identity = 'name:' || author;
+    ",
+    },
+    Migration {
+        version: 3,
+        name: "per_server_registrations",
+        // Registration IDs became per server. Migrations don't know the server
+        // names, so rows still keyed `user:<id>` are moved to the first server
+        // on the next start, which is recorded here so it only happens once
+        sql: "
+        CREATE TABLE registration_scope (
+            server TEXT NOT NULL,
+            scoped_at TEXT NOT NULL
+        );
+    ",
+    },
+];
+
+/// Returns the latest schema version known to this build
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+/// Returns the schema version currently recorded in the database (0 if none)
+pub fn current_version(conn: &Connection) -> Result<u32, Error> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY NOT NULL,
+            name TEXT NOT NULL,
+            applied_at TEXT NOT NULL
+        );",
+    )
+    .map_err(|e| Error::DatabaseError(format!("Failed to create schema_version table: {}", e)))?;
+
+    let version: Option<u32> = conn
+        .query_row("SELECT MAX(version) FROM schema_version", [], |row| {
+            row.get(0)
+        })
+        .optional()
+        .map_err(|e| Error::DatabaseError(format!("Failed to read schema version: {}", e)))?
+        .flatten();
+
+    Ok(version.unwrap_or(0))
+}
+
+/// Applies all pending migrations, each inside its own transaction.
+///
+/// Returns the schema version the database is at afterwards.
+pub fn run_migrations(conn: &mut Connection) -> Result<u32, Error> {
+    let current = current_version(conn)?;
+    let latest = latest_version();
+
+    if current > latest {
+        return Err(Error::DatabaseError(format!(
+            "Database schema version {} is newer than the latest version supported by this build ({})",
+            current, latest
+        )));
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        info!(
+            "Applying database migration {} ({})",
+            migration.version, migration.name
+        );
+
+        let tx = conn.transaction().map_err(|e| {
+            Error::DatabaseError(format!("Failed to begin migration transaction: {}", e))
+        })?;
+
+        tx.execute_batch(migration.sql).map_err(|e| {
+            Error::DatabaseError(format!(
+                "Migration {} ({}) failed: {}",
+                migration.version, migration.name, e
+            ))
+        })?;
+
+        tx.execute(
+            "INSERT INTO schema_version (version, name, applied_at) VALUES (?1, ?2, ?3)",
+            params![migration.version, migration.name, Utc::now().to_rfc3339()],
+        )
+        .map_err(|e| {
+            Error::DatabaseError(format!(
+                "Failed to record migration {}: {}",
+                migration.version, e
+            ))
+        })?;
+
+        tx.commit().map_err(|e| {
+            Error::DatabaseError(format!(
+                "Failed to commit migration {}: {}",
+                migration.version, e
+            ))
+        })?;
+    }
+
+    Ok(latest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table_exists(conn: &Connection, table: &str) -> bool {
+        conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            params![table],
+            |row| row.get::<_, i64>(0),
+        )
+        .unwrap()
+            > 0
+    }
+
+    #[test]
+    fn test_migrations_are_ordered() {
+        for pair in MIGRATIONS.windows(2) {
+            assert!(pair[0].version < pair[1].version);
+        }
+        assert_eq!(MIGRATIONS[0].version, 1);
+    }
+
+    #[test]
+    fn test_fresh_database_bootstrap() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert_eq!(current_version(&conn).unwrap(), 0);
+
+        let version = run_migrations(&mut conn).unwrap();
+        assert_eq!(version, latest_version());
+        assert_eq!(current_version(&conn).unwrap(), latest_version());
+
+        for table in [
+            "sounds",
+            "aliases",
+            "user_settings",
+            "users",
+            "registration_scope",
+            "schema_version",
+        ] {
+            assert!(table_exists(&conn, table), "missing table {}", table);
+        }
+
+        // Running again is a no-op
+        assert_eq!(run_migrations(&mut conn).unwrap(), latest_version());
+        let applied: i64 = conn
+            .query_row("SELECT COUNT(*) FROM schema_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(applied, MIGRATIONS.len() as i64);
+    }
+
+    #[test]
+    fn test_unversioned_database_is_adopted() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE sounds (code TEXT PRIMARY KEY, author TEXT NOT NULL, created_at TEXT NOT NULL,
+                source_url TEXT, start_time TEXT NOT NULL, length REAL NOT NULL);
+             INSERT INTO sounds VALUES ('ABCD', 'alice', '2024-01-01T00:00:00+00:00', NULL, '0:00', 1.5);",
+        )
+        .unwrap();
+
+        run_migrations(&mut conn).unwrap();
+
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM sounds", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+        assert!(table_exists(&conn, "aliases"));
+    }
+
+    #[test]
+    fn test_name_keyed_rows_become_legacy_identities() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        current_version(&conn).unwrap();
+        let tx = conn.transaction().unwrap();
+        tx.execute_batch(MIGRATIONS[0].sql).unwrap();
+        tx.execute_batch(
+            "INSERT INTO user_settings VALUES ('alice:greeting', 'alice', 'greeting', '!sound play ABCD', 'now', 'now');
+             INSERT INTO aliases VALUES ('hi', 'bob', 'now', '!sound play WXYZ');",
+        )
+        .unwrap();
+        tx.execute(
+            "INSERT INTO schema_version (version, name, applied_at) VALUES (1, 'initial_schema', 'now')",
+            [],
+        )
+        .unwrap();
+        tx.commit().unwrap();
+
+        run_migrations(&mut conn).unwrap();
+
+        let (id, identity): (String, String) = conn
+            .query_row("SELECT id, identity FROM user_settings", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(id, "name:alice:greeting");
+        assert_eq!(identity, "name:alice");
+
+        let (author, author_identity): (String, String) = conn
+            .query_row("SELECT author, author_identity FROM aliases", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(author, "bob");
+        assert_eq!(author_identity, "name:bob");
+    }
+
+    #[test]
+    fn test_newer_database_is_rejected() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run_migrations(&mut conn).unwrap();
+        conn.execute(
+            "INSERT INTO schema_version (version, name, applied_at) VALUES (?1, 'future', 'now')",
+            params![latest_version() + 1],
+        )
+        .unwrap();
+
+        assert!(run_migrations(&mut conn).is_err());
+    }
+}
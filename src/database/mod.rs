@@ -1,4 +1,5 @@
 pub mod connection;
 pub mod entities;
+pub mod migrations;
 
 pub use connection::*;
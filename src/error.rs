@@ -13,6 +13,8 @@ pub enum Error {
     DatabaseError(String),
     InvalidInput(String),
     ConfigError(String),
+    Rejected(String),
+    PermissionDenied(String),
 }
 
 impl From<std::io::Error> for Error {
@@ -51,6 +53,8 @@ impl std::fmt::Display for Error {
             Error::DatabaseError(msg) => write!(f, "Database error: {}", msg),
             Error::InvalidInput(msg) => write!(f, "Invalid input: {}", msg),
             Error::ConfigError(msg) => write!(f, "Configuration error: {}", msg),
+            Error::Rejected(msg) => write!(f, "Rejected by server: {}", msg),
+            Error::PermissionDenied(msg) => write!(f, "Permission denied: {}", msg),
         }
     }
 }
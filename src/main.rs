@@ -1,22 +1,36 @@
 mod alias;
 mod audio;
+mod client_cert;
 mod commands;
 mod config;
+mod context_actions;
+mod cooldown;
+mod crypt;
 mod database;
 mod error;
+mod permissions;
+mod ping;
+mod profile;
 mod protos;
+mod reconnect;
+mod relay;
 mod session;
+mod shutdown;
 mod sounds;
+mod text;
+mod udp;
 mod user_settings;
+mod users;
 mod util;
 mod verifier;
 
 #[macro_use]
 extern crate log;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use config::BotConfig;
 use std::error::Error;
+use std::process::ExitCode;
 
 #[derive(Parser)]
 #[command(name = "threebot", version = "0.1.0", author = "Justin Stanley")]
@@ -33,10 +47,167 @@ struct Cli {
         help = "Configuration file path (default: ~/.threebot/config.yml)"
     )]
     config: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Fetch a server's certificate and pin it for future connections
+    Trust {
+        #[arg(help = "Server hostname or IP address")]
+        host: String,
+
+        #[arg(short, long, default_value_t = 64738, help = "Server port")]
+        port: u16,
+
+        #[arg(
+            short,
+            long,
+            help = "Only pin the certificate if it has this SHA-256 fingerprint"
+        )]
+        fingerprint: Option<String>,
+    },
+
+    /// Manage the client certificate the bot identifies itself with
+    Cert {
+        #[command(subcommand)]
+        action: CertCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum CertCommand {
+    /// Replace the certificate with a newly generated one, keeping the old one as .old
+    Rotate,
+
+    /// Use the certificate and key from a PKCS#12 bundle exported from Mumble
+    Import {
+        #[arg(help = "PKCS#12 file (.p12 or .pfx)")]
+        file: std::path::PathBuf,
+
+        #[arg(
+            short,
+            long,
+            default_value = "",
+            help = "Password the bundle was exported with"
+        )]
+        password: String,
+    },
+}
+
+/// Runs a `threebot cert` subcommand
+fn manage_certificate(
+    config: &BotConfig,
+    cert_path: &std::path::Path,
+    key_path: &std::path::Path,
+    action: &CertCommand,
+) -> Result<(), Box<dyn Error>> {
+    let hash = match action {
+        CertCommand::Rotate => {
+            let (old_hash, new_hash) = client_cert::rotate(
+                cert_path,
+                key_path,
+                &config.bot.username,
+                config.certificate.validity_days,
+            )?;
+            if let Some(old_hash) = old_hash {
+                println!("Replaced certificate {}", old_hash);
+            }
+            println!(
+                "Servers see this as a new identity; register the bot again if it was registered"
+            );
+            new_hash
+        }
+        CertCommand::Import { file, password } => {
+            client_cert::import_pkcs12(file, password, cert_path, key_path)?
+        }
+    };
+
+    println!(
+        "Now using certificate {} from {}",
+        hash,
+        cert_path.display()
+    );
+    Ok(())
+}
+
+/// Fetches the certificate `host` presents and saves it as trusted for it
+async fn trust_server(
+    config: &BotConfig,
+    host: &str,
+    port: u16,
+    expected: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let timeout = std::time::Duration::from_secs(config.server.timeout_seconds);
+    let cert = verifier::fetch_certificate(host, port, timeout).await?;
+    let fingerprint = verifier::fingerprint(&cert);
+    println!(
+        "{}:{} presented a certificate with SHA-256 fingerprint:\n  {}",
+        host, port, fingerprint
+    );
+
+    let matches = |expected: &str| {
+        verifier::normalize_fingerprint(expected) == verifier::normalize_fingerprint(&fingerprint)
+    };
+    if let Some(expected) = expected.filter(|expected| !matches(expected)) {
+        return Err(format!("Fingerprint does not match {}, not pinning it", expected).into());
+    }
+
+    let path = verifier::TrustStore::new(config.get_trusted_certs_dir()).save(host, &cert)?;
+    println!("Pinned in {}", path.display());
+    Ok(())
+}
+
+/// Builds the connection options of one server from its configuration
+fn connection_options(
+    config: BotConfig,
+    cert_path: &std::path::Path,
+    key_path: &std::path::Path,
+    trusted_certs_dir: &std::path::Path,
+) -> session::ConnectionOptions {
+    session::ConnectionOptions {
+        name: config.server_name(),
+        host: config.server.host,
+        port: config.server.port,
+        username: config.bot.username,
+        cert: cert_path.to_string_lossy().to_string(),
+        key: key_path.to_string_lossy().to_string(),
+        password: config.bot.password,
+        timeout: Some(config.server.timeout_seconds),
+        reconnect: config.server.reconnect,
+        udp_voice: config.server.udp_voice,
+        home_channel: config.server.home_channel,
+        ping_interval: config.server.ping_interval_seconds,
+        max_missed_pings: config.server.max_missed_pings,
+        trust: config.server.trust,
+        trusted_certs_dir: trusted_certs_dir.to_path_buf(),
+        cert_expiry_warning_days: config.certificate.expiry_warning_days,
+        behavior_settings: config.behavior,
+        music: config.music,
+        encoder: config.encoder,
+        profile: config.profile,
+        permissions: config.permissions,
+        audio_effects: config.audio_effects,
+        external_tools: config.external_tools,
+    }
 }
 
+/// Exits with 0 after a graceful shutdown and 1 on errors. A second signal
+/// during shutdown exits immediately with 128 + the signal number.
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run() -> Result<(), Box<dyn Error>> {
     // Parse command line arguments
     let cli = Cli::parse();
 
@@ -97,6 +268,19 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // Get certificate and key paths from configuration
     let cert_path = config.get_cert_path();
     let key_path = config.get_key_path();
+    let trusted_certs_dir = config.get_trusted_certs_dir();
+
+    match &cli.command {
+        Some(Command::Trust {
+            host,
+            port,
+            fingerprint,
+        }) => return trust_server(&config, host, *port, fingerprint.as_deref()).await,
+        Some(Command::Cert { action }) => {
+            return manage_certificate(&config, &cert_path, &key_path, action);
+        }
+        None => {}
+    }
 
     let has_cert = cert_path.exists();
     let has_key = key_path.exists();
@@ -111,44 +295,62 @@ async fn main() -> Result<(), Box<dyn Error>> {
             "No certificate found at {}, generating self-signed certificate...",
             cert_path.display()
         );
-        std::process::Command::new("openssl")
-            .args(&[
-                "req",
-                "-x509",
-                "-newkey",
-                "rsa:2048",
-                "-keyout",
-                key_path.to_str().unwrap(),
-                "-out",
-                cert_path.to_str().unwrap(),
-                "-days",
-                "365",
-                "-nodes",
-                "-subj",
-                &format!("/CN={}", config.bot.username),
-            ])
-            .status()
-            .expect("Failed to generate self-signed certificate");
+        client_cert::generate(
+            &cert_path,
+            &key_path,
+            &config.bot.username,
+            config.certificate.validity_days,
+        )?;
     } else {
         info!("Using existing certificate at {}", cert_path.display());
     }
 
-    let session = session::Session::new(session::ConnectionOptions {
-        host: config.server.host,
-        port: config.server.port,
-        username: config.bot.username,
-        cert: cert_path.to_string_lossy().to_string(),
-        key: key_path.to_string_lossy().to_string(),
-        password: config.bot.password,
-        timeout: Some(config.server.timeout_seconds),
-        data_dir: Some(data_dir.to_string_lossy().to_string()),
-        behavior_settings: config.behavior,
-        audio_effects: config.audio_effects,
-        external_tools: config.external_tools,
-    })
+    let shared = session::SharedState::open(
+        Some(data_dir.to_string_lossy().as_ref()),
+        &config.server_name(),
+    )
     .await?;
 
-    session.start_main_loop().await?;
+    let mut sessions = Vec::new();
+    for server in config.server_configs()? {
+        let name = server.server_name();
+        let options = connection_options(server, &cert_path, &key_path, &trusted_certs_dir);
+        sessions.push((name, session::Session::new(options, &shared).await?));
+    }
 
+    let mut signals = shutdown::ShutdownSignals::install()?;
+    let (shutdown_senders, shutdown_receivers): (Vec<_>, Vec<_>) = sessions
+        .iter()
+        .map(|_| tokio::sync::oneshot::channel())
+        .unzip();
+    tokio::spawn(async move {
+        let signal = signals.recv().await;
+        info!("Received {}, shutting down", signal);
+        for sender in shutdown_senders {
+            let _ = sender.send(());
+        }
+
+        let signal = signals.recv().await;
+        warn!("Received {} during shutdown, exiting immediately", signal);
+        std::process::exit(signal.exit_code());
+    });
+
+    // A server the bot gave up on doesn't stop the others
+    let results = futures::future::join_all(sessions.into_iter().zip(shutdown_receivers).map(
+        |((name, session), shutdown)| async move {
+            session
+                .start_main_loop(shutdown)
+                .await
+                .map_err(|e| format!("{}: {}", name, e))
+        },
+    ))
+    .await;
+
+    shared.database.checkpoint().await?;
+    info!("Shutdown complete");
+
+    for result in results {
+        result?;
+    }
     Ok(())
 }
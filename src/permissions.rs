@@ -0,0 +1,229 @@
+// Role-based command permissions
+
+use std::collections::{HashMap, HashSet};
+
+use crate::config::{PermissionSettings, Role};
+use crate::protos::generated::Mumble;
+use crate::users::Identity;
+
+/// Mumble's Write permission bit, needed to read a channel's ACL and groups
+pub const PERMISSION_WRITE: u32 = 0x1;
+
+/// Resolves users' roles and the roles commands require
+pub struct Permissions {
+    settings: PermissionSettings,
+    /// Name of the server these permissions belong to, the only one whose
+    /// registered users the `user:` entries refer to
+    server: String,
+    /// Registered user IDs in each Mumble group of the root channel
+    group_members: std::sync::RwLock<HashMap<String, HashSet<u32>>>,
+}
+
+impl Permissions {
+    pub fn new(mut settings: PermissionSettings, server: &str) -> Self {
+        // Certificate hashes are compared in lowercase
+        settings.users = settings
+            .users
+            .into_iter()
+            .map(|(identity, role)| (identity.to_lowercase(), role))
+            .collect();
+
+        // A configured map replaces the defaults when deserialized, which would
+        // silently open up restricted commands such as `profile`
+        for (command, role) in PermissionSettings::default().commands {
+            settings.commands.entry(command).or_insert(role);
+        }
+
+        Self {
+            settings,
+            server: server.to_string(),
+            group_members: std::sync::RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn default_role(&self) -> Role {
+        self.settings.default_role
+    }
+
+    /// Whether any roles come from Mumble groups, making the root ACL worth reading
+    pub fn uses_groups(&self) -> bool {
+        !self.settings.groups.is_empty()
+    }
+
+    /// Replaces the known group memberships with those of a root channel ACL
+    pub fn update_groups(&self, acl: &Mumble::ACL) {
+        let groups = acl
+            .groups
+            .iter()
+            .map(|group| {
+                let mut members: HashSet<u32> = group
+                    .add
+                    .iter()
+                    .chain(&group.inherited_members)
+                    .copied()
+                    .collect();
+                for removed in &group.remove {
+                    members.remove(removed);
+                }
+                (group.name().to_string(), members)
+            })
+            .collect();
+
+        *self.group_members.write().unwrap() = groups;
+    }
+
+    /// Role of a user: an explicit entry for their identity wins, then the
+    /// highest role among their Mumble groups, then the default role. Users
+    /// registered on other servers only get the default role.
+    pub fn role_of(&self, identity: Option<&Identity>) -> Role {
+        let Some(identity) = identity else {
+            return self.settings.default_role;
+        };
+        if matches!(identity, Identity::Registered { server, .. } if *server != self.server) {
+            return self.settings.default_role;
+        }
+        if let Some(role) = self.settings.users.get(&identity.config_key()) {
+            return *role;
+        }
+
+        if let Identity::Registered { user_id, .. } = identity {
+            let group_members = self.group_members.read().unwrap();
+            let group_role = self
+                .settings
+                .groups
+                .iter()
+                .filter(|(group, _)| {
+                    group_members
+                        .get(*group)
+                        .is_some_and(|members| members.contains(user_id))
+                })
+                .map(|(_, role)| *role)
+                .max();
+            if let Some(role) = group_role {
+                return role;
+            }
+        }
+
+        self.settings.default_role
+    }
+
+    /// Minimum role for a command, looking at `command subcommand` before `command`
+    pub fn required_role(&self, command: &str, subcommand: Option<&str>) -> Role {
+        subcommand
+            .and_then(|sub| {
+                self.settings
+                    .commands
+                    .get(&format!("{} {}", command, sub.to_lowercase()))
+            })
+            .or_else(|| self.settings.commands.get(command))
+            .copied()
+            .unwrap_or(Role::User)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn permissions() -> Permissions {
+        let mut settings = PermissionSettings::default();
+        settings
+            .users
+            .insert("cert:ABCDEF".to_string(), Role::Banned);
+        settings.users.insert("user:1".to_string(), Role::Trusted);
+        settings.groups.insert("admin".to_string(), Role::Admin);
+        settings.groups.insert("mods".to_string(), Role::Trusted);
+        settings.commands.insert("music".to_string(), Role::Trusted);
+        Permissions::new(settings, "main")
+    }
+
+    fn registered(user_id: u32) -> Identity {
+        Identity::Registered {
+            server: "main".to_string(),
+            user_id,
+        }
+    }
+
+    #[test]
+    fn test_role_resolution() {
+        let permissions = permissions();
+        assert_eq!(permissions.role_of(None), Role::User);
+        assert_eq!(
+            permissions.role_of(Some(&Identity::Certificate("abcdef".to_string()))),
+            Role::Banned
+        );
+        assert_eq!(permissions.role_of(Some(&registered(1))), Role::Trusted);
+        assert_eq!(permissions.role_of(Some(&registered(2))), Role::User);
+
+        let mut acl = Mumble::ACL::new();
+        for (name, add, remove) in [("admin", vec![2, 3], vec![3]), ("mods", vec![2], vec![])] {
+            let mut group = Mumble::acl::ChanGroup::new();
+            group.set_name(name.to_string());
+            group.add = add;
+            group.remove = remove;
+            acl.groups.push(group);
+        }
+        permissions.update_groups(&acl);
+
+        // The highest group role wins, but explicit entries still come first
+        assert_eq!(permissions.role_of(Some(&registered(2))), Role::Admin);
+        assert_eq!(permissions.role_of(Some(&registered(3))), Role::User);
+        assert_eq!(permissions.role_of(Some(&registered(1))), Role::Trusted);
+
+        // The same registration ID on another server is someone else
+        let elsewhere = Identity::Registered {
+            server: "games".to_string(),
+            user_id: 2,
+        };
+        assert_eq!(permissions.role_of(Some(&elsewhere)), Role::User);
+    }
+
+    #[test]
+    fn test_groups_are_only_read_when_configured() {
+        assert!(!Permissions::new(PermissionSettings::default(), "main").uses_groups());
+        assert!(permissions().uses_groups());
+    }
+
+    #[test]
+    fn test_required_role() {
+        let permissions = permissions();
+        assert_eq!(
+            permissions.required_role("sound", Some("stopall")),
+            Role::Trusted
+        );
+        assert_eq!(
+            permissions.required_role("sound", Some("StopAll")),
+            Role::Trusted
+        );
+        assert_eq!(permissions.required_role("sound", Some("play")), Role::User);
+        assert_eq!(permissions.required_role("sound", None), Role::User);
+        assert_eq!(
+            permissions.required_role("music", Some("play")),
+            Role::Trusted
+        );
+    }
+
+    #[test]
+    fn test_configured_commands_keep_the_defaults() {
+        let settings: PermissionSettings =
+            serde_yaml::from_str("commands:\n  music: trusted\n  \"sound stopall\": admin\n")
+                .unwrap();
+        let permissions = Permissions::new(settings, "main");
+        assert_eq!(
+            permissions.required_role("profile", Some("avatar")),
+            Role::Admin
+        );
+        assert_eq!(
+            permissions.required_role("music", Some("stop")),
+            Role::Trusted
+        );
+        assert_eq!(
+            permissions.required_role("music", Some("play")),
+            Role::Trusted
+        );
+        assert_eq!(
+            permissions.required_role("sound", Some("stopall")),
+            Role::Admin
+        );
+    }
+}
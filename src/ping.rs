@@ -0,0 +1,209 @@
+// Connection health: ping round trips and the stats reported back to the server
+
+use std::time::{Duration, Instant};
+
+use crate::crypt::CryptStats;
+use crate::protos::generated::Mumble;
+
+/// Running round-trip time statistics
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RttStats {
+    packets: u32,
+    mean_ms: f64,
+    /// Sum of squared differences from the mean (Welford's method)
+    m2: f64,
+    last: Option<Duration>,
+}
+
+impl RttStats {
+    pub fn record(&mut self, rtt: Duration) {
+        let ms = rtt.as_secs_f64() * 1000.0;
+        self.packets += 1;
+        let delta = ms - self.mean_ms;
+        self.mean_ms += delta / self.packets as f64;
+        self.m2 += delta * (ms - self.mean_ms);
+        self.last = Some(rtt);
+    }
+
+    /// Number of replies measured
+    pub fn packets(&self) -> u32 {
+        self.packets
+    }
+
+    pub fn average_ms(&self) -> f32 {
+        self.mean_ms as f32
+    }
+
+    /// Variance of the round trip times, in ms²
+    pub fn variance_ms(&self) -> f32 {
+        if self.packets < 2 {
+            return 0.0;
+        }
+        (self.m2 / (self.packets - 1) as f64) as f32
+    }
+
+    /// Most recent round trip time
+    pub fn last(&self) -> Option<Duration> {
+        self.last
+    }
+}
+
+/// UDP channel statistics, as reported in pings
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UdpStats {
+    pub rtt: RttStats,
+    /// How the voice packets we received from the server fared
+    pub crypt: CryptStats,
+}
+
+/// Snapshot of the connection health shown by `!ping`
+#[derive(Debug, Clone)]
+pub struct ConnectionStats {
+    pub tcp: RttStats,
+    /// Pings sent since the server last answered
+    pub unanswered: u32,
+    pub udp: Option<UdpStats>,
+    /// The server's last reply, carrying its view of the packets we sent
+    pub server: Option<Mumble::Ping>,
+}
+
+/// TCP ping bookkeeping for one connection
+pub struct PingTracker {
+    started: Instant,
+    tcp: RttStats,
+    unanswered: u32,
+}
+
+impl Default for PingTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PingTracker {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            tcp: RttStats::default(),
+            unanswered: 0,
+        }
+    }
+
+    /// Builds the next ping carrying our statistics, and counts it as unanswered
+    pub fn next_ping(&mut self, udp: Option<UdpStats>) -> Mumble::Ping {
+        self.next_ping_at(Instant::now(), udp)
+    }
+
+    fn next_ping_at(&mut self, now: Instant, udp: Option<UdpStats>) -> Mumble::Ping {
+        self.unanswered += 1;
+
+        let mut ping = Mumble::Ping::new();
+        ping.set_timestamp(now.saturating_duration_since(self.started).as_micros() as u64);
+        ping.set_tcp_packets(self.tcp.packets());
+        ping.set_tcp_ping_avg(self.tcp.average_ms());
+        ping.set_tcp_ping_var(self.tcp.variance_ms());
+        if let Some(udp) = udp {
+            ping.set_udp_packets(udp.rtt.packets());
+            ping.set_udp_ping_avg(udp.rtt.average_ms());
+            ping.set_udp_ping_var(udp.rtt.variance_ms());
+            ping.set_good(udp.crypt.good);
+            ping.set_late(udp.crypt.late);
+            ping.set_lost(udp.crypt.lost);
+            ping.set_resync(udp.crypt.resync);
+        }
+        ping
+    }
+
+    /// Records the server's reply to one of our pings, returning its round trip time
+    pub fn reply(&mut self, ping: &Mumble::Ping) -> Option<Duration> {
+        self.reply_at(Instant::now(), ping)
+    }
+
+    fn reply_at(&mut self, now: Instant, ping: &Mumble::Ping) -> Option<Duration> {
+        self.unanswered = 0;
+        if !ping.has_timestamp() {
+            return None;
+        }
+
+        let sent = Duration::from_micros(ping.timestamp());
+        let rtt = now
+            .saturating_duration_since(self.started)
+            .checked_sub(sent)?;
+        self.tcp.record(rtt);
+        Some(rtt)
+    }
+
+    /// Pings sent since the server last answered
+    pub fn unanswered(&self) -> u32 {
+        self.unanswered
+    }
+
+    pub fn tcp(&self) -> RttStats {
+        self.tcp
+    }
+}
+
+/// Share of packets lost out of all that were due, as a percentage
+pub fn loss_percent(stats: &CryptStats) -> f32 {
+    let total = stats.good + stats.lost;
+    if total == 0 {
+        return 0.0;
+    }
+    stats.lost as f32 * 100.0 / total as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rtt_stats() {
+        let mut stats = RttStats::default();
+        assert_eq!(stats.variance_ms(), 0.0);
+        for ms in [10, 20, 30] {
+            stats.record(Duration::from_millis(ms));
+        }
+        assert_eq!(stats.packets(), 3);
+        assert!((stats.average_ms() - 20.0).abs() < 1e-4);
+        assert!((stats.variance_ms() - 100.0).abs() < 1e-3);
+        assert_eq!(stats.last(), Some(Duration::from_millis(30)));
+    }
+
+    #[test]
+    fn test_ping_round_trip() {
+        let mut tracker = PingTracker::new();
+        let start = tracker.started;
+        let udp = UdpStats {
+            crypt: CryptStats {
+                good: 90,
+                late: 1,
+                lost: 10,
+                resync: 0,
+            },
+            ..Default::default()
+        };
+
+        let first = tracker.next_ping_at(start + Duration::from_secs(5), Some(udp));
+        tracker.next_ping_at(start + Duration::from_secs(10), None);
+        assert_eq!(tracker.unanswered(), 2);
+        assert_eq!(first.good(), 90);
+        assert_eq!(first.tcp_packets(), 0);
+
+        let rtt = tracker.reply_at(start + Duration::from_millis(5040), &first);
+        assert_eq!(rtt, Some(Duration::from_millis(40)));
+        assert_eq!(tracker.unanswered(), 0);
+
+        let second = tracker.next_ping_at(start + Duration::from_secs(15), None);
+        assert_eq!(second.tcp_packets(), 1);
+        assert!((second.tcp_ping_avg() - 40.0).abs() < 1e-3);
+        assert!(!second.has_udp_packets());
+
+        // A reply without a timestamp still shows the server is alive
+        tracker.next_ping_at(start + Duration::from_secs(20), None);
+        assert_eq!(tracker.reply_at(start, &Mumble::Ping::new()), None);
+        assert_eq!(tracker.unanswered(), 0);
+
+        assert!((loss_percent(&udp.crypt) - 10.0).abs() < 1e-4);
+        assert_eq!(loss_percent(&CryptStats::default()), 0.0);
+    }
+}
@@ -0,0 +1,143 @@
+// The bot's own profile: registration, comment and avatar
+
+use std::path::Path;
+
+use crate::error::Error;
+use crate::protos::generated::Mumble;
+
+/// Short descriptions of the commands listed in the profile comment
+const COMMAND_SUMMARIES: &[(&str, &str)] = &[
+    ("sound", "play, pull, clip and manage sounds"),
+    ("music", "stream tracks and playlists"),
+    ("queue", "show what is playing and up next"),
+    ("skip", "skip the current sound"),
+    ("pause", "pause the current sound"),
+    ("resume", "resume the current sound"),
+    ("nowplaying", "show the current sound and its progress"),
+    ("join", "move the bot to a channel"),
+    ("follow", "follow a user between channels"),
+    ("home", "return to the home channel"),
+    ("alias", "create command shortcuts"),
+    ("greeting", "set your join command"),
+    ("farewell", "set your leave command"),
+    ("bind", "save a command to rerun with !bind"),
+    ("whoami", "show your identity and role"),
+    ("ping", "show connection latency"),
+    (
+        "profile",
+        "manage the bot's registration, comment and avatar",
+    ),
+];
+
+/// Builds the profile comment: the intro, the number of sounds and a table
+/// of the commands users can run. Commands without a summary are left out.
+pub fn comment_html(intro: Option<&str>, sound_count: usize, commands: &[&str]) -> String {
+    let mut html = String::new();
+    if let Some(intro) = intro.filter(|intro| !intro.trim().is_empty()) {
+        html.push_str(intro);
+        html.push_str("<br>");
+    }
+
+    html.push_str(&format!(
+        "<b>{}</b> sound{} available. Commands:",
+        sound_count,
+        if sound_count == 1 { "" } else { "s" }
+    ));
+    html.push_str("<table>");
+    for (name, summary) in COMMAND_SUMMARIES {
+        if commands.contains(name) {
+            html.push_str(&format!(
+                "<tr><td><b>!{}</b></td><td>{}</td></tr>",
+                name, summary
+            ));
+        }
+    }
+    html.push_str("</table>");
+    html
+}
+
+/// Reads an avatar image, which Mumble clients show as PNG or JPEG
+pub fn load_avatar(path: &Path, max_length: Option<usize>) -> Result<Vec<u8>, Error> {
+    let image = std::fs::read(path).map_err(|e| {
+        Error::InvalidInput(format!("Failed to read avatar {}: {}", path.display(), e))
+    })?;
+
+    let is_png = image.starts_with(b"\x89PNG\r\n\x1a\n");
+    let is_jpeg = image.starts_with(&[0xFF, 0xD8, 0xFF]);
+    if !is_png && !is_jpeg {
+        return Err(Error::InvalidInput(format!(
+            "{} is not a PNG or JPEG image",
+            path.display()
+        )));
+    }
+
+    if let Some(max) = max_length.filter(|max| image.len() > *max) {
+        return Err(Error::InvalidInput(format!(
+            "{} is {} bytes, more than the server's limit of {}",
+            path.display(),
+            image.len(),
+            max
+        )));
+    }
+    Ok(image)
+}
+
+/// Asks the server to register the certificate of the user in `session`
+pub fn register(session: u32) -> Mumble::UserState {
+    Mumble::UserState {
+        session: Some(session),
+        // Registering yourself is requested with user ID 0
+        user_id: Some(0),
+        ..Default::default()
+    }
+}
+
+pub fn set_comment(session: u32, comment: &str) -> Mumble::UserState {
+    Mumble::UserState {
+        session: Some(session),
+        comment: Some(comment.to_string()),
+        ..Default::default()
+    }
+}
+
+/// Sets the avatar of the user in `session`; an empty image removes it
+pub fn set_avatar(session: u32, image: Vec<u8>) -> Mumble::UserState {
+    Mumble::UserState {
+        session: Some(session),
+        texture: Some(image),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_comment_lists_available_commands() {
+        let html = comment_html(Some("<i>Hello</i>"), 42, &["sound", "ping", "unknown"]);
+        assert!(html.starts_with("<i>Hello</i><br><b>42</b> sounds available"));
+        assert!(html.contains("<b>!sound</b>"));
+        assert!(html.contains("<b>!ping</b>"));
+        assert!(!html.contains("!music"));
+        assert!(!html.contains("unknown"));
+
+        let html = comment_html(Some("  "), 1, &[]);
+        assert!(html.starts_with("<b>1</b> sound available"));
+    }
+
+    #[test]
+    fn test_avatar_must_be_an_image_within_the_limit() {
+        let dir = std::env::temp_dir().join(format!("threebot-avatar-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let png = dir.join("avatar.png");
+        let text = dir.join("avatar.txt");
+        std::fs::write(&png, b"\x89PNG\r\n\x1a\n0123456789").unwrap();
+        std::fs::write(&text, b"not an image").unwrap();
+
+        assert_eq!(load_avatar(&png, None).unwrap().len(), 18);
+        assert!(load_avatar(&png, Some(10)).is_err());
+        assert!(load_avatar(&text, None).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
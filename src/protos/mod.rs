@@ -32,3 +32,32 @@ pub mod types {
     pub const MESSAGE_SERVER_CONFIG: u16 = 24;
     pub const MESSAGE_SUGGEST_CONFIG: u16 = 25;
 }
+
+pub mod version {
+    /// Legacy version format: 0xMMmmpp (patch saturates at 255)
+    pub const fn v1(major: u32, minor: u32, patch: u32) -> u32 {
+        let patch = if patch > 255 { 255 } else { patch };
+        (major << 16) | (minor << 8) | patch
+    }
+
+    /// Version format introduced in 1.5: 16 bits each for major, minor and patch
+    pub const fn v2(major: u64, minor: u64, patch: u64) -> u64 {
+        (major << 48) | (minor << 32) | (patch << 16)
+    }
+
+    /// Converts a legacy version number to the v2 format
+    pub const fn v1_to_v2(version: u32) -> u64 {
+        v2(
+            (version >> 16) as u64,
+            ((version >> 8) & 0xFF) as u64,
+            (version & 0xFF) as u64,
+        )
+    }
+
+    /// The protocol version this bot implements
+    pub const CLIENT_V1: u32 = v1(1, 5, 0);
+    pub const CLIENT_V2: u64 = v2(1, 5, 0);
+
+    /// Servers from this version on exchange protobuf (MumbleUDP) voice packets
+    pub const PROTOBUF_VOICE: u64 = v2(1, 5, 0);
+}
@@ -0,0 +1,109 @@
+use crate::config::ReconnectSettings;
+use rand::Rng;
+use std::time::Duration;
+
+/// Exponential backoff with jitter used between reconnection attempts
+pub struct Backoff {
+    settings: ReconnectSettings,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(settings: ReconnectSettings) -> Self {
+        Self {
+            settings,
+            attempt: 0,
+        }
+    }
+
+    /// Number of consecutive failed attempts since the last reset
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// Whether the configured attempt limit has been reached (0 = unlimited)
+    pub fn exhausted(&self) -> bool {
+        self.settings.max_attempts != 0 && self.attempt >= self.settings.max_attempts
+    }
+
+    /// Forget previous failures, called once a connection is fully synchronized
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    /// Registers a failed attempt and returns how long to wait before the next one
+    pub fn next_delay(&mut self) -> Duration {
+        let base = self.base_delay_ms(self.attempt);
+        self.attempt = self.attempt.saturating_add(1);
+
+        let jitter = self.settings.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return Duration::from_millis(base);
+        }
+
+        // Spread the delay uniformly over [base * (1 - jitter), base * (1 + jitter)]
+        let factor = rand::thread_rng().gen_range((1.0 - jitter)..=(1.0 + jitter));
+        let delay = (base as f64 * factor).min(self.settings.max_delay_ms as f64);
+        Duration::from_millis(delay.max(0.0) as u64)
+    }
+
+    /// Un-jittered delay for the given attempt number, capped at `max_delay_ms`
+    fn base_delay_ms(&self, attempt: u32) -> u64 {
+        let multiplier = self.settings.backoff_multiplier.max(1.0);
+        let delay = self.settings.initial_delay_ms as f64 * multiplier.powi(attempt as i32);
+        delay.min(self.settings.max_delay_ms as f64) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(jitter: f64, max_attempts: u32) -> ReconnectSettings {
+        ReconnectSettings {
+            enabled: true,
+            initial_delay_ms: 1000,
+            max_delay_ms: 10_000,
+            backoff_multiplier: 2.0,
+            jitter,
+            max_attempts,
+        }
+    }
+
+    #[test]
+    fn test_exponential_growth_is_capped() {
+        let mut backoff = Backoff::new(settings(0.0, 0));
+        let delays: Vec<u64> = (0..6)
+            .map(|_| backoff.next_delay().as_millis() as u64)
+            .collect();
+        assert_eq!(delays, vec![1000, 2000, 4000, 8000, 10_000, 10_000]);
+        assert!(!backoff.exhausted());
+    }
+
+    #[test]
+    fn test_jitter_stays_in_bounds() {
+        let mut backoff = Backoff::new(settings(0.5, 0));
+        for _ in 0..50 {
+            backoff.reset();
+            let delay = backoff.next_delay().as_millis() as u64;
+            assert!(
+                (500..=1500).contains(&delay),
+                "delay {} out of range",
+                delay
+            );
+        }
+    }
+
+    #[test]
+    fn test_attempt_limit_and_reset() {
+        let mut backoff = Backoff::new(settings(0.0, 2));
+        backoff.next_delay();
+        assert!(!backoff.exhausted());
+        backoff.next_delay();
+        assert!(backoff.exhausted());
+
+        backoff.reset();
+        assert_eq!(backoff.attempt(), 0);
+        assert_eq!(backoff.next_delay(), Duration::from_millis(1000));
+    }
+}
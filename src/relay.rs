@@ -0,0 +1,106 @@
+// Passing commands between the sessions of a bot connected to several servers
+
+use tokio::sync::mpsc;
+
+use crate::error::Error;
+use crate::users::Identity;
+
+/// Commands a session holds on to while it is not connected
+const INBOX_SIZE: usize = 16;
+
+/// Who sent a relayed command
+#[derive(Debug, Clone)]
+pub struct RelayedSender {
+    /// Name of the server the command came from
+    pub server: String,
+    /// Name of the user who sent it
+    pub name: String,
+    /// The sender's identity, which decides their role on the server running
+    /// the command and owns whatever the command creates there
+    pub identity: Option<Identity>,
+}
+
+/// A command sent from one server to be run on another
+#[derive(Debug, Clone)]
+pub struct RelayedCommand {
+    pub sender: RelayedSender,
+    /// Command line to run, starting with `!`
+    pub command: String,
+}
+
+/// The inboxes of every session in this process, by server name
+#[derive(Default)]
+pub struct Relays {
+    inboxes: std::sync::RwLock<Vec<(String, mpsc::Sender<RelayedCommand>)>>,
+}
+
+impl Relays {
+    /// Creates the inbox of the session for `server`
+    pub fn register(&self, server: &str) -> mpsc::Receiver<RelayedCommand> {
+        let (sender, receiver) = mpsc::channel(INBOX_SIZE);
+        let mut inboxes = self.inboxes.write().unwrap();
+        inboxes.retain(|(name, _)| !name.eq_ignore_ascii_case(server));
+        inboxes.push((server.to_string(), sender));
+        receiver
+    }
+
+    /// Names of the servers commands can be relayed to
+    pub fn servers(&self) -> Vec<String> {
+        let inboxes = self.inboxes.read().unwrap();
+        inboxes.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    /// Queues `command` for the session of `server`
+    pub fn send(&self, server: &str, command: RelayedCommand) -> Result<(), Error> {
+        let inboxes = self.inboxes.read().unwrap();
+        let (name, sender) = inboxes
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(server))
+            .ok_or_else(|| Error::InvalidArgument(format!("No server named '{}'", server)))?;
+
+        sender.try_send(command).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => {
+                Error::InvalidArgument(format!("Too many commands waiting for {}", name))
+            }
+            mpsc::error::TrySendError::Closed(_) => {
+                Error::InvalidArgument(format!("{} is no longer running", name))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(text: &str) -> RelayedCommand {
+        RelayedCommand {
+            sender: RelayedSender {
+                server: "main".to_string(),
+                name: "alice".to_string(),
+                identity: None,
+            },
+            command: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_commands_reach_the_named_server() {
+        let relays = Relays::default();
+        let mut games = relays.register("games");
+        let _main = relays.register("main");
+        assert_eq!(relays.servers(), vec!["games", "main"]);
+
+        relays.send("GAMES", command("!sound play abc")).unwrap();
+        assert_eq!(games.try_recv().unwrap().command, "!sound play abc");
+        assert!(relays.send("other", command("!ping")).is_err());
+
+        for _ in 0..INBOX_SIZE {
+            relays.send("games", command("!ping")).unwrap();
+        }
+        assert!(relays.send("games", command("!ping")).is_err());
+
+        drop(games);
+        assert!(relays.send("games", command("!ping")).is_err());
+    }
+}
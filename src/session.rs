@@ -1,19 +1,39 @@
 use crate::{
-    audio::{AudioMixer, AudioMixerTask},
+    audio::{
+        AudioMixer, AudioMixerTask,
+        clip::ClipRecorder,
+        encoder::{EncoderConfig, SharedEncoderConfig},
+        packet::{PacketFormat, SharedPacketFormat, VoicePacket},
+        receive::VoiceReceiver,
+    },
+    client_cert,
     commands::{CommandContext, Executor, SessionTools},
     config::{
-        AudioEffectSettings, BehaviorSettings, ExternalToolsSettings, FarewellMode, GreetingMode,
+        AudioEffectSettings, BehaviorSettings, EncoderSettings, ExternalToolsSettings,
+        FarewellMode, GreetingMode, MusicSettings, PermissionSettings, ProfileSettings,
+        ReconnectSettings, TrustSettings,
     },
+    context_actions,
+    cooldown::GreetingCooldowns,
+    crypt::CryptState,
     error::Error,
-    protos::{self, generated::Mumble::CryptSetup},
+    permissions::{self, Permissions},
+    ping::{ConnectionStats, PingTracker},
+    profile,
+    protos::{self, version},
+    reconnect::Backoff,
+    relay::{RelayedCommand, Relays},
+    text::{self, SharedTextLimits, TextLimits},
+    udp::{UdpTransport, UdpVoice},
+    users::{Identity, UsersManager},
 };
 use protobuf::{Message, SpecialFields};
-use rustls_pki_types::{CertificateDer, PrivateKeyDer, ServerName, pem::PemObject};
-use std::{collections::HashMap, sync::Arc, vec};
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject};
+use std::{collections::HashMap, path::Path, sync::Arc, vec};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::TcpStream,
-    sync::mpsc,
+    sync::{mpsc, oneshot},
 };
 use tokio_rustls::{
     TlsConnector,
@@ -25,7 +45,7 @@ use crate::protos::generated::Mumble;
 use crate::verifier;
 
 /// Escapes HTML entities in a string for safe display
-fn escape_html(input: &str) -> String {
+pub fn escape_html(input: &str) -> String {
     input
         .replace("&", "&amp;")
         .replace("<", "&lt;")
@@ -68,7 +88,48 @@ pub fn markdown_to_html(input: &str) -> String {
     result
 }
 
+/// Looks up a channel by name, or by a `/`-separated path starting below the
+/// root channel (optionally naming the root first). Matching ignores case.
+pub fn find_channel(channels: &HashMap<u32, Mumble::ChannelState>, query: &str) -> Option<u32> {
+    let segments: Vec<&str> = query
+        .split('/')
+        .map(str::trim)
+        .filter(|segment| !segment.is_empty())
+        .collect();
+
+    // Lowest channel ID named `name`, optionally restricted to children of `parent`
+    let named = |parent: Option<u32>, name: &str| {
+        channels
+            .iter()
+            .filter(|(_, channel)| {
+                parent.is_none_or(|parent| channel.parent == Some(parent))
+                    && channel
+                        .name
+                        .as_deref()
+                        .is_some_and(|n| n.eq_ignore_ascii_case(name))
+            })
+            .map(|(id, _)| *id)
+            .min()
+    };
+    let walk = |path: &[&str]| {
+        path.iter()
+            .try_fold(ROOT_CHANNEL_ID, |parent, name| named(Some(parent), name))
+    };
+
+    match segments.as_slice() {
+        [] => None,
+        [name] => named(None, name),
+        [root, below_root @ ..] => walk(&segments).or_else(|| {
+            (named(None, root) == Some(ROOT_CHANNEL_ID))
+                .then(|| walk(below_root))
+                .flatten()
+        }),
+    }
+}
+
 pub struct ConnectionOptions {
+    /// Name of the server in logs and `!relay`
+    pub name: String,
     pub host: String,
     pub port: u16,
     pub username: String,
@@ -76,41 +137,214 @@ pub struct ConnectionOptions {
     pub cert: String,
     pub key: String,
     pub timeout: Option<u64>,
-    pub data_dir: Option<String>,
+    pub reconnect: ReconnectSettings,
+    pub udp_voice: bool,
+    pub home_channel: Option<String>,
+    /// Seconds between pings
+    pub ping_interval: u64,
+    /// Unanswered pings before the connection is considered dead (0 = never)
+    pub max_missed_pings: u32,
+    pub trust: TrustSettings,
+    /// Directory of server certificates saved as trusted
+    pub trusted_certs_dir: std::path::PathBuf,
+    /// Warn when the client certificate expires within this many days (0 = never)
+    pub cert_expiry_warning_days: u32,
     pub behavior_settings: BehaviorSettings,
+    pub music: MusicSettings,
+    pub encoder: EncoderSettings,
+    pub profile: ProfileSettings,
+    pub permissions: PermissionSettings,
     pub audio_effects: AudioEffectSettings,
     pub external_tools: ExternalToolsSettings,
 }
 
+/// What the sessions of every server share: the database, the managers
+/// built on it and the inboxes for relayed commands
+#[derive(Clone)]
+pub struct SharedState {
+    pub database: Arc<crate::database::DatabaseManager>,
+    sounds_manager: Option<Arc<crate::sounds::SoundsManager>>,
+    alias_manager: Option<Arc<crate::alias::AliasManager>>,
+    user_settings_manager: Option<Arc<crate::user_settings::UserSettingsManager>>,
+    users_manager: Option<Arc<UsersManager>>,
+    relays: Arc<Relays>,
+}
+
+impl SharedState {
+    /// Get the threebot configuration paths
+    fn get_threebot_paths_from_dir(
+        data_dir: Option<&str>,
+    ) -> Result<(std::path::PathBuf, std::path::PathBuf), Error> {
+        let threebot_dir = if let Some(dir) = data_dir {
+            std::path::PathBuf::from(dir)
+        } else {
+            // Get home directory using dirs crate for cross-platform compatibility
+            let home_dir = dirs::home_dir().ok_or_else(|| {
+                Error::ConnectionError("Unable to determine home directory".to_string())
+            })?;
+            home_dir.join(".threebot")
+        };
+
+        let sounds_dir = threebot_dir.join("sounds");
+        let database_path = threebot_dir.join("database.sql");
+
+        // Ensure the .threebot directory exists
+        std::fs::create_dir_all(&threebot_dir).map_err(|e| {
+            Error::ConnectionError(format!("Failed to create .threebot directory: {}", e))
+        })?;
+
+        Ok((sounds_dir, database_path))
+    }
+
+    /// Opens the database and sounds in `data_dir`. `primary_server` is the
+    /// server that registrations saved before they were kept per server belong to.
+    pub async fn open(data_dir: Option<&str>, primary_server: &str) -> Result<Self, Error> {
+        // Initialize paths
+        let (sounds_dir, database_path) = Self::get_threebot_paths_from_dir(data_dir)?;
+
+        // Initialize database manager
+        let database_manager = match crate::database::DatabaseManager::new(&database_path).await {
+            Ok(manager) => {
+                info!("Database manager initialized successfully");
+                manager
+            }
+            Err(e) => {
+                return Err(Error::DatabaseError(format!(
+                    "Failed to initialize database: {}",
+                    e
+                )));
+            }
+        };
+
+        // Initialize sounds manager
+        let sounds_manager =
+            match crate::sounds::SoundsManager::new(database_manager.pool_clone(), sounds_dir) {
+                Ok(manager) => {
+                    info!("Sounds manager initialized successfully");
+                    Some(Arc::new(manager))
+                }
+                Err(e) => {
+                    warn!("Failed to initialize sounds manager: {}", e);
+                    None
+                }
+            };
+
+        // Initialize alias manager
+        let alias_manager = {
+            let manager = crate::alias::AliasManager::new(database_manager.pool_clone());
+            info!("Alias manager initialized successfully");
+            Some(Arc::new(manager))
+        };
+
+        // Initialize user settings manager
+        let user_settings_manager = {
+            let manager =
+                crate::user_settings::UserSettingsManager::new(database_manager.pool_clone());
+            info!("User settings manager initialized successfully");
+            Some(Arc::new(manager))
+        };
+
+        // Initialize users manager
+        let users_manager = UsersManager::new(database_manager.pool_clone());
+        match users_manager.scope_registrations(primary_server).await {
+            Ok(0) => {}
+            Ok(moved) => info!(
+                "Moved {} rows of registered users to server {}",
+                moved, primary_server
+            ),
+            Err(e) => warn!("Failed to move registered users to their server: {}", e),
+        }
+        let users_manager = Some(Arc::new(users_manager));
+
+        Ok(SharedState {
+            database: Arc::new(database_manager),
+            sounds_manager,
+            alias_manager,
+            user_settings_manager,
+            users_manager,
+            relays: Arc::new(Relays::default()),
+        })
+    }
+}
+
+/// Mumble servers always give the root channel ID 0
+const ROOT_CHANNEL_ID: u32 = 0;
+
+/// How often the comment is checked for sounds added through other servers
+const COMMENT_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
 pub enum OutgoingMessage {
-    AudioData(Vec<u8>),       // audio data, encoded through opus
+    AudioData(VoicePacket),   // opus voice, framed by the writer
     TextMessage(String, u32), // channel message
     PrivMessage(String, u32), // private message
     Raw(u16, Vec<u8>),        // raw message type and payload
 }
 
+/// Handle to the writer task of a single connection.
+///
+/// The outgoing message queue outlives connections: when the connection ends the
+/// writer hands its receiver back so the next connection can pick it up.
 pub struct WriterTask {
-    sender: mpsc::Sender<OutgoingMessage>,
-    _task: tokio::task::JoinHandle<Result<(), Error>>,
+    /// Stops the writer; `true` asks it to flush the queue and close the stream first
+    stop: Option<oneshot::Sender<bool>>,
+    task: tokio::task::JoinHandle<(mpsc::Receiver<OutgoingMessage>, Result<(), Error>)>,
 }
 
 pub struct Writer {
     writer: tokio::io::WriteHalf<TlsStream<TcpStream>>,
     receiver: mpsc::Receiver<OutgoingMessage>,
+    udp: Arc<UdpVoice>,
+    packet_format: Arc<SharedPacketFormat>,
+    text_limits: Arc<SharedTextLimits>,
 }
 
 impl WriterTask {
-    pub fn new(writer: tokio::io::WriteHalf<TlsStream<TcpStream>>) -> Self {
-        let (sender, receiver) = mpsc::channel(100); // Channel with a buffer size of 100
+    /// Spawns a writer that sends `handshake` frames before draining the queue
+    pub fn new(
+        writer: tokio::io::WriteHalf<TlsStream<TcpStream>>,
+        receiver: mpsc::Receiver<OutgoingMessage>,
+        handshake: Vec<(u16, Vec<u8>)>,
+        udp: Arc<UdpVoice>,
+        packet_format: Arc<SharedPacketFormat>,
+        text_limits: Arc<SharedTextLimits>,
+    ) -> Self {
+        let (stop, stop_receiver) = oneshot::channel();
 
         let task = tokio::spawn(async move {
-            let writer_task = Writer::new(writer, receiver);
-            writer_task.run().await
+            let writer_task = Writer::new(writer, receiver, udp, packet_format, text_limits);
+            writer_task.run(handshake, stop_receiver).await
         });
 
         WriterTask {
-            sender,
-            _task: task,
+            stop: Some(stop),
+            task,
+        }
+    }
+
+    /// Stops the writer and returns the outgoing queue for reuse
+    pub async fn shutdown(self) -> Result<mpsc::Receiver<OutgoingMessage>, Error> {
+        self.stop(false).await
+    }
+
+    /// Writes out whatever is still queued, then closes the TLS stream so the
+    /// server sees a clean disconnect instead of a dropped connection
+    pub async fn close(self) -> Result<(), Error> {
+        self.stop(true).await.map(|_| ())
+    }
+
+    async fn stop(mut self, close: bool) -> Result<mpsc::Receiver<OutgoingMessage>, Error> {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(close);
+        }
+
+        match self.task.await {
+            Ok((receiver, result)) => {
+                if let Err(e) = result {
+                    debug!("Writer task ended with error: {}", e);
+                }
+                Ok(receiver)
+            }
+            Err(e) => Err(Error::ConnectionError(format!("Writer task failed: {}", e))),
         }
     }
 }
@@ -119,20 +353,74 @@ impl Writer {
     pub fn new(
         writer: tokio::io::WriteHalf<TlsStream<TcpStream>>,
         receiver: mpsc::Receiver<OutgoingMessage>,
+        udp: Arc<UdpVoice>,
+        packet_format: Arc<SharedPacketFormat>,
+        text_limits: Arc<SharedTextLimits>,
     ) -> Self {
-        Self { writer, receiver }
+        Self {
+            writer,
+            receiver,
+            udp,
+            packet_format,
+            text_limits,
+        }
     }
 
-    pub async fn run(mut self) -> Result<(), Error> {
-        loop {
-            match self.receiver.recv().await {
-                Some(OutgoingMessage::AudioData(data)) => {
+    pub async fn run(
+        mut self,
+        handshake: Vec<(u16, Vec<u8>)>,
+        mut stop: oneshot::Receiver<bool>,
+    ) -> (mpsc::Receiver<OutgoingMessage>, Result<(), Error>) {
+        let result = async {
+            for (msg_type, payload) in handshake {
+                self.write_mumble_frame(msg_type, payload).await?;
+            }
+
+            loop {
+                let message = tokio::select! {
+                    close = &mut stop => {
+                        if close == Ok(true) {
+                            self.close().await?;
+                        }
+                        debug!("Writer task stopped");
+                        return Ok(());
+                    }
+                    message = self.receiver.recv() => message,
+                };
+
+                self.write_message(message).await?;
+            }
+        }
+        .await;
+
+        (self.receiver, result)
+    }
+
+    /// Flushes the queued messages and shuts down the TLS stream
+    async fn close(&mut self) -> Result<(), Error> {
+        while let Ok(message) = self.receiver.try_recv() {
+            self.write_message(Some(message)).await?;
+        }
+        self.writer
+            .shutdown()
+            .await
+            .map_err(|e| Error::ConnectionError(format!("Failed to close connection: {}", e)))
+    }
+
+    async fn write_message(&mut self, message: Option<OutgoingMessage>) -> Result<(), Error> {
+        match message {
+            Some(OutgoingMessage::AudioData(packet)) => {
+                let data = packet.encode(self.packet_format.get());
+                // Prefer the UDP channel, tunnel through TCP when it is unavailable
+                if !self.udp.try_send(&data) {
                     self.write_mumble_frame(protos::types::MESSAGE_UDP_TUNNEL, data)
                         .await?;
                 }
-                Some(OutgoingMessage::TextMessage(msg, channel)) => {
+            }
+            Some(OutgoingMessage::TextMessage(msg, channel)) => {
+                for part in text::fit_message(&msg, &self.text_limits.get()) {
                     let payload = Mumble::TextMessage {
-                        message: Some(msg),
+                        message: Some(part),
                         channel_id: vec![channel],
                         ..Default::default()
                     }
@@ -140,9 +428,11 @@ impl Writer {
                     self.write_mumble_frame(protos::types::MESSAGE_TEXT_MESSAGE, payload)
                         .await?;
                 }
-                Some(OutgoingMessage::PrivMessage(msg, target)) => {
+            }
+            Some(OutgoingMessage::PrivMessage(msg, target)) => {
+                for part in text::fit_message(&msg, &self.text_limits.get()) {
                     let payload = Mumble::TextMessage {
-                        message: Some(msg),
+                        message: Some(part),
                         session: vec![target],
                         ..Default::default()
                     }
@@ -150,16 +440,20 @@ impl Writer {
                     self.write_mumble_frame(protos::types::MESSAGE_TEXT_MESSAGE, payload)
                         .await?;
                 }
-                Some(OutgoingMessage::Raw(msg_type, payload)) => {
-                    self.write_mumble_frame(msg_type, payload).await?;
-                }
-                None => {
-                    // Channel closed, exit the loop
-                    info!("Writer task channel closed, exiting");
-                    return Ok(());
-                }
+            }
+            Some(OutgoingMessage::Raw(msg_type, payload)) => {
+                self.write_mumble_frame(msg_type, payload).await?;
+            }
+            None => {
+                // Channel closed, nothing left to write
+                info!("Writer task channel closed, exiting");
+                return Err(Error::ConnectionError(
+                    "Outgoing message queue closed".to_string(),
+                ));
             }
         }
+
+        Ok(())
     }
 
     async fn write_mumble_frame(&mut self, msg_type: u16, payload: Vec<u8>) -> Result<(), Error> {
@@ -183,22 +477,79 @@ impl Writer {
     }
 }
 
+/// The live TLS connection to the server, replaced on every reconnect
+struct Connection {
+    reader: tokio::io::ReadHalf<TlsStream<TcpStream>>,
+    writer: WriterTask,
+    ping_task: tokio::task::JoinHandle<()>,
+    /// Fires with the number of missed pings once the server stops answering
+    watchdog: oneshot::Receiver<u32>,
+    /// Picks up sounds added through other servers sharing the database
+    comment_refresh: tokio::time::Interval,
+}
+
 pub struct Session {
-    crypt_setup: Option<CryptSetup>,
+    /// Name of the server in logs and `!relay`
+    server_name: String,
+    relays: Arc<Relays>,
+    /// Commands relayed from other servers, run once connected
+    relay_inbox: mpsc::Receiver<RelayedCommand>,
+    host: String,
+    port: u16,
+    username: String,
+    password: Option<String>,
+    cert_path: String,
+    key_path: String,
+    cert_expiry_warning_days: u32,
+    /// When the client certificate's expiry was last checked
+    cert_checked: Option<std::time::Instant>,
+    connect_timeout: Option<u64>,
+    trust: TrustSettings,
+    trusted_certs_dir: std::path::PathBuf,
+    /// Public CA roots server certificates are validated against first
+    ca_roots: Arc<RootCertStore>,
+    reconnect: ReconnectSettings,
+    outgoing: mpsc::Sender<OutgoingMessage>,
+    outgoing_receiver: Option<mpsc::Receiver<OutgoingMessage>>,
+    synchronized: bool,
+    udp_enabled: bool,
+    udp: Arc<UdpVoice>,
+    packet_format: Arc<SharedPacketFormat>,
+    /// Encoder settings from the config, before fitting them to the server
+    encoder_settings: EncoderConfig,
+    encoder_config: Arc<SharedEncoderConfig>,
+    text_limits: Arc<SharedTextLimits>,
+    server_addr: Option<std::net::SocketAddr>,
     channels: HashMap<u32, Mumble::ChannelState>,
     users: HashMap<u32, Mumble::UserState>,
-    writer: WriterTask,
-    reader: tokio::io::ReadHalf<TlsStream<TcpStream>>,
     last_server_ping: Option<Mumble::Ping>,
+    ping: Arc<std::sync::Mutex<PingTracker>>,
+    ping_interval: std::time::Duration,
+    max_missed_pings: u32,
     server_version: Option<Mumble::Version>,
     audio_mixer: AudioMixerTask,
+    voice_receiver: VoiceReceiver,
+    clip_recorder: Option<Arc<ClipRecorder>>,
     command_executor: Executor,
     current_user_id: Option<u32>,
     current_channel_id: Option<u32>,
+    /// Channel the bot moves to after every ServerSync
+    home_channel: Option<String>,
+    /// Name of the user whose channel changes the bot mirrors
+    follow_target: std::sync::Mutex<Option<String>>,
     sounds_manager: Option<Arc<crate::sounds::SoundsManager>>,
     alias_manager: Option<Arc<crate::alias::AliasManager>>,
     user_settings_manager: Option<Arc<crate::user_settings::UserSettingsManager>>,
+    users_manager: Option<Arc<UsersManager>>,
     behavior_settings: BehaviorSettings,
+    music_settings: MusicSettings,
+    profile_settings: ProfileSettings,
+    /// Intro of the comment, initially the configured one
+    comment_intro: std::sync::Mutex<Option<String>>,
+    /// Comment last sent on this connection
+    last_comment: std::sync::Mutex<Option<String>>,
+    permissions: Permissions,
+    greeting_cooldowns: std::sync::Mutex<GreetingCooldowns>,
     audio_effects: AudioEffectSettings,
     external_tools: ExternalToolsSettings,
     sound_history:
@@ -206,41 +557,106 @@ pub struct Session {
 }
 
 impl Session {
-    /// Get the threebot configuration paths
-    fn get_threebot_paths_from_dir(
-        data_dir: Option<&str>,
-    ) -> Result<(std::path::PathBuf, std::path::PathBuf, std::path::PathBuf), Error> {
-        let threebot_dir = if let Some(dir) = data_dir {
-            std::path::PathBuf::from(dir)
-        } else {
-            // Get home directory using dirs crate for cross-platform compatibility
-            let home_dir = dirs::home_dir().ok_or_else(|| {
-                Error::ConnectionError("Unable to determine home directory".to_string())
-            })?;
-            home_dir.join(".threebot")
-        };
+    /// Creates the session state that persists across reconnects.
+    ///
+    /// No connection is made until `start_main_loop` is called.
+    pub async fn new(options: ConnectionOptions, shared: &SharedState) -> Result<Self, Error> {
+        // The outgoing queue lives as long as the session so the mixer and
+        // command handlers keep a valid sender across reconnects
+        let (outgoing, outgoing_receiver) = mpsc::channel(100); // Channel with a buffer size of 100
+
+        let encoder_settings = EncoderConfig::from_settings(&options.encoder);
+        let encoder_config = Arc::new(SharedEncoderConfig::new(encoder_settings));
+        let audio_mixer = AudioMixer::spawn(
+            outgoing.clone(),
+            &options.behavior_settings,
+            &options.audio_effects,
+            &options.music,
+            options.external_tools.get_ytdlp_cookies_path(),
+            encoder_config.clone(),
+        );
 
-        let sounds_dir = threebot_dir.join("sounds");
-        let database_path = threebot_dir.join("database.sql");
-        let trusted_certs_dir = threebot_dir.join("trusted_certificates");
+        let voice_receiver = VoiceReceiver::spawn();
 
-        // Ensure the .threebot directory exists
-        std::fs::create_dir_all(&threebot_dir).map_err(|e| {
-            Error::ConnectionError(format!("Failed to create .threebot directory: {}", e))
-        })?;
+        // Keep recent channel audio around for clipping
+        let clip_recorder = match options.behavior_settings.clip_buffer_seconds {
+            0 => None,
+            seconds => Some(Arc::new(ClipRecorder::spawn(
+                &voice_receiver,
+                std::time::Duration::from_secs(seconds as u64),
+            ))),
+        };
 
-        Ok((sounds_dir, database_path, trusted_certs_dir))
-    }
+        let greeting_cooldowns =
+            std::sync::Mutex::new(GreetingCooldowns::new(&options.behavior_settings));
 
-    pub async fn new(options: ConnectionOptions) -> Result<Self, Error> {
-        let mut root_cert_store = RootCertStore::empty();
-        root_cert_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        Ok(Session {
+            server_name: options.name.clone(),
+            relays: shared.relays.clone(),
+            relay_inbox: shared.relays.register(&options.name),
+            host: options.host,
+            port: options.port,
+            username: options.username,
+            password: options.password,
+            cert_path: options.cert,
+            key_path: options.key,
+            cert_expiry_warning_days: options.cert_expiry_warning_days,
+            cert_checked: None,
+            connect_timeout: options.timeout,
+            trust: options.trust,
+            trusted_certs_dir: options.trusted_certs_dir,
+            ca_roots: verifier::root_store(),
+            reconnect: options.reconnect,
+            outgoing,
+            outgoing_receiver: Some(outgoing_receiver),
+            synchronized: false,
+            udp_enabled: options.udp_voice,
+            udp: Arc::new(UdpVoice::new()),
+            packet_format: Arc::new(SharedPacketFormat::default()),
+            encoder_settings,
+            encoder_config,
+            text_limits: Arc::new(SharedTextLimits::default()),
+            server_addr: None,
+            audio_mixer,
+            voice_receiver,
+            clip_recorder,
+            channels: HashMap::new(),
+            users: HashMap::new(),
+            last_server_ping: None,
+            ping: Arc::new(std::sync::Mutex::new(PingTracker::new())),
+            ping_interval: std::time::Duration::from_secs(options.ping_interval.max(1)),
+            max_missed_pings: options.max_missed_pings,
+            server_version: None,
+            command_executor: Executor::new(),
+            current_user_id: None,
+            current_channel_id: None,
+            home_channel: options.home_channel,
+            follow_target: std::sync::Mutex::new(None),
+            sounds_manager: shared.sounds_manager.clone(),
+            alias_manager: shared.alias_manager.clone(),
+            user_settings_manager: shared.user_settings_manager.clone(),
+            users_manager: shared.users_manager.clone(),
+            behavior_settings: options.behavior_settings,
+            music_settings: options.music,
+            comment_intro: std::sync::Mutex::new(options.profile.comment_intro.clone()),
+            last_comment: std::sync::Mutex::new(None),
+            profile_settings: options.profile,
+            permissions: Permissions::new(options.permissions, &options.name),
+            greeting_cooldowns,
+            audio_effects: options.audio_effects,
+            external_tools: options.external_tools,
+            sound_history: std::sync::Mutex::new(std::collections::VecDeque::new()),
+        })
+    }
 
-        let cert_chain = CertificateDer::pem_file_iter(&options.cert)
+    /// Establishes the TLS connection and starts the writer with the Version and
+    /// Authenticate handshake queued ahead of any pending messages.
+    async fn connect(&mut self) -> Result<Connection, Error> {
+        let cert_chain = CertificateDer::pem_file_iter(&self.cert_path)
             .map_err(|e| {
                 Error::InvalidCertificate(format!(
                     "Error opening certificate: {}: {}",
-                    options.cert,
+                    self.cert_path,
                     e.to_string()
                 ))
             })?
@@ -248,99 +664,117 @@ impl Session {
             .map_err(|e| {
                 Error::InvalidCertificate(format!(
                     "Error reading certificate: {}: {}",
-                    options.cert,
+                    self.cert_path,
                     e.to_string()
                 ))
             })?;
 
-        let key_der = PrivateKeyDer::from_pem_file(&options.key).map_err(|e| {
+        if self.cert_check_delay().is_zero() {
+            if let Some(cert) = cert_chain.first() {
+                client_cert::warn_if_expiring(cert, self.cert_expiry_warning_days);
+            }
+            self.cert_checked = Some(std::time::Instant::now());
+        }
+
+        let key_der = PrivateKeyDer::from_pem_file(&self.key_path).map_err(|e| {
             Error::InvalidCertificate(format!(
                 "Error reading private key: {}: {}",
-                options.key,
+                self.key_path,
                 e.to_string()
             ))
         })?;
 
-        info!("Connecting to {} as {}", options.host, options.username);
+        info!("Connecting to {} as {}", self.host, self.username);
 
         // Resolve hostname to IP address
-        let ip = tokio::net::lookup_host((options.host.as_str(), options.port))
+        let ip = tokio::net::lookup_host((self.host.as_str(), self.port))
             .await
-            .map_err(|e| {
-                Error::ConnectionError(format!("Failed to resolve {}: {}", options.host, e))
-            })?
+            .map_err(|e| Error::ConnectionError(format!("Failed to resolve {}: {}", self.host, e)))?
             .next()
             .ok_or_else(|| {
-                Error::ConnectionError(format!("No IP address found for {}", options.host))
+                Error::ConnectionError(format!("No IP address found for {}", self.host))
             })?;
 
-        debug!("Resolved {} to {}", options.host, ip);
-
-        // Initialize a new session with the given destination address
-        let socket = TcpStream::connect(ip).await.map_err(|e| {
-            Error::ConnectionError(format!("Failed to connect to {}: {}", options.host, e))
-        })?;
-
-        // Initialize paths
-        let (sounds_dir, database_path, trusted_certs_dir) =
-            Self::get_threebot_paths_from_dir(options.data_dir.as_deref())?;
+        debug!("Resolved {} to {}", self.host, ip);
 
+        let verifier = verifier::TrustPolicyVerifier::new(
+            &self.host,
+            &self.trust,
+            verifier::TrustStore::new(self.trusted_certs_dir.clone()),
+            self.ca_roots.clone(),
+        )?;
         let config = ClientConfig::builder()
             .dangerous()
-            .with_custom_certificate_verifier(Arc::new(verifier::PromptingCertVerifier::new(Some(
-                trusted_certs_dir,
-            ))))
+            .with_custom_certificate_verifier(Arc::new(verifier))
             .with_client_auth_cert(cert_chain, key_der)?;
 
-        let server_name = if let Ok(ip_addr) = options.host.parse::<std::net::IpAddr>() {
-            ServerName::IpAddress(ip_addr.into())
-        } else {
-            ServerName::try_from(options.host.clone()).map_err(|e| {
-                Error::ConnectionError(format!("Invalid server name {}: {}", options.host, e))
-            })?
-        };
+        let server_name = verifier::server_name(&self.host)?;
 
         debug!("Resolved server name: {:?}", server_name);
 
-        let stream = TlsConnector::from(Arc::new(config))
-            .connect(server_name, socket)
-            .await?;
+        let host = self.host.clone();
+        let handshake = async move {
+            // Initialize a new session with the given destination address
+            let socket = TcpStream::connect(ip).await.map_err(|e| {
+                Error::ConnectionError(format!("Failed to connect to {}: {}", host, e))
+            })?;
+
+            let stream = TlsConnector::from(Arc::new(config))
+                .connect(server_name, socket)
+                .await?;
+
+            Ok::<_, Error>(stream)
+        };
+
+        let stream = match self.connect_timeout {
+            Some(seconds) => {
+                tokio::time::timeout(tokio::time::Duration::from_secs(seconds), handshake)
+                    .await
+                    .map_err(|_| {
+                        Error::ConnectionError(format!(
+                            "Timed out connecting to {} after {}s",
+                            self.host, seconds
+                        ))
+                    })??
+            }
+            None => handshake.await?,
+        };
 
         let (reader, writer) = tokio::io::split(stream);
 
         info!("TLS session established OK");
 
-        let writer_task = WriterTask::new(writer);
+        let mut outgoing_receiver = self.outgoing_receiver.take().ok_or_else(|| {
+            Error::ConnectionError("Outgoing message queue is unavailable".to_string())
+        })?;
+
+        // Anything queued while disconnected belongs to the previous session
+        let mut discarded = 0;
+        while outgoing_receiver.try_recv().is_ok() {
+            discarded += 1;
+        }
+        if discarded > 0 {
+            debug!("Discarded {} stale outgoing messages", discarded);
+        }
 
-        writer_task
-            .sender
-            .send(OutgoingMessage::Raw(
+        let handshake = vec![
+            (
                 protos::types::MESSAGE_VERSION,
                 Mumble::Version {
-                    version_v1: Some(1),
-                    version_v2: Some(2),
-                    release: Some("1.0.0".into()),
+                    version_v1: Some(version::CLIENT_V1),
+                    version_v2: Some(version::CLIENT_V2),
+                    release: Some(format!("threebot {}", env!("CARGO_PKG_VERSION"))),
                     os: Some("rust".into()),
                     os_version: Some("5.4.0".into()),
                     special_fields: SpecialFields::default(),
                 }
                 .write_to_bytes()?,
-            ))
-            .await
-            .map_err(|e| {
-                Error::ConnectionError(format!("Failed to send version message: {}", e))
-            })?;
-
-        info!("Sent version message to server");
-
-        // Write Authenticate message
-        writer_task
-            .sender
-            .send(OutgoingMessage::Raw(
+            ),
+            (
                 protos::types::MESSAGE_AUTHENTICATE,
                 Mumble::Authenticate {
-                    username: Some(options.username.clone()),
-                    password: options.password.clone(),
+                    username: Some(self.username.clone()),
+                    password: self.password.clone(),
                     tokens: vec![],
                     celt_versions: vec![0, 1, 2],
                     opus: Some(true),
@@ -348,82 +782,209 @@ impl Session {
                     special_fields: SpecialFields::default(),
                 }
                 .write_to_bytes()?,
-            ))
-            .await
-            .map_err(|e| {
-                Error::ConnectionError(format!("Failed to send authenticate message: {}", e))
-            })?;
+            ),
+        ];
+
+        let writer = WriterTask::new(
+            writer,
+            outgoing_receiver,
+            handshake,
+            self.udp.clone(),
+            self.packet_format.clone(),
+            self.text_limits.clone(),
+        );
+        self.server_addr = Some(ip);
+
+        info!("Sent version and authenticate messages to server");
+
+        // Start ping writer task, which doubles as the watchdog
+        *self.ping.lock().unwrap() = PingTracker::new();
+        let (watchdog_sender, watchdog) = oneshot::channel();
+        let ping_task = tokio::spawn(Self::ping_loop(
+            self.ping.clone(),
+            self.outgoing.clone(),
+            self.udp.clone(),
+            self.ping_interval,
+            self.max_missed_pings,
+            watchdog_sender,
+        ));
+
+        let mut comment_refresh = tokio::time::interval_at(
+            tokio::time::Instant::now() + COMMENT_REFRESH_INTERVAL,
+            COMMENT_REFRESH_INTERVAL,
+        );
+        comment_refresh.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
 
-        info!("Sent authenticate message to server");
+        Ok(Connection {
+            reader,
+            writer,
+            ping_task,
+            watchdog,
+            comment_refresh,
+        })
+    }
 
-        let audio_mixer = AudioMixer::spawn(
-            writer_task.sender.clone(),
-            &options.behavior_settings,
-            &options.audio_effects,
-        );
+    /// Sends a ping every `interval` carrying our latency and packet stats.
+    /// Gives up and fires `watchdog` once `max_missed` pings in a row went
+    /// unanswered.
+    async fn ping_loop(
+        tracker: Arc<std::sync::Mutex<PingTracker>>,
+        outgoing: mpsc::Sender<OutgoingMessage>,
+        udp: Arc<UdpVoice>,
+        interval: std::time::Duration,
+        max_missed: u32,
+        watchdog: oneshot::Sender<u32>,
+    ) {
+        let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
 
-        // Initialize database manager
-        let database_manager = match crate::database::DatabaseManager::new(&database_path).await {
-            Ok(manager) => {
-                info!("Database manager initialized successfully");
-                manager
-            }
-            Err(e) => {
-                return Err(Error::DatabaseError(format!(
-                    "Failed to initialize database: {}",
-                    e
-                )));
-            }
-        };
+        loop {
+            ticks.tick().await;
 
-        // Initialize sounds manager
-        let sounds_manager =
-            match crate::sounds::SoundsManager::new(database_manager.pool_clone(), sounds_dir) {
-                Ok(manager) => {
-                    info!("Sounds manager initialized successfully");
-                    Some(Arc::new(manager))
+            let ping = {
+                let mut tracker = tracker.lock().unwrap();
+                if max_missed > 0 && tracker.unanswered() >= max_missed {
+                    let _ = watchdog.send(tracker.unanswered());
+                    return;
                 }
+                tracker.next_ping(udp.with(|udp| udp.stats()))
+            };
+
+            let payload = match ping.write_to_bytes() {
+                Ok(payload) => payload,
                 Err(e) => {
-                    warn!("Failed to initialize sounds manager: {}", e);
-                    None
+                    warn!("Failed to encode ping message: {}", e);
+                    continue;
                 }
             };
+            if let Err(e) = outgoing
+                .send(OutgoingMessage::Raw(protos::types::MESSAGE_PING, payload))
+                .await
+            {
+                warn!("Failed to send ping message: {}", e);
+                break;
+            }
+        }
+    }
 
-        // Initialize alias manager
-        let alias_manager = {
-            let manager = crate::alias::AliasManager::new(database_manager.pool_clone());
-            info!("Alias manager initialized successfully");
-            Some(Arc::new(manager))
-        };
+    /// Tears down a connection and reclaims the outgoing queue
+    async fn disconnect(&mut self, connection: Connection) -> Result<(), Error> {
+        connection.ping_task.abort();
+        drop(connection.reader);
+        self.outgoing_receiver = Some(connection.writer.shutdown().await?);
+        Ok(())
+    }
 
-        // Initialize user settings manager
-        let user_settings_manager = {
-            let manager =
-                crate::user_settings::UserSettingsManager::new(database_manager.pool_clone());
-            info!("User settings manager initialized successfully");
-            Some(Arc::new(manager))
-        };
+    /// Fits the configured encoder settings into the server's bandwidth limit
+    fn apply_bandwidth_limit(&self, max_bandwidth: u32) {
+        let fitted = self.encoder_settings.fit(max_bandwidth);
+        if fitted != self.encoder_settings {
+            info!(
+                "Server allows {} bit/s, lowering voice to {} bit/s in {}ms frames ({} bit/s with headers)",
+                max_bandwidth,
+                fitted.bitrate,
+                fitted.frame_ms,
+                fitted.bandwidth()
+            );
+        } else {
+            debug!("Server max bandwidth: {}", max_bandwidth);
+        }
+        self.encoder_config.set(fitted);
+    }
 
-        Ok(Session {
-            reader,
-            audio_mixer,
-            writer: writer_task,
-            crypt_setup: None,
-            channels: HashMap::new(),
-            users: HashMap::new(),
-            last_server_ping: None,
-            server_version: None,
-            command_executor: Executor::new(),
-            current_user_id: None,
-            current_channel_id: None,
-            sounds_manager,
-            alias_manager,
-            user_settings_manager,
-            behavior_settings: options.behavior_settings,
-            audio_effects: options.audio_effects,
-            external_tools: options.external_tools,
-            sound_history: std::sync::Mutex::new(std::collections::VecDeque::new()),
-        })
+    /// Clears everything learned from the server during the previous connection
+    fn reset_connection_state(&mut self) {
+        self.synchronized = false;
+        self.udp.clear();
+        self.server_addr = None;
+        self.voice_receiver.reset();
+        self.channels.clear();
+        self.users.clear();
+        self.last_server_ping = None;
+        self.server_version = None;
+        self.packet_format.set(PacketFormat::Legacy);
+        self.encoder_config.set(self.encoder_settings);
+        self.text_limits.set(TextLimits::default());
+        *self.last_comment.lock().unwrap() = None;
+        self.current_user_id = None;
+        self.current_channel_id = None;
+    }
+
+    /// Handles the three forms of CryptSetup: a full key exchange that brings
+    /// up the UDP channel, a server nonce answering our resync request, or an
+    /// empty message asking for our client nonce.
+    async fn handle_crypt_setup(&mut self, crypt_setup: Mumble::CryptSetup) -> Result<(), Error> {
+        if crypt_setup.has_key() && crypt_setup.has_client_nonce() && crypt_setup.has_server_nonce()
+        {
+            self.udp.clear();
+
+            if !self.udp_enabled {
+                debug!("UDP voice disabled, using the TCP tunnel");
+                return Ok(());
+            }
+
+            let Some(server_addr) = self.server_addr else {
+                return Ok(());
+            };
+
+            let Some(crypt) = CryptState::new(
+                crypt_setup.key(),
+                crypt_setup.client_nonce(),
+                crypt_setup.server_nonce(),
+            ) else {
+                warn!("Received malformed CryptSetup, using the TCP tunnel for voice");
+                return Ok(());
+            };
+
+            match UdpTransport::start(
+                server_addr,
+                crypt,
+                self.outgoing.clone(),
+                self.voice_receiver.clone(),
+                self.packet_format.clone(),
+            )
+            .await
+            {
+                Ok(transport) => self.udp.install(transport),
+                Err(e) => warn!("Failed to open UDP voice socket: {}", e),
+            }
+        } else if crypt_setup.has_server_nonce() {
+            let applied = self
+                .udp
+                .with(|udp| udp.set_decrypt_iv(crypt_setup.server_nonce()))
+                .unwrap_or(false);
+            debug!("Crypt resync from server (applied: {})", applied);
+        } else if let Some(client_nonce) = self.udp.with(|udp| udp.encrypt_iv()) {
+            debug!("Server requested crypt resync, sending client nonce");
+            let payload = Mumble::CryptSetup {
+                client_nonce: Some(client_nonce),
+                ..Default::default()
+            }
+            .write_to_bytes()?;
+            self.outgoing
+                .send(OutgoingMessage::Raw(
+                    protos::types::MESSAGE_CRYPT_SETUP,
+                    payload,
+                ))
+                .await
+                .map_err(|e| {
+                    Error::ConnectionError(format!("Failed to send crypt resync: {}", e))
+                })?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether an error should be answered with a reconnection attempt
+    fn is_recoverable(error: &Error) -> bool {
+        match error {
+            Error::ConnectionError(_) | Error::IOError(_) | Error::ProtobufError(_) => true,
+            Error::TLSError(e) => !matches!(
+                e,
+                tokio_rustls::rustls::Error::InvalidCertificate(_)
+                    | tokio_rustls::rustls::Error::General(_)
+            ),
+            _ => false,
+        }
     }
 
     /// Receives a Mumble frame from the stream.
@@ -450,302 +1011,671 @@ impl Session {
         Ok((msg_type, buf))
     }
 
-    pub async fn start_main_loop(mut self) -> Result<(), Error> {
-        // Main loop for handling incoming messages
-
-        // Start ping writer task
-        let ping_interval = 15; // seconds
-        let ping_writer = self.writer.sender.clone();
+    /// Runs the session, reconnecting with exponential backoff whenever the
+    /// connection is lost. Database pools and the audio mixer are kept alive
+    /// across reconnects; server state is resynchronized from scratch.
+    ///
+    /// Returns `Ok` once `shutdown` fires and the session has shut down.
+    pub async fn start_main_loop(
+        mut self,
+        mut shutdown: oneshot::Receiver<()>,
+    ) -> Result<(), Error> {
+        let mut backoff = Backoff::new(self.reconnect.clone());
 
-        tokio::spawn(async move {
-            loop {
-                tokio::time::sleep(tokio::time::Duration::from_secs(ping_interval)).await;
+        loop {
+            let connected = tokio::select! {
+                connected = self.connect() => connected,
+                _ = &mut shutdown => return self.shut_down(None).await,
+            };
 
-                if let Err(e) = ping_writer
-                    .send(OutgoingMessage::Raw(protos::types::MESSAGE_PING, vec![]))
-                    .await
-                {
-                    warn!("Failed to send ping message: {}", e);
-                    break;
+            let result = match connected {
+                Ok(mut connection) => {
+                    let result = tokio::select! {
+                        result = self.run_connection(&mut connection) => result,
+                        _ = &mut shutdown => return self.shut_down(Some(connection)).await,
+                    };
+                    if let Err(e) = self.disconnect(connection).await {
+                        error!("Failed to clean up connection: {}", e);
+                        return Err(e);
+                    }
+                    result
                 }
+                Err(e) => Err(e),
+            };
+
+            let error = match result {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+
+            // A session that reached ServerSync counts as a success for backoff purposes
+            if self.synchronized {
+                backoff.reset();
+            }
+            self.reset_connection_state();
+
+            if !self.reconnect.enabled || !Self::is_recoverable(&error) {
+                error!("Connection failed: {}", error);
+                return Err(error);
             }
-        });
 
+            if backoff.exhausted() {
+                error!(
+                    "Giving up after {} failed reconnection attempts: {}",
+                    backoff.attempt(),
+                    error
+                );
+                return Err(error);
+            }
+
+            let delay = backoff.next_delay();
+            warn!(
+                "Connection lost: {}. Reconnecting in {:.1}s (attempt {})",
+                error,
+                delay.as_secs_f64(),
+                backoff.attempt()
+            );
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = &mut shutdown => return self.shut_down(None).await,
+            }
+        }
+    }
+
+    /// Stops playback, plays the shutdown sound and disconnects cleanly.
+    /// Anything still running after `shutdown_timeout_seconds` is abandoned.
+    async fn shut_down(mut self, connection: Option<Connection>) -> Result<(), Error> {
+        info!("Shutting down");
+        let timeout =
+            std::time::Duration::from_secs(self.behavior_settings.shutdown_timeout_seconds);
+
+        if tokio::time::timeout(timeout, self.close_connection(connection))
+            .await
+            .is_err()
+        {
+            warn!(
+                "Shutdown did not finish within {}s, disconnecting anyway",
+                timeout.as_secs()
+            );
+        }
+
+        // Dropping the streams kills their ffmpeg/sox pipelines
+        self.audio_mixer.control().stop_all_streams().await;
+        info!("Disconnected from {}", self.server_name);
+        Ok(())
+    }
+
+    /// The part of shutting down that talks to the server
+    async fn close_connection(&mut self, connection: Option<Connection>) {
+        let control = self.audio_mixer.control();
+        control.stop_all_streams().await;
+
+        let Some(connection) = connection else {
+            return;
+        };
+        connection.ping_task.abort();
+
+        let sound = self
+            .behavior_settings
+            .shutdown_sound
+            .clone()
+            .filter(|_| self.synchronized);
+        if let Some(code) = sound {
+            self.play_shutdown_sound(&code).await;
+        }
+
+        if let Err(e) = connection.writer.close().await {
+            warn!("Failed to disconnect cleanly: {}", e);
+        }
+    }
+
+    /// Plays a sound and waits for it to finish
+    async fn play_shutdown_sound(&self, code: &str) {
+        let sound = match &self.sounds_manager {
+            Some(manager) => manager.get_sound(code).await,
+            None => return,
+        };
+        let sound = match sound {
+            Ok(Some(sound)) => sound,
+            Ok(None) => {
+                warn!("Shutdown sound {} does not exist", code);
+                return;
+            }
+            Err(e) => {
+                warn!("Failed to look up shutdown sound {}: {}", code, e);
+                return;
+            }
+        };
+
+        let control = self.audio_mixer.control();
+        let id = match control.play_sound(&sound.file_path.to_string_lossy()).await {
+            Ok(
+                crate::audio::PlayOutcome::Started(id) | crate::audio::PlayOutcome::Queued(id, _),
+            ) => id,
+            Err(e) => {
+                warn!("Failed to play shutdown sound {}: {}", code, e);
+                return;
+            }
+        };
+
+        while control.stream_info(id).await.is_some() {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        // Let the mixer send the end of the transmission
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    }
+
+    /// Processes incoming messages until the connection fails or the
+    /// server stops answering pings
+    async fn run_connection(&mut self, connection: &mut Connection) -> Result<(), Error> {
         loop {
-            let (msg_type, msg_payload) = Session::receive_mumble_frame(&mut self.reader).await?;
+            let cert_check = tokio::time::sleep(self.cert_check_delay());
+            let (msg_type, msg_payload) = tokio::select! {
+                frame = Session::receive_mumble_frame(&mut connection.reader) => frame?,
+                missed = &mut connection.watchdog => {
+                    return Err(Error::ConnectionError(match missed {
+                        Ok(missed) => format!("Server did not answer {} pings in a row", missed),
+                        Err(_) => "Ping task stopped".to_string(),
+                    }));
+                }
+                Some(relayed) = self.relay_inbox.recv(), if self.synchronized => {
+                    self.run_relayed_command(relayed).await;
+                    continue;
+                }
+                _ = cert_check => {
+                    self.check_certificate_expiry();
+                    continue;
+                }
+                _ = connection.comment_refresh.tick() => {
+                    if let Err(e) = self.refresh_comment().await {
+                        warn!("Failed to update the comment: {}", e);
+                    }
+                    continue;
+                }
+            };
+            self.handle_message(msg_type, msg_payload).await?;
+        }
+    }
+
+    /// Time until the client certificate's expiry is due to be checked again.
+    /// It is checked on the first connection and then daily while connected.
+    fn cert_check_delay(&self) -> std::time::Duration {
+        let day = std::time::Duration::from_secs(24 * 60 * 60);
+        self.cert_checked
+            .map(|checked| day.saturating_sub(checked.elapsed()))
+            .unwrap_or_default()
+    }
+
+    /// Warns if the certificate on disk is close to expiring
+    fn check_certificate_expiry(&mut self) {
+        self.cert_checked = Some(std::time::Instant::now());
+        match CertificateDer::from_pem_file(&self.cert_path) {
+            Ok(cert) => client_cert::warn_if_expiring(&cert, self.cert_expiry_warning_days),
+            Err(e) => warn!(
+                "Could not read {} to check its expiry: {}",
+                self.cert_path, e
+            ),
+        }
+    }
+
+    /// Runs a command sent from another server in the bot's current channel,
+    /// with the sender's role from that server
+    async fn run_relayed_command(&mut self, relayed: RelayedCommand) {
+        info!(
+            "Running `{}` relayed from {} by {}",
+            relayed.command, relayed.sender.server, relayed.sender.name
+        );
+        let context = CommandContext {
+            triggering_user_id: None,
+            source_channel_id: self.current_channel_id,
+            is_private_message: false,
+            relayed_sender: Some(relayed.sender),
+            command_line: String::new(),
+        };
+
+        if let Err(e) = self
+            .execute_command_internal(&relayed.command, context)
+            .await
+        {
+            warn!("Relayed command failed: {}", e);
+            let html = format!(
+                "<span style=\"color: #ff4d4f;\">error: {}</span>",
+                markdown_to_html(&e.to_string())
+            );
+            if let Err(e) = self.broadcast(&html).await {
+                warn!("Failed to send error reply: {}", e);
+            }
+        }
+    }
+
+    async fn handle_message(&mut self, msg_type: u16, msg_payload: Vec<u8>) -> Result<(), Error> {
+        match msg_type {
+            protos::types::MESSAGE_VERSION => {
+                let server_version = Mumble::Version::parse_from_bytes(&msg_payload)?;
+                let format = PacketFormat::for_server(&server_version);
+                info!(
+                    "Received server version {} ({:?} voice packets)",
+                    server_version.release(),
+                    format
+                );
+                self.packet_format.set(format);
+                self.server_version = Some(server_version);
+            }
+            protos::types::MESSAGE_UDP_TUNNEL => {
+                match VoicePacket::decode_incoming(&msg_payload, self.packet_format.get()) {
+                    Some(packet) => self.voice_receiver.push_packet(packet),
+                    None => trace!("Ignoring unsupported or malformed tunneled voice packet"),
+                }
+            }
+            protos::types::MESSAGE_AUTHENTICATE => {
+                warn!("Unexpected Authenticate message received")
+            }
+            protos::types::MESSAGE_PING => {
+                let ping = Mumble::Ping::parse_from_bytes(&msg_payload)?;
+                if let Some(rtt) = self.ping.lock().unwrap().reply(&ping) {
+                    trace!("TCP ping round trip {:?}", rtt);
+                }
+                self.last_server_ping = Some(ping);
+            }
+            protos::types::MESSAGE_REJECT => {
+                let reject = Mumble::Reject::parse_from_bytes(&msg_payload)?;
+                let reject_type = reject.type_();
+                let err = format!(
+                    "Server rejected connection: {}",
+                    reject.reason.unwrap_or("(no reason provided)".into())
+                );
+                warn!("{}", err);
+
+                // A ghost session from before a server restart or a full server
+                // clears up on its own; anything else needs operator attention
+                return match reject_type {
+                    Mumble::reject::RejectType::UsernameInUse
+                    | Mumble::reject::RejectType::ServerFull
+                    | Mumble::reject::RejectType::NoNewConnections
+                    | Mumble::reject::RejectType::AuthenticatorFail
+                    | Mumble::reject::RejectType::None => Err(Error::ConnectionError(err)),
+                    _ => Err(Error::Rejected(err)),
+                };
+            }
+            protos::types::MESSAGE_SERVER_SYNC => {
+                let server_sync = Mumble::ServerSync::parse_from_bytes(&msg_payload)?;
+
+                // Set current user and channel from server sync
+                if let Some(session_id) = server_sync.session {
+                    self.current_user_id = Some(session_id);
+                    debug!("Set current user ID to: {}", session_id);
+
+                    // Try to set channel from user state
+                    self.try_set_channel_from_user_state();
+
+                    // Fallback: set to root channel if we still don't have one
+                    if self.current_channel_id.is_none() {
+                        self.current_channel_id = Some(ROOT_CHANNEL_ID);
+                        debug!("Set fallback channel ID to root channel (0)");
+                    }
+                }
+                if let Some(max_bandwidth) = server_sync.max_bandwidth {
+                    self.apply_bandwidth_limit(max_bandwidth);
+                }
+
+                self.synchronized = true;
+
+                info!(
+                    "Server synchronized. Welcome message: {}",
+                    server_sync.welcome_text()
+                );
+
+                self.move_to_starting_channel().await;
+
+                if self.behavior_settings.context_actions {
+                    self.register_context_actions().await?;
+                }
 
-            match msg_type {
-                protos::types::MESSAGE_VERSION => {
-                    self.server_version = Some(Mumble::Version::parse_from_bytes(&msg_payload)?);
-                    info!("Received server version");
+                self.set_up_profile().await;
+
+                if self.permissions.uses_groups() {
+                    self.query_root_permissions().await?;
+                }
+            }
+            protos::types::MESSAGE_CRYPT_SETUP => {
+                let crypt_setup = Mumble::CryptSetup::parse_from_bytes(&msg_payload)?;
+                debug!("Received voice crypt data");
+                self.handle_crypt_setup(crypt_setup).await?;
+            }
+            protos::types::MESSAGE_CONTEXT_ACTION => {
+                let action = Mumble::ContextAction::parse_from_bytes(&msg_payload)?;
+                // The message names the user or channel the action was used on,
+                // not the user who picked it
+                if let Err(e) = self.handle_context_action(&action, None).await {
+                    warn!("Context action '{}' failed: {}", action.action(), e);
+                }
+            }
+            protos::types::MESSAGE_CODEC_VERSION => {}
+            protos::types::MESSAGE_PERMISSION_QUERY => {
+                let query = Mumble::PermissionQuery::parse_from_bytes(&msg_payload)?;
+                if !self.permissions.uses_groups() {
+                    return Ok(());
                 }
-                protos::types::MESSAGE_UDP_TUNNEL => {}
-                protos::types::MESSAGE_AUTHENTICATE => {
-                    warn!("Unexpected Authenticate message received")
+
+                if query.flush() {
+                    // ACLs changed somewhere, so group memberships may have too
+                    self.query_root_permissions().await?;
+                } else if query.channel_id == Some(ROOT_CHANNEL_ID) {
+                    if query.permissions() & permissions::PERMISSION_WRITE != 0 {
+                        let acl = Mumble::ACL {
+                            channel_id: Some(ROOT_CHANNEL_ID),
+                            query: Some(true),
+                            ..Default::default()
+                        };
+                        self.send_raw(protos::types::MESSAGE_ACL, acl.write_to_bytes()?)
+                            .await?;
+                    } else {
+                        warn!(
+                            "Mumble group roles are configured, but the bot lacks Write permission on the root channel to read its groups"
+                        );
+                    }
                 }
-                protos::types::MESSAGE_PING => {
-                    let ping = Mumble::Ping::parse_from_bytes(&msg_payload)?;
-                    self.last_server_ping = Some(ping);
+            }
+            protos::types::MESSAGE_ACL => {
+                let acl = Mumble::ACL::parse_from_bytes(&msg_payload)?;
+                if acl.channel_id == Some(ROOT_CHANNEL_ID) {
+                    self.permissions.update_groups(&acl);
+                    info!("Loaded {} Mumble groups for roles", acl.groups.len());
+                }
+            }
+            protos::types::MESSAGE_SERVER_CONFIG => {
+                let config = Mumble::ServerConfig::parse_from_bytes(&msg_payload)?;
+                if let Some(max_bandwidth) = config.max_bandwidth {
+                    self.apply_bandwidth_limit(max_bandwidth);
+                }
+
+                let mut limits = self.text_limits.get();
+                limits.apply(&config);
+                debug!(
+                    "Text messages limited to {:?} characters (HTML allowed: {})",
+                    limits.message_length, limits.allow_html
+                );
+                self.text_limits.set(limits);
+            }
+            protos::types::MESSAGE_PERMISSION_DENIED => {
+                let denied = Mumble::PermissionDenied::parse_from_bytes(&msg_payload)?;
+                warn!(
+                    "Server denied a request ({:?}): {}",
+                    denied.type_(),
+                    denied.reason()
+                );
+            }
+            protos::types::MESSAGE_CHANNEL_STATE => {
+                let channel_state = Mumble::ChannelState::parse_from_bytes(&msg_payload)?;
+                if channel_state.channel_id.is_none() {
+                    warn!("Received ChannelState message without channel_id");
+                    return Ok(());
+                }
+
+                debug!(
+                    "Received channel state for {}",
+                    channel_state.name.as_ref().unwrap()
+                );
+                self.channels
+                    .insert(channel_state.channel_id.unwrap(), channel_state);
+            }
+            protos::types::MESSAGE_CHANNEL_REMOVE => {
+                let channel_remove = Mumble::ChannelRemove::parse_from_bytes(&msg_payload)?;
+                if channel_remove.channel_id.is_none() {
+                    warn!("Received ChannelRemove message without channel_id");
+                    return Ok(());
                 }
-                protos::types::MESSAGE_REJECT => {
-                    let reject = Mumble::Reject::parse_from_bytes(&msg_payload)?;
-                    let err = format!(
-                        "Server rejected connection: {}",
-                        reject.reason.unwrap_or("(no reason provided)".into())
-                    );
-                    warn!("{}", err);
-                    return Err(Error::ConnectionError(err));
+
+                self.channels.remove(&channel_remove.channel_id.unwrap());
+            }
+            protos::types::MESSAGE_USER_STATE => {
+                let user_state = Mumble::UserState::parse_from_bytes(&msg_payload)?;
+                if user_state.session.is_none() {
+                    warn!("Received UserState message without session");
+                    return Ok(());
                 }
-                protos::types::MESSAGE_SERVER_SYNC => {
-                    let server_sync = Mumble::ServerSync::parse_from_bytes(&msg_payload)?;
 
-                    // Set current user and channel from server sync
-                    if let Some(session_id) = server_sync.session {
-                        self.current_user_id = Some(session_id);
-                        debug!("Set current user ID to: {}", session_id);
+                let session_id = user_state.session.unwrap();
 
-                        // Try to set channel from user state
-                        self.try_set_channel_from_user_state();
+                debug!(
+                    "Received user state for {} (session: {})",
+                    user_state.name.as_ref().unwrap_or(&"(unknown)".to_string()),
+                    session_id
+                );
 
-                        // Fallback: set to root channel if we still don't have one
-                        if self.current_channel_id.is_none() {
-                            self.current_channel_id = Some(0);
-                            debug!("Set fallback channel ID to root channel (0)");
-                        }
-                    }
-                    if let Some(max_bandwidth) = server_sync.max_bandwidth {
-                        // We can use this or other fields if needed
-                        debug!("Server max bandwidth: {}", max_bandwidth);
+                // Check if this is a new user joining (not already in our users map)
+                let is_new_user = !self.users.contains_key(&session_id)
+                    && Some(session_id) != self.current_user_id;
+                // The server leaves out the channel of users in the root channel
+                let previous_channel = self
+                    .users
+                    .get(&session_id)
+                    .map(|user| user.channel_id.unwrap_or(ROOT_CHANNEL_ID));
+
+                // Updates only carry the fields that changed, so merge them into
+                // what we know to keep the name, registration and certificate hash
+                let updated_user_state = match self.users.get_mut(&session_id) {
+                    Some(existing) => {
+                        existing.merge_from_bytes(&msg_payload)?;
+                        existing.clone()
                     }
+                    None => user_state.clone(),
+                };
+                self.users.insert(session_id, updated_user_state.clone());
 
-                    info!(
-                        "Server synchronized. Welcome message: {}",
-                        server_sync.welcome_text()
-                    );
+                if user_state.name.is_some()
+                    || user_state.user_id.is_some()
+                    || user_state.hash.is_some()
+                {
+                    self.record_user_identity(&updated_user_state).await;
                 }
-                protos::types::MESSAGE_CRYPT_SETUP => {
-                    let crypt_setup = Mumble::CryptSetup::parse_from_bytes(&msg_payload)?;
-                    self.crypt_setup = Some(crypt_setup);
 
-                    debug!("Received voice crypt data");
-                }
-                protos::types::MESSAGE_CODEC_VERSION => {}
-                protos::types::MESSAGE_PERMISSION_QUERY => {}
-                protos::types::MESSAGE_CHANNEL_STATE => {
-                    let channel_state = Mumble::ChannelState::parse_from_bytes(&msg_payload)?;
-                    if channel_state.channel_id.is_none() {
-                        warn!("Received ChannelState message without channel_id");
-                        continue;
+                // Mirror channel changes of the user we are following
+                let followed_move = user_state.channel_id.filter(|channel_id| {
+                    self.is_follow_target(&updated_user_state)
+                        && Some(session_id) != self.current_user_id
+                        && self.current_channel_id != Some(*channel_id)
+                });
+                if let Some(channel_id) = followed_move {
+                    debug!("Following user {} to channel {}", session_id, channel_id);
+                    if let Err(e) = self.move_to_channel(channel_id).await {
+                        warn!("Failed to follow user {}: {}", session_id, e);
                     }
-
-                    debug!(
-                        "Received channel state for {}",
-                        channel_state.name.as_ref().unwrap()
-                    );
-                    self.channels
-                        .insert(channel_state.channel_id.unwrap(), channel_state);
                 }
-                protos::types::MESSAGE_CHANNEL_REMOVE => {
-                    let channel_remove = Mumble::ChannelRemove::parse_from_bytes(&msg_payload)?;
-                    if channel_remove.channel_id.is_none() {
-                        warn!("Received ChannelRemove message without channel_id");
-                        continue;
-                    }
 
-                    self.channels.remove(&channel_remove.channel_id.unwrap());
+                // If this is our user, try to update current channel
+                if Some(session_id) == self.current_user_id {
+                    self.try_set_channel_from_user_state();
                 }
-                protos::types::MESSAGE_USER_STATE => {
-                    let user_state = Mumble::UserState::parse_from_bytes(&msg_payload)?;
-                    if user_state.session.is_none() {
-                        warn!("Received UserState message without session");
-                        continue;
-                    }
-
-                    let session_id = user_state.session.unwrap();
-
+                // Also try if we haven't identified our user yet but this might be us
+                // (this handles the case where USER_STATE comes before SERVER_SYNC)
+                else if self.current_user_id.is_none() && self.current_channel_id.is_none() {
                     debug!(
-                        "Received user state for {} (session: {})",
-                        user_state.name.as_ref().unwrap_or(&"(unknown)".to_string()),
+                        "Received user state for session {} before knowing our own ID",
                         session_id
                     );
-
-                    // Check if this is a new user joining (not already in our users map)
-                    let is_new_user = !self.users.contains_key(&session_id)
-                        && Some(session_id) != self.current_user_id;
-
-                    // Store the user state, but preserve username if it exists in previous state
-                    let mut updated_user_state = user_state.clone();
-                    if updated_user_state.name.is_none()
-                        || updated_user_state.name.as_ref().unwrap().is_empty()
-                    {
-                        // If the new state has no username, try to preserve the old one
-                        if let Some(existing_user) = self.users.get(&session_id) {
-                            if let Some(existing_name) = &existing_user.name {
-                                if !existing_name.is_empty() {
-                                    debug!(
-                                        "Preserving username '{}' for session {}",
-                                        existing_name, session_id
-                                    );
-                                    updated_user_state.name = Some(existing_name.clone());
-                                }
-                            }
-                        }
-                    }
-
-                    self.users.insert(session_id, updated_user_state.clone());
-
-                    // If this is our user, try to update current channel
-                    if Some(session_id) == self.current_user_id {
-                        self.try_set_channel_from_user_state();
-                    }
-                    // Also try if we haven't identified our user yet but this might be us
-                    // (this handles the case where USER_STATE comes before SERVER_SYNC)
-                    else if self.current_user_id.is_none() && self.current_channel_id.is_none() {
-                        debug!(
-                            "Received user state for session {} before knowing our own ID",
-                            session_id
-                        );
-                    }
-                    // Handle new user joining - play their greeting sound
-                    else if is_new_user {
-                        let user_name = updated_user_state
-                            .name
-                            .as_ref()
-                            .unwrap_or(&"(unknown)".to_string())
-                            .clone();
-                        info!("New user joined: {} (session: {})", user_name, session_id);
-
-                        // Play greeting sound in the background only if auto_greetings is enabled
-                        if !matches!(self.behavior_settings.auto_greetings, GreetingMode::None) {
-                            if let Err(e) = self.play_user_greeting(session_id).await {
-                                warn!("Failed to play greeting for user {}: {}", user_name, e);
-                            }
-                        } else {
-                            debug!(
-                                "Auto greetings disabled, skipping greeting for user {}",
-                                user_name
-                            );
-                        }
-                    }
                 }
-                protos::types::MESSAGE_USER_REMOVE => {
-                    let user_remove = Mumble::UserRemove::parse_from_bytes(&msg_payload)?;
-                    if user_remove.session.is_none() {
-                        warn!("Received UserRemove message without session");
-                        continue;
-                    }
-
-                    let session_id = user_remove.session.unwrap();
-
-                    // Get user info before removing them
-                    let user_name = self
-                        .users
-                        .get(&session_id)
-                        .and_then(|user| user.name.as_ref())
+                // Handle other users arriving or leaving - play their greeting or farewell
+                else {
+                    let user_name = updated_user_state
+                        .name
+                        .as_ref()
                         .unwrap_or(&"(unknown)".to_string())
                         .clone();
+                    if is_new_user {
+                        info!("New user joined: {} (session: {})", user_name, session_id);
+                    }
 
-                    info!("User left: {} (session: {})", user_name, session_id);
-
-                    // Play farewell sound before removing user data only if auto_farewells is enabled
-                    if !matches!(self.behavior_settings.auto_farewells, FarewellMode::None) {
-                        if let Err(e) = self.play_user_farewell(session_id).await {
-                            warn!("Failed to play farewell for user {}: {}", user_name, e);
-                        }
-                    } else {
+                    let channel = updated_user_state.channel_id.unwrap_or(ROOT_CHANNEL_ID);
+                    let entered_channel = self.current_channel_id == Some(channel)
+                        && previous_channel != Some(channel);
+                    let left_channel = previous_channel.is_some()
+                        && previous_channel == self.current_channel_id
+                        && previous_channel != Some(channel);
+
+                    let greet = self
+                        .behavior_settings
+                        .greeting_trigger
+                        .fires(is_new_user, entered_channel);
+                    let farewell =
+                        left_channel && self.behavior_settings.farewell_trigger.fires(false, true);
+
+                    // Play greeting sound in the background only if auto_greetings is enabled
+                    if greet && !matches!(self.behavior_settings.auto_greetings, GreetingMode::None)
+                    {
+                        self.automatic_greeting(session_id, &user_name, is_new_user)
+                            .await;
+                    } else if greet {
                         debug!(
-                            "Auto farewells disabled, skipping farewell for user {}",
+                            "Auto greetings disabled, skipping greeting for user {}",
                             user_name
                         );
+                    } else if farewell {
+                        info!("User {} left the bot's channel", user_name);
+                        self.automatic_farewell(session_id, &user_name).await;
                     }
-
-                    self.users.remove(&session_id);
                 }
-                protos::types::MESSAGE_TEXT_MESSAGE => {
-                    let text_message = Mumble::TextMessage::parse_from_bytes(&msg_payload)?;
+            }
+            protos::types::MESSAGE_USER_REMOVE => {
+                let user_remove = Mumble::UserRemove::parse_from_bytes(&msg_payload)?;
+                if user_remove.session.is_none() {
+                    warn!("Received UserRemove message without session");
+                    return Ok(());
+                }
 
-                    if text_message.actor.is_none() {
-                        warn!("Received TextMessage without actor");
-                        continue;
-                    }
+                let session_id = user_remove.session.unwrap();
+
+                // Get user info before removing them
+                let user_name = self
+                    .users
+                    .get(&session_id)
+                    .and_then(|user| user.name.as_ref())
+                    .unwrap_or(&"(unknown)".to_string())
+                    .clone();
+
+                info!("User left: {} (session: {})", user_name, session_id);
 
-                    let actor_id = text_message.actor.unwrap();
-                    let name = self
+                let was_in_channel = self.current_channel_id.is_some()
+                    && self
                         .users
-                        .get(&actor_id)
-                        .and_then(|user| user.name.clone())
-                        .unwrap_or_else(|| "(unknown)".to_string());
+                        .get(&session_id)
+                        .map(|user| user.channel_id.unwrap_or(ROOT_CHANNEL_ID))
+                        == self.current_channel_id;
+
+                // Play farewell sound before removing user data only if auto_farewells is enabled
+                if !self
+                    .behavior_settings
+                    .farewell_trigger
+                    .fires(true, was_in_channel)
+                {
+                    debug!(
+                        "User {} left from another channel, skipping farewell",
+                        user_name
+                    );
+                } else if !matches!(self.behavior_settings.auto_farewells, FarewellMode::None) {
+                    self.automatic_farewell(session_id, &user_name).await;
+                } else {
+                    debug!(
+                        "Auto farewells disabled, skipping farewell for user {}",
+                        user_name
+                    );
+                }
 
-                    let message_text = text_message
-                        .message
-                        .as_ref()
-                        .unwrap_or(&"(no message)".to_string())
-                        .clone();
+                let key = self.cooldown_key(session_id);
+                self.greeting_cooldowns
+                    .lock()
+                    .unwrap()
+                    .user_disconnected(&key, tokio::time::Instant::now());
+                self.users.remove(&session_id);
+                self.voice_receiver.speaker_left(session_id);
+            }
+            protos::types::MESSAGE_TEXT_MESSAGE => {
+                let text_message = Mumble::TextMessage::parse_from_bytes(&msg_payload)?;
 
-                    info!("{} > {}", name, message_text);
+                if text_message.actor.is_none() {
+                    warn!("Received TextMessage without actor");
+                    return Ok(());
+                }
 
-                    // Check if this is a command (starts with !)
-                    if message_text.starts_with("!") {
-                        // Determine if this is a private message or channel message
-                        let is_private_message = !text_message.session.is_empty();
-                        let source_channel_id = if is_private_message {
-                            None
-                        } else {
-                            text_message.channel_id.first().copied()
-                        };
+                let actor_id = text_message.actor.unwrap();
+                let name = self
+                    .users
+                    .get(&actor_id)
+                    .and_then(|user| user.name.clone())
+                    .unwrap_or_else(|| "(unknown)".to_string());
+
+                let message_text = text_message
+                    .message
+                    .as_ref()
+                    .unwrap_or(&"(no message)".to_string())
+                    .clone();
+
+                info!("{} > {}", name, message_text);
+
+                // Check if this is a command (starts with !)
+                if message_text.starts_with("!") {
+                    // Determine if this is a private message or channel message
+                    let is_private_message = !text_message.session.is_empty();
+                    let source_channel_id = if is_private_message {
+                        None
+                    } else {
+                        text_message.channel_id.first().copied()
+                    };
 
-                        // Check if private commands are allowed
-                        if is_private_message && !self.behavior_settings.allow_private_commands {
-                            debug!(
-                                "Private command from {} ignored (private commands disabled)",
-                                name
+                    // Check if private commands are allowed
+                    if is_private_message && !self.behavior_settings.allow_private_commands {
+                        debug!(
+                            "Private command from {} ignored (private commands disabled)",
+                            name
+                        );
+                        let error_msg = "Private commands are disabled on this bot.";
+                        if let Err(reply_err) = self.send_private_message(actor_id, error_msg).await
+                        {
+                            warn!(
+                                "Failed to send private command disabled message: {}",
+                                reply_err
                             );
-                            let error_msg = "Private commands are disabled on this bot.";
-                            if let Err(reply_err) =
-                                self.send_private_message(actor_id, error_msg).await
-                            {
-                                warn!(
-                                    "Failed to send private command disabled message: {}",
-                                    reply_err
-                                );
-                            }
-                            continue;
                         }
+                        return Ok(());
+                    }
 
-                        // Create command context
-                        let context = CommandContext {
-                            triggering_user_id: Some(actor_id),
-                            source_channel_id,
-                            is_private_message,
-                        };
+                    // Create command context
+                    let context = CommandContext {
+                        triggering_user_id: Some(actor_id),
+                        source_channel_id,
+                        is_private_message,
+                        relayed_sender: None,
+                        command_line: String::new(),
+                    };
 
-                        // Execute command - we need to handle this carefully due to borrowing
-                        match self.execute_command_internal(&message_text, context).await {
-                            Ok(_) => {
-                                debug!("Command executed successfully");
-                            }
-                            Err(e) => {
-                                warn!("Command execution failed: {}", e);
-                                // Send error message back to user
-                                let error_msg = format!("error: {}", e);
-                                if let Err(reply_err) =
-                                    self.send_error_reply(&error_msg, actor_id).await
-                                {
-                                    warn!("Failed to send error reply: {}", reply_err);
-                                }
+                    // Execute command - we need to handle this carefully due to borrowing
+                    match self.execute_command_internal(&message_text, context).await {
+                        Ok(_) => {
+                            debug!("Command executed successfully");
+                        }
+                        Err(e) => {
+                            warn!("Command execution failed: {}", e);
+                            // Send error message back to user
+                            let error_msg = format!("error: {}", e);
+                            if let Err(reply_err) =
+                                self.send_error_reply(&error_msg, actor_id).await
+                            {
+                                warn!("Failed to send error reply: {}", reply_err);
                             }
                         }
                     }
                 }
-                _ => {
-                    warn!(
-                        "Received unknown message type {} with payload length {}",
-                        msg_type,
-                        msg_payload.len()
-                    );
-                }
+            }
+            _ => {
+                warn!(
+                    "Received unknown message type {} with payload length {}",
+                    msg_type,
+                    msg_payload.len()
+                );
             }
         }
+
+        Ok(())
     }
 
     async fn execute_command_internal(
@@ -759,9 +1689,16 @@ impl Session {
         );
 
         // Execute the command using self directly as SessionTools
-        self.command_executor
+        let result = self
+            .command_executor
             .execute(command_text, self, context)
-            .await
+            .await;
+
+        // Commands may have added or removed sounds
+        if let Err(e) = self.refresh_comment().await {
+            warn!("Failed to update the comment: {}", e);
+        }
+        result
     }
 
     async fn send_error_reply(&self, error_msg: &str, actor_id: u32) -> Result<(), Error> {
@@ -772,6 +1709,219 @@ impl Session {
         self.send_private_message(actor_id, &html).await
     }
 
+    async fn send_raw(&self, msg_type: u16, payload: Vec<u8>) -> Result<(), Error> {
+        self.outgoing
+            .send(OutgoingMessage::Raw(msg_type, payload))
+            .await
+            .map_err(|e| Error::ConnectionError(format!("Failed to send message: {}", e)))
+    }
+
+    /// Asks for the bot's permissions on the root channel, which decide
+    /// whether its groups can be read for roles
+    async fn query_root_permissions(&self) -> Result<(), Error> {
+        let query = Mumble::PermissionQuery {
+            channel_id: Some(ROOT_CHANNEL_ID),
+            ..Default::default()
+        };
+        self.send_raw(
+            protos::types::MESSAGE_PERMISSION_QUERY,
+            query.write_to_bytes()?,
+        )
+        .await
+    }
+
+    /// Registers the bot and sets its avatar and comment, as configured
+    async fn set_up_profile(&self) {
+        let registered = self
+            .own_state()
+            .is_some_and(|state| state.user_id.is_some());
+        if self.profile_settings.register && !registered {
+            info!("Registering with the server");
+            if let Err(e) = self.register_self().await {
+                warn!("Failed to register: {}", e);
+            }
+        }
+
+        let avatar = match &self.profile_settings.avatar {
+            Some(avatar) => self.set_avatar(Some(Path::new(avatar))).await,
+            None => Ok(()),
+        };
+        if let Err(e) = avatar {
+            warn!("Failed to set the avatar: {}", e);
+        }
+
+        if let Err(e) = self.refresh_comment().await {
+            warn!("Failed to set the comment: {}", e);
+        }
+    }
+
+    /// The bot's own user state
+    fn own_state(&self) -> Option<&Mumble::UserState> {
+        self.users.get(&self.current_user_id?)
+    }
+
+    /// Adds the bot's entries to the users' right-click menus
+    async fn register_context_actions(&self) -> Result<(), Error> {
+        for modify in context_actions::registrations() {
+            self.outgoing
+                .send(OutgoingMessage::Raw(
+                    protos::types::MESSAGE_CONTEXT_ACTION_MODIFY,
+                    modify.write_to_bytes()?,
+                ))
+                .await
+                .map_err(|e| {
+                    Error::ConnectionError(format!("Failed to register context action: {}", e))
+                })?;
+        }
+        debug!(
+            "Registered {} context actions",
+            context_actions::ACTIONS.len()
+        );
+        Ok(())
+    }
+
+    /// Runs a context action picked from a user's or channel's menu by `sender`.
+    /// Actions from an unknown sender are refused, since they would otherwise
+    /// run with the bot's rights.
+    async fn handle_context_action(
+        &mut self,
+        action: &Mumble::ContextAction,
+        sender: Option<u32>,
+    ) -> Result<(), Error> {
+        let Some(entry) = context_actions::find(action.action()) else {
+            debug!("Ignoring unknown context action '{}'", action.action());
+            return Ok(());
+        };
+        let Some(sender) = sender else {
+            warn!(
+                "Refusing context action '{}': the server did not say who picked it",
+                entry.action
+            );
+            return Ok(());
+        };
+        info!(
+            "Context action '{}' by session {} (user: {:?}, channel: {:?})",
+            entry.action, sender, action.session, action.channel_id
+        );
+
+        // The executor checks the sender's role like for a typed command
+        let context = CommandContext {
+            triggering_user_id: Some(sender),
+            source_channel_id: action.channel_id.or(self.current_channel_id),
+            is_private_message: false,
+            relayed_sender: None,
+            command_line: String::new(),
+        };
+
+        match entry.kind {
+            context_actions::ActionKind::Command(command) => {
+                self.execute_command_internal(&format!("!{}", command), context)
+                    .await
+            }
+            context_actions::ActionKind::Greeting => {
+                let Some(session) = action.session else {
+                    return Err(Error::InvalidInput(
+                        "greeting action used without a target user".to_string(),
+                    ));
+                };
+                self.run_greeting_command(session, context).await
+            }
+        }
+    }
+
+    /// Runs a user's custom greeting command on request, regardless of
+    /// `auto_greetings`, with the rights of whoever asked for it in `context`
+    async fn run_greeting_command(
+        &mut self,
+        user_id: u32,
+        context: CommandContext,
+    ) -> Result<(), Error> {
+        let user = self
+            .users
+            .get(&user_id)
+            .ok_or_else(|| Error::InvalidInput(format!("unknown user session {}", user_id)))?;
+        let name = user.name.clone().unwrap_or_else(|| "(unknown)".to_string());
+        let identity = Identity::of(user, &self.server_name);
+        let Some(manager) = self.user_settings_manager.clone() else {
+            return Ok(());
+        };
+
+        let greeting = match &identity {
+            Some(identity) => manager.get_greeting(identity).await?,
+            None => None,
+        };
+        match greeting {
+            Some(greeting_command) => {
+                self.command_executor
+                    .execute(&greeting_command, self, context)
+                    .await
+            }
+            None => {
+                self.broadcast(&markdown_to_html(&format!(" {} has no greeting set", name)))
+                    .await
+            }
+        }
+    }
+
+    /// Remembers which name a user's identity was last seen with, letting them
+    /// claim settings saved under that name before identities were tracked
+    async fn record_user_identity(&self, user_state: &Mumble::UserState) {
+        let (Some(manager), Some(identity), Some(name)) = (
+            &self.users_manager,
+            Identity::of(user_state, &self.server_name),
+            user_state.name.as_deref().filter(|name| !name.is_empty()),
+        ) else {
+            return;
+        };
+
+        match manager.record_seen(&identity, name).await {
+            Ok(0) => {}
+            Ok(claimed) => info!(
+                "User {} ({}) claimed {} settings, sounds and aliases saved under their name",
+                name, identity, claimed
+            ),
+            Err(e) => warn!("Failed to record identity {} for {}: {}", identity, name, e),
+        }
+    }
+
+    fn is_follow_target(&self, user_state: &Mumble::UserState) -> bool {
+        let target = self.follow_target.lock().ok().and_then(|t| t.clone());
+        match (target, user_state.name.as_ref()) {
+            (Some(target), Some(name)) => name.eq_ignore_ascii_case(&target),
+            _ => false,
+        }
+    }
+
+    /// Moves to the followed user's channel if they are online, otherwise to
+    /// the configured home channel
+    async fn move_to_starting_channel(&mut self) {
+        let followed_channel = self
+            .users
+            .iter()
+            .filter(|(session, _)| Some(**session) != self.current_user_id)
+            .find(|(_, user)| self.is_follow_target(user))
+            .and_then(|(_, user)| user.channel_id);
+
+        let target = match (followed_channel, &self.home_channel) {
+            (Some(channel_id), _) => Some(channel_id),
+            (None, Some(home)) => {
+                let channel_id = find_channel(&self.channels, home);
+                if channel_id.is_none() {
+                    warn!("Home channel '{}' not found on this server", home);
+                }
+                channel_id
+            }
+            (None, None) => None,
+        };
+
+        if let Some(channel_id) = target.filter(|id| self.current_channel_id != Some(*id)) {
+            info!("Moving to channel {}", channel_id);
+            if let Err(e) = self.move_to_channel(channel_id).await {
+                warn!("Failed to move to channel {}: {}", channel_id, e);
+            }
+        }
+    }
+
     /// Attempts to set the current channel ID from our user state
     fn try_set_channel_from_user_state(&mut self) {
         if let Some(user_id) = self.current_user_id {
@@ -830,8 +1980,16 @@ impl Session {
         );
 
         if let Some(user_settings_manager) = &self.user_settings_manager {
-            // Try to get the user's custom greeting
-            match user_settings_manager.get_greeting(&username).await {
+            // Try to get the user's custom greeting; users without an identity can't have one
+            let greeting = match self
+                .users
+                .get(&user_id)
+                .and_then(|user| Identity::of(user, &self.server_name))
+            {
+                Some(identity) => user_settings_manager.get_greeting(&identity).await,
+                None => Ok(None),
+            };
+            match greeting {
                 Ok(Some(greeting_command)) => {
                     info!(
                         "Playing custom greeting for user {} ({}): {}",
@@ -843,6 +2001,8 @@ impl Session {
                         triggering_user_id: Some(user_id),
                         source_channel_id: self.current_channel_id,
                         is_private_message: false,
+                        relayed_sender: None,
+                        command_line: String::new(),
                     };
 
                     // Execute the greeting command
@@ -904,6 +2064,56 @@ impl Session {
         Ok(())
     }
 
+    /// Key greetings and farewells are rate limited by: the user's identity,
+    /// or their name if they have none
+    fn cooldown_key(&self, session_id: u32) -> String {
+        let user = self.users.get(&session_id);
+        user.and_then(|user| Identity::of(user, &self.server_name))
+            .unwrap_or_else(|| {
+                Identity::Legacy(user.and_then(|u| u.name.clone()).unwrap_or_default())
+            })
+            .to_string()
+    }
+
+    /// Greets an arriving user unless cooldowns, a reconnect or quiet hours hold it back
+    async fn automatic_greeting(&self, session_id: u32, user_name: &str, joined_server: bool) {
+        let key = self.cooldown_key(session_id);
+        let allowed = self.greeting_cooldowns.lock().unwrap().check_greeting(
+            &key,
+            joined_server,
+            tokio::time::Instant::now(),
+            chrono::Local::now().time(),
+        );
+
+        match allowed {
+            Ok(()) => {
+                if let Err(e) = self.play_user_greeting(session_id).await {
+                    warn!("Failed to play greeting for user {}: {}", user_name, e);
+                }
+            }
+            Err(reason) => debug!("Skipping greeting for user {}: {:?}", user_name, reason),
+        }
+    }
+
+    /// Says farewell to a departing user unless cooldowns or quiet hours hold it back
+    async fn automatic_farewell(&self, session_id: u32, user_name: &str) {
+        let key = self.cooldown_key(session_id);
+        let allowed = self.greeting_cooldowns.lock().unwrap().check_farewell(
+            &key,
+            tokio::time::Instant::now(),
+            chrono::Local::now().time(),
+        );
+
+        match allowed {
+            Ok(()) => {
+                if let Err(e) = self.play_user_farewell(session_id).await {
+                    warn!("Failed to play farewell for user {}: {}", user_name, e);
+                }
+            }
+            Err(reason) => debug!("Skipping farewell for user {}: {:?}", user_name, reason),
+        }
+    }
+
     /// Plays a farewell sound for a user who just left
     async fn play_user_farewell(&self, user_id: u32) -> Result<(), Error> {
         // Check if farewells are enabled
@@ -941,8 +2151,16 @@ impl Session {
         );
 
         if let Some(user_settings_manager) = &self.user_settings_manager {
-            // Try to get the user's custom farewell
-            match user_settings_manager.get_farewell(&username).await {
+            // Try to get the user's custom farewell; users without an identity can't have one
+            let farewell = match self
+                .users
+                .get(&user_id)
+                .and_then(|user| Identity::of(user, &self.server_name))
+            {
+                Some(identity) => user_settings_manager.get_farewell(&identity).await,
+                None => Ok(None),
+            };
+            match farewell {
                 Ok(Some(farewell_command)) => {
                     info!(
                         "Playing custom farewell for user {} ({}): {}",
@@ -954,6 +2172,8 @@ impl Session {
                         triggering_user_id: Some(user_id),
                         source_channel_id: self.current_channel_id,
                         is_private_message: false,
+                        relayed_sender: None,
+                        command_line: String::new(),
                     };
 
                     // Execute the farewell command
@@ -1022,6 +2242,8 @@ impl Session {
             triggering_user_id: None, // System-triggered
             source_channel_id: self.current_channel_id,
             is_private_message: false,
+            relayed_sender: None,
+            command_line: String::new(),
         };
 
         if let Err(e) = self
@@ -1037,7 +2259,7 @@ impl Session {
 
 #[async_trait::async_trait]
 impl SessionTools for Session {
-    async fn play_sound(&self, file_path: &str) -> Result<(), Error> {
+    async fn play_sound(&self, file_path: &str) -> Result<crate::audio::PlayOutcome, Error> {
         self.audio_mixer
             .control()
             .play_sound(file_path)
@@ -1049,7 +2271,7 @@ impl SessionTools for Session {
         &self,
         file_path: &str,
         effects: &[crate::audio::effects::AudioEffect],
-    ) -> Result<(), Error> {
+    ) -> Result<crate::audio::PlayOutcome, Error> {
         self.audio_mixer
             .control()
             .play_sound_with_effects(file_path, effects)
@@ -1059,7 +2281,11 @@ impl SessionTools for Session {
             })
     }
 
-    async fn play_sound_with_code(&self, file_path: &str, sound_code: &str) -> Result<(), Error> {
+    async fn play_sound_with_code(
+        &self,
+        file_path: &str,
+        sound_code: &str,
+    ) -> Result<crate::audio::PlayOutcome, Error> {
         let result = self.play_sound(file_path).await;
         if result.is_ok() {
             self.record_sound_played(sound_code);
@@ -1072,7 +2298,7 @@ impl SessionTools for Session {
         file_path: &str,
         effects: &[crate::audio::effects::AudioEffect],
         sound_code: &str,
-    ) -> Result<(), Error> {
+    ) -> Result<crate::audio::PlayOutcome, Error> {
         let result = self.play_sound_with_effects(file_path, effects).await;
         if result.is_ok() {
             self.record_sound_played(sound_code);
@@ -1085,9 +2311,12 @@ impl SessionTools for Session {
         Ok(())
     }
 
+    fn audio_control(&self) -> crate::audio::AudioMixerControl {
+        self.audio_mixer.control()
+    }
+
     async fn send_channel_message(&self, channel_id: u32, message: &str) -> Result<(), Error> {
-        self.writer
-            .sender
+        self.outgoing
             .send(OutgoingMessage::TextMessage(
                 message.to_string(),
                 channel_id,
@@ -1105,8 +2334,7 @@ impl SessionTools for Session {
     }
 
     async fn send_private_message(&self, user_id: u32, message: &str) -> Result<(), Error> {
-        self.writer
-            .sender
+        self.outgoing
             .send(OutgoingMessage::PrivMessage(message.to_string(), user_id))
             .await
             .map_err(|e| Error::ConnectionError(format!("Failed to send private message: {}", e)))
@@ -1137,6 +2365,23 @@ impl SessionTools for Session {
         self.users.get(&user_id)
     }
 
+    fn get_user_identity(&self, user_id: u32) -> Option<crate::users::Identity> {
+        self.users
+            .get(&user_id)
+            .and_then(|user| Identity::of(user, &self.server_name))
+    }
+
+    fn find_user_by_name(&self, name: &str) -> Option<u32> {
+        self.users
+            .iter()
+            .find(|(_, user)| {
+                user.name
+                    .as_ref()
+                    .is_some_and(|n| n.eq_ignore_ascii_case(name))
+            })
+            .map(|(session_id, _)| *session_id)
+    }
+
     fn get_channel_info(
         &self,
         channel_id: u32,
@@ -1144,6 +2389,44 @@ impl SessionTools for Session {
         self.channels.get(&channel_id)
     }
 
+    fn find_channel(&self, query: &str) -> Option<u32> {
+        find_channel(&self.channels, query)
+    }
+
+    async fn move_to_channel(&self, channel_id: u32) -> Result<(), Error> {
+        let session = self
+            .current_user_id
+            .ok_or_else(|| Error::ConnectionError("Not connected to a server".to_string()))?;
+        let payload = Mumble::UserState {
+            session: Some(session),
+            channel_id: Some(channel_id),
+            ..Default::default()
+        }
+        .write_to_bytes()?;
+
+        self.outgoing
+            .send(OutgoingMessage::Raw(
+                protos::types::MESSAGE_USER_STATE,
+                payload,
+            ))
+            .await
+            .map_err(|e| Error::ConnectionError(format!("Failed to send channel move: {}", e)))
+    }
+
+    fn home_channel(&self) -> Option<&str> {
+        self.home_channel.as_deref()
+    }
+
+    fn follow_target(&self) -> Option<String> {
+        self.follow_target.lock().ok().and_then(|t| t.clone())
+    }
+
+    fn set_follow_target(&self, user_name: Option<String>) {
+        if let Ok(mut target) = self.follow_target.lock() {
+            *target = user_name;
+        }
+    }
+
     fn get_sounds_manager(&self) -> Option<std::sync::Arc<crate::sounds::SoundsManager>> {
         self.sounds_manager.clone()
     }
@@ -1158,6 +2441,10 @@ impl SessionTools for Session {
         self.user_settings_manager.clone()
     }
 
+    fn get_clip_recorder(&self) -> Option<std::sync::Arc<crate::audio::clip::ClipRecorder>> {
+        self.clip_recorder.clone()
+    }
+
     async fn execute_command(&self, command: &str, context: &CommandContext) -> Result<(), Error> {
         self.command_executor
             .execute(command, self, context.clone())
@@ -1168,6 +2455,103 @@ impl SessionTools for Session {
         &self.behavior_settings
     }
 
+    fn permissions(&self) -> &Permissions {
+        &self.permissions
+    }
+
+    fn connection_stats(&self) -> ConnectionStats {
+        let tracker = self.ping.lock().unwrap();
+        ConnectionStats {
+            tcp: tracker.tcp(),
+            unanswered: tracker.unanswered(),
+            udp: self.udp.with(|udp| udp.stats()),
+            server: self.last_server_ping.clone(),
+        }
+    }
+
+    fn music_settings(&self) -> &crate::config::MusicSettings {
+        &self.music_settings
+    }
+
+    fn profile_settings(&self) -> &ProfileSettings {
+        &self.profile_settings
+    }
+
+    async fn register_self(&self) -> Result<(), Error> {
+        let session = self
+            .current_user_id
+            .ok_or_else(|| Error::ConnectionError("Not connected to a server".to_string()))?;
+        self.send_raw(
+            protos::types::MESSAGE_USER_STATE,
+            profile::register(session).write_to_bytes()?,
+        )
+        .await
+    }
+
+    fn set_comment_intro(&self, intro: Option<String>) {
+        *self.comment_intro.lock().unwrap() =
+            intro.or_else(|| self.profile_settings.comment_intro.clone());
+    }
+
+    async fn refresh_comment(&self) -> Result<bool, Error> {
+        let Some(session) = self
+            .current_user_id
+            .filter(|_| self.profile_settings.comment)
+        else {
+            return Ok(false);
+        };
+
+        let sound_count = match &self.sounds_manager {
+            Some(manager) => manager.count_sounds().await? as usize,
+            None => 0,
+        };
+        // List what everyone may run
+        let commands: Vec<&str> = self
+            .command_executor
+            .command_names()
+            .into_iter()
+            .filter(|name| {
+                self.permissions.required_role(name, None) <= self.permissions.default_role()
+            })
+            .collect();
+        let intro = self.comment_intro.lock().unwrap().clone();
+        let comment = profile::comment_html(intro.as_deref(), sound_count, &commands);
+
+        if self.last_comment.lock().unwrap().as_ref() == Some(&comment) {
+            return Ok(false);
+        }
+        self.send_raw(
+            protos::types::MESSAGE_USER_STATE,
+            profile::set_comment(session, &comment).write_to_bytes()?,
+        )
+        .await?;
+        *self.last_comment.lock().unwrap() = Some(comment);
+        Ok(true)
+    }
+
+    async fn set_avatar(&self, path: Option<&Path>) -> Result<(), Error> {
+        let session = self
+            .current_user_id
+            .ok_or_else(|| Error::ConnectionError("Not connected to a server".to_string()))?;
+        let image = match path {
+            Some(path) => profile::load_avatar(path, self.text_limits.get().image_message_length)?,
+            None => Vec::new(),
+        };
+        self.send_raw(
+            protos::types::MESSAGE_USER_STATE,
+            profile::set_avatar(session, image).write_to_bytes()?,
+        )
+        .await
+    }
+
+    fn server_name(&self) -> &str {
+        &self.server_name
+    }
+
+    fn relays(&self) -> &Relays {
+        &self.relays
+    }
+
     fn audio_effect_settings(&self) -> &crate::config::AudioEffectSettings {
         &self.audio_effects
     }
@@ -1201,6 +2585,40 @@ impl SessionTools for Session {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_find_channel() {
+        let channel = |id: u32, parent: Option<u32>, name: &str| {
+            (
+                id,
+                Mumble::ChannelState {
+                    channel_id: Some(id),
+                    parent,
+                    name: Some(name.to_string()),
+                    ..Default::default()
+                },
+            )
+        };
+        let channels: HashMap<u32, Mumble::ChannelState> = [
+            channel(0, None, "Root"),
+            channel(1, Some(0), "Games"),
+            channel(2, Some(1), "Lobby"),
+            channel(3, Some(0), "Music"),
+            channel(4, Some(3), "Lobby"),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(find_channel(&channels, "music"), Some(3));
+        assert_eq!(find_channel(&channels, "Root"), Some(0));
+        // A bare name picks the lowest ID, a path picks the exact channel
+        assert_eq!(find_channel(&channels, "Lobby"), Some(2));
+        assert_eq!(find_channel(&channels, "Music/Lobby"), Some(4));
+        assert_eq!(find_channel(&channels, "/root/ music / lobby/"), Some(4));
+        assert_eq!(find_channel(&channels, "Games/Music"), None);
+        assert_eq!(find_channel(&channels, "Nowhere"), None);
+        assert_eq!(find_channel(&channels, " / "), None);
+    }
+
     #[test]
     fn test_markdown_to_html() {
         // Test bold formatting
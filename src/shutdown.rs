@@ -0,0 +1,72 @@
+// Signal handling for graceful shutdown
+
+use std::fmt;
+
+#[cfg(unix)]
+use tokio::signal::unix::{Signal, SignalKind, signal};
+
+/// A signal asking the bot to exit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownSignal {
+    Interrupt,
+    Terminate,
+}
+
+impl ShutdownSignal {
+    /// Conventional exit status of a process killed by this signal
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ShutdownSignal::Interrupt => 130,
+            ShutdownSignal::Terminate => 143,
+        }
+    }
+}
+
+impl fmt::Display for ShutdownSignal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShutdownSignal::Interrupt => write!(f, "SIGINT"),
+            ShutdownSignal::Terminate => write!(f, "SIGTERM"),
+        }
+    }
+}
+
+/// Listens for SIGINT and SIGTERM. Handlers are installed up front so a
+/// failure shows up at startup rather than as a shutdown request.
+pub struct ShutdownSignals {
+    #[cfg(unix)]
+    interrupt: Signal,
+    #[cfg(unix)]
+    terminate: Signal,
+}
+
+impl ShutdownSignals {
+    #[cfg(unix)]
+    pub fn install() -> std::io::Result<Self> {
+        Ok(Self {
+            interrupt: signal(SignalKind::interrupt())?,
+            terminate: signal(SignalKind::terminate())?,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn install() -> std::io::Result<Self> {
+        Ok(Self {})
+    }
+
+    /// Waits for the next shutdown signal
+    #[cfg(unix)]
+    pub async fn recv(&mut self) -> ShutdownSignal {
+        tokio::select! {
+            _ = self.interrupt.recv() => ShutdownSignal::Interrupt,
+            _ = self.terminate.recv() => ShutdownSignal::Terminate,
+        }
+    }
+
+    /// Waits for the next shutdown signal
+    #[cfg(not(unix))]
+    pub async fn recv(&mut self) -> ShutdownSignal {
+        let _ = tokio::signal::ctrl_c().await;
+        ShutdownSignal::Interrupt
+    }
+}
@@ -2,6 +2,7 @@ use crate::database::connection::DbPool;
 use crate::database::entities::sounds as sound_entity;
 use crate::error::Error;
 use crate::sounds::{SoundFile, validate_sound_code};
+use crate::users::Identity;
 use chrono::Utc;
 use rand::Rng;
 use rusqlite::{OptionalExtension, params};
@@ -42,6 +43,7 @@ impl SoundsManager {
             source_url: row.get(3)?,
             start_time: row.get(4)?,
             length: row.get(5)?,
+            author_identity: row.get(6)?,
         })
     }
 
@@ -86,7 +88,7 @@ impl SoundsManager {
                 .get()
                 .map_err(|e| Error::DatabaseError(format!("Failed to open database: {}", e)))?;
             conn.query_row(
-                "SELECT code, author, created_at, source_url, start_time, length FROM sounds WHERE code = ?1",
+                "SELECT code, author, created_at, source_url, start_time, length, author_identity FROM sounds WHERE code = ?1",
                 params![code_upper],
                 Self::row_to_model,
             )
@@ -110,6 +112,7 @@ impl SoundsManager {
         &self,
         code: &str,
         author: String,
+        author_identity: Option<Identity>,
         source_url: Option<String>,
         start_time: f64,
         length: f64,
@@ -127,6 +130,7 @@ impl SoundsManager {
             )));
         }
 
+        let author_identity = author_identity.map(|identity| identity.to_string());
         let start_time_str = Self::format_timestamp(start_time);
         let created_at = Utc::now().to_rfc3339();
         let pool = self.database.clone();
@@ -136,15 +140,16 @@ impl SoundsManager {
                 .get()
                 .map_err(|e| Error::DatabaseError(format!("Failed to open database: {}", e)))?;
             conn.execute(
-                "INSERT INTO sounds (code, author, created_at, source_url, start_time, length)
-                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                "INSERT INTO sounds (code, author, created_at, source_url, start_time, length, author_identity)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
                 params![
                     code_upper,
                     author,
                     created_at,
                     source_url,
                     start_time_str,
-                    length
+                    length,
+                    author_identity
                 ],
             )
             .map_err(|e| Error::DatabaseError(format!("Failed to insert sound: {}", e)))?;
@@ -216,7 +221,7 @@ impl SoundsManager {
                 .map_err(|e| Error::DatabaseError(format!("Failed to open database: {}", e)))?;
             let mut stmt = conn
                 .prepare(
-                    "SELECT code, author, created_at, source_url, start_time, length
+                    "SELECT code, author, created_at, source_url, start_time, length, author_identity
                      FROM sounds
                      ORDER BY created_at DESC",
                 )
@@ -238,10 +243,10 @@ impl SoundsManager {
         .map_err(|e| Error::DatabaseError(format!("List sounds task failed: {}", e)))?
     }
 
-    /// Gets a random sound from the database
-    pub async fn get_random_sound(&self) -> Result<Option<SoundFile>, Error> {
+    /// Counts the sounds in the database
+    pub async fn count_sounds(&self) -> Result<i64, Error> {
         let pool = self.database.clone();
-        let count = tokio::task::spawn_blocking(move || -> Result<i64, Error> {
+        tokio::task::spawn_blocking(move || -> Result<i64, Error> {
             let conn = pool
                 .get()
                 .map_err(|e| Error::DatabaseError(format!("Failed to open database: {}", e)))?;
@@ -249,7 +254,12 @@ impl SoundsManager {
                 .map_err(|e| Error::DatabaseError(format!("Failed to count sounds: {}", e)))
         })
         .await
-        .map_err(|e| Error::DatabaseError(format!("Count sounds task failed: {}", e)))??;
+        .map_err(|e| Error::DatabaseError(format!("Count sounds task failed: {}", e)))?
+    }
+
+    /// Gets a random sound from the database
+    pub async fn get_random_sound(&self) -> Result<Option<SoundFile>, Error> {
+        let count = self.count_sounds().await?;
 
         if count == 0 {
             return Ok(None);
@@ -267,7 +277,7 @@ impl SoundsManager {
                     .get()
                     .map_err(|e| Error::DatabaseError(format!("Failed to open database: {}", e)))?;
                 conn.query_row(
-                    "SELECT code, author, created_at, source_url, start_time, length
+                    "SELECT code, author, created_at, source_url, start_time, length, author_identity
                  FROM sounds
                  LIMIT 1 OFFSET ?1",
                     params![offset],
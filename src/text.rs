@@ -0,0 +1,338 @@
+// Fits outgoing text messages to the server's length and HTML limits
+
+use std::sync::Mutex;
+
+use crate::protos::generated::Mumble;
+
+/// Text message limits announced in the server's ServerConfig
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextLimits {
+    /// Maximum characters per message, if the server has a limit
+    pub message_length: Option<usize>,
+    /// Maximum bytes of messages with images, which also bounds avatars
+    pub image_message_length: Option<usize>,
+    pub allow_html: bool,
+}
+
+impl Default for TextLimits {
+    fn default() -> Self {
+        Self {
+            message_length: None,
+            image_message_length: None,
+            allow_html: true,
+        }
+    }
+}
+
+impl TextLimits {
+    /// Takes over the limits a ServerConfig message sets, keeping the others
+    pub fn apply(&mut self, config: &Mumble::ServerConfig) {
+        if let Some(length) = config.message_length {
+            self.message_length = (length > 0).then_some(length as usize);
+        }
+        if let Some(length) = config.image_message_length {
+            self.image_message_length = (length > 0).then_some(length as usize);
+        }
+        if let Some(allow_html) = config.allow_html {
+            self.allow_html = allow_html;
+        }
+    }
+}
+
+/// Limits shared between the session, which learns them, and the writer
+#[derive(Default)]
+pub struct SharedTextLimits(Mutex<TextLimits>);
+
+impl SharedTextLimits {
+    pub fn get(&self) -> TextLimits {
+        *self.0.lock().unwrap()
+    }
+
+    pub fn set(&self, limits: TextLimits) {
+        *self.0.lock().unwrap() = limits;
+    }
+}
+
+const TABLE_CLOSE: &str = "</table>";
+
+fn length(text: &str) -> usize {
+    text.chars().count()
+}
+
+/// Turns a message into one or more messages the server accepts: HTML is
+/// stripped if the server forbids it, and long messages are split between
+/// table rows or lines. HTML that can't be split without cutting into a tag
+/// is sent as plain text.
+pub fn fit_message(message: &str, limits: &TextLimits) -> Vec<String> {
+    let message = if limits.allow_html {
+        message.to_string()
+    } else {
+        strip_html(message)
+    };
+
+    let Some(max) = limits.message_length else {
+        return vec![message];
+    };
+    if length(&message) <= max {
+        return vec![message];
+    }
+
+    if limits.allow_html {
+        if let Some(parts) = split_table(&message, max) {
+            return parts;
+        }
+        split_html(&message, max)
+    } else {
+        split_lines(&message, "\n", max)
+    }
+}
+
+/// Splits a message holding an HTML table into several tables, repeating the
+/// header row in each. Text before the table goes with the first part and
+/// text after it (such as pagination hints) with the last.
+fn split_table(html: &str, max: usize) -> Option<Vec<String>> {
+    let start = html.find("<table")?;
+    let open_end = start + html[start..].find('>')? + 1;
+    let close = open_end + html[open_end..].find(TABLE_CLOSE)?;
+
+    let open = &html[start..open_end];
+    let after = &html[close + TABLE_CLOSE.len()..];
+    let mut rows: Vec<&str> = html[open_end..close]
+        .split_inclusive("</tr>")
+        .filter(|row| !row.trim().is_empty())
+        .collect();
+    let header = match rows.first() {
+        Some(row) if row.contains("<th") => rows.remove(0),
+        _ => "",
+    };
+
+    // Every part needs the header, and the first one the text before the table
+    let empty_table = length(open) + length(header) + TABLE_CLOSE.len();
+    if length(&html[..start]) + empty_table > max
+        || rows.iter().any(|row| empty_table + length(row) > max)
+    {
+        return None;
+    }
+
+    let mut parts = Vec::new();
+    let mut current = format!("{}{}{}", &html[..start], open, header);
+    let mut has_rows = false;
+    for row in rows {
+        if has_rows && length(&current) + length(row) + TABLE_CLOSE.len() > max {
+            current.push_str(TABLE_CLOSE);
+            parts.push(current);
+            current = format!("{}{}", open, header);
+        }
+        current.push_str(row);
+        has_rows = true;
+    }
+    current.push_str(TABLE_CLOSE);
+
+    if length(&current) + length(after) <= max {
+        current.push_str(after);
+        parts.push(current);
+    } else {
+        parts.push(current);
+        parts.extend(split_html(after, max));
+    }
+    Some(parts)
+}
+
+/// Splits HTML between lines, or as plain text if a line is too long to send
+/// whole, since cutting it could break a tag
+fn split_html(html: &str, max: usize) -> Vec<String> {
+    if html.split("<br>").all(|line| length(line) <= max) {
+        split_lines(html, "<br>", max)
+    } else {
+        split_lines(&strip_html(html), "\n", max)
+    }
+}
+
+/// Packs whole lines into messages of at most `max` characters, cutting
+/// lines that are too long on their own
+fn split_lines(text: &str, separator: &str, max: usize) -> Vec<String> {
+    let mut parts: Vec<String> = Vec::new();
+    let mut current = String::new();
+
+    for line in text.split(separator) {
+        if !current.is_empty() && length(&current) + separator.len() + length(line) <= max {
+            current.push_str(separator);
+            current.push_str(line);
+            continue;
+        }
+        if !current.is_empty() {
+            parts.push(std::mem::take(&mut current));
+        }
+
+        let chars: Vec<char> = line.chars().collect();
+        let mut chunks = chars.chunks(max.max(1)).peekable();
+        while let Some(chunk) = chunks.next() {
+            let chunk: String = chunk.iter().collect();
+            if chunks.peek().is_some() {
+                parts.push(chunk);
+            } else {
+                current = chunk;
+            }
+        }
+    }
+
+    if !current.is_empty() || parts.is_empty() {
+        parts.push(current);
+    }
+    parts
+}
+
+/// Converts HTML to plain text, keeping line breaks and table layout readable
+pub fn strip_html(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(open) = rest.find('<') {
+        text.push_str(&rest[..open]);
+        let Some(close) = rest[open..].find('>') else {
+            rest = &rest[open..];
+            break;
+        };
+
+        let tag = rest[open + 1..open + close].trim().to_ascii_lowercase();
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+        match (name, closing) {
+            ("br", _) | ("table", false) | ("p" | "div" | "tr" | "li", true) => text.push('\n'),
+            ("td" | "th", true) => text.push_str("  "),
+            _ => {}
+        }
+
+        rest = &rest[open + close + 1..];
+    }
+    text.push_str(rest);
+
+    let text = text
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+    text.lines()
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(message_length: usize, allow_html: bool) -> TextLimits {
+        TextLimits {
+            message_length: Some(message_length),
+            allow_html,
+            ..Default::default()
+        }
+    }
+
+    fn table(rows: usize) -> String {
+        let mut html = String::from("<b>Sounds</b><table><tr><th>Code</th></tr>");
+        for row in 0..rows {
+            html.push_str(&format!("<tr><td>S{:03}</td></tr>", row));
+        }
+        html.push_str("</table><br>Page 1 of 3");
+        html
+    }
+
+    #[test]
+    fn test_short_messages_pass_through() {
+        let html = table(2);
+        assert_eq!(
+            fit_message(&html, &TextLimits::default()),
+            vec![html.clone()]
+        );
+        assert_eq!(fit_message(&html, &limits(5000, true)), vec![html]);
+    }
+
+    #[test]
+    fn test_tables_split_between_rows() {
+        let html = table(20);
+        let parts = fit_message(&html, &limits(120, true));
+        assert!(parts.len() > 1);
+        for part in &parts {
+            assert!(part.chars().count() <= 120, "{}", part);
+            assert!(part.contains("<tr><th>Code</th></tr>"));
+            assert!(part.ends_with("</table>") || part.ends_with("Page 1 of 3"));
+        }
+        assert!(parts[0].starts_with("<b>Sounds</b>"));
+
+        let rows: usize = parts.iter().map(|part| part.matches("<td>").count()).sum();
+        assert_eq!(rows, 20);
+    }
+
+    #[test]
+    fn test_oversized_html_falls_back_to_plain_text() {
+        // The header alone leaves no room for rows
+        let html = format!(
+            "<table><tr><th>{}</th></tr><tr><td>S000</td></tr></table>",
+            "Code ".repeat(20)
+        );
+        let parts = fit_message(&html, &limits(60, true));
+        for part in &parts {
+            assert!(part.chars().count() <= 60, "{}", part);
+            assert!(!part.contains('<'), "{}", part);
+        }
+        assert!(parts.last().unwrap().contains("S000"));
+
+        // So does text before the table
+        let html = format!("{}{}", "Sounds ".repeat(20), table(3));
+        for part in fit_message(&html, &limits(100, true)) {
+            assert!(part.chars().count() <= 100, "{}", part);
+            assert!(!part.contains('<'), "{}", part);
+        }
+
+        // A line too long to send whole isn't cut inside a tag
+        let html = format!("<a href=\"https://example.com/\">{}</a>", "x".repeat(50));
+        let parts = fit_message(&html, &limits(20, true));
+        assert!(parts.iter().all(|part| !part.contains('<')));
+        assert_eq!(parts.concat(), "x".repeat(50));
+    }
+
+    #[test]
+    fn test_html_is_stripped_when_not_allowed() {
+        let text = fit_message(
+            &table(2),
+            &TextLimits {
+                allow_html: false,
+                ..Default::default()
+            },
+        );
+        assert_eq!(text, vec!["Sounds\nCode\nS000\nS001\n\nPage 1 of 3"]);
+        assert_eq!(strip_html("a &lt;b&gt; &amp; c<br/>d"), "a <b> & c\nd");
+    }
+
+    #[test]
+    fn test_long_lines_are_packed_and_cut() {
+        let parts = fit_message("aaaa\nbb\ncc\ndddddddddd", &limits(5, false));
+        assert_eq!(parts, vec!["aaaa", "bb\ncc", "ddddd", "ddddd"]);
+    }
+
+    #[test]
+    fn test_server_config_updates_limits() {
+        let mut limits = TextLimits::default();
+        let mut config = Mumble::ServerConfig::new();
+        config.set_message_length(5000);
+        limits.apply(&config);
+        assert_eq!(limits.message_length, Some(5000));
+        assert!(limits.allow_html);
+
+        let mut config = Mumble::ServerConfig::new();
+        config.set_allow_html(false);
+        limits.apply(&config);
+        assert_eq!(limits.message_length, Some(5000));
+        assert!(!limits.allow_html);
+    }
+}
@@ -0,0 +1,412 @@
+// Native UDP voice channel, encrypted with the keys from CryptSetup
+
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use protobuf::Message;
+use tokio::{net::UdpSocket, sync::mpsc, task::JoinHandle};
+
+use crate::{
+    audio::{
+        packet::{self, SharedPacketFormat, VoicePacket},
+        receive::VoiceReceiver,
+    },
+    crypt::CryptState,
+    ping::{RttStats, UdpStats},
+    protos::{self, generated::Mumble},
+    session::OutgoingMessage,
+};
+
+/// How often UDP pings are sent to probe the channel
+const PING_INTERVAL: Duration = Duration::from_secs(5);
+/// UDP is considered unreachable if no ping reply arrived within this window
+const UDP_TIMEOUT: Duration = Duration::from_secs(12);
+/// Minimum time without a good packet (and between requests) before asking for a resync
+const RESYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+const MAX_PACKET_SIZE: usize = 1024;
+
+#[derive(Default)]
+struct UdpStatus {
+    last_pong: Option<Instant>,
+    rtt: RttStats,
+    last_resync_request: Option<Instant>,
+}
+
+struct Shared {
+    crypt: Mutex<CryptState>,
+    status: Mutex<UdpStatus>,
+    packet_format: Arc<SharedPacketFormat>,
+    started: Instant,
+}
+
+/// An established UDP voice socket with its receive and ping tasks
+pub struct UdpTransport {
+    socket: Arc<UdpSocket>,
+    shared: Arc<Shared>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl UdpTransport {
+    /// Binds a socket towards `server` and starts pinging it.
+    ///
+    /// `control` is the TCP message queue, used to request a nonce resync
+    /// when packets stop decrypting. Incoming voice is handed to `voice`.
+    /// Pings and voice are framed in whichever format `packet_format` holds.
+    pub async fn start(
+        server: SocketAddr,
+        crypt: CryptState,
+        control: mpsc::Sender<OutgoingMessage>,
+        voice: VoiceReceiver,
+        packet_format: Arc<SharedPacketFormat>,
+    ) -> io::Result<Self> {
+        let bind_addr: SocketAddr = if server.is_ipv4() {
+            "0.0.0.0:0".parse().unwrap()
+        } else {
+            "[::]:0".parse().unwrap()
+        };
+
+        let socket = Arc::new(UdpSocket::bind(bind_addr).await?);
+        socket.connect(server).await?;
+
+        let shared = Arc::new(Shared {
+            crypt: Mutex::new(crypt),
+            status: Mutex::new(UdpStatus::default()),
+            packet_format,
+            started: Instant::now(),
+        });
+
+        let recv_task = tokio::spawn(Self::receive_loop(
+            socket.clone(),
+            shared.clone(),
+            control,
+            voice,
+        ));
+        let ping_task = tokio::spawn(Self::ping_loop(socket.clone(), shared.clone()));
+
+        debug!("UDP voice socket bound to {}", socket.local_addr()?);
+
+        Ok(Self {
+            socket,
+            shared,
+            tasks: vec![recv_task, ping_task],
+        })
+    }
+
+    /// Whether the server has answered a UDP ping recently
+    pub fn is_usable(&self) -> bool {
+        self.shared
+            .status
+            .lock()
+            .unwrap()
+            .last_pong
+            .is_some_and(|t| t.elapsed() < UDP_TIMEOUT)
+    }
+
+    /// Ping round trips and received packet counts, reported in TCP pings
+    pub fn stats(&self) -> UdpStats {
+        UdpStats {
+            rtt: self.shared.status.lock().unwrap().rtt,
+            crypt: self.shared.crypt.lock().unwrap().stats,
+        }
+    }
+
+    /// Encrypts and sends a plaintext voice packet without waiting
+    pub fn send_voice(&self, packet: &[u8]) -> io::Result<()> {
+        let encrypted = self.shared.crypt.lock().unwrap().encrypt(packet);
+        self.socket.try_send(&encrypted).map(|_| ())
+    }
+
+    /// Applies the server nonce from a resync response
+    pub fn set_decrypt_iv(&self, iv: &[u8]) -> bool {
+        self.shared.crypt.lock().unwrap().set_decrypt_iv(iv)
+    }
+
+    /// Our current client nonce, sent when the server asks for a resync
+    pub fn encrypt_iv(&self) -> Vec<u8> {
+        self.shared.crypt.lock().unwrap().encrypt_iv().to_vec()
+    }
+
+    async fn ping_loop(socket: Arc<UdpSocket>, shared: Arc<Shared>) {
+        let mut interval = tokio::time::interval(PING_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let timestamp = shared.started.elapsed().as_micros() as u64;
+            let packet = packet::encode_ping(timestamp, shared.packet_format.get());
+
+            let encrypted = shared.crypt.lock().unwrap().encrypt(&packet);
+            if let Err(e) = socket.send(&encrypted).await {
+                debug!("Failed to send UDP ping: {}", e);
+            }
+        }
+    }
+
+    async fn receive_loop(
+        socket: Arc<UdpSocket>,
+        shared: Arc<Shared>,
+        control: mpsc::Sender<OutgoingMessage>,
+        voice: VoiceReceiver,
+    ) {
+        let mut buf = vec![0u8; MAX_PACKET_SIZE];
+
+        loop {
+            let len = match socket.recv(&mut buf).await {
+                Ok(len) => len,
+                Err(e) => {
+                    // ICMP port unreachable and friends surface here; keep listening
+                    trace!("UDP receive error: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+
+            let plain = shared.crypt.lock().unwrap().decrypt(&buf[..len]);
+
+            let Some(plain) = plain else {
+                Self::maybe_request_resync(&shared, &control).await;
+                continue;
+            };
+
+            let format = shared.packet_format.get();
+
+            if let Some(timestamp) = packet::decode_ping(&plain, format) {
+                let sent = Duration::from_micros(timestamp);
+                let rtt = shared.started.elapsed().saturating_sub(sent);
+
+                let mut status = shared.status.lock().unwrap();
+                if status.last_pong.is_none() {
+                    info!("UDP voice channel established (rtt {:?})", rtt);
+                }
+                status.last_pong = Some(Instant::now());
+                status.rtt.record(rtt);
+            } else if let Some(packet) = VoicePacket::decode_incoming(&plain, format) {
+                voice.push_packet(packet);
+            } else {
+                trace!(
+                    "Ignoring unsupported UDP packet ({} bytes, header {:#04x})",
+                    plain.len(),
+                    plain.first().copied().unwrap_or_default()
+                );
+            }
+        }
+    }
+
+    /// Asks the server for its current nonce if decryption has been failing for a while
+    async fn maybe_request_resync(shared: &Shared, control: &mpsc::Sender<OutgoingMessage>) {
+        let last_good = shared
+            .crypt
+            .lock()
+            .unwrap()
+            .last_good()
+            .unwrap_or(shared.started);
+
+        {
+            let mut status = shared.status.lock().unwrap();
+            if last_good.elapsed() < RESYNC_INTERVAL
+                || status
+                    .last_resync_request
+                    .is_some_and(|t| t.elapsed() < RESYNC_INTERVAL)
+            {
+                return;
+            }
+            status.last_resync_request = Some(Instant::now());
+        }
+
+        debug!("UDP decryption failing, requesting crypt resync");
+
+        // An empty CryptSetup asks the server to send its nonce
+        match Mumble::CryptSetup::new().write_to_bytes() {
+            Ok(payload) => {
+                let _ = control
+                    .send(OutgoingMessage::Raw(
+                        protos::types::MESSAGE_CRYPT_SETUP,
+                        payload,
+                    ))
+                    .await;
+            }
+            Err(e) => warn!("Failed to encode crypt resync request: {}", e),
+        }
+    }
+}
+
+impl Drop for UdpTransport {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Voice route shared between the session and the writer task.
+///
+/// Holds the UDP transport once CryptSetup arrives; voice falls back to the
+/// TCP tunnel whenever UDP is missing or unresponsive.
+#[derive(Default)]
+pub struct UdpVoice {
+    transport: Mutex<Option<UdpTransport>>,
+    active: AtomicBool,
+}
+
+impl UdpVoice {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn install(&self, transport: UdpTransport) {
+        *self.transport.lock().unwrap() = Some(transport);
+    }
+
+    pub fn clear(&self) {
+        *self.transport.lock().unwrap() = None;
+        self.active.store(false, Ordering::Relaxed);
+    }
+
+    /// Runs `f` against the current transport, if any
+    pub fn with<R>(&self, f: impl FnOnce(&UdpTransport) -> R) -> Option<R> {
+        self.transport.lock().unwrap().as_ref().map(f)
+    }
+
+    /// Sends a voice packet over UDP if the channel is healthy.
+    ///
+    /// Returns false when the caller should use the TCP tunnel instead.
+    pub fn try_send(&self, packet: &[u8]) -> bool {
+        let sent = self
+            .with(|udp| udp.is_usable() && udp.send_voice(packet).is_ok())
+            .unwrap_or(false);
+
+        if self.active.swap(sent, Ordering::Relaxed) != sent {
+            if sent {
+                info!("Sending voice over UDP");
+            } else {
+                info!("UDP unavailable, sending voice through the TCP tunnel");
+            }
+        }
+
+        sent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 16] = [7; 16];
+    const CLIENT_NONCE: [u8; 16] = [1; 16];
+    const SERVER_NONCE: [u8; 16] = [2; 16];
+
+    /// Loopback stand-in for the server's UDP voice port
+    async fn stand_in() -> (UdpSocket, CryptState) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let crypt = CryptState::new(&KEY, &SERVER_NONCE, &CLIENT_NONCE).unwrap();
+        (socket, crypt)
+    }
+
+    fn client_crypt() -> CryptState {
+        CryptState::new(&KEY, &CLIENT_NONCE, &SERVER_NONCE).unwrap()
+    }
+
+    async fn recv_plain(socket: &UdpSocket, crypt: &mut CryptState) -> (Vec<u8>, SocketAddr) {
+        let mut buf = vec![0u8; MAX_PACKET_SIZE];
+        let (len, from) = tokio::time::timeout(Duration::from_secs(2), socket.recv_from(&mut buf))
+            .await
+            .expect("timed out waiting for packet")
+            .unwrap();
+        (crypt.decrypt(&buf[..len]).expect("decrypt failed"), from)
+    }
+
+    #[tokio::test]
+    async fn test_ping_echo_enables_udp() {
+        let (server, mut server_crypt) = stand_in().await;
+        let (control, _control_rx) = mpsc::channel(4);
+        let voice = UdpVoice::new();
+        voice.install(
+            UdpTransport::start(
+                server.local_addr().unwrap(),
+                client_crypt(),
+                control,
+                VoiceReceiver::spawn(),
+                Arc::new(SharedPacketFormat::default()),
+            )
+            .await
+            .unwrap(),
+        );
+
+        // Not usable until the server answers a ping
+        assert!(!voice.try_send(&[0x80, 0, 0]));
+
+        let (ping, from) = recv_plain(&server, &mut server_crypt).await;
+        assert_eq!(ping[0] >> 5, packet::TYPE_PING);
+        server
+            .send_to(&server_crypt.encrypt(&ping), from)
+            .await
+            .unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !voice.with(|udp| udp.is_usable()).unwrap() {
+            assert!(Instant::now() < deadline, "UDP never became usable");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(voice.with(|udp| udp.stats().rtt.last()).unwrap().is_some());
+
+        let packet = [0x80, 0x04, 0x03, 0xAA, 0xBB, 0xCC];
+        assert!(voice.try_send(&packet));
+        let (received, _) = recv_plain(&server, &mut server_crypt).await;
+        assert_eq!(received, packet);
+
+        voice.clear();
+        assert!(!voice.try_send(&packet));
+    }
+
+    #[tokio::test]
+    async fn test_decrypt_failures_request_resync() {
+        let (server, _) = stand_in().await;
+        let (control, mut control_rx) = mpsc::channel(4);
+        let transport = UdpTransport::start(
+            server.local_addr().unwrap(),
+            client_crypt(),
+            control,
+            VoiceReceiver::spawn(),
+            Arc::new(SharedPacketFormat::default()),
+        )
+        .await
+        .unwrap();
+
+        // Pretend the last good packet was long ago
+        let mut buf = vec![0u8; MAX_PACKET_SIZE];
+        let (_, from) = server.recv_from(&mut buf).await.unwrap();
+        let stale = Instant::now() - RESYNC_INTERVAL * 2;
+        let shared = Shared {
+            crypt: Mutex::new(client_crypt()),
+            status: Mutex::new(UdpStatus::default()),
+            packet_format: Arc::new(SharedPacketFormat::default()),
+            started: stale,
+        };
+        let (tx, mut rx) = mpsc::channel(4);
+        UdpTransport::maybe_request_resync(&shared, &tx).await;
+        match rx.try_recv() {
+            Ok(OutgoingMessage::Raw(msg_type, payload)) => {
+                assert_eq!(msg_type, protos::types::MESSAGE_CRYPT_SETUP);
+                assert!(payload.is_empty());
+            }
+            _ => panic!("expected a CryptSetup resync request"),
+        }
+        // Rate limited
+        UdpTransport::maybe_request_resync(&shared, &tx).await;
+        assert!(rx.try_recv().is_err());
+
+        // Garbage on the real socket is ignored without a request (transport is fresh)
+        server.send_to(&[0u8; 32], from).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(control_rx.try_recv().is_err());
+        drop(transport);
+    }
+}
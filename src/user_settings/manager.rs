@@ -1,6 +1,7 @@
 use crate::database::connection::DbPool;
 use crate::database::entities::user_settings::SettingType;
 use crate::error::Error;
+use crate::users::Identity;
 use chrono::Utc;
 use rusqlite::{OptionalExtension, params};
 
@@ -14,15 +15,15 @@ impl UserSettingsManager {
         Self { db }
     }
 
-    /// Set a user setting (bind, greeting, farewell)
+    /// Set a user setting (bind, greeting, farewell) for an identity
     pub async fn set_user_setting(
         &self,
-        username: &str,
+        identity: &Identity,
         setting_type: SettingType,
         value: &str,
     ) -> Result<(), Error> {
         let pool = self.db.clone();
-        let username = username.to_string();
+        let identity = identity.to_string();
         let setting_type = setting_type.as_str().to_string();
         let value = value.to_string();
 
@@ -30,7 +31,7 @@ impl UserSettingsManager {
             let conn = pool
                 .get()
                 .map_err(|e| Error::DatabaseError(format!("Failed to open database: {}", e)))?;
-            let id = format!("{}:{}", username, setting_type);
+            let id = format!("{}:{}", identity, setting_type);
             let now = Utc::now().to_rfc3339();
 
             let existing: Option<String> = conn
@@ -52,9 +53,9 @@ impl UserSettingsManager {
                 .map_err(|e| Error::DatabaseError(format!("Failed to update user setting: {}", e)))?;
             } else {
                 conn.execute(
-                    "INSERT INTO user_settings (id, username, setting_type, setting_value, created_at, updated_at)
+                    "INSERT INTO user_settings (id, identity, setting_type, setting_value, created_at, updated_at)
                      VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-                    params![id, username, setting_type, value, now, now],
+                    params![id, identity, setting_type, value, now, now],
                 )
                 .map_err(|e| Error::DatabaseError(format!("Failed to insert user setting: {}", e)))?;
             }
@@ -67,11 +68,11 @@ impl UserSettingsManager {
     /// Get a user setting by type
     pub async fn get_user_setting(
         &self,
-        username: &str,
+        identity: &Identity,
         setting_type: SettingType,
     ) -> Result<Option<String>, Error> {
         let pool = self.db.clone();
-        let id = format!("{}:{}", username, setting_type.as_str());
+        let id = format!("{}:{}", identity, setting_type.as_str());
 
         tokio::task::spawn_blocking(move || -> Result<Option<String>, Error> {
             let conn = pool
@@ -93,11 +94,11 @@ impl UserSettingsManager {
     /// Delete a user setting
     pub async fn delete_user_setting(
         &self,
-        username: &str,
+        identity: &Identity,
         setting_type: SettingType,
     ) -> Result<bool, Error> {
         let pool = self.db.clone();
-        let id = format!("{}:{}", username, setting_type.as_str());
+        let id = format!("{}:{}", identity, setting_type.as_str());
 
         tokio::task::spawn_blocking(move || -> Result<bool, Error> {
             let conn = pool
@@ -115,40 +116,40 @@ impl UserSettingsManager {
     }
 
     /// Convenience methods for specific setting types
-    pub async fn set_bind(&self, username: &str, command: &str) -> Result<(), Error> {
-        self.set_user_setting(username, SettingType::Bind, command)
+    pub async fn set_bind(&self, identity: &Identity, command: &str) -> Result<(), Error> {
+        self.set_user_setting(identity, SettingType::Bind, command)
             .await
     }
 
-    pub async fn get_bind(&self, username: &str) -> Result<Option<String>, Error> {
-        self.get_user_setting(username, SettingType::Bind).await
+    pub async fn get_bind(&self, identity: &Identity) -> Result<Option<String>, Error> {
+        self.get_user_setting(identity, SettingType::Bind).await
     }
 
-    pub async fn set_greeting(&self, username: &str, command: &str) -> Result<(), Error> {
-        self.set_user_setting(username, SettingType::Greeting, command)
+    pub async fn set_greeting(&self, identity: &Identity, command: &str) -> Result<(), Error> {
+        self.set_user_setting(identity, SettingType::Greeting, command)
             .await
     }
 
-    pub async fn get_greeting(&self, username: &str) -> Result<Option<String>, Error> {
-        self.get_user_setting(username, SettingType::Greeting).await
+    pub async fn get_greeting(&self, identity: &Identity) -> Result<Option<String>, Error> {
+        self.get_user_setting(identity, SettingType::Greeting).await
     }
 
-    pub async fn set_farewell(&self, username: &str, command: &str) -> Result<(), Error> {
-        self.set_user_setting(username, SettingType::Farewell, command)
+    pub async fn set_farewell(&self, identity: &Identity, command: &str) -> Result<(), Error> {
+        self.set_user_setting(identity, SettingType::Farewell, command)
             .await
     }
 
-    pub async fn get_farewell(&self, username: &str) -> Result<Option<String>, Error> {
-        self.get_user_setting(username, SettingType::Farewell).await
+    pub async fn get_farewell(&self, identity: &Identity) -> Result<Option<String>, Error> {
+        self.get_user_setting(identity, SettingType::Farewell).await
     }
 
-    pub async fn clear_greeting(&self, username: &str) -> Result<bool, Error> {
-        self.delete_user_setting(username, SettingType::Greeting)
+    pub async fn clear_greeting(&self, identity: &Identity) -> Result<bool, Error> {
+        self.delete_user_setting(identity, SettingType::Greeting)
             .await
     }
 
-    pub async fn clear_farewell(&self, username: &str) -> Result<bool, Error> {
-        self.delete_user_setting(username, SettingType::Farewell)
+    pub async fn clear_farewell(&self, identity: &Identity) -> Result<bool, Error> {
+        self.delete_user_setting(identity, SettingType::Farewell)
             .await
     }
 }
@@ -0,0 +1,145 @@
+use std::fmt;
+
+use crate::protos::generated::Mumble;
+
+/// Stable identity of a Mumble user, independent of the name they connect with
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Identity {
+    /// A user registered on a server, by the server's name and their
+    /// registration ID, which is only unique within that server
+    Registered { server: String, user_id: u32 },
+    /// An unregistered user, by the SHA-1 hash of their client certificate
+    Certificate(String),
+    /// Rows saved by display name before identities existed, waiting to be
+    /// claimed by the first identified user with that name
+    Legacy(String),
+}
+
+impl Identity {
+    /// Identity of a user connected to `server`. Users with neither a registration
+    /// nor a client certificate have none, since their name is all that tells them apart.
+    pub fn of(user: &Mumble::UserState, server: &str) -> Option<Self> {
+        if let Some(user_id) = user.user_id {
+            return Some(Identity::Registered {
+                server: server.to_string(),
+                user_id,
+            });
+        }
+        user.hash
+            .as_ref()
+            .filter(|hash| !hash.is_empty())
+            .map(|hash| Identity::Certificate(hash.to_lowercase()))
+    }
+
+    /// Parses the key form written by `Display`
+    pub fn parse(key: &str) -> Option<Self> {
+        let (kind, value) = key.split_once(':')?;
+        match kind {
+            "user" => {
+                let (server, user_id) = value.rsplit_once('/')?;
+                Some(Identity::Registered {
+                    server: server.to_string(),
+                    user_id: user_id.parse().ok()?,
+                })
+                .filter(|_| !server.is_empty())
+            }
+            "cert" if !value.is_empty() => Some(Identity::Certificate(value.to_string())),
+            "name" => Some(Identity::Legacy(value.to_string())),
+            _ => None,
+        }
+    }
+
+    /// Key for `permissions.users` in the config. Each server has its own
+    /// permissions, so registered users are written without the server there.
+    pub fn config_key(&self) -> String {
+        match self {
+            Identity::Registered { user_id, .. } => format!("user:{}", user_id),
+            _ => self.to_string(),
+        }
+    }
+}
+
+/// Whether `caller` may change or remove something whose author has the
+/// identity key `author`. Things without a verified author stay open to everyone.
+pub fn can_modify(author: Option<&str>, caller: Option<&Identity>) -> bool {
+    match author.and_then(Identity::parse) {
+        None | Some(Identity::Legacy(_)) => true,
+        Some(author) => caller == Some(&author),
+    }
+}
+
+/// Key stored in the database, e.g. `user:main/42` or `cert:0a1b...`
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Identity::Registered { server, user_id } => write!(f, "user:{}/{}", server, user_id),
+            Identity::Certificate(hash) => write!(f, "cert:{}", hash),
+            Identity::Legacy(name) => write!(f, "name:{}", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity_of_user_state() {
+        let mut user = Mumble::UserState {
+            name: Some("alice".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(Identity::of(&user, "main"), None);
+
+        user.hash = Some("ABCDEF".to_string());
+        assert_eq!(
+            Identity::of(&user, "main"),
+            Some(Identity::Certificate("abcdef".to_string()))
+        );
+
+        // Registration wins over the certificate, and is only unique per server
+        user.user_id = Some(42);
+        let registered = Identity::of(&user, "main").unwrap();
+        assert_eq!(registered.to_string(), "user:main/42");
+        assert_eq!(registered.config_key(), "user:42");
+        assert_ne!(Identity::of(&user, "games"), Some(registered));
+    }
+
+    #[test]
+    fn test_identity_key_round_trip() {
+        for identity in [
+            Identity::Registered {
+                server: "mumble.example.com".to_string(),
+                user_id: 7,
+            },
+            Identity::Certificate("0a1b2c".to_string()),
+            Identity::Legacy("bob:the:builder".to_string()),
+        ] {
+            assert_eq!(Identity::parse(&identity.to_string()), Some(identity));
+        }
+        assert_eq!(Identity::parse("user:main/abc"), None);
+        assert_eq!(Identity::parse("user:42"), None);
+        assert_eq!(Identity::parse("user:/42"), None);
+        assert_eq!(Identity::parse("alice"), None);
+    }
+
+    #[test]
+    fn test_can_modify() {
+        let alice = Identity::Certificate("aaaa".to_string());
+        let mallory = Identity::Certificate("ffff".to_string());
+        let registered = |server: &str| Identity::Registered {
+            server: server.to_string(),
+            user_id: 42,
+        };
+        assert!(can_modify(Some("user:main/42"), Some(&registered("main"))));
+        assert!(!can_modify(
+            Some("user:main/42"),
+            Some(&registered("games"))
+        ));
+        assert!(can_modify(Some("cert:aaaa"), Some(&alice)));
+        assert!(!can_modify(Some("cert:aaaa"), Some(&mallory)));
+        assert!(!can_modify(Some("cert:aaaa"), None));
+        assert!(can_modify(Some("name:alice"), Some(&mallory)));
+        assert!(can_modify(None, None));
+    }
+}
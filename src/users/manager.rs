@@ -0,0 +1,228 @@
+use crate::database::connection::DbPool;
+use crate::error::Error;
+use crate::users::Identity;
+use chrono::Utc;
+use rusqlite::{Connection, OptionalExtension, params};
+
+/// Tracks which identities have been seen and under which name
+#[derive(Clone)]
+pub struct UsersManager {
+    db: DbPool,
+}
+
+impl UsersManager {
+    pub fn new(db: DbPool) -> Self {
+        Self { db }
+    }
+
+    /// Records a connected user's identity and current name.
+    ///
+    /// The first time an identity shows up it claims any settings, sounds and
+    /// aliases still saved under its name from before identities existed.
+    /// Returns how many such rows were claimed.
+    pub async fn record_seen(&self, identity: &Identity, name: &str) -> Result<usize, Error> {
+        let pool = self.db.clone();
+        let identity = identity.to_string();
+        let name = name.to_string();
+
+        tokio::task::spawn_blocking(move || -> Result<usize, Error> {
+            let mut conn = pool
+                .get()
+                .map_err(|e| Error::DatabaseError(format!("Failed to open database: {}", e)))?;
+            record_seen(&mut conn, &identity, &name)
+        })
+        .await
+        .map_err(|e| Error::DatabaseError(format!("Record user task failed: {}", e)))?
+    }
+
+    /// Moves rows saved as `user:<id>`, from before registrations were kept
+    /// per server, to `server`: the one the bot connected to on its own.
+    /// This only happens once per database. Returns how many rows were moved.
+    pub async fn scope_registrations(&self, server: &str) -> Result<usize, Error> {
+        let pool = self.db.clone();
+        let server = server.to_string();
+
+        tokio::task::spawn_blocking(move || -> Result<usize, Error> {
+            let mut conn = pool
+                .get()
+                .map_err(|e| Error::DatabaseError(format!("Failed to open database: {}", e)))?;
+            scope_registrations(&mut conn, &server)
+        })
+        .await
+        .map_err(|e| Error::DatabaseError(format!("Scope registrations task failed: {}", e)))?
+    }
+}
+
+fn scope_registrations(conn: &mut Connection, server: &str) -> Result<usize, Error> {
+    let tx = conn
+        .transaction()
+        .map_err(|e| Error::DatabaseError(format!("Failed to begin transaction: {}", e)))?;
+
+    let scoped: Option<String> = tx
+        .query_row("SELECT server FROM registration_scope", [], |row| {
+            row.get(0)
+        })
+        .optional()
+        .map_err(|e| Error::DatabaseError(format!("Failed to read registration scope: {}", e)))?;
+    if scoped.is_some() {
+        return Ok(0);
+    }
+
+    // `user:42` becomes `user:<server>/42`; scoped keys always contain a slash
+    let prefix = format!("user:{}/", server);
+    let mut moved = 0;
+    for sql in [
+        "UPDATE OR IGNORE users SET identity = ?1 || substr(identity, 6)
+         WHERE identity GLOB 'user:[0-9]*' AND identity NOT GLOB '*/*'",
+        "UPDATE OR IGNORE user_settings
+         SET identity = ?1 || substr(identity, 6), id = ?1 || substr(id, 6)
+         WHERE identity GLOB 'user:[0-9]*' AND identity NOT GLOB '*/*'",
+        "UPDATE sounds SET author_identity = ?1 || substr(author_identity, 6)
+         WHERE author_identity GLOB 'user:[0-9]*' AND author_identity NOT GLOB '*/*'",
+        "UPDATE aliases SET author_identity = ?1 || substr(author_identity, 6)
+         WHERE author_identity GLOB 'user:[0-9]*' AND author_identity NOT GLOB '*/*'",
+    ] {
+        moved += tx
+            .execute(sql, params![prefix])
+            .map_err(|e| Error::DatabaseError(format!("Failed to scope registrations: {}", e)))?;
+    }
+    tx.execute(
+        "INSERT INTO registration_scope (server, scoped_at) VALUES (?1, ?2)",
+        params![server, Utc::now().to_rfc3339()],
+    )
+    .map_err(|e| Error::DatabaseError(format!("Failed to record registration scope: {}", e)))?;
+
+    tx.commit()
+        .map_err(|e| Error::DatabaseError(format!("Failed to commit registrations: {}", e)))?;
+    Ok(moved)
+}
+
+fn record_seen(conn: &mut Connection, identity: &str, name: &str) -> Result<usize, Error> {
+    let tx = conn
+        .transaction()
+        .map_err(|e| Error::DatabaseError(format!("Failed to begin transaction: {}", e)))?;
+    let now = Utc::now().to_rfc3339();
+
+    let known: Option<String> = tx
+        .query_row(
+            "SELECT identity FROM users WHERE identity = ?1",
+            params![identity],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| Error::DatabaseError(format!("Failed to look up user: {}", e)))?;
+
+    let mut claimed = 0;
+    if known.is_some() {
+        tx.execute(
+            "UPDATE users SET name = ?1, last_seen = ?2 WHERE identity = ?3",
+            params![name, now, identity],
+        )
+        .map_err(|e| Error::DatabaseError(format!("Failed to update user: {}", e)))?;
+    } else {
+        tx.execute(
+            "INSERT INTO users (identity, name, first_seen, last_seen) VALUES (?1, ?2, ?3, ?3)",
+            params![identity, name, now],
+        )
+        .map_err(|e| Error::DatabaseError(format!("Failed to insert user: {}", e)))?;
+
+        let legacy = Identity::Legacy(name.to_string()).to_string();
+        for sql in [
+            "UPDATE OR IGNORE user_settings SET identity = ?1, id = ?1 || ':' || setting_type
+             WHERE identity = ?2",
+            "UPDATE sounds SET author_identity = ?1 WHERE author_identity = ?2",
+            "UPDATE aliases SET author_identity = ?1 WHERE author_identity = ?2",
+        ] {
+            claimed += tx
+                .execute(sql, params![identity, legacy])
+                .map_err(|e| Error::DatabaseError(format!("Failed to claim legacy rows: {}", e)))?;
+        }
+    }
+
+    tx.commit()
+        .map_err(|e| Error::DatabaseError(format!("Failed to commit user: {}", e)))?;
+    Ok(claimed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::migrations::run_migrations;
+
+    #[test]
+    fn test_first_sighting_claims_legacy_rows() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run_migrations(&mut conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO user_settings VALUES ('name:alice:greeting', 'name:alice', 'greeting', '!sound play ABCD', 'now', 'now');
+             INSERT INTO aliases VALUES ('hi', 'alice', 'now', '!sound play WXYZ', 'name:alice');",
+        )
+        .unwrap();
+
+        assert_eq!(record_seen(&mut conn, "cert:abcd", "alice").unwrap(), 2);
+        let id: String = conn
+            .query_row("SELECT id FROM user_settings", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(id, "cert:abcd:greeting");
+
+        // Someone else showing up as "alice" later gets nothing
+        assert_eq!(record_seen(&mut conn, "cert:ffff", "alice").unwrap(), 0);
+        assert_eq!(record_seen(&mut conn, "cert:abcd", "alice2").unwrap(), 0);
+        let name: String = conn
+            .query_row(
+                "SELECT name FROM users WHERE identity = 'cert:abcd'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(name, "alice2");
+    }
+
+    #[test]
+    fn test_registrations_are_scoped_to_the_first_server() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run_migrations(&mut conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO users VALUES ('user:42', 'alice', 'now', 'now');
+             INSERT INTO users VALUES ('user:games/7', 'bob', 'now', 'now');
+             INSERT INTO user_settings VALUES ('user:42:greeting', 'user:42', 'greeting', '!sound play ABCD', 'now', 'now');
+             INSERT INTO sounds VALUES ('ABCD', 'alice', 'now', NULL, '0', 1.0, 'user:42');
+             INSERT INTO aliases VALUES ('hi', 'carol', 'now', '!sound play WXYZ', 'cert:abcd');",
+        )
+        .unwrap();
+
+        assert_eq!(scope_registrations(&mut conn, "main").unwrap(), 3);
+        let identities: Vec<String> = conn
+            .prepare("SELECT identity FROM users ORDER BY name")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(identities, vec!["user:main/42", "user:games/7"]);
+        let (id, identity): (String, String) = conn
+            .query_row("SELECT id, identity FROM user_settings", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(id, "user:main/42:greeting");
+        assert_eq!(identity, "user:main/42");
+        let author: String = conn
+            .query_row("SELECT author_identity FROM sounds", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(author, "user:main/42");
+
+        // It only ever runs once, even for another first server
+        conn.execute_batch("INSERT INTO users VALUES ('user:43', 'dave', 'now', 'now');")
+            .unwrap();
+        assert_eq!(scope_registrations(&mut conn, "games").unwrap(), 0);
+        let left: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM users WHERE identity = 'user:43'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(left, 1);
+    }
+}
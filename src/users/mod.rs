@@ -0,0 +1,5 @@
+pub mod identity;
+pub mod manager;
+
+pub use identity::Identity;
+pub use manager::UsersManager;
@@ -1,9 +1,178 @@
+// Mumble variable-length integer codec.
+//
+// Values are big-endian with the length encoded in the leading bits:
+//   0xxxxxxx                        7-bit positive
+//   10xxxxxx + 1 byte               14-bit positive
+//   110xxxxx + 2 bytes              21-bit positive
+//   1110xxxx + 3 bytes              28-bit positive
+//   111100__ + 4 bytes              32-bit positive
+//   111101__ + 8 bytes              64-bit
+//   111110__ + varint               negative recursive (bitwise inverse)
+//   111111xx                        byte-inverted negative two-bit number (-1 to -4)
+//
+// Negative numbers are passed around as the two's complement bit pattern in a u64.
+
 // Encode a varint in mumble format (64-bit version)
 pub fn encode_varint_long(value: u64) -> Vec<u8> {
-    // TODO: actual varint encoding,
+    let mut out = Vec::with_capacity(9);
+    let mut value = value;
+
+    // Small negative numbers get their own forms instead of the full 9 bytes
+    if value & 0x8000_0000_0000_0000 != 0 && !value < 0x1_0000_0000 {
+        value = !value;
+        if value <= 0x3 {
+            out.push(0xFC | value as u8);
+            return out;
+        }
+        out.push(0xF8);
+    }
 
-    let mut out = vec![0b11110100];
-    out.extend(value.to_be_bytes());
+    if value < 0x80 {
+        out.push(value as u8);
+    } else if value < 0x4000 {
+        out.push(((value >> 8) | 0x80) as u8);
+        out.push(value as u8);
+    } else if value < 0x20_0000 {
+        out.push(((value >> 16) | 0xC0) as u8);
+        out.extend_from_slice(&(value as u16).to_be_bytes());
+    } else if value < 0x1000_0000 {
+        out.push(((value >> 24) | 0xE0) as u8);
+        out.extend_from_slice(&(value as u32).to_be_bytes()[1..]);
+    } else if value < 0x1_0000_0000 {
+        out.push(0xF0);
+        out.extend_from_slice(&(value as u32).to_be_bytes());
+    } else {
+        out.push(0xF4);
+        out.extend_from_slice(&value.to_be_bytes());
+    }
 
     out
 }
+
+// Decode a mumble format varint, returning the value and the number of bytes consumed
+pub fn decode_varint(buf: &[u8]) -> Option<(u64, usize)> {
+    let first = *buf.first()? as u64;
+
+    let read = |count: usize| -> Option<u64> {
+        let bytes = buf.get(1..1 + count)?;
+        Some(bytes.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64))
+    };
+
+    if first & 0x80 == 0x00 {
+        Some((first & 0x7F, 1))
+    } else if first & 0xC0 == 0x80 {
+        Some((((first & 0x3F) << 8) | read(1)?, 2))
+    } else if first & 0xE0 == 0xC0 {
+        Some((((first & 0x1F) << 16) | read(2)?, 3))
+    } else if first & 0xF0 == 0xE0 {
+        Some((((first & 0x0F) << 24) | read(3)?, 4))
+    } else {
+        match first & 0xFC {
+            0xF0 => Some((read(4)?, 5)),
+            0xF4 => Some((read(8)?, 9)),
+            0xF8 => {
+                // Negative recursive varint
+                let (value, len) = decode_varint(&buf[1..])?;
+                Some((!value, len + 1))
+            }
+            0xFC => Some((!(first & 0x03), 1)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn encoded_len(value: u64) -> usize {
+        encode_varint_long(value).len()
+    }
+
+    #[test]
+    fn test_varint_boundaries() {
+        assert_eq!(encode_varint_long(0), vec![0x00]);
+        assert_eq!(encode_varint_long(0x7F), vec![0x7F]);
+        assert_eq!(encode_varint_long(0x80), vec![0x80, 0x80]);
+        assert_eq!(encode_varint_long(0x3FFF), vec![0xBF, 0xFF]);
+        assert_eq!(encode_varint_long(0x4000), vec![0xC0, 0x40, 0x00]);
+        assert_eq!(encode_varint_long(0x1F_FFFF), vec![0xDF, 0xFF, 0xFF]);
+        assert_eq!(encode_varint_long(0x20_0000), vec![0xE0, 0x20, 0x00, 0x00]);
+        assert_eq!(
+            encode_varint_long(0x0FFF_FFFF),
+            vec![0xEF, 0xFF, 0xFF, 0xFF]
+        );
+        assert_eq!(
+            encode_varint_long(0x1000_0000),
+            vec![0xF0, 0x10, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(
+            encode_varint_long(0xFFFF_FFFF),
+            vec![0xF0, 0xFF, 0xFF, 0xFF, 0xFF]
+        );
+        assert_eq!(encoded_len(0x1_0000_0000), 9);
+        assert_eq!(encode_varint_long(0x1_0000_0000)[0], 0xF4);
+    }
+
+    #[test]
+    fn test_varint_negative_forms() {
+        // -1 to -4 fit in a single byte
+        assert_eq!(encode_varint_long(-1i64 as u64), vec![0xFC]);
+        assert_eq!(encode_varint_long(-4i64 as u64), vec![0xFF]);
+        // Larger negatives use the recursive form
+        assert_eq!(encode_varint_long(-5i64 as u64), vec![0xF8, 0x04]);
+        assert_eq!(encode_varint_long(-200i64 as u64), vec![0xF8, 0x80, 0xC7]);
+        // Beyond 32 bits the plain 64-bit form is used
+        assert_eq!(encoded_len(i64::MIN as u64), 9);
+
+        assert_eq!(decode_varint(&[0xFE]), Some((-3i64 as u64, 1)));
+        assert_eq!(decode_varint(&[0xF8, 0x04]), Some((-5i64 as u64, 2)));
+    }
+
+    #[test]
+    fn test_varint_truncated() {
+        assert_eq!(decode_varint(&[]), None);
+        assert_eq!(decode_varint(&[0x80]), None);
+        assert_eq!(decode_varint(&[0xF0, 0x00, 0x00]), None);
+        assert_eq!(decode_varint(&[0xF4, 0x00]), None);
+        assert_eq!(decode_varint(&[0xF8]), None);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_varint_roundtrip(value: u64, trailing in proptest::collection::vec(any::<u8>(), 0..4)) {
+            let mut encoded = encode_varint_long(value);
+            let len = encoded.len();
+            encoded.extend(trailing);
+            prop_assert_eq!(decode_varint(&encoded), Some((value, len)));
+        }
+
+        #[test]
+        fn prop_varint_signed_roundtrip(value: i64) {
+            let encoded = encode_varint_long(value as u64);
+            let (decoded, len) = decode_varint(&encoded).unwrap();
+            prop_assert_eq!(decoded as i64, value);
+            prop_assert_eq!(len, encoded.len());
+        }
+
+        #[test]
+        fn prop_varint_uses_shortest_positive_form(value in 0u64..0x1_0000_0000) {
+            let expected = match value {
+                0..0x80 => 1,
+                0x80..0x4000 => 2,
+                0x4000..0x20_0000 => 3,
+                0x20_0000..0x1000_0000 => 4,
+                _ => 5,
+            };
+            prop_assert_eq!(encoded_len(value), expected);
+        }
+
+        #[test]
+        fn prop_varint_decode_never_panics(bytes in proptest::collection::vec(any::<u8>(), 0..12)) {
+            if let Some((_, len)) = decode_varint(&bytes) {
+                prop_assert!(len <= bytes.len());
+            }
+        }
+    }
+}
@@ -1,143 +1,369 @@
+// Server certificate verification, following the configured trust policy
+
+use crate::config::{TrustPolicy, TrustSettings};
+use crate::error::Error;
 use rustls::{
-    DigitallySignedStruct, SignatureScheme,
-    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    CertificateError, DigitallySignedStruct, RootCertStore, SignatureScheme,
+    client::{
+        WebPkiServerVerifier,
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    },
 };
 use rustls_pki_types::{CertificateDer, ServerName, UnixTime};
 use std::{
     collections::HashSet,
     fs,
-    io::{self, Write},
-    path::PathBuf,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
+    time::Duration,
 };
+use tokio::net::TcpStream;
+use tokio_rustls::{TlsConnector, rustls::ClientConfig};
+
+/// SHA-256 fingerprint of a DER certificate, written as colon separated hex
+pub fn fingerprint(cert: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    let hash = Sha256::digest(cert);
+    hash.iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Uppercase hex without separators, so fingerprints compare however they were written
+pub fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint
+        .chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .collect::<String>()
+        .to_ascii_uppercase()
+}
+
+/// Public CA roots, shared by every connection of a session
+pub fn root_store() -> Arc<RootCertStore> {
+    Arc::new(RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    })
+}
+
+/// The TLS server name for a host, which may be an IP address
+pub fn server_name(host: &str) -> Result<ServerName<'static>, Error> {
+    if let Ok(ip_addr) = host.parse::<std::net::IpAddr>() {
+        Ok(ServerName::IpAddress(ip_addr.into()))
+    } else {
+        ServerName::try_from(host.to_string())
+            .map_err(|e| Error::ConnectionError(format!("Invalid server name {}: {}", host, e)))
+    }
+}
 
+/// Whether a certificate the CA check rejected may still be trusted through
+/// the policy: one from an unknown issuer, such as a self-signed one. Expired,
+/// revoked and wrong-name certificates are rejected outright.
+fn may_trust_without_ca(error: &CertificateError, cert: &[u8]) -> bool {
+    match error {
+        CertificateError::UnknownIssuer => true,
+        CertificateError::Expired
+        | CertificateError::ExpiredContext { .. }
+        | CertificateError::NotValidYet
+        | CertificateError::NotValidYetContext { .. }
+        | CertificateError::NotValidForName
+        | CertificateError::NotValidForNameContext { .. }
+        | CertificateError::Revoked => false,
+        // Self-signed certificates made with CA:TRUE fail on that before their issuer is checked
+        _ => x509_parser::parse_x509_certificate(cert)
+            .is_ok_and(|(_, parsed)| parsed.issuer() == parsed.subject()),
+    }
+}
+
+/// Trusted server certificates on disk, one directory per host.
+///
+/// Certificates directly in the top directory were saved before trust was
+/// kept per host and are accepted for any host.
 #[derive(Clone, Debug)]
-pub struct PromptingCertVerifier {
-    trusted_self_signed: Arc<Mutex<HashSet<Vec<u8>>>>,
-    trusted_certs_dir: PathBuf,
+pub struct TrustStore {
+    dir: PathBuf,
 }
 
-impl PromptingCertVerifier {
-    pub fn new(trust_dir: Option<PathBuf>) -> Self {
-        let trusted_certs_dir = trust_dir.unwrap_or_else(|| {
-            dirs::home_dir()
-                .unwrap_or_else(|| PathBuf::from("."))
-                .join(".threebot")
-                .join("trusted_certificates")
-        });
-
-        // Ensure the trusted certificates directory exists
-        if let Err(e) = fs::create_dir_all(&trusted_certs_dir) {
-            eprintln!(
-                "Warning: Failed to create trusted certificates directory: {}",
-                e
-            );
+impl TrustStore {
+    pub fn new(dir: PathBuf) -> Self {
+        if let Err(e) = fs::create_dir_all(&dir) {
+            warn!("Failed to create trusted certificates directory: {}", e);
         }
+        Self { dir }
+    }
+
+    fn host_dir(&self, host: &str) -> PathBuf {
+        let name: String = host
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        self.dir.join(name)
+    }
 
-        let mut verifier = Self {
-            trusted_self_signed: Arc::new(Mutex::new(HashSet::new())),
-            trusted_certs_dir,
+    fn read_certificates(dir: &Path) -> Vec<Vec<u8>> {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Vec::new();
         };
 
-        // Load existing trusted certificates
-        verifier.load_trusted_certificates();
-        verifier
+        entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "der"))
+            .filter_map(|path| {
+                let cert = fs::read(&path).ok()?;
+                debug!("Loaded trusted certificate: {}", path.display());
+                Some(cert)
+            })
+            .collect()
     }
 
-    fn load_trusted_certificates(&mut self) {
-        if let Ok(entries) = fs::read_dir(&self.trusted_certs_dir) {
-            let mut trusted = self.trusted_self_signed.lock().unwrap();
-            for entry in entries.flatten() {
-                if let Some(extension) = entry.path().extension() {
-                    if extension == "der" {
-                        if let Ok(cert_data) = fs::read(entry.path()) {
-                            trusted.insert(cert_data);
-                            if let Some(filename) = entry.path().file_name() {
-                                println!("Loaded trusted certificate: {:?}", filename);
-                            }
-                        }
-                    }
-                }
-            }
-        }
+    /// Certificates saved for `host`
+    pub fn host_certificates(&self, host: &str) -> Vec<Vec<u8>> {
+        Self::read_certificates(&self.host_dir(host))
     }
 
-    fn save_certificate(
-        &self,
-        cert: &CertificateDer,
-        fingerprint: &str,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let filename = format!("{}.der", fingerprint.replace(":", ""));
-        let cert_path = self.trusted_certs_dir.join(filename);
+    /// Certificates saved without a host, accepted for every host
+    pub fn shared_certificates(&self) -> Vec<Vec<u8>> {
+        Self::read_certificates(&self.dir)
+    }
 
-        fs::write(&cert_path, cert.as_ref())?;
-        println!("Saved trusted certificate to: {:?}", cert_path);
-        Ok(())
+    /// Saves a certificate as trusted for `host`, returning where it was written
+    pub fn save(&self, host: &str, cert: &CertificateDer) -> io::Result<PathBuf> {
+        let dir = self.host_dir(host);
+        fs::create_dir_all(&dir)?;
+
+        let path = dir.join(format!("{}.der", normalize_fingerprint(&fingerprint(cert))));
+        fs::write(&path, cert.as_ref())?;
+        Ok(path)
+    }
+}
+
+#[derive(Debug)]
+struct Trusted {
+    certs: HashSet<Vec<u8>>,
+    /// Whether the host already has a trusted certificate, pinned, saved for
+    /// it or saved before trust was kept per host
+    for_host: bool,
+}
+
+/// Verifies the server's certificate. Certificates issued by a public CA are
+/// validated by webpki (chain, hostname and expiry); any other certificate is
+/// handled according to the `TrustPolicy`. Nothing ever waits for input
+/// unless the policy is interactive and there is a terminal to ask on.
+#[derive(Debug)]
+pub struct TrustPolicyVerifier {
+    policy: TrustPolicy,
+    host: String,
+    /// Normalized fingerprints pinned for the host in the config
+    pinned: HashSet<String>,
+    store: TrustStore,
+    trusted: Mutex<Trusted>,
+    webpki: Arc<WebPkiServerVerifier>,
+}
+
+impl TrustPolicyVerifier {
+    pub fn new(
+        host: &str,
+        settings: &TrustSettings,
+        store: TrustStore,
+        roots: Arc<RootCertStore>,
+    ) -> Result<Self, Error> {
+        let pinned: HashSet<String> = settings
+            .fingerprints
+            .get(host)
+            .into_iter()
+            .flatten()
+            .map(|fp| normalize_fingerprint(fp))
+            .collect();
+
+        let host_certs = store.host_certificates(host);
+        // Older versions saved every certificate in one directory; a host
+        // trusted back then must not accept a different certificate now
+        let shared_certs = store.shared_certificates();
+        let trusted = Trusted {
+            for_host: !pinned.is_empty() || !host_certs.is_empty() || !shared_certs.is_empty(),
+            certs: host_certs.into_iter().chain(shared_certs).collect(),
+        };
+
+        let webpki = WebPkiServerVerifier::builder(roots).build().map_err(|e| {
+            Error::InvalidCertificate(format!("Failed to set up CA verification: {}", e))
+        })?;
+
+        Ok(Self {
+            policy: settings.policy,
+            host: host.to_string(),
+            pinned,
+            store,
+            trusted: Mutex::new(trusted),
+            webpki,
+        })
+    }
+
+    fn trust(&self, trusted: &mut Trusted, cert: &CertificateDer) {
+        trusted.certs.insert(cert.to_vec());
+        trusted.for_host = true;
+        match self.store.save(&self.host, cert) {
+            Ok(path) => info!("Saved trusted certificate to {}", path.display()),
+            Err(e) => warn!("Failed to save trusted certificate: {}", e),
+        }
     }
 
     fn prompt_user(&self, fingerprint: &str) -> bool {
+        if !io::stdin().is_terminal() {
+            warn!(
+                "Cannot ask whether to trust the certificate of {} without a terminal",
+                self.host
+            );
+            return false;
+        }
+
         println!(
-            "Encountered self-signed certificate with fingerprint:\n  {}",
-            fingerprint
+            "{} presented a self-signed certificate with fingerprint:\n  {}",
+            self.host, fingerprint
         );
         print!("Do you want to trust this certificate? [y/N]: ");
-        io::stdout().flush().unwrap();
+        let _ = io::stdout().flush();
 
         let mut input = String::new();
-        io::stdin().read_line(&mut input).unwrap();
+        if io::stdin().read_line(&mut input).is_err() {
+            return false;
+        }
         matches!(input.trim().to_lowercase().as_str(), "y" | "yes")
     }
-
-    fn fingerprint(cert: &CertificateDer) -> String {
-        use sha2::{Digest, Sha256};
-        let hash = Sha256::digest(&cert.to_vec());
-        hash.iter()
-            .map(|b| format!("{:02X}:", b))
-            .collect::<String>()
-            .trim_end_matches(':')
-            .to_string()
-    }
 }
 
-impl ServerCertVerifier for PromptingCertVerifier {
+impl ServerCertVerifier for TrustPolicyVerifier {
     fn verify_server_cert(
         &self,
-        _end_entity: &CertificateDer,
-        _intermediates: &[CertificateDer],
-        _server_name: &ServerName,
-        _ocsp_response: &[u8],
-        _now: UnixTime,
+        end_entity: &CertificateDer,
+        intermediates: &[CertificateDer],
+        server_name: &ServerName,
+        ocsp_response: &[u8],
+        now: UnixTime,
     ) -> Result<ServerCertVerified, rustls::Error> {
-        let cert = _end_entity;
-        let mut trusted = self.trusted_self_signed.lock().unwrap();
+        let ca_error = match self.webpki.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        ) {
+            Ok(verified) => return Ok(verified),
+            Err(rustls::Error::InvalidCertificate(e))
+                if self.policy != TrustPolicy::Strict && may_trust_without_ca(&e, end_entity) =>
+            {
+                e
+            }
+            Err(e) => return Err(e),
+        };
+        debug!(
+            "Certificate of {} failed CA validation ({}), checking pinned certificates",
+            self.host, ca_error
+        );
 
-        if trusted.contains(&cert.to_vec()) {
+        let cert = end_entity;
+        let fp = fingerprint(cert);
+        let mut trusted = self.trusted.lock().unwrap();
+
+        if self.pinned.contains(&normalize_fingerprint(&fp))
+            || trusted.certs.contains(cert.as_ref())
+        {
             return Ok(ServerCertVerified::assertion());
         }
 
-        let fp = Self::fingerprint(cert);
-        if self.prompt_user(&fp) {
-            trusted.insert(cert.to_vec());
-            // Save the certificate to disk for future use
-            if let Err(e) = self.save_certificate(cert, &fp) {
-                eprintln!("Warning: Failed to save trusted certificate: {}", e);
+        match self.policy {
+            TrustPolicy::TrustOnFirstUse if !trusted.for_host => {
+                warn!(
+                    "Trusting certificate {} presented by {} on first use",
+                    fp, self.host
+                );
+                self.trust(&mut trusted, cert);
+                Ok(ServerCertVerified::assertion())
             }
-            Ok(ServerCertVerified::assertion())
-        } else {
-            Err(rustls::Error::General(
-                "User rejected self-signed certificate".into(),
-            ))
+            TrustPolicy::TrustOnFirstUse => Err(rustls::Error::General(format!(
+                "{} presented certificate {}, which is not the one trusted before. \
+                 If the server's certificate was replaced, run `threebot trust {}`",
+                self.host, fp, self.host
+            ))),
+            TrustPolicy::Interactive => {
+                if self.prompt_user(&fp) {
+                    self.trust(&mut trusted, cert);
+                    Ok(ServerCertVerified::assertion())
+                } else {
+                    Err(rustls::Error::General(
+                        "User rejected self-signed certificate".into(),
+                    ))
+                }
+            }
+            TrustPolicy::Pinned | TrustPolicy::Strict => Err(rustls::Error::General(format!(
+                "Certificate {} of {} is not pinned; run `threebot trust {}` to pin it",
+                fp, self.host, self.host
+            ))),
         }
     }
 
     fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
-        vec![
-            SignatureScheme::RSA_PKCS1_SHA256,
-            SignatureScheme::ECDSA_NISTP256_SHA256,
-            SignatureScheme::ED25519,
-            SignatureScheme::RSA_PKCS1_SHA1,
-            SignatureScheme::RSA_PSS_SHA256,
-        ]
+        self.webpki.supported_verify_schemes()
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        signed: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        // Checks the signature against the certificate's key, which holds for
+        // pinned self-signed certificates too
+        self.webpki.verify_tls12_signature(message, cert, signed)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        signed: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        // Checks the signature against the certificate's key, which holds for
+        // pinned self-signed certificates too
+        self.webpki.verify_tls13_signature(message, cert, signed)
+    }
+}
+
+/// Records the certificate a server presents and then aborts the handshake,
+/// so nothing is trusted or sent while fetching it
+#[derive(Debug, Default)]
+struct CapturingVerifier {
+    captured: Mutex<Option<CertificateDer<'static>>>,
+}
+
+impl ServerCertVerifier for CapturingVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer,
+        _intermediates: &[CertificateDer],
+        _server_name: &ServerName,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        *self.captured.lock().unwrap() = Some(end_entity.clone().into_owned());
+        Err(rustls::Error::General("certificate captured".into()))
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        rustls::crypto::CryptoProvider::get_default()
+            .map(|provider| {
+                provider
+                    .signature_verification_algorithms
+                    .supported_schemes()
+            })
+            .unwrap_or_default()
     }
 
     fn verify_tls12_signature(
@@ -146,8 +372,7 @@ impl ServerCertVerifier for PromptingCertVerifier {
         _cert: &CertificateDer<'_>,
         _signed: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, rustls::Error> {
-        // Simplified: Accept all for now (but you can validate with ring or webpki if desired)
-        Ok(HandshakeSignatureValid::assertion())
+        Err(rustls::Error::General("certificate captured".into()))
     }
 
     fn verify_tls13_signature(
@@ -156,7 +381,269 @@ impl ServerCertVerifier for PromptingCertVerifier {
         _cert: &CertificateDer<'_>,
         _signed: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, rustls::Error> {
-        // Simplified: Accept all for now (but you can validate with ring or webpki if desired)
-        Ok(HandshakeSignatureValid::assertion())
+        Err(rustls::Error::General("certificate captured".into()))
+    }
+}
+
+/// Connects to a server just long enough to read the certificate it presents
+pub async fn fetch_certificate(
+    host: &str,
+    port: u16,
+    timeout: Duration,
+) -> Result<CertificateDer<'static>, Error> {
+    let verifier = Arc::new(CapturingVerifier::default());
+    let config = ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(verifier.clone())
+        .with_no_client_auth();
+    let server_name = server_name(host)?;
+
+    let handshake = async {
+        let socket = TcpStream::connect((host, port))
+            .await
+            .map_err(|e| Error::ConnectionError(format!("Failed to connect to {}: {}", host, e)))?;
+        // The handshake fails on purpose once the certificate is captured
+        let _ = TlsConnector::from(Arc::new(config))
+            .connect(server_name, socket)
+            .await;
+        Ok::<_, Error>(())
+    };
+    tokio::time::timeout(timeout, handshake)
+        .await
+        .map_err(|_| {
+            Error::ConnectionError(format!(
+                "Timed out connecting to {} after {}s",
+                host,
+                timeout.as_secs()
+            ))
+        })??;
+
+    verifier
+        .captured
+        .lock()
+        .unwrap()
+        .take()
+        .ok_or_else(|| Error::InvalidCertificate(format!("{} did not present a certificate", host)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use rustls::internal::msgs::codec::{Codec, Reader};
+    use std::collections::HashMap;
+
+    const HOST: &str = "mumble.example.com";
+
+    fn self_signed(name: &str) -> (CertificateDer<'static>, KeyPair) {
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        (cert.der().clone(), key_pair)
+    }
+
+    fn verifier_with_roots(
+        policy: TrustPolicy,
+        pinned: &[&str],
+        dir: &Path,
+        roots: Arc<RootCertStore>,
+    ) -> TrustPolicyVerifier {
+        let settings = TrustSettings {
+            policy,
+            fingerprints: HashMap::from([(
+                HOST.to_string(),
+                pinned.iter().map(|fp| fp.to_string()).collect(),
+            )]),
+        };
+        TrustPolicyVerifier::new(HOST, &settings, TrustStore::new(dir.to_path_buf()), roots)
+            .unwrap()
+    }
+
+    fn verifier(policy: TrustPolicy, pinned: &[&str], dir: &Path) -> TrustPolicyVerifier {
+        verifier_with_roots(policy, pinned, dir, root_store())
+    }
+
+    fn verify(verifier: &TrustPolicyVerifier, cert: &CertificateDer) -> bool {
+        verifier
+            .verify_server_cert(cert, &[], &server_name(HOST).unwrap(), &[], UnixTime::now())
+            .is_ok()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("threebot-trust-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// Signs `message` with `key` the way a server signs its handshake
+    fn sign(key: &KeyPair, message: &[u8]) -> DigitallySignedStruct {
+        let provider = rustls::crypto::aws_lc_rs::default_provider();
+        let key = provider
+            .key_provider
+            .load_private_key(key.serialize_der().try_into().unwrap())
+            .unwrap();
+        let scheme = SignatureScheme::ECDSA_NISTP256_SHA256;
+        let signature = key.choose_scheme(&[scheme]).unwrap().sign(message).unwrap();
+
+        let mut encoded = Vec::new();
+        scheme.encode(&mut encoded);
+        (signature.len() as u16).encode(&mut encoded);
+        encoded.extend_from_slice(&signature);
+        DigitallySignedStruct::read(&mut Reader::init(&encoded)).unwrap()
+    }
+
+    #[test]
+    fn test_fingerprints_compare_in_any_format() {
+        let fp = fingerprint(b"certificate");
+        assert_eq!(fp.len(), 32 * 3 - 1);
+        assert_eq!(
+            normalize_fingerprint(&fp),
+            normalize_fingerprint(&fp.replace(':', "").to_lowercase())
+        );
+    }
+
+    #[test]
+    fn test_trust_on_first_use() {
+        let dir = temp_dir("tofu");
+        let (first, _) = self_signed(HOST);
+        let (second, _) = self_signed(HOST);
+
+        let tofu = verifier(TrustPolicy::TrustOnFirstUse, &[], &dir);
+        assert!(verify(&tofu, &first));
+        assert!(verify(&tofu, &first));
+        assert!(!verify(&tofu, &second));
+
+        // The first certificate was saved for the next connection
+        let tofu = verifier(TrustPolicy::TrustOnFirstUse, &[], &dir);
+        assert!(verify(&tofu, &first));
+        assert!(!verify(&tofu, &second));
+
+        // Garbage is rejected rather than trusted on first use
+        let garbage_dir = temp_dir("tofu-garbage");
+        let tofu = verifier(TrustPolicy::TrustOnFirstUse, &[], &garbage_dir);
+        assert!(!verify(&tofu, &CertificateDer::from(b"garbage".to_vec())));
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::remove_dir_all(&garbage_dir);
+    }
+
+    #[test]
+    fn test_pinned() {
+        let dir = temp_dir("pinned");
+        let (cert, _) = self_signed(HOST);
+        let (other, _) = self_signed(HOST);
+        let pin = fingerprint(&cert).to_lowercase();
+
+        let pinned = verifier(TrustPolicy::Pinned, &[&pin], &dir);
+        assert!(verify(&pinned, &cert));
+        assert!(!verify(&pinned, &other));
+
+        // A pinned fingerprint also rules out trusting another certificate on first use
+        let tofu = verifier(TrustPolicy::TrustOnFirstUse, &[&pin], &dir);
+        assert!(!verify(&tofu, &other));
+
+        // Certificates saved ahead of time are accepted too
+        let (saved, _) = self_signed(HOST);
+        TrustStore::new(dir.clone()).save(HOST, &saved).unwrap();
+        let pinned = verifier(TrustPolicy::Pinned, &[], &dir);
+        assert!(verify(&pinned, &saved));
+
+        // Strict validation ignores pins and saved certificates
+        let strict = verifier(TrustPolicy::Strict, &[&pin], &dir);
+        assert!(!verify(&strict, &cert));
+        assert!(!verify(&strict, &saved));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_certificates_saved_before_per_host_trust_still_count() {
+        let dir = temp_dir("legacy");
+        let (legacy, _) = self_signed(HOST);
+        let (other, _) = self_signed(HOST);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("server.der"), &legacy).unwrap();
+
+        let tofu = verifier(TrustPolicy::TrustOnFirstUse, &[], &dir);
+        assert!(verify(&tofu, &legacy));
+        assert!(!verify(&tofu, &other));
+        assert!(
+            TrustStore::new(dir.clone())
+                .host_certificates(HOST)
+                .is_empty()
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_ca_issued_certificates() {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.der().clone()).unwrap();
+        let roots = Arc::new(roots);
+
+        let issue = |name: &str, expired: bool| -> CertificateDer<'static> {
+            let mut params = CertificateParams::new(vec![name.to_string()]).unwrap();
+            if expired {
+                params.not_before = time::OffsetDateTime::now_utc() - time::Duration::days(30);
+                params.not_after = time::OffsetDateTime::now_utc() - time::Duration::days(1);
+            }
+            let key = KeyPair::generate().unwrap();
+            params.signed_by(&key, &ca, &ca_key).unwrap().der().clone()
+        };
+
+        let dir = temp_dir("ca");
+        let strict = verifier_with_roots(TrustPolicy::Strict, &[], &dir, roots.clone());
+        assert!(verify(&strict, &issue(HOST, false)));
+
+        // Problems with a CA-issued certificate are never handed to trust on first use
+        let tofu = verifier_with_roots(TrustPolicy::TrustOnFirstUse, &[], &dir, roots);
+        assert!(!verify(&tofu, &issue("other.example.com", false)));
+        assert!(!verify(&tofu, &issue(HOST, true)));
+        assert!(
+            TrustStore::new(dir.clone())
+                .host_certificates(HOST)
+                .is_empty()
+        );
+
+        // A self-signed certificate still falls back to the policy
+        let (self_signed, _) = self_signed(HOST);
+        assert!(verify(&tofu, &self_signed));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_handshake_signatures_are_checked() {
+        let dir = temp_dir("signatures");
+        let (cert, key) = self_signed(HOST);
+        let (_, other_key) = self_signed(HOST);
+        let tofu = verifier(TrustPolicy::TrustOnFirstUse, &[], &dir);
+        let message = b"handshake transcript";
+
+        let valid = sign(&key, message);
+        assert!(tofu.verify_tls13_signature(message, &cert, &valid).is_ok());
+        assert!(tofu.verify_tls12_signature(message, &cert, &valid).is_ok());
+
+        // Signed over different data, or by a key other than the certificate's
+        let tampered = sign(&key, b"another transcript");
+        assert!(
+            tofu.verify_tls13_signature(message, &cert, &tampered)
+                .is_err()
+        );
+        assert!(
+            tofu.verify_tls12_signature(message, &cert, &tampered)
+                .is_err()
+        );
+        let forged = sign(&other_key, message);
+        assert!(
+            tofu.verify_tls13_signature(message, &cert, &forged)
+                .is_err()
+        );
+        assert!(
+            tofu.verify_tls12_signature(message, &cert, &forged)
+                .is_err()
+        );
+        let _ = fs::remove_dir_all(&dir);
     }
 }